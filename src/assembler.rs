@@ -0,0 +1,1228 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::instruction::Instruction;
+
+const PROGRAM_START: usize = 0x200;
+const MEMORY_END: usize = 0x10000;
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+
+// 行・列は1始まり
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: impl Into<String>) -> AssembleError {
+        AssembleError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+// 空白で区切り、# から行末まではコメントとして捨てる
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let mut current: Option<Token> = None;
+        for (column_index, c) in line.chars().enumerate() {
+            if c.is_whitespace() {
+                tokens.extend(current.take());
+                continue;
+            }
+            if c == '#' && current.is_none() {
+                break;
+            }
+            current
+                .get_or_insert_with(|| Token {
+                    text: String::new(),
+                    line: line_index + 1,
+                    column: column_index + 1,
+                })
+                .text
+                .push(c);
+        }
+        tokens.extend(current.take());
+    }
+    tokens
+}
+
+// Octo形式のソースをアセンブルし、0x200 から始まるROMイメージを返す
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    analyze(source).result
+}
+
+// エディタ支援用に、アセンブル結果と一緒に定義された名前と出力した命令の位置を返す
+// エラーの場合も、そこまでに見つかった名前と命令は返す
+pub fn analyze(source: &str) -> Analysis {
+    let mut assembler = Assembler::new(tokenize(source));
+    let result = assembler.run().map(|()| assembler.rom.clone());
+    let listing = assembler
+        .emitted
+        .iter()
+        .filter_map(|&(line, column, address)| {
+            let instruction = Instruction::fetch(&assembler.rom, address - PROGRAM_START).ok()?;
+            Some(SourceInstruction {
+                line,
+                column,
+                address,
+                instruction,
+            })
+        })
+        .collect();
+    Analysis {
+        result,
+        symbols: assembler.symbols,
+        listing,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolKind {
+    Label(usize),
+    Constant(f64),
+    Alias(u8),
+    Macro(Vec<String>),
+}
+
+// 名前の定義。行・列は名前のトークンの位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+// 出力した命令と、その元になったトークンの位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceInstruction {
+    pub line: usize,
+    pub column: usize,
+    pub address: usize,
+    pub instruction: Instruction,
+}
+
+pub struct Analysis {
+    pub result: Result<Vec<u8>, AssembleError>,
+    pub symbols: Vec<Symbol>,
+    pub listing: Vec<SourceInstruction>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    Address,    // nnn (下位12ビット)
+    Long,       // F000 NNNN の NNNN
+    Unpack(u8), // :unpack の v0 / v1
+}
+
+// まだ定義されていないラベルを参照している箇所
+struct Fixup {
+    address: usize,
+    kind: FixupKind,
+    token: Token,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// if ... begin / else / end の未解決のジャンプ
+struct Branch {
+    jump: usize,
+    has_else: bool,
+    token: Token,
+}
+
+// loop ... again の先頭と、while が抜けるためのジャンプ
+struct Loop {
+    start: usize,
+    exits: Vec<usize>,
+    token: Token,
+}
+
+// if / while の条件式。setup の後に skip を実行すると、条件が成り立たないときに次の命令を飛ばす
+struct Condition {
+    setup: Vec<Instruction>,
+    skip: Instruction,
+}
+
+struct Assembler {
+    tokens: Vec<Token>,
+    position: usize,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    branches: Vec<Branch>,
+    loops: Vec<Loop>,
+    expansions: usize,
+    main_slot: bool, // 0x200 に jump main を置く場所を確保しているか
+    symbols: Vec<Symbol>,
+    emitted: Vec<(usize, usize, usize)>, // (行, 列, アドレス)
+}
+
+impl Assembler {
+    fn new(tokens: Vec<Token>) -> Self {
+        // Octoと同様に main があれば 0x200 から main へジャンプする
+        let has_main = tokens
+            .windows(2)
+            .any(|pair| pair[0].text == ":" && pair[1].text == "main");
+        Assembler {
+            tokens,
+            position: 0,
+            rom: Vec::new(),
+            here: PROGRAM_START + if has_main { 2 } else { 0 },
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+            expansions: 0,
+            main_slot: has_main,
+            symbols: Vec::new(),
+            emitted: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), AssembleError> {
+        while let Some(token) = self.next_token() {
+            self.statement(token)?;
+        }
+
+        if let Some(branch) = self.branches.first() {
+            return Err(branch.token.error("'if ... begin' is missing its 'end'"));
+        }
+        if let Some(open) = self.loops.first() {
+            return Err(open.token.error("'loop' is missing its 'again'"));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&target) = self.labels.get(&fixup.token.text) else {
+                return Err(fixup
+                    .token
+                    .error(format!("undefined name '{}'", fixup.token.text)));
+            };
+            self.patch(fixup.address, fixup.kind, target, &fixup.token)?;
+        }
+
+        if self.main_slot {
+            let main = self.labels["main"];
+            self.write_at(PROGRAM_START, &Instruction::Jp(main as u16).to_bytes());
+        }
+        Ok(())
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect_token(&mut self, after: &Token) -> Result<Token, AssembleError> {
+        self.next_token()
+            .ok_or_else(|| after.error(format!("unexpected end of source after '{}'", after.text)))
+    }
+
+    fn expect(&mut self, after: &Token, text: &str) -> Result<Token, AssembleError> {
+        let token = self.expect_token(after)?;
+        if token.text != text {
+            return Err(token.error(format!("expected '{}' but found '{}'", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn define(&mut self, token: &Token, kind: SymbolKind) {
+        self.symbols.push(Symbol {
+            name: token.text.clone(),
+            kind,
+            line: token.line,
+            column: token.column,
+        });
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens
+            .get(self.position)
+            .is_some_and(|token| token.text == text)
+    }
+
+    // ---- 出力 ----
+
+    fn write_at(&mut self, address: usize, bytes: &[u8]) {
+        let offset = address - PROGRAM_START;
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn emit_bytes(&mut self, bytes: &[u8], token: &Token) -> Result<(), AssembleError> {
+        if self.here + bytes.len() > MEMORY_END {
+            return Err(token.error("program does not fit in memory"));
+        }
+        self.write_at(self.here, bytes);
+        self.here += bytes.len();
+        Ok(())
+    }
+
+    fn emit(&mut self, instruction: Instruction, token: &Token) -> Result<(), AssembleError> {
+        self.emitted.push((token.line, token.column, self.here));
+        self.emit_bytes(&instruction.to_bytes(), token)
+    }
+
+    // 後で飛び先を埋めるジャンプを出力し、その位置を返す
+    fn emit_placeholder_jump(&mut self, token: &Token) -> Result<usize, AssembleError> {
+        let address = self.here;
+        self.emit(Instruction::Jp(0), token)?;
+        Ok(address)
+    }
+
+    fn patch(
+        &mut self,
+        address: usize,
+        kind: FixupKind,
+        target: usize,
+        token: &Token,
+    ) -> Result<(), AssembleError> {
+        let offset = address - PROGRAM_START;
+        match kind {
+            FixupKind::Address => {
+                if target > 0xFFF {
+                    return Err(
+                        token.error(format!("address 0x{:X} does not fit in 12 bits", target))
+                    );
+                }
+                self.rom[offset] = (self.rom[offset] & 0xF0) | (target >> 8) as u8;
+                self.rom[offset + 1] = target as u8;
+            }
+            FixupKind::Long => {
+                self.rom[offset..offset + 2].copy_from_slice(&(target as u16).to_be_bytes());
+            }
+            FixupKind::Unpack(high) => {
+                self.rom[offset + 1] = high << 4 | ((target >> 8) & 0xF) as u8;
+                self.rom[offset + 3] = target as u8;
+            }
+        }
+        Ok(())
+    }
+
+    // ---- 値の解釈 ----
+
+    fn register(&self, token: &Token) -> Result<u8, AssembleError> {
+        if let Some(&register) = self.aliases.get(&token.text) {
+            return Ok(register);
+        }
+        parse_register(&token.text)
+            .ok_or_else(|| token.error(format!("expected a register but found '{}'", token.text)))
+    }
+
+    fn is_register(&self, token: &Token) -> bool {
+        self.aliases.contains_key(&token.text) || parse_register(&token.text).is_some()
+    }
+
+    // 数値・定数・定義済みのラベル
+    fn known_value(&self, token: &Token) -> Option<f64> {
+        if let Some(number) = parse_number(&token.text) {
+            return Some(number as f64);
+        }
+        if let Some(&value) = self.constants.get(&token.text) {
+            return Some(value);
+        }
+        self.labels.get(&token.text).map(|&address| address as f64)
+    }
+
+    fn integer(&self, token: &Token, min: i64, max: i64) -> Result<i64, AssembleError> {
+        let value = self
+            .known_value(token)
+            .ok_or_else(|| token.error(format!("undefined name '{}'", token.text)))?;
+        let value = value.trunc() as i64;
+        if value < min || value > max {
+            return Err(token.error(format!(
+                "value {} is out of range ({} to {})",
+                value, min, max
+            )));
+        }
+        Ok(value)
+    }
+
+    fn byte(&self, token: &Token) -> Result<u8, AssembleError> {
+        Ok(self.integer(token, -128, 255)? as u8)
+    }
+
+    fn nibble(&self, token: &Token) -> Result<u8, AssembleError> {
+        Ok(self.integer(token, 0, 15)? as u8)
+    }
+
+    fn valid_name(&self, token: &Token) -> Result<String, AssembleError> {
+        let name = &token.text;
+        let valid = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid || parse_register(name).is_some() || KEYWORDS.contains(&name.as_str()) {
+            return Err(token.error(format!("'{}' cannot be used as a name", name)));
+        }
+        Ok(name.clone())
+    }
+
+    // アドレスを出力する命令。未定義のラベルなら後で埋める
+    fn emit_with_address(
+        &mut self,
+        instruction: impl Fn(u16) -> Instruction,
+        target: &Token,
+        kind: FixupKind,
+    ) -> Result<(), AssembleError> {
+        let max = match kind {
+            FixupKind::Address => 0xFFF,
+            _ => 0xFFFF,
+        };
+        match self.known_value(target) {
+            Some(_) => {
+                let address = self.integer(target, 0, max)?;
+                self.emit(instruction(address as u16), target)
+            }
+            None => {
+                self.valid_name(target)?;
+                let offset = match kind {
+                    FixupKind::Long => 2,
+                    _ => 0,
+                };
+                self.fixups.push(Fixup {
+                    address: self.here + offset,
+                    kind,
+                    token: target.clone(),
+                });
+                self.emit(instruction(0), target)
+            }
+        }
+    }
+
+    // ---- 文 ----
+
+    fn statement(&mut self, token: Token) -> Result<(), AssembleError> {
+        use Instruction::*;
+
+        match token.text.as_str() {
+            ":" => {
+                let name_token = self.expect_token(&token)?;
+                let name = self.valid_name(&name_token)?;
+                if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+                    return Err(name_token.error(format!("'{}' is already defined", name)));
+                }
+                // main が先頭にある場合はジャンプが不要
+                if name == "main"
+                    && self.main_slot
+                    && self.here == PROGRAM_START + 2
+                    && self.rom.is_empty()
+                {
+                    self.main_slot = false;
+                    self.here = PROGRAM_START;
+                }
+                self.define(&name_token, SymbolKind::Label(self.here));
+                self.labels.insert(name, self.here);
+            }
+            ":const" => {
+                let name_token = self.expect_token(&token)?;
+                let name = self.valid_name(&name_token)?;
+                let value_token = self.expect_token(&name_token)?;
+                let value = self.known_value(&value_token).ok_or_else(|| {
+                    value_token.error(format!("undefined name '{}'", value_token.text))
+                })?;
+                self.define(&name_token, SymbolKind::Constant(value));
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name_token = self.expect_token(&token)?;
+                let name = self.valid_name(&name_token)?;
+                let register_token = self.expect_token(&name_token)?;
+                let register = self.register(&register_token)?;
+                self.define(&name_token, SymbolKind::Alias(register));
+                self.aliases.insert(name, register);
+            }
+            ":macro" => self.define_macro(&token)?,
+            ":calc" => {
+                let name_token = self.expect_token(&token)?;
+                let name = self.valid_name(&name_token)?;
+                let value = self.braced_expression(&name_token)?;
+                self.define(&name_token, SymbolKind::Constant(value));
+                self.constants.insert(name, value);
+            }
+            ":byte" => {
+                let value_token = self.expect_token(&token)?;
+                let value = if value_token.text == "{" {
+                    self.position -= 1;
+                    let value = self.braced_expression(&token)?.trunc() as i64;
+                    if !(-128..=255).contains(&value) {
+                        return Err(
+                            value_token.error(format!("value {} does not fit in a byte", value))
+                        );
+                    }
+                    value as u8
+                } else {
+                    self.byte(&value_token)?
+                };
+                self.emit_bytes(&[value], &token)?;
+            }
+            ":org" => {
+                let address_token = self.expect_token(&token)?;
+                let address =
+                    self.integer(&address_token, PROGRAM_START as i64, MEMORY_END as i64 - 1)?;
+                self.here = address as usize;
+            }
+            ":call" => {
+                let target = self.expect_token(&token)?;
+                self.emit_with_address(Call, &target, FixupKind::Address)?;
+            }
+            ":unpack" => {
+                let high_token = self.expect_token(&token)?;
+                let high = self.nibble(&high_token)?;
+                let target = self.expect_token(&high_token)?;
+                let address = self.here;
+                match self.known_value(&target) {
+                    Some(_) => {
+                        let value = self.integer(&target, 0, 0xFFF)? as u16;
+                        self.emit(LdByte(0, high << 4 | (value >> 8) as u8), &target)?;
+                        self.emit(LdByte(1, value as u8), &target)?;
+                    }
+                    None => {
+                        self.valid_name(&target)?;
+                        self.emit(LdByte(0, 0), &target)?;
+                        self.emit(LdByte(1, 0), &target)?;
+                        self.fixups.push(Fixup {
+                            address,
+                            kind: FixupKind::Unpack(high),
+                            token: target,
+                        });
+                    }
+                }
+            }
+            "clear" => self.emit(Cls, &token)?,
+            "return" | ";" => self.emit(Ret, &token)?,
+            "exit" => self.emit(Exit, &token)?,
+            "hires" => self.emit(High, &token)?,
+            "lores" => self.emit(Low, &token)?,
+            "scroll-left" => self.emit(Scl, &token)?,
+            "scroll-right" => self.emit(Scr, &token)?,
+            "audio" => self.emit(Audio, &token)?,
+            "scroll-down" | "scroll-up" | "plane" => {
+                let operand = self.expect_token(&token)?;
+                let n = self.nibble(&operand)?;
+                let instruction = match token.text.as_str() {
+                    "scroll-down" => Scd(n),
+                    "scroll-up" => Scu(n),
+                    _ => Plane(n),
+                };
+                self.emit(instruction, &token)?;
+            }
+            "bcd" | "saveflags" | "loadflags" => {
+                let operand = self.expect_token(&token)?;
+                let x = self.register(&operand)?;
+                let instruction = match token.text.as_str() {
+                    "bcd" => LdBVx(x),
+                    "saveflags" => LdRVx(x),
+                    _ => LdVxR(x),
+                };
+                self.emit(instruction, &token)?;
+            }
+            "save" | "load" => {
+                let operand = self.expect_token(&token)?;
+                let x = self.register(&operand)?;
+                let instruction = if self.peek_is("-") {
+                    self.position += 1;
+                    let last = self.expect_token(&operand)?;
+                    let y = self.register(&last)?;
+                    if token.text == "save" {
+                        SaveXy(x, y)
+                    } else {
+                        LoadXy(x, y)
+                    }
+                } else if token.text == "save" {
+                    LdIVx(x)
+                } else {
+                    LdVxI(x)
+                };
+                self.emit(instruction, &token)?;
+            }
+            "sprite" => {
+                let x_token = self.expect_token(&token)?;
+                let y_token = self.expect_token(&x_token)?;
+                let n_token = self.expect_token(&y_token)?;
+                let instruction = Drw(
+                    self.register(&x_token)?,
+                    self.register(&y_token)?,
+                    self.nibble(&n_token)?,
+                );
+                self.emit(instruction, &token)?;
+            }
+            "jump" | "jump0" | "native" => {
+                let target = self.expect_token(&token)?;
+                let instruction = match token.text.as_str() {
+                    "jump" => Jp,
+                    "jump0" => JpV0,
+                    _ => Sys,
+                };
+                self.emit_with_address(instruction, &target, FixupKind::Address)?;
+            }
+            "i" => self.index_statement(&token)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(&token, ":=")?;
+                let operand = self.expect_token(&token)?;
+                let x = self.register(&operand)?;
+                let instruction = match token.text.as_str() {
+                    "delay" => LdDtVx(x),
+                    "buzzer" => LdStVx(x),
+                    _ => Pitch(x),
+                };
+                self.emit(instruction, &token)?;
+            }
+            "if" => self.if_statement(&token)?,
+            "else" => {
+                let Some(branch) = self.branches.last_mut() else {
+                    return Err(token.error("'else' without 'if ... begin'"));
+                };
+                if branch.has_else {
+                    return Err(token.error("'if ... begin' already has an 'else'"));
+                }
+                branch.has_else = true;
+                let begin_jump = branch.jump;
+                let jump = self.emit_placeholder_jump(&token)?;
+                self.patch(begin_jump, FixupKind::Address, self.here, &token)?;
+                self.branches.last_mut().unwrap().jump = jump;
+            }
+            "end" => {
+                let Some(branch) = self.branches.pop() else {
+                    return Err(token.error("'end' without 'if ... begin'"));
+                };
+                self.patch(branch.jump, FixupKind::Address, self.here, &token)?;
+            }
+            "loop" => self.loops.push(Loop {
+                start: self.here,
+                exits: Vec::new(),
+                token: token.clone(),
+            }),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(token.error("'while' outside of 'loop ... again'"));
+                }
+                // 条件が成り立つ間はループを抜けるジャンプを飛ばす
+                let condition = self.condition(&token, true)?;
+                self.emit_condition(condition, &token)?;
+                let jump = self.emit_placeholder_jump(&token)?;
+                self.loops.last_mut().unwrap().exits.push(jump);
+            }
+            "again" => {
+                let Some(open) = self.loops.pop() else {
+                    return Err(token.error("'again' without 'loop'"));
+                };
+                self.emit(Jp(0), &token)?;
+                self.patch(self.here - 2, FixupKind::Address, open.start, &token)?;
+                for exit in open.exits {
+                    self.patch(exit, FixupKind::Address, self.here, &token)?;
+                }
+            }
+            _ if self.is_register(&token) => self.register_statement(&token)?,
+            _ if self.macros.contains_key(&token.text) => self.expand_macro(&token)?,
+            _ if parse_number(&token.text).is_some()
+                || self.constants.contains_key(&token.text) =>
+            {
+                let value = self.byte(&token)?;
+                self.emit_bytes(&[value], &token)?;
+            }
+            _ if token.text.starts_with(':') => {
+                return Err(token.error(format!("unknown directive '{}'", token.text)));
+            }
+            // それ以外の名前はサブルーチン呼び出し
+            _ => self.emit_with_address(Call, &token, FixupKind::Address)?,
+        }
+        Ok(())
+    }
+
+    fn index_statement(&mut self, token: &Token) -> Result<(), AssembleError> {
+        use Instruction::*;
+
+        let operator = self.expect_token(token)?;
+        match operator.text.as_str() {
+            ":=" => {
+                let operand = self.expect_token(&operator)?;
+                match operand.text.as_str() {
+                    "hex" | "bighex" => {
+                        let register_token = self.expect_token(&operand)?;
+                        let x = self.register(&register_token)?;
+                        let instruction = if operand.text == "hex" {
+                            LdFVx(x)
+                        } else {
+                            LdHfVx(x)
+                        };
+                        self.emit(instruction, token)
+                    }
+                    "long" => {
+                        let target = self.expect_token(&operand)?;
+                        self.emit_with_address(LdILong, &target, FixupKind::Long)
+                    }
+                    _ => self.emit_with_address(LdI, &operand, FixupKind::Address),
+                }
+            }
+            "+=" => {
+                let operand = self.expect_token(&operator)?;
+                let x = self.register(&operand)?;
+                self.emit(AddIVx(x), token)
+            }
+            _ => Err(operator.error(format!("unexpected '{}' after 'i'", operator.text))),
+        }
+    }
+
+    fn register_statement(&mut self, token: &Token) -> Result<(), AssembleError> {
+        use Instruction::*;
+
+        let x = self.register(token)?;
+        let operator = self.expect_token(token)?;
+        let operand = self.expect_token(&operator)?;
+
+        let instruction = match (operator.text.as_str(), operand.text.as_str()) {
+            (":=", "random") => {
+                let mask = self.expect_token(&operand)?;
+                Rnd(x, self.byte(&mask)?)
+            }
+            (":=", "key") => LdVxK(x),
+            (":=", "delay") => LdVxDt(x),
+            _ if self.is_register(&operand) => {
+                let y = self.register(&operand)?;
+                match operator.text.as_str() {
+                    ":=" => LdXy(x, y),
+                    "+=" => AddXy(x, y),
+                    "-=" => SubXy(x, y),
+                    "=-" => SubnXy(x, y),
+                    "|=" => OrXy(x, y),
+                    "&=" => AndXy(x, y),
+                    "^=" => XorXy(x, y),
+                    ">>=" => ShrXy(x, y),
+                    "<<=" => ShlXy(x, y),
+                    _ => {
+                        return Err(operator.error(format!("unknown operator '{}'", operator.text)))
+                    }
+                }
+            }
+            _ => {
+                let value = self.byte(&operand)?;
+                match operator.text.as_str() {
+                    ":=" => LdByte(x, value),
+                    "+=" => AddByte(x, value),
+                    "-=" => AddByte(x, value.wrapping_neg()),
+                    _ => {
+                        return Err(operator.error(format!(
+                            "operator '{}' needs a register operand",
+                            operator.text
+                        )))
+                    }
+                }
+            }
+        };
+        self.emit(instruction, token)
+    }
+
+    fn if_statement(&mut self, token: &Token) -> Result<(), AssembleError> {
+        // 条件式の後ろが then か begin かで組み立て方が変わるので、先に読む位置を探す
+        let start = self.position;
+        let condition = self.condition(token, false)?;
+        let keyword = self.expect_token(token)?;
+        match keyword.text.as_str() {
+            "then" => self.emit_condition(condition, token),
+            "begin" => {
+                // 条件が成り立つときに else/end へのジャンプを飛ばす
+                self.position = start;
+                let condition = self.condition(token, true)?;
+                self.position += 1;
+                self.emit_condition(condition, token)?;
+                let jump = self.emit_placeholder_jump(token)?;
+                self.branches.push(Branch {
+                    jump,
+                    has_else: false,
+                    token: token.clone(),
+                });
+                Ok(())
+            }
+            _ => Err(keyword.error(format!(
+                "expected 'then' or 'begin' but found '{}'",
+                keyword.text
+            ))),
+        }
+    }
+
+    fn emit_condition(&mut self, condition: Condition, token: &Token) -> Result<(), AssembleError> {
+        for instruction in condition.setup {
+            self.emit(instruction, token)?;
+        }
+        self.emit(condition.skip, token)
+    }
+
+    // 条件が成り立たないときに次の命令を飛ばす命令列を作る
+    // negate が true の場合は条件が成り立つときに飛ばす
+    fn condition(&mut self, token: &Token, negate: bool) -> Result<Condition, AssembleError> {
+        use Instruction::*;
+
+        let left = self.expect_token(token)?;
+        let x = self.register(&left)?;
+        let operator = self.expect_token(&left)?;
+        let mut op = operator.text.clone();
+        if negate {
+            op = match op.as_str() {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">=" => "<",
+                ">" => "<=",
+                "<=" => ">",
+                other => return Err(operator.error(format!("unknown comparison '{}'", other))),
+            }
+            .to_string();
+        }
+
+        match op.as_str() {
+            "key" => {
+                return Ok(Condition {
+                    setup: vec![],
+                    skip: Sknp(x),
+                })
+            }
+            "-key" => {
+                return Ok(Condition {
+                    setup: vec![],
+                    skip: Skp(x),
+                })
+            }
+            _ => {}
+        }
+
+        let right = self.expect_token(&operator)?;
+        let y = if self.is_register(&right) {
+            Some(self.register(&right)?)
+        } else {
+            None
+        };
+        let value = match y {
+            Some(_) => 0,
+            None => self.byte(&right)?,
+        };
+
+        let condition = match (op.as_str(), y) {
+            ("==", Some(y)) => Condition {
+                setup: vec![],
+                skip: SneXy(x, y),
+            },
+            ("==", None) => Condition {
+                setup: vec![],
+                skip: SneByte(x, value),
+            },
+            ("!=", Some(y)) => Condition {
+                setup: vec![],
+                skip: SeXy(x, y),
+            },
+            ("!=", None) => Condition {
+                setup: vec![],
+                skip: SeByte(x, value),
+            },
+            ("<" | ">=" | ">" | "<=", _) => {
+                // VF に右辺を入れて引き算し、借りが発生したかで大小を判定する
+                let load = match y {
+                    Some(y) => LdXy(0xF, y),
+                    None => LdByte(0xF, value),
+                };
+                let (compare, skip_if) = match op.as_str() {
+                    "<" => (SubnXy(0xF, x), 1), // VF = (x >= 右辺)
+                    ">=" => (SubnXy(0xF, x), 0),
+                    ">" => (SubXy(0xF, x), 1), // VF = (右辺 >= x)
+                    _ => (SubXy(0xF, x), 0),
+                };
+                Condition {
+                    setup: vec![load, compare],
+                    skip: SeByte(0xF, skip_if),
+                }
+            }
+            _ => return Err(operator.error(format!("unknown comparison '{}'", operator.text))),
+        };
+        Ok(condition)
+    }
+
+    // ---- マクロ ----
+
+    fn define_macro(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let name_token = self.expect_token(token)?;
+        let name = self.valid_name(&name_token)?;
+        let mut params = Vec::new();
+        loop {
+            let param = self.expect_token(&name_token)?;
+            if param.text == "{" {
+                break;
+            }
+            params.push(self.valid_name(&param)?);
+        }
+        let body = self.braced_tokens(&name_token)?;
+        self.define(&name_token, SymbolKind::Macro(params.clone()));
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    // { の直後から対応する } までのトークンを読む
+    fn braced_tokens(&mut self, token: &Token) -> Result<Vec<Token>, AssembleError> {
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let Some(next) = self.next_token() else {
+                return Err(token.error("missing '}'"));
+            };
+            match next.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(next);
+        }
+    }
+
+    fn expand_macro(&mut self, token: &Token) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(token.error("too many macro expansions (recursive macro?)"));
+        }
+        let params = self.macros[&token.text].params.clone();
+        let mut arguments = HashMap::new();
+        for param in params {
+            let argument = self.expect_token(token)?;
+            arguments.insert(param, argument.text);
+        }
+        let expanded: Vec<Token> = self.macros[&token.text]
+            .body
+            .iter()
+            .map(|body_token| Token {
+                text: arguments
+                    .get(&body_token.text)
+                    .cloned()
+                    .unwrap_or_else(|| body_token.text.clone()),
+                ..body_token.clone()
+            })
+            .collect();
+        self.tokens.splice(self.position..self.position, expanded);
+        Ok(())
+    }
+
+    // ---- :calc の式 ----
+
+    // { 式 } を評価する。Octoと同様に演算子の優先順位は無く、右から順に計算する
+    fn braced_expression(&mut self, token: &Token) -> Result<f64, AssembleError> {
+        self.expect(token, "{")?;
+        let tokens = self.braced_tokens(token)?;
+        if tokens.is_empty() {
+            return Err(token.error("empty expression"));
+        }
+        let mut position = 0;
+        let value = self.expression(&tokens, &mut position)?;
+        if let Some(extra) = tokens.get(position) {
+            return Err(extra.error(format!("unexpected '{}' in expression", extra.text)));
+        }
+        Ok(value)
+    }
+
+    fn expression(&self, tokens: &[Token], position: &mut usize) -> Result<f64, AssembleError> {
+        let left = self.term(tokens, position)?;
+        let Some(operator) = tokens.get(*position) else {
+            return Ok(left);
+        };
+        if operator.text == ")" {
+            return Ok(left);
+        }
+        *position += 1;
+        let right = self.expression(tokens, position)?;
+        let (a, b) = (left, right);
+        let int = |value: f64| value as i64;
+        let value = match operator.text.as_str() {
+            "+" => a + b,
+            "-" => a - b,
+            "*" => a * b,
+            "/" => {
+                if b == 0.0 {
+                    return Err(operator.error("division by zero"));
+                }
+                a / b
+            }
+            "%" => {
+                if b == 0.0 {
+                    return Err(operator.error("division by zero"));
+                }
+                a % b
+            }
+            "&" => (int(a) & int(b)) as f64,
+            "|" => (int(a) | int(b)) as f64,
+            "^" => (int(a) ^ int(b)) as f64,
+            "<<" => (int(a) << (int(b) & 63)) as f64,
+            ">>" => (int(a) >> (int(b) & 63)) as f64,
+            "pow" => a.powf(b),
+            "min" => a.min(b),
+            "max" => a.max(b),
+            "<" => (a < b) as i64 as f64,
+            "<=" => (a <= b) as i64 as f64,
+            ">" => (a > b) as i64 as f64,
+            ">=" => (a >= b) as i64 as f64,
+            "==" => (a == b) as i64 as f64,
+            "!=" => (a != b) as i64 as f64,
+            _ => return Err(operator.error(format!("unknown operator '{}'", operator.text))),
+        };
+        Ok(value)
+    }
+
+    fn term(&self, tokens: &[Token], position: &mut usize) -> Result<f64, AssembleError> {
+        let Some(token) = tokens.get(*position) else {
+            let last = tokens.last().unwrap();
+            return Err(last.error("expression ends unexpectedly"));
+        };
+        *position += 1;
+        let value = match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, position)?;
+                match tokens.get(*position) {
+                    Some(close) if close.text == ")" => *position += 1,
+                    _ => return Err(token.error("missing ')'")),
+                }
+                value
+            }
+            "-" => -self.term(tokens, position)?,
+            "~" => !(self.term(tokens, position)? as i64) as f64,
+            "!" => (self.term(tokens, position)? == 0.0) as i64 as f64,
+            "abs" => self.term(tokens, position)?.abs(),
+            "floor" => self.term(tokens, position)?.floor(),
+            "ceil" => self.term(tokens, position)?.ceil(),
+            "sqrt" => self.term(tokens, position)?.sqrt(),
+            "sin" => self.term(tokens, position)?.sin(),
+            "cos" => self.term(tokens, position)?.cos(),
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            _ => self
+                .known_value(token)
+                .ok_or_else(|| token.error(format!("undefined name '{}'", token.text)))?,
+        };
+        Ok(value)
+    }
+}
+
+// 名前に使えない予約語
+pub(crate) const KEYWORDS: &[&str] = &[
+    "clear",
+    "return",
+    "exit",
+    "hires",
+    "lores",
+    "scroll-down",
+    "scroll-up",
+    "scroll-left",
+    "scroll-right",
+    "bcd",
+    "save",
+    "load",
+    "saveflags",
+    "loadflags",
+    "sprite",
+    "jump",
+    "jump0",
+    "native",
+    "plane",
+    "audio",
+    "i",
+    "delay",
+    "buzzer",
+    "pitch",
+    "if",
+    "then",
+    "begin",
+    "else",
+    "end",
+    "loop",
+    "while",
+    "again",
+    "key",
+    "random",
+    "hex",
+    "bighex",
+    "long",
+];
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['v', 'V'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+// 10進・0x(16進)・0b(2進)。先頭の - で負の値
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::disassemble;
+    use crate::platform::Platform;
+
+    fn words(bytes: &[u8]) -> Vec<u16> {
+        bytes
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+            .collect()
+    }
+
+    #[test]
+    fn test_basic_program() {
+        let source = "
+            :const SPEED 3
+            :alias x v1
+            : main
+                x := SPEED      # 定数
+                x += 1
+                x -= 1
+                i := ball
+                sprite x x 2
+                loop
+                    x := key
+                    if x == 5 then jump done
+                again
+            : done
+                jump done
+            : ball
+                0b11000000 0xC0
+        ";
+        let rom = assemble(source).unwrap();
+        assert_eq!(
+            words(&rom),
+            vec![
+                0x6103, 0x7101, 0x71FF, 0xA214, 0xD112, 0xF10A, 0x4105, 0x1212, 0x120A, 0x1212,
+                0xC0C0
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jump_to_main_and_forward_references() {
+        let source = "
+            : sub
+                return
+            : main
+                sub
+                :call sub
+        ";
+        let rom = assemble(source).unwrap();
+        assert_eq!(words(&rom), vec![0x1204, 0x00EE, 0x2202, 0x2202]);
+    }
+
+    #[test]
+    fn test_macros_calc_and_branches() {
+        let source = "
+            :macro twice op { op op }
+            :calc HALF { 64 / 2 }
+            :calc ADDR { 0x210 - 4 - 2 }   # 右から計算するので 0x20E
+            twice clear
+            if v0 != HALF begin
+                v1 := 1
+            else
+                v1 := 2
+            end
+            :org ADDR
+            :byte { HERE & 0xFF }
+        ";
+        let rom = assemble(source).unwrap();
+        assert_eq!(&rom[..4], &[0x00, 0xE0, 0x00, 0xE0]);
+        // 条件が成り立つとき(v0 != 32)はジャンプを飛ばして then 側に入る
+        assert_eq!(
+            words(&rom[4..14]),
+            vec![0x4020, 0x120C, 0x6101, 0x120E, 0x6102]
+        );
+        assert_eq!(&rom[14..], &[0x0E]);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let error = assemble("clear\n  v0 := 300").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 9));
+        assert!(error.message.contains("out of range"));
+
+        let error = assemble("jump nowhere").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 6));
+        assert_eq!(error.to_string(), "1:6: undefined name 'nowhere'");
+
+        let error = assemble("loop\n  clear").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 1));
+    }
+
+    #[test]
+    fn test_disassembly_round_trip() {
+        let roms: [&[u8]; 3] = [
+            include_bytes!("../rom/BRIX"),
+            include_bytes!("../rom/INVADERS"),
+            include_bytes!("../rom/GUESS"),
+        ];
+        for rom in roms {
+            let listing = disassemble(rom, None).to_string();
+            assert_eq!(assemble(&listing).unwrap(), rom);
+        }
+
+        let xochip = [0xF0, 0x00, 0x02, 0x08, 0xF2, 0x01, 0x00, 0xFD, 0xAA, 0xBB];
+        let listing = disassemble(&xochip, Some(Platform::XoChip)).to_string();
+        assert_eq!(assemble(&listing).unwrap(), xochip);
+    }
+}
@@ -0,0 +1,143 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// サウンドタイマーの状態変化を受け取る出力先
+pub trait AudioSink {
+    // サウンドタイマーが0より大きくなった/0になったときに呼ばれる
+    fn set_active(&mut self, active: bool);
+    // 60Hzのタイマー更新ごとに呼ばれる（1フレーム分の音声を生成する場合に使う）
+    fn frame(&mut self) {}
+}
+
+// 矩形波のPCMジェネレータ
+#[derive(Debug, Clone)]
+pub struct SquareWave {
+    frequency: f32,
+    volume: f32,
+    sample_rate: u32,
+    phase: f32,
+}
+
+impl SquareWave {
+    pub fn new(frequency: f32, volume: f32, sample_rate: u32) -> Self {
+        SquareWave {
+            frequency,
+            volume: volume.clamp(0.0, 1.0),
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let sample = if self.phase < 0.5 {
+            self.volume
+        } else {
+            -self.volume
+        };
+        self.phase = (self.phase + self.frequency / self.sample_rate as f32).fract();
+        sample
+    }
+
+    // active が false の場合は無音で埋める（位相は進めない）
+    pub fn fill(&mut self, buffer: &mut [f32], active: bool) {
+        for sample in buffer {
+            *sample = if active { self.next_sample() } else { 0.0 };
+        }
+    }
+}
+
+impl Default for SquareWave {
+    fn default() -> Self {
+        SquareWave::new(440.0, 0.25, 44100)
+    }
+}
+
+// 生成した波形をメモリに溜めるだけの出力先（テスト用）
+pub struct BufferSink {
+    generator: SquareWave,
+    active: bool,
+    samples: Rc<RefCell<Vec<f32>>>,
+}
+
+impl BufferSink {
+    pub fn new(generator: SquareWave) -> Self {
+        BufferSink {
+            generator,
+            active: false,
+            samples: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    // CPUに渡した後でも中身を確認できるように共有ハンドルを返す
+    pub fn samples(&self) -> Rc<RefCell<Vec<f32>>> {
+        self.samples.clone()
+    }
+}
+
+impl AudioSink for BufferSink {
+    fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    fn frame(&mut self) {
+        let len = (self.generator.sample_rate() / 60) as usize;
+        let mut buffer = vec![0.0; len];
+        self.generator.fill(&mut buffer, self.active);
+        self.samples.borrow_mut().extend_from_slice(&buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square_wave() {
+        // 1周期が4サンプルになる設定
+        let mut wave = SquareWave::new(1000.0, 0.5, 4000);
+        let mut buffer = [0.0; 8];
+        wave.fill(&mut buffer, true);
+        assert_eq!(buffer, [0.5, 0.5, -0.5, -0.5, 0.5, 0.5, -0.5, -0.5]);
+
+        wave.fill(&mut buffer, false);
+        assert!(buffer.iter().all(|&s| s == 0.0));
+
+        wave.set_volume(2.0);
+        assert_eq!(wave.volume(), 1.0);
+    }
+
+    #[test]
+    fn test_buffer_sink_renders_one_frame_per_tick() {
+        let mut sink = BufferSink::new(SquareWave::new(1000.0, 0.5, 6000));
+        let samples = sink.samples();
+
+        sink.frame();
+        sink.set_active(true);
+        sink.frame();
+
+        let samples = samples.borrow();
+        assert_eq!(samples.len(), 200);
+        assert!(samples[..100].iter().all(|&s| s == 0.0));
+        assert!(samples[100..].iter().any(|&s| s != 0.0));
+    }
+}
@@ -0,0 +1,9 @@
+// Octo形式のソースのためのLanguage Server。エディタから標準入出力で起動する
+fn main() {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    if let Err(e) = chip8::lsp::run(stdin.lock(), stdout.lock()) {
+        eprintln!("chip8-lsp: {}", e);
+        std::process::exit(1);
+    }
+}
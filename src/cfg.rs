@@ -0,0 +1,503 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::disassembler::{decode_at, skipped_size};
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+use crate::quirks::{Quirks, SysCall};
+
+const PROGRAM_START: usize = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Fallthrough,
+    Jump,
+    Skip, // スキップ命令の条件が成り立ったとき
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+// 静的な解析ではこれ以上追えない箇所
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Boundary {
+    // Bnnn: 飛び先がレジスタの値で決まる
+    ComputedJump { address: usize, base: u16 },
+    // Fx55 / Fx33 / 5xy2 がコードの範囲に書き込む
+    SelfModifyingStore { address: usize, target: usize },
+    // ROMの外へのジャンプや呼び出し
+    ExternalTarget { address: usize, target: usize },
+    // 解釈できない命令に到達した
+    InvalidInstruction { address: usize },
+}
+
+impl Boundary {
+    pub fn address(&self) -> usize {
+        match *self {
+            Boundary::ComputedJump { address, .. }
+            | Boundary::SelfModifyingStore { address, .. }
+            | Boundary::ExternalTarget { address, .. }
+            | Boundary::InvalidInstruction { address } => address,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize, // 最後の命令の次のアドレス
+    pub instructions: Vec<(usize, Instruction)>,
+    pub successors: Vec<Edge>,
+    pub calls: Vec<usize>, // ブロック内で呼び出すサブルーチン
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub entry: usize,
+    pub blocks: BTreeSet<usize>,
+    pub callees: BTreeSet<usize>,
+}
+
+pub struct ControlFlowGraph {
+    blocks: BTreeMap<usize, BasicBlock>,
+    functions: BTreeMap<usize, Function>,
+    boundaries: Vec<Boundary>,
+}
+
+impl ControlFlowGraph {
+    pub fn blocks(&self) -> &BTreeMap<usize, BasicBlock> {
+        &self.blocks
+    }
+
+    pub fn block(&self, start: usize) -> Option<&BasicBlock> {
+        self.blocks.get(&start)
+    }
+
+    // address の命令を含むブロック
+    pub fn block_containing(&self, address: usize) -> Option<&BasicBlock> {
+        self.blocks
+            .range(..=address)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| address < block.end)
+    }
+
+    // 0x200 と呼び出し先ごとの関数
+    pub fn functions(&self) -> &BTreeMap<usize, Function> {
+        &self.functions
+    }
+
+    // 関数の入口 -> 呼び出す関数の入口
+    pub fn call_graph(&self) -> BTreeMap<usize, BTreeSet<usize>> {
+        self.functions
+            .values()
+            .map(|function| (function.entry, function.callees.clone()))
+            .collect()
+    }
+
+    pub fn boundaries(&self) -> &[Boundary] {
+        &self.boundaries
+    }
+
+    // Graphviz DOT 形式で出力する
+    // 呼び出しは点線、解析の境界を含むブロックは赤で表示する
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        let flagged: BTreeSet<usize> = self
+            .boundaries
+            .iter()
+            .filter_map(|boundary| self.block_containing(boundary.address()))
+            .map(|block| block.start)
+            .collect();
+
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (address, instruction) in &block.instructions {
+                write!(label, "{:03X}: {}\\l", address, instruction).unwrap();
+            }
+            let mut attributes = format!("label=\"{}\"", label.replace('"', "\\\""));
+            if self.functions.contains_key(&block.start) {
+                attributes.push_str(", penwidth=2");
+            }
+            if flagged.contains(&block.start) {
+                attributes.push_str(", color=red");
+            }
+            writeln!(dot, "    b{:03X} [{}];", block.start, attributes).unwrap();
+        }
+        for block in self.blocks.values() {
+            for edge in &block.successors {
+                let style = match edge.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                    EdgeKind::Skip => " [label=\"skip\", style=dashed]",
+                };
+                writeln!(
+                    dot,
+                    "    b{:03X} -> b{:03X}{};",
+                    block.start, edge.target, style
+                )
+                .unwrap();
+            }
+            for callee in &block.calls {
+                writeln!(
+                    dot,
+                    "    b{:03X} -> b{:03X} [label=\"call\", style=dotted];",
+                    block.start, callee
+                )
+                .unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+// 0x200 から jump / call / スキップ / return を辿り、基本ブロックと呼び出しグラフを作る
+pub fn analyze(rom: &[u8], platform: Option<Platform>) -> ControlFlowGraph {
+    let in_rom = |address: usize| address >= PROGRAM_START && address - PROGRAM_START < rom.len();
+    // 0nnn (SYS) はプラットフォームの設定に従い、ジャンプ・何もしない・未定義の命令のいずれか
+    let sys_call = platform
+        .map_or(Quirks::default(), |platform| platform.quirks())
+        .sys_call;
+    let jumps = |instruction: Instruction| match instruction {
+        Instruction::Jp(_) => true,
+        Instruction::Sys(_) => sys_call == SysCall::Jump,
+        _ => false,
+    };
+
+    // 1. 到達する命令と、ブロックの先頭になる位置を集める
+    let mut instructions: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut leaders: BTreeSet<usize> = BTreeSet::from([PROGRAM_START]);
+    let mut entries: BTreeSet<usize> = BTreeSet::from([PROGRAM_START]);
+    let mut boundaries = Vec::new();
+    let mut pending = vec![PROGRAM_START];
+
+    while let Some(address) = pending.pop() {
+        if !in_rom(address) || instructions.contains_key(&address) {
+            continue;
+        }
+        let instruction = decode_at(rom, address - PROGRAM_START, platform).filter(|instruction| {
+            !matches!(instruction, Instruction::Sys(_)) || sys_call != SysCall::Unsupported
+        });
+        let Some(instruction) = instruction else {
+            boundaries.push(Boundary::InvalidInstruction { address });
+            continue;
+        };
+        instructions.insert(address, instruction);
+        let next = address + instruction.size();
+
+        use Instruction::*;
+        match instruction {
+            Jp(nnn) | Sys(nnn) if jumps(instruction) => {
+                let target = nnn as usize;
+                if in_rom(target) {
+                    leaders.insert(target);
+                    pending.push(target);
+                } else {
+                    boundaries.push(Boundary::ExternalTarget { address, target });
+                }
+                leaders.insert(next);
+            }
+            JpV0(base) => {
+                boundaries.push(Boundary::ComputedJump { address, base });
+                leaders.insert(next);
+            }
+            Call(nnn) => {
+                let target = nnn as usize;
+                if in_rom(target) {
+                    leaders.insert(target);
+                    entries.insert(target);
+                    pending.push(target);
+                } else {
+                    boundaries.push(Boundary::ExternalTarget { address, target });
+                }
+                pending.push(next);
+            }
+            Ret | Exit => {
+                leaders.insert(next);
+            }
+            SeByte(..) | SneByte(..) | SeXy(..) | SneXy(..) | Skp(_) | Sknp(_) => {
+                let skipped = next + skipped_size(rom, next - PROGRAM_START, platform);
+                leaders.insert(next);
+                leaders.insert(skipped);
+                pending.push(next);
+                pending.push(skipped);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    // 2. 先頭から次の先頭まで（または制御が移るまで）を1ブロックにする
+    let mut blocks: BTreeMap<usize, BasicBlock> = BTreeMap::new();
+    for &start in leaders
+        .iter()
+        .filter(|address| instructions.contains_key(address))
+    {
+        let mut block = BasicBlock {
+            start,
+            end: start,
+            instructions: Vec::new(),
+            successors: Vec::new(),
+            calls: Vec::new(),
+        };
+        let mut address = start;
+        // 解釈できない命令があればその手前で終わる
+        while let Some(&instruction) = instructions.get(&address) {
+            block.instructions.push((address, instruction));
+            let next = address + instruction.size();
+            block.end = next;
+
+            use Instruction::*;
+            let edges: Option<Vec<Edge>> = match instruction {
+                Jp(nnn) | Sys(nnn) if jumps(instruction) => Some(
+                    in_rom(nnn as usize)
+                        .then_some(Edge {
+                            target: nnn as usize,
+                            kind: EdgeKind::Jump,
+                        })
+                        .into_iter()
+                        .collect(),
+                ),
+                JpV0(_) | Ret | Exit => Some(Vec::new()),
+                SeByte(..) | SneByte(..) | SeXy(..) | SneXy(..) | Skp(_) | Sknp(_) => {
+                    let skipped = next + skipped_size(rom, next - PROGRAM_START, platform);
+                    Some(vec![
+                        Edge {
+                            target: next,
+                            kind: EdgeKind::Fallthrough,
+                        },
+                        Edge {
+                            target: skipped,
+                            kind: EdgeKind::Skip,
+                        },
+                    ])
+                }
+                _ => {
+                    if let Call(nnn) = instruction {
+                        if in_rom(nnn as usize) {
+                            block.calls.push(nnn as usize);
+                        }
+                    }
+                    None
+                }
+            };
+
+            if let Some(edges) = edges {
+                block.successors = edges
+                    .into_iter()
+                    .filter(|edge| instructions.contains_key(&edge.target))
+                    .collect();
+                break;
+            }
+            if leaders.contains(&next) {
+                if instructions.contains_key(&next) {
+                    block.successors.push(Edge {
+                        target: next,
+                        kind: EdgeKind::Fallthrough,
+                    });
+                }
+                break;
+            }
+            address = next;
+        }
+        blocks.insert(start, block);
+    }
+
+    // 3. 各関数の入口から呼び出し以外の辺で到達するブロックを集める
+    let mut functions = BTreeMap::new();
+    for &entry in &entries {
+        if !blocks.contains_key(&entry) {
+            continue;
+        }
+        let mut function = Function {
+            entry,
+            blocks: BTreeSet::new(),
+            callees: BTreeSet::new(),
+        };
+        let mut pending = vec![entry];
+        while let Some(start) = pending.pop() {
+            if !function.blocks.insert(start) {
+                continue;
+            }
+            let block = &blocks[&start];
+            function.callees.extend(block.calls.iter().copied());
+            pending.extend(block.successors.iter().map(|edge| edge.target));
+        }
+        functions.insert(entry, function);
+    }
+
+    // 4. 値が分かっている I へのストアがコードを書き換えないか調べる
+    let code: BTreeSet<usize> = instructions
+        .iter()
+        .flat_map(|(&address, instruction)| address..address + instruction.size())
+        .collect();
+    for block in blocks.values() {
+        let mut index: Option<usize> = None;
+        for &(address, instruction) in &block.instructions {
+            use Instruction::*;
+            let written = match instruction {
+                LdI(nnn) | LdILong(nnn) => {
+                    index = Some(nnn as usize);
+                    None
+                }
+                LdIVx(x) => Some(x as usize + 1),
+                LdBVx(_) => Some(3),
+                SaveXy(x, y) => Some(x.abs_diff(y) as usize + 1),
+                AddIVx(_) | LdFVx(_) | LdHfVx(_) => {
+                    index = None;
+                    None
+                }
+                _ => None,
+            };
+            if let (Some(length), Some(start)) = (written, index) {
+                if let Some(target) = (start..start + length).find(|byte| code.contains(byte)) {
+                    boundaries.push(Boundary::SelfModifyingStore { address, target });
+                }
+            }
+            // Fx55 の後の I はQuirksによって変わる
+            if matches!(instruction, LdIVx(_) | LdVxI(_)) {
+                index = None;
+            }
+        }
+    }
+    boundaries.sort_by_key(Boundary::address);
+    boundaries.dedup();
+
+    ControlFlowGraph {
+        blocks,
+        functions,
+        boundaries,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn test_blocks_and_call_graph() {
+        let rom = assemble(
+            "
+            : main
+                v0 := 0
+            : top
+                draw
+                if v0 != 5 then
+                jump top
+                jump0 table
+            : draw
+                v0 += 1
+                return
+            : table
+                0x00 0xE0
+            ",
+        )
+        .unwrap();
+        let cfg = analyze(&rom, None);
+
+        let starts: Vec<usize> = cfg.blocks().keys().copied().collect();
+        assert_eq!(starts, vec![0x200, 0x202, 0x206, 0x208, 0x20A]);
+        assert_eq!(
+            cfg.block(0x202).unwrap().successors,
+            vec![
+                Edge {
+                    target: 0x206,
+                    kind: EdgeKind::Fallthrough
+                },
+                Edge {
+                    target: 0x208,
+                    kind: EdgeKind::Skip
+                },
+            ]
+        );
+        assert_eq!(cfg.block(0x202).unwrap().calls, vec![0x20A]);
+        assert_eq!(cfg.block_containing(0x204).unwrap().start, 0x202);
+
+        let call_graph = cfg.call_graph();
+        assert_eq!(call_graph[&0x200], BTreeSet::from([0x20A]));
+        assert!(call_graph[&0x20A].is_empty());
+        assert_eq!(
+            cfg.boundaries(),
+            &[Boundary::ComputedJump {
+                address: 0x208,
+                base: 0x20E
+            }]
+        );
+
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("b202 -> b20A [label=\"call\", style=dotted];"));
+        assert!(dot.contains("b202 -> b208 [label=\"skip\", style=dashed];"));
+    }
+
+    #[test]
+    fn test_self_modifying_store() {
+        let rom = assemble(
+            "
+            : main
+                i := patch
+                save v1
+            : patch
+                clear
+                jump main
+            ",
+        )
+        .unwrap();
+        let cfg = analyze(&rom, None);
+        assert_eq!(
+            cfg.boundaries(),
+            &[Boundary::SelfModifyingStore {
+                address: 0x202,
+                target: 0x204
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sys_follows_platform() {
+        // 200: SYS 204 / 202: (データ) / 204: v0 := 1 / 206: JP 206
+        let rom = [0x02, 0x04, 0x00, 0x00, 0x60, 0x01, 0x12, 0x06];
+        // 既定ではジャンプする
+        let cfg = analyze(&rom, None);
+        assert_eq!(
+            cfg.block(0x200).unwrap().successors,
+            vec![Edge {
+                target: 0x204,
+                kind: EdgeKind::Jump
+            }]
+        );
+        assert!(cfg.block_containing(0x202).is_none());
+        // CHIP-48では何もせず次の命令に進む
+        let cfg = analyze(&rom, Some(Platform::Chip48));
+        assert_eq!(cfg.block(0x200).unwrap().end, 0x206);
+        // XO-CHIPでは未定義の命令
+        let cfg = analyze(&rom, Some(Platform::XoChip));
+        assert_eq!(
+            cfg.boundaries(),
+            &[Boundary::InvalidInstruction { address: 0x200 }]
+        );
+    }
+
+    #[test]
+    fn test_analyze_bundled_roms() {
+        for rom in [
+            &include_bytes!("../rom/BRIX")[..],
+            &include_bytes!("../rom/INVADERS")[..],
+        ] {
+            let cfg = analyze(rom, None);
+            assert!(cfg.functions().contains_key(&0x200));
+            // すべての辺がブロックの先頭を指している
+            for block in cfg.blocks().values() {
+                for edge in &block.successors {
+                    assert!(cfg.block(edge.target).is_some());
+                }
+            }
+        }
+    }
+}
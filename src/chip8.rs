@@ -1,42 +1,113 @@
 use log::{debug, warn};
-use rand::random;
-use std::{fs::File, io::Read, path::Path};
-
-use crate::keyboard::KeyboardInput;
-
+use std::{fmt, fs::File, io::Read, path::Path};
+
+use crate::audio::AudioSink;
+use crate::font::{BIG_FONT, BIG_FONT_ADDRESS, SMALL_FONT};
+use crate::framebuffer::{FrameBuffer, PLANE_COUNT};
+use crate::instruction::{DecodeError, Instruction, InstructionSet};
+use crate::journal::{self, Journal};
+use crate::keyboard::{KeyEvent, KeyboardInput};
+use crate::platform::{Platform, Resolution};
+use crate::quirks::{IndexIncrement, Quirks, SysCall};
+use crate::rng::{Rng, RngAlgorithm};
+use crate::savestate::{self, SaveStateError, StateReader, StateWriter};
+
+// 低解像度（通常）と高解像度（SUPER-CHIP）の画面サイズ
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
-const FONTSET: [u8; 80] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-];
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+const PROGRAM_START: usize = 0x200;
+
+// update() 1回分の実行結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    WaitingForKey,
+    WaitingForVblank,
+    Exited, // 00FD (EXIT) を実行した
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+}
+
+// ROMの実行中に発生する回復可能なエラー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionError {
+    UnknownOpcode {
+        address: usize,
+        opcode: u16,
+    },
+    StackOverflow,
+    StackUnderflow,
+    MemoryFault {
+        address: usize,
+        access: MemoryAccess,
+    },
+    PcOutOfRange {
+        pc: usize,
+    },
+}
+
+impl fmt::Display for MemoryAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryAccess::Read => write!(f, "read"),
+            MemoryAccess::Write => write!(f, "write"),
+        }
+    }
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::UnknownOpcode { address, opcode } => {
+                write!(f, "unknown opcode {:04X} at {:03X}", opcode, address)
+            }
+            ExecutionError::StackOverflow => write!(f, "stack overflow"),
+            ExecutionError::StackUnderflow => write!(f, "stack underflow"),
+            ExecutionError::MemoryFault { address, access } => {
+                write!(f, "memory fault: {} at {:04X}", access, address)
+            }
+            ExecutionError::PcOutOfRange { pc } => {
+                write!(f, "program counter out of range: {:04X}", pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
 
 pub struct Cpu<T: KeyboardInput> {
     registers: [u8; 16],
     program_counter: usize,
-    memory: [u8; 0x1000],
-    stack: [u16; 16],
+    memory: Vec<u8>,
+    stack: Vec<u16>,
     stack_pointer: usize,
     index_register: u16,
-    display: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
-    sound_timer: u8, //使わない
+    display: FrameBuffer,
+    sound_timer: u8,
+    rpl_flags: [u8; 16],     // SUPER-CHIPのRPLユーザーフラグ
+    planes: u8,              // XO-CHIPの描画対象プレーン（ビットマスク）
+    audio_pattern: [u8; 16], // XO-CHIPの1ビット音声パターン
+    pitch: u8,               // XO-CHIPの再生ピッチ
     delay_timer: u8,
-    key: Option<u8>,
+    key: Option<u8>, // Fx0Aで押されたまま離されるのを待っているキー
+    keypad: u16,     // 現在押されているキーのビットマップ
+    key_events: Vec<KeyEvent>,
     keyboard: T,
+    quirks: Quirks,
+    platform: Option<Platform>, // from_bytesで作った場合はNone
+    vblank: bool,               // 前回のタイマー更新以降まだ描画していない
+    audio: Option<Box<dyn AudioSink>>,
+    sound_active: bool,
+    rng: Rng,
+    rom_hash: u64,
+    memory_trace: Option<Vec<(usize, MemoryAccess)>>, // 直前の命令が読み書きしたアドレス
+    journal: Option<Journal>,                         // 逆実行用の取り消し記録（無効ならNone）
 }
 
 impl<T: KeyboardInput> Cpu<T> {
@@ -50,39 +121,502 @@ impl<T: KeyboardInput> Cpu<T> {
     }
 
     pub fn from_bytes(rom_data: &[u8], keyboard: T) -> Cpu<T> {
+        Self::with_quirks(rom_data, keyboard, Quirks::default())
+    }
+
+    pub fn with_quirks(rom_data: &[u8], keyboard: T, quirks: Quirks) -> Cpu<T> {
+        Self::build(rom_data, keyboard, quirks, None, Rng::default())
+    }
+
+    // 乱数生成器を指定して作る（シードを固定すると実行結果を再現できる）
+    pub fn with_rng(rom_data: &[u8], keyboard: T, rng: Rng) -> Cpu<T> {
+        Self::build(rom_data, keyboard, Quirks::default(), None, rng)
+    }
+
+    pub fn from_platform(rom_data: &[u8], keyboard: T, platform: Platform) -> Cpu<T> {
+        Self::from_platform_with_rng(rom_data, keyboard, platform, Rng::default())
+    }
+
+    pub fn from_platform_with_rng(
+        rom_data: &[u8],
+        keyboard: T,
+        platform: Platform,
+        rng: Rng,
+    ) -> Cpu<T> {
+        Self::build(rom_data, keyboard, platform.quirks(), Some(platform), rng)
+    }
+
+    fn build(
+        rom_data: &[u8],
+        keyboard: T,
+        quirks: Quirks,
+        platform: Option<Platform>,
+        rng: Rng,
+    ) -> Cpu<T> {
+        // from_bytesで作った場合はオリジナルのCHIP-8と同じ構成にする
+        let (memory_size, stack_depth, font): (usize, usize, &[u8]) = match platform {
+            Some(platform) => (
+                platform.memory_size(),
+                platform.stack_depth(),
+                platform.font(),
+            ),
+            None => (0x1000, 16, &SMALL_FONT),
+        };
+        let resolution = Platform::resolutions_of(platform)[0];
         let mut cpu = Cpu {
             registers: [0; 16],
-            program_counter: 0x200,
-            memory: [0; 0x1000],
-            stack: [0; 16],
+            program_counter: PROGRAM_START,
+            memory: vec![0; memory_size],
+            stack: vec![0; stack_depth],
             stack_pointer: 0,
             index_register: 0,
             delay_timer: 0,
             sound_timer: 0,
-            display: [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            display: FrameBuffer::new(resolution.width, resolution.height),
+            rpl_flags: [0; 16],
+            planes: 1,
+            audio_pattern: [0; 16],
+            pitch: 64,
             key: None,
+            keypad: 0,
+            key_events: Vec::new(),
             keyboard,
+            quirks,
+            platform,
+            vblank: true,
+            audio: None,
+            sound_active: false,
+            rng,
+            rom_hash: savestate::rom_hash(rom_data),
+            memory_trace: None,
+            journal: None,
         };
 
-        for (i, byte) in FONTSET.iter().enumerate() {
-            cpu.memory[i] = *byte;
+        cpu.memory[..font.len()].copy_from_slice(font);
+        if cpu.superchip() {
+            cpu.memory[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS + BIG_FONT.len()]
+                .copy_from_slice(&BIG_FONT);
         }
 
-        for (i, byte) in rom_data.iter().enumerate() {
-            cpu.memory[0x200 + i] = *byte;
+        let capacity = memory_size - PROGRAM_START;
+        if rom_data.len() > capacity {
+            warn!(
+                "ROM is {} bytes but only {} bytes fit in memory; truncating",
+                rom_data.len(),
+                capacity
+            );
         }
+        let rom_len = rom_data.len().min(capacity);
+        cpu.memory[PROGRAM_START..PROGRAM_START + rom_len].copy_from_slice(&rom_data[..rom_len]);
 
         cpu
     }
 
-    fn read_opcode(&self) -> u16 {
+    fn read_opcode(&self) -> Result<u16, ExecutionError> {
         let p = self.program_counter;
+        if p + 1 >= self.memory.len() {
+            return Err(ExecutionError::PcOutOfRange { pc: p });
+        }
         let op_byte_1 = self.memory[p] as u16;
         let op_byte_2 = self.memory[p + 1] as u16;
-        op_byte_1 << 8 | op_byte_2
+        Ok(op_byte_1 << 8 | op_byte_2)
+    }
+
+    fn read_memory(&mut self, address: usize) -> Result<u8, ExecutionError> {
+        if let Some(trace) = self.memory_trace.as_mut() {
+            trace.push((address, MemoryAccess::Read));
+        }
+        self.memory
+            .get(address)
+            .copied()
+            .ok_or(ExecutionError::MemoryFault {
+                address,
+                access: MemoryAccess::Read,
+            })
+    }
+
+    fn write_memory(&mut self, address: usize, value: u8) -> Result<(), ExecutionError> {
+        if let Some(trace) = self.memory_trace.as_mut() {
+            trace.push((address, MemoryAccess::Write));
+        }
+        if let (Some(journal), Some(&old)) = (self.journal.as_mut(), self.memory.get(address)) {
+            journal.memory(address, old);
+        }
+        match self.memory.get_mut(address) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            }
+            None => Err(ExecutionError::MemoryFault {
+                address,
+                access: MemoryAccess::Write,
+            }),
+        }
+    }
+
+    pub fn platform(&self) -> Option<Platform> {
+        self.platform
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // デバッガから値を書き換えるためのアクセサ
+    pub fn registers_mut(&mut self) -> &mut [u8; 16] {
+        &mut self.registers
+    }
+
+    pub fn set_program_counter(&mut self, address: usize) {
+        self.program_counter = address;
+    }
+
+    pub fn set_index_register(&mut self, value: u16) {
+        self.index_register = value;
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    // 呼び出し中のサブルーチンの戻り先（古い順）
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[..self.stack_pointer]
+    }
+
+    // 命令が読み書きしたメモリの記録を有効にする（デバッガのウォッチポイント用）
+    pub fn set_memory_trace(&mut self, enabled: bool) {
+        self.memory_trace = enabled.then(Vec::new);
+    }
+
+    // 直前に実行した命令が読み書きしたアドレス。命令の取り出しは含まない
+    pub fn memory_trace(&self) -> &[(usize, MemoryAccess)] {
+        self.memory_trace.as_deref().unwrap_or(&[])
+    }
+
+    // 命令単位の取り消し記録を最大 capacity 件まで残す（None で無効にする）
+    // 無効の間は記録のための処理を一切しない
+    pub fn set_journal(&mut self, capacity: Option<usize>) {
+        self.journal = capacity.map(Journal::new);
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    // デバッガで状態を直接書き換えた場合など、記録と辻褄が合わなくなったときに呼ぶ
+    pub fn clear_journal(&mut self) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.clear();
+        }
+    }
+
+    // 最後に実行した命令を、その後のタイマーの更新も含めて取り消す
+    // 書き戻したメモリを (アドレス, 戻した値, 命令が書いた値) で返す。取り消せる命令が無ければNone
+    pub fn undo(&mut self) -> Option<Vec<(usize, u8, u8)>> {
+        let records = self.journal.as_mut()?.undo()?;
+        let mut writes = Vec::new();
+        for record in records {
+            if let Some(display) = record.display {
+                self.display = display;
+            }
+            for (y, pixels) in &record.rows {
+                self.display.set_row(*y, pixels);
+            }
+            for &(address, old) in record.memory.iter().rev() {
+                writes.push((address, old, self.memory[address]));
+                self.memory[address] = old;
+            }
+            if let Some((slot, old)) = record.stack {
+                self.stack[slot] = old;
+            }
+            self.restore_journal_state(record.state);
+        }
+        if let Some(trace) = self.memory_trace.as_mut() {
+            trace.clear();
+        }
+        self.update_sound();
+        Some(writes)
+    }
+
+    fn journal_state(&self) -> journal::State {
+        journal::State {
+            registers: self.registers,
+            program_counter: self.program_counter,
+            index_register: self.index_register,
+            stack_pointer: self.stack_pointer,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            rpl_flags: self.rpl_flags,
+            planes: self.planes,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            key: self.key,
+            vblank: self.vblank,
+            rng: self.rng.clone(),
+        }
+    }
+
+    fn restore_journal_state(&mut self, state: journal::State) {
+        self.registers = state.registers;
+        self.program_counter = state.program_counter;
+        self.index_register = state.index_register;
+        self.stack_pointer = state.stack_pointer;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.rpl_flags = state.rpl_flags;
+        self.planes = state.planes;
+        self.audio_pattern = state.audio_pattern;
+        self.pitch = state.pitch;
+        self.key = state.key;
+        self.vblank = state.vblank;
+        self.rng = state.rng;
+    }
+
+    // 命令の実行（instruction が false ならタイマーの更新）の前に記録を始める
+    fn begin_journal(&mut self, instruction: bool) {
+        if self.journal.is_none() {
+            return;
+        }
+        let state = self.journal_state();
+        if let Some(journal) = self.journal.as_mut() {
+            journal.begin(state, instruction);
+        }
+    }
+
+    fn end_journal(&mut self) {
+        if self.journal.is_none() {
+            return;
+        }
+        let state = self.journal_state();
+        if let Some(journal) = self.journal.as_mut() {
+            journal.end(&state);
+        }
+    }
+
+    // 消去・スクロール・解像度の切り替えの前に画面全体を記録する
+    fn journal_display(&mut self) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.display(&self.display);
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio = Some(sink);
+        self.sound_active = false;
+        self.update_sound();
+    }
+
+    // 乱数生成器を差し替える（シードを固定すると実行結果を再現できる）
+    pub fn set_rng(&mut self, rng: Rng) {
+        self.rng = rng;
+    }
+
+    pub fn rng(&self) -> &Rng {
+        &self.rng
+    }
+
+    pub fn is_sound_active(&self) -> bool {
+        self.sound_active
+    }
+
+    pub fn keyboard_mut(&mut self) -> &mut T {
+        &mut self.keyboard
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    // マシンの状態をすべてセーブステートに書き出す
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(savestate::MAGIC);
+        w.u16(savestate::VERSION);
+        w.u64(self.rom_hash);
+        w.u8(self.platform.map_or(0, |p| p.id()));
+
+        w.bytes(&self.quirks.to_bytes());
+        w.bytes(&self.registers);
+        w.u32(self.program_counter as u32);
+        w.u16(self.index_register);
+        w.u8(self.stack.len() as u8);
+        w.u8(self.stack_pointer as u8);
+        for &address in &self.stack {
+            w.u16(address);
+        }
+        w.u8(self.delay_timer);
+        w.u8(self.sound_timer);
+        w.u8(self.key.map_or(0xFF, |key| key));
+        w.bytes(&self.rpl_flags);
+        w.u8(self.planes);
+        w.bytes(&self.audio_pattern);
+        w.u8(self.pitch);
+        w.bool(self.vblank);
+        w.u8(self.rng.algorithm().id());
+        w.u32(self.rng.state());
+
+        w.u16(self.display.width() as u16);
+        w.u16(self.display.height() as u16);
+        for plane in (0..PLANE_COUNT).map(|i| 1u8 << i) {
+            w.bits(self.display.pixels().iter().map(|&p| p & plane != 0));
+        }
+
+        w.u32(self.memory.len() as u32);
+        w.bytes(&self.memory);
+        w.finish()
+    }
+
+    // セーブステートを読み込む。エラーの場合は状態を一切変更しない
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut r = StateReader::new(data);
+        if r.bytes(4)? != savestate::MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != savestate::VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        let rom_hash = r.u64()?;
+        if rom_hash != self.rom_hash {
+            return Err(SaveStateError::RomMismatch {
+                expected: self.rom_hash,
+                found: rom_hash,
+            });
+        }
+        let platform = r.u8()?;
+        let expected_platform = self.platform.map_or(0, |p| p.id());
+        if platform != expected_platform {
+            return Err(SaveStateError::PlatformMismatch {
+                expected: expected_platform,
+                found: platform,
+            });
+        }
+
+        let quirks = Quirks::from_bytes(r.bytes(3)?.try_into().unwrap())
+            .ok_or(SaveStateError::Invalid("quirks"))?;
+        let registers: [u8; 16] = r.bytes(16)?.try_into().unwrap();
+        let program_counter = r.u32()? as usize;
+        let index_register = r.u16()?;
+        let stack_len = r.u8()? as usize;
+        if stack_len != self.stack.len() {
+            return Err(SaveStateError::Invalid("stack depth"));
+        }
+        let stack_pointer = r.u8()? as usize;
+        if stack_pointer > stack_len {
+            return Err(SaveStateError::Invalid("stack pointer"));
+        }
+        let mut stack = Vec::with_capacity(stack_len);
+        for _ in 0..stack_len {
+            stack.push(r.u16()?);
+        }
+        let delay_timer = r.u8()?;
+        let sound_timer = r.u8()?;
+        let key = match r.u8()? {
+            0xFF => None,
+            key if key < 16 => Some(key),
+            _ => return Err(SaveStateError::Invalid("pending key")),
+        };
+        let rpl_flags: [u8; 16] = r.bytes(16)?.try_into().unwrap();
+        let planes = r.u8()?;
+        if planes >= 1 << PLANE_COUNT {
+            return Err(SaveStateError::Invalid("planes"));
+        }
+        let audio_pattern: [u8; 16] = r.bytes(16)?.try_into().unwrap();
+        let pitch = r.u8()?;
+        let vblank = r.bool()?;
+        let algorithm =
+            RngAlgorithm::from_id(r.u8()?).ok_or(SaveStateError::Invalid("rng algorithm"))?;
+        let rng_state = r.u32()?;
+        // xorshiftは状態0から抜け出せず、それ以外はR9と同じ16ビット
+        let valid = match algorithm {
+            RngAlgorithm::Xorshift => rng_state != 0,
+            RngAlgorithm::Timer | RngAlgorithm::CosmacVip => rng_state <= 0xFFFF,
+        };
+        if !valid {
+            return Err(SaveStateError::Invalid("rng state"));
+        }
+        let rng = Rng::from_state(algorithm, rng_state);
+
+        // 画面の大きさはプラットフォームの解像度のどれかに限る（確保する前に確かめる）
+        let width = r.u16()? as usize;
+        let height = r.u16()? as usize;
+        if !Platform::resolutions_of(self.platform).contains(&Resolution { width, height }) {
+            return Err(SaveStateError::Invalid("display size"));
+        }
+        let mut pixels = vec![0u8; width * height];
+        for plane in (0..PLANE_COUNT).map(|i| 1u8 << i) {
+            for (pixel, bit) in pixels.iter_mut().zip(r.bits(width * height)?) {
+                if bit {
+                    *pixel |= plane;
+                }
+            }
+        }
+        let display = FrameBuffer::from_pixels(width, height, pixels)
+            .ok_or(SaveStateError::Invalid("display"))?;
+
+        let memory_len = r.u32()? as usize;
+        if memory_len != self.memory.len() {
+            return Err(SaveStateError::Invalid("memory size"));
+        }
+        let memory = r.bytes(memory_len)?.to_vec();
+        r.finish()?;
+
+        self.quirks = quirks;
+        self.registers = registers;
+        self.program_counter = program_counter;
+        self.index_register = index_register;
+        self.stack = stack;
+        self.stack_pointer = stack_pointer;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.key = key;
+        self.rpl_flags = rpl_flags;
+        self.planes = planes;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.vblank = vblank;
+        self.rng = rng;
+        self.display = display;
+        self.memory = memory;
+        self.update_sound();
+        self.clear_journal();
+        Ok(())
     }
 
     pub fn decrement_timers(&mut self) {
+        self.begin_journal(false);
+        self.vblank = true;
+        self.rng.tick();
+
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -90,12 +624,46 @@ impl<T: KeyboardInput> Cpu<T> {
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
         }
+
+        self.update_sound();
+        if let Some(audio) = self.audio.as_mut() {
+            audio.frame();
+        }
     }
 
-    pub fn get_display(&self) -> &[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT] {
+    // サウンドタイマーが有効/無効に切り替わったら出力先に通知する
+    fn update_sound(&mut self) {
+        let active = self.sound_timer > 0;
+        if active != self.sound_active {
+            self.sound_active = active;
+            if let Some(audio) = self.audio.as_mut() {
+                audio.set_active(active);
+            }
+        }
+    }
+
+    pub fn get_display(&self) -> &FrameBuffer {
         &self.display
     }
 
+    // SUPER-CHIPの拡張命令が使えるか
+    fn superchip(&self) -> bool {
+        self.platform
+            .is_some_and(|platform| platform.superchip_instructions())
+    }
+
+    fn xochip(&self) -> bool {
+        self.platform == Some(Platform::XoChip)
+    }
+
+    // 条件付きスキップ: XO-CHIPでは4バイト命令 F000 NNNN をまとめて飛ばす
+    fn skip(&mut self) {
+        let long = self.xochip()
+            && self.memory.get(self.program_counter) == Some(&0xF0)
+            && self.memory.get(self.program_counter + 1) == Some(&0x00);
+        self.program_counter += if long { 4 } else { 2 };
+    }
+
     fn logging(&self, text: &str) {
         debug!(
             "{} v={:?} i={}({:x}) stack={:?} sp={:x} pc={}({:x}) dt={:x} key={:?}",
@@ -112,444 +680,612 @@ impl<T: KeyboardInput> Cpu<T> {
         );
     }
 
-    pub fn update(&mut self) {
-        let opcode = self.read_opcode();
+    // 命令を実行する前にキーボードの状態を取り込む
+    fn poll_keyboard(&mut self) {
+        self.keypad = self.keyboard.pressed_keys();
+        self.key_events = self.keyboard.take_events();
+    }
 
-        self.program_counter += 2;
+    fn is_key_pressed(&self, key: u8) -> bool {
+        self.keypad & (1 << (key & 0xF)) != 0
+    }
 
-        let c = ((opcode & 0xF000) >> 12) as u8;
-        let x = ((opcode & 0x0F00) >> 8) as u8;
-        let y = ((opcode & 0x00F0) >> 4) as u8;
-        let d = ((opcode & 0x000F) >> 0) as u8;
+    pub fn update(&mut self) -> Result<StepOutcome, ExecutionError> {
+        self.poll_keyboard();
 
-        let nnn = opcode & 0x0FFF;
-        let kk: u8 = (opcode & 0x00FF) as u8;
+        self.begin_journal(true);
+        let result = self
+            .fetch()
+            .and_then(|instruction| self.execute(instruction));
+        self.end_journal();
+        result
+    }
 
-        match (c, x, y, d) {
-            (0, 0, 0xE, 0) => {
-                self.cls();
-            }
-            (0, 0, 0xE, 0xE) => {
-                self.ret();
-            }
-            (0, _, _, _) => {
-                self.sys_addr(nnn);
-            }
-            (0x1, _, _, _) => {
-                self.jp_addr(nnn);
-            }
-            (0x2, _, _, _) => {
-                self.call(nnn);
-            }
-            (0x3, _, _, _) => {
-                self.se_byte(x, kk);
-            }
-            (0x4, _, _, _) => {
-                self.sne_byte(x, kk);
-            }
-            (0x5, _, _, 0) => {
-                self.se_xy(x, y);
-            }
-            (0x6, _, _, _) => {
-                self.ld_byte(x, kk);
-            }
-            (0x7, _, _, _) => {
-                self.add_byte(x, kk);
-            }
-            (0x8, _, _, 0) => {
-                self.ld_xy(x, y);
-            }
-            (0x8, _, _, 1) => {
-                self.or_xy(x, y);
-            }
-            (0x8, _, _, 2) => {
-                self.and_xy(x, y);
-            }
-            (0x8, _, _, 3) => {
-                self.xor_xy(x, y);
-            }
-            (0x8, _, _, 4) => {
-                self.add_xy(x, y);
-            }
-            (0x8, _, _, 5) => {
-                self.sub_xy(x, y);
-            }
-            (0x8, _, _, 6) => {
-                self.shr_xy(x);
-            }
-            (0x8, _, _, 7) => {
-                self.subn_xy(x, y);
-            }
-            (0x8, _, _, 0xE) => {
-                self.shl_xy(x);
-            }
-            (0x9, _, _, 0) => {
-                self.sne_xy(x, y);
-            }
-            (0xA, _, _, _) => {
-                self.ld_i_addr(nnn);
-            }
-            (0xB, _, _, _) => {
-                self.jp_v0_addr(nnn);
-            }
-            (0xC, _, _, _) => {
-                self.rnd_byte(x, kk);
-            }
-            (0xD, _, _, _) => {
-                self.drw_xy(x, y, d);
-            }
-            (0xE, _, 9, 0xE) => {
-                self.skp_vx(x);
-            }
-            (0xE, _, 0xA, 1) => {
-                self.sknp_vx(x);
-            }
-            (0xF, _, 0, 7) => {
-                self.ld_vx_dt(x);
-            }
-            (0xF, _, 0, 0xA) => {
-                self.ld_vx_k(x);
-            }
-            (0xF, _, 1, 5) => {
-                self.ld_dt_vx(x);
-            }
-            (0xF, _, 1, 8) => {
-                self.ld_st_vx(x);
-            }
-            (0xF, _, 1, 0xE) => {
-                self.add_i_vx(x);
-            }
-            (0xF, _, 2, 9) => {
-                self.ld_f_vx(x);
-            }
-            (0xF, _, 3, 3) => {
-                self.ld_b_vx(x);
-            }
-            (0xF, _, 5, 5) => {
-                self.ld_i_vx(x);
-            }
-            (0xF, _, 6, 5) => {
-                self.ld_vx_i(x);
+    // 命令が現在のプラットフォームで使えるか
+    fn supports(&self, set: InstructionSet) -> bool {
+        Platform::supports(self.platform, set)
+    }
+
+    // PCの位置の命令を現在のプラットフォームに合わせて解釈し、PCを次の命令に進める
+    fn fetch(&mut self) -> Result<Instruction, ExecutionError> {
+        let address = self.program_counter;
+        let opcode = self.read_opcode()?;
+        self.program_counter += 2;
+
+        let instruction = match Instruction::decode(opcode) {
+            Ok(instruction) if self.supports(instruction.instruction_set()) => instruction,
+            Err(DecodeError::MissingOperand) if self.xochip() => {
+                let high = self.read_memory(self.program_counter)?;
+                let low = self.read_memory(self.program_counter + 1)?;
+                self.program_counter += 2;
+                Instruction::LdILong(u16::from_be_bytes([high, low]))
             }
+            // 拡張命令が使えない場合、0から始まる命令はSYSとして扱う
+            _ if opcode & 0xF000 == 0 => Instruction::Sys(opcode),
             _ => {
                 warn!("Executing unknown opcode");
-                panic!("opcode {:04x}", opcode);
+                return Err(ExecutionError::UnknownOpcode { address, opcode });
             }
+        };
+
+        if matches!(instruction, Instruction::Sys(_))
+            && self.quirks.sys_call == SysCall::Unsupported
+        {
+            warn!("Executing unknown opcode");
+            return Err(ExecutionError::UnknownOpcode { address, opcode });
         }
+        Ok(instruction)
+    }
+
+    // 解釈済みの命令を1つ実行する。PCはすでに次の命令を指している必要がある
+    pub fn execute(&mut self, instruction: Instruction) -> Result<StepOutcome, ExecutionError> {
+        use Instruction::*;
+
+        self.logging(&format!("{:04X} - {}", instruction.encode(), instruction));
+        if let Some(trace) = self.memory_trace.as_mut() {
+            trace.clear();
+        }
+
+        match instruction {
+            Sys(nnn) => self.sys_addr(nnn),
+            Cls => self.cls(),
+            Ret => self.ret()?,
+            Scd(n) => self.scd(n),
+            Scu(n) => self.scu(n),
+            Scr => self.scr(),
+            Scl => self.scl(),
+            Exit => return Ok(self.exit()),
+            Low => self.low(),
+            High => self.high(),
+            Jp(nnn) => self.jp_addr(nnn),
+            Call(nnn) => self.call(nnn)?,
+            SeByte(x, kk) => self.se_byte(x, kk),
+            SneByte(x, kk) => self.sne_byte(x, kk),
+            SeXy(x, y) => self.se_xy(x, y),
+            SaveXy(x, y) => self.save_xy(x, y)?,
+            LoadXy(x, y) => self.load_xy(x, y)?,
+            LdByte(x, kk) => self.ld_byte(x, kk),
+            AddByte(x, kk) => self.add_byte(x, kk),
+            LdXy(x, y) => self.ld_xy(x, y),
+            OrXy(x, y) => self.or_xy(x, y),
+            AndXy(x, y) => self.and_xy(x, y),
+            XorXy(x, y) => self.xor_xy(x, y),
+            AddXy(x, y) => self.add_xy(x, y),
+            SubXy(x, y) => self.sub_xy(x, y),
+            ShrXy(x, y) => self.shr_xy(x, y),
+            SubnXy(x, y) => self.subn_xy(x, y),
+            ShlXy(x, y) => self.shl_xy(x, y),
+            SneXy(x, y) => self.sne_xy(x, y),
+            LdI(nnn) => self.ld_i_addr(nnn),
+            JpV0(nnn) => self.jp_v0_addr(nnn),
+            Rnd(x, kk) => self.rnd_byte(x, kk),
+            Drw(x, y, n) => {
+                if !self.drw_xy(x, y, n)? {
+                    return Ok(StepOutcome::WaitingForVblank);
+                }
+            }
+            Skp(x) => self.skp_vx(x),
+            Sknp(x) => self.sknp_vx(x),
+            LdILong(nnnn) => self.index_register = nnnn,
+            Plane(n) => self.plane(n),
+            Audio => self.audio()?,
+            LdVxDt(x) => self.ld_vx_dt(x),
+            LdVxK(x) => return Ok(self.ld_vx_k(x)),
+            LdDtVx(x) => self.ld_dt_vx(x),
+            LdStVx(x) => self.ld_st_vx(x),
+            AddIVx(x) => self.add_i_vx(x),
+            LdFVx(x) => self.ld_f_vx(x),
+            LdHfVx(x) => self.ld_hf_vx(x),
+            LdBVx(x) => self.ld_b_vx(x)?,
+            Pitch(x) => self.pitch_vx(x),
+            LdIVx(x) => self.ld_i_vx(x)?,
+            LdVxI(x) => self.ld_vx_i(x)?,
+            LdRVx(x) => self.ld_r_vx(x),
+            LdVxR(x) => self.ld_vx_r(x),
+        }
+
+        Ok(StepOutcome::Executed)
     }
 
     fn sys_addr(&mut self, nnn: u16) {
-        self.logging(&format!("0nnn - SYS {}", nnn));
-        self.program_counter = nnn as usize;
+        if self.quirks.sys_call == SysCall::Jump {
+            self.program_counter = nnn as usize;
+        }
     }
 
     fn cls(&mut self) {
-        self.logging("00E0 - CLS");
-        self.display = [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        self.journal_display();
+        self.display.clear(self.planes);
+    }
+
+    fn scd(&mut self, n: u8) {
+        self.journal_display();
+        self.display.scroll_down(n as usize, self.planes);
+    }
+
+    fn scu(&mut self, n: u8) {
+        self.journal_display();
+        self.display.scroll_up(n as usize, self.planes);
+    }
+
+    fn scr(&mut self) {
+        self.journal_display();
+        self.display.scroll_right(4, self.planes);
+    }
+
+    fn scl(&mut self) {
+        self.journal_display();
+        self.display.scroll_left(4, self.planes);
+    }
+
+    fn exit(&mut self) -> StepOutcome {
+        self.program_counter -= 2; // 以降もEXITに留まる
+        StepOutcome::Exited
+    }
+
+    // 00FE/00FF はプラットフォームの最低・最高の解像度に切り替える
+    fn low(&mut self) {
+        let resolutions = Platform::resolutions_of(self.platform);
+        self.set_resolution(resolutions[0]);
+    }
+
+    fn high(&mut self) {
+        let resolutions = Platform::resolutions_of(self.platform);
+        self.set_resolution(resolutions[resolutions.len() - 1]);
     }
 
-    fn ret(&mut self) {
-        self.logging("00EE - RET");
+    fn set_resolution(&mut self, resolution: Resolution) {
+        self.journal_display();
+        self.display.resize(resolution.width, resolution.height);
+    }
+
+    fn ret(&mut self) -> Result<(), ExecutionError> {
         if self.stack_pointer == 0 {
-            panic!("Stack underflow")
+            return Err(ExecutionError::StackUnderflow);
         }
 
         self.stack_pointer -= 1;
         self.program_counter = self.stack[self.stack_pointer] as usize;
+        Ok(())
     }
 
     fn jp_addr(&mut self, nnn: u16) {
-        self.logging(&format!("1nnn - JP {}", nnn));
         self.program_counter = nnn as usize;
     }
 
-    fn call(&mut self, nnn: u16) {
-        self.logging(&format!("2nnn - CALL {}", nnn));
+    fn call(&mut self, nnn: u16) -> Result<(), ExecutionError> {
         let sp = self.stack_pointer;
         let stack = &mut self.stack;
 
-        if sp > stack.len() {
-            panic!("Stack overflow")
+        if sp >= stack.len() {
+            return Err(ExecutionError::StackOverflow);
         }
 
+        if let Some(journal) = self.journal.as_mut() {
+            journal.stack(sp, stack[sp]);
+        }
         stack[sp] = self.program_counter as u16;
         self.stack_pointer += 1;
         self.program_counter = nnn as usize;
+        Ok(())
     }
 
     fn se_byte(&mut self, x: u8, kk: u8) {
-        self.logging(&format!("3xkk - SE V{} KK:{}", x, kk));
         let vx = self.registers[x as usize];
         if vx == kk {
-            self.program_counter += 2;
+            self.skip();
         }
     }
 
     fn sne_byte(&mut self, x: u8, kk: u8) {
-        self.logging(&format!("4xkk - SNE V{} KK:{}", x, kk));
         let vx = self.registers[x as usize];
         if vx != kk {
-            self.program_counter += 2;
+            self.skip();
         }
     }
 
     fn se_xy(&mut self, x: u8, y: u8) {
-        self.logging(&format!("5xy0 - SE V{} V{}", x, y));
         let vx = self.registers[x as usize];
         let vy = self.registers[y as usize];
 
         if vx == vy {
-            self.program_counter += 2;
+            self.skip();
+        }
+    }
+
+    // x > y の場合は逆順に保存する
+    fn register_range(x: u8, y: u8) -> Vec<usize> {
+        if x <= y {
+            (x as usize..=y as usize).collect()
+        } else {
+            (y as usize..=x as usize).rev().collect()
         }
     }
 
+    fn save_xy(&mut self, x: u8, y: u8) -> Result<(), ExecutionError> {
+        for (offset, register) in Self::register_range(x, y).into_iter().enumerate() {
+            self.write_memory(
+                self.index_register as usize + offset,
+                self.registers[register],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn load_xy(&mut self, x: u8, y: u8) -> Result<(), ExecutionError> {
+        for (offset, register) in Self::register_range(x, y).into_iter().enumerate() {
+            self.registers[register] = self.read_memory(self.index_register as usize + offset)?;
+        }
+        Ok(())
+    }
+
     fn ld_byte(&mut self, x: u8, kk: u8) {
-        self.logging(&format!("6xkk - LD V{} KK:{}", x, kk));
         self.registers[x as usize] = kk;
     }
 
     fn add_byte(&mut self, x: u8, kk: u8) {
-        self.logging(&format!("7xkk - ADD V{} KK:{}", x, kk));
         let vx = self.registers[x as usize];
         self.registers[x as usize] = vx.overflowing_add(kk).0;
     }
 
     fn ld_xy(&mut self, x: u8, y: u8) {
-        self.logging(&format!("8xy0 - LD V{} V{}", x, y));
         self.registers[x as usize] = self.registers[y as usize];
     }
 
     fn or_xy(&mut self, x: u8, y: u8) {
-        self.logging(&format!("8xy1 - OR V{} V{}", x, y));
         self.registers[x as usize] |= self.registers[y as usize];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
     }
 
     fn and_xy(&mut self, x: u8, y: u8) {
-        self.logging(&format!("8xy2 - AND V{} V{}", x, y));
         self.registers[x as usize] &= self.registers[y as usize];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
     }
 
     fn xor_xy(&mut self, x: u8, y: u8) {
-        self.logging(&format!("8xy3 - XOR V{} V{}", x, y));
         self.registers[x as usize] ^= self.registers[y as usize];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
     }
 
     fn add_xy(&mut self, x: u8, y: u8) {
-        self.logging(&format!("8xy4 - ADD V{} V{}", x, y));
         let vx = self.registers[x as usize] as u16;
         let vy = self.registers[y as usize] as u16;
 
-        if vx + vy > 0xFF {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
-        }
+        // VxがVFの場合もフラグが残るよう、結果の後にVFを設定する
         self.registers[x as usize] = ((vx + vy) & 0xFF) as u8;
+        self.registers[0xF] = (vx + vy > 0xFF) as u8;
     }
 
     fn sub_xy(&mut self, x: u8, y: u8) {
-        self.logging(&format!("8xy5 - SUB V{} V{}", x, y));
         let vx = self.registers[x as usize];
         let vy = self.registers[y as usize];
 
         let (val, overflow) = vx.overflowing_sub(vy);
-        if !overflow {
-            self.registers[0xF] = 1;
+        self.registers[x as usize] = val;
+        self.registers[0xF] = !overflow as u8;
+    }
+
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.registers[y as usize]
         } else {
-            self.registers[0xF] = 0;
+            self.registers[x as usize]
         }
-        self.registers[x as usize] = val;
     }
 
-    fn shr_xy(&mut self, x: u8) {
-        self.logging(&format!("8xy6 - SHR V{}", x));
-        let vx = self.registers[x as usize];
-        // 最下位ビットをVfにセット
-        self.registers[0xF] = vx & 0x01;
+    fn shr_xy(&mut self, x: u8, y: u8) {
+        let value = self.shift_source(x, y);
         // 右シフト（2で割る）
-        self.registers[x as usize] = vx >> 1;
+        self.registers[x as usize] = value >> 1;
+        // 最下位ビットをVfにセット
+        self.registers[0xF] = value & 0x01;
     }
 
     fn subn_xy(&mut self, x: u8, y: u8) {
-        self.logging(&format!("8xy7 - SUBN V{} V{}", x, y));
         let vx = self.registers[x as usize];
         let vy = self.registers[y as usize];
 
         let (val, overflow) = vy.overflowing_sub(vx);
-        if !overflow {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
-        }
         self.registers[x as usize] = val;
+        self.registers[0xF] = !overflow as u8;
     }
 
-    fn shl_xy(&mut self, x: u8) {
-        self.logging(&format!("8xyE - SHL V{}", x));
-        let vx = self.registers[x as usize];
-        // 最上位ビット（7番目のビット）をVfにセット
-        self.registers[0xF] = (vx & 0x80) >> 7;
+    fn shl_xy(&mut self, x: u8, y: u8) {
+        let value = self.shift_source(x, y);
         // 左シフト（2倍）
-        self.registers[x as usize] = vx.overflowing_mul(2).0;
+        self.registers[x as usize] = value.overflowing_mul(2).0;
+        // 最上位ビット（7番目のビット）をVfにセット
+        self.registers[0xF] = (value & 0x80) >> 7;
     }
 
     fn sne_xy(&mut self, x: u8, y: u8) {
-        self.logging(&format!("9xy0 - SNE V{} V{}", x, y));
         if self.registers[x as usize] != self.registers[y as usize] {
-            self.program_counter += 2;
+            self.skip();
         }
     }
 
     fn ld_i_addr(&mut self, nnn: u16) {
-        self.logging(&format!("Annn - LD I {}", nnn));
         self.index_register = nnn;
     }
 
     fn jp_v0_addr(&mut self, nnn: u16) {
-        self.logging(&format!("Bnnn - JP V0 {}", nnn));
-        self.program_counter = (self.registers[0] as u16 + nnn) as usize;
+        let offset = if self.quirks.jump_uses_vx {
+            self.registers[((nnn & 0x0F00) >> 8) as usize]
+        } else {
+            self.registers[0]
+        };
+        self.program_counter = (offset as u16 + nnn) as usize;
     }
 
     fn rnd_byte(&mut self, x: u8, kk: u8) {
-        self.logging(&format!("Cxkk - RND V{} {}", x, kk));
-        let random_number: u8 = random();
+        let random_number = self.rng.next_byte();
         self.registers[x as usize] = random_number & kk;
     }
 
-    fn drw_xy(&mut self, x: u8, y: u8, n: u8) {
-        self.logging(&format!("Dxyn - DRW V{} V{} {}", x, y, n));
-        let vx = self.registers[x as usize] as usize;
-        let vy = self.registers[y as usize] as usize;
+    // 描画した場合はtrue、垂直帰線期間を待つ場合はfalseを返す
+    fn drw_xy(&mut self, x: u8, y: u8, n: u8) -> Result<bool, ExecutionError> {
+        if self.quirks.display_wait {
+            if !self.vblank {
+                self.program_counter -= 2;
+                return Ok(false);
+            }
+            self.vblank = false;
+        }
+
+        let width = self.display.width();
+        let height = self.display.height();
+
+        // 開始座標は常に画面内に折り返す
+        let vx = self.registers[x as usize] as usize % width;
+        let vy = self.registers[y as usize] as usize % height;
+
+        // SUPER-CHIPではn=0で16x16のスプライトを描画する
+        let (rows, bytes_per_row) = if n == 0 && self.superchip() {
+            (16, 2)
+        } else {
+            (n as usize, 1)
+        };
 
         self.registers[0xF] = 0;
 
-        for byte_offset in 0..n {
-            let byte = self.memory[self.index_register as usize + byte_offset as usize];
-            for bit_offset in 0..8 {
-                let bit = (byte >> (7 - bit_offset)) & 1;
-                let curr_x = (vx + bit_offset) % DISPLAY_WIDTH;
-                let curr_y = (vy + byte_offset as usize) % DISPLAY_HEIGHT;
-                let prev = self.display[curr_y][curr_x];
-                self.display[curr_y][curr_x] ^= bit == 1;
-                if prev && !self.display[curr_y][curr_x] {
-                    self.registers[0xF] = 1;
+        // 複数のプレーンを選択している場合、各プレーンのデータは連続して並ぶ
+        let mut address = self.index_register as usize;
+        for plane in (0..PLANE_COUNT).map(|i| 1u8 << i) {
+            if self.planes & plane == 0 {
+                continue;
+            }
+            self.draw_sprite_plane(address, vx, vy, rows, bytes_per_row, plane)?;
+            address += rows * bytes_per_row;
+        }
+        Ok(true)
+    }
+
+    fn draw_sprite_plane(
+        &mut self,
+        base: usize,
+        vx: usize,
+        vy: usize,
+        rows: usize,
+        bytes_per_row: usize,
+        plane: u8,
+    ) -> Result<(), ExecutionError> {
+        let width = self.display.width();
+        let height = self.display.height();
+
+        for row in 0..rows {
+            for byte_index in 0..bytes_per_row {
+                let address = base + row * bytes_per_row + byte_index;
+                let byte = self.read_memory(address)?;
+                for bit_offset in 0..8 {
+                    let bit = (byte >> (7 - bit_offset)) & 1;
+                    if bit == 0 {
+                        continue;
+                    }
+                    let mut curr_x = vx + byte_index * 8 + bit_offset;
+                    let mut curr_y = vy + row;
+                    if self.quirks.clip_sprites {
+                        if curr_x >= width || curr_y >= height {
+                            continue;
+                        }
+                    } else {
+                        curr_x %= width;
+                        curr_y %= height;
+                    }
+                    if let Some(journal) = self.journal.as_mut() {
+                        journal.row(curr_y, &self.display);
+                    }
+                    if self.display.toggle(curr_x, curr_y, plane) {
+                        self.registers[0xF] = 1;
+                    }
                 }
             }
         }
+        Ok(())
     }
 
     fn skp_vx(&mut self, x: u8) {
-        self.logging(&format!("Ex9E - SKP V{}", x));
         let vx = self.registers[x as usize];
-        if let Some(key) = self.keyboard.get_key() {
-            if key == vx {
-                self.program_counter += 2;
-                self.key = None;
-            }
+        if self.is_key_pressed(vx) {
+            self.skip();
         }
     }
 
     fn sknp_vx(&mut self, x: u8) {
-        self.logging(&format!("ExA1 - SKNP V{}", x));
         let vx = self.registers[x as usize];
-        if let Some(key) = self.keyboard.get_key() {
-            if key != vx {
-                self.program_counter += 2;
-                self.key = None;
-            }
+        if !self.is_key_pressed(vx) {
+            self.skip();
         }
     }
 
+    fn plane(&mut self, n: u8) {
+        self.planes = n & 0x3;
+    }
+
+    fn audio(&mut self) -> Result<(), ExecutionError> {
+        for i in 0..self.audio_pattern.len() {
+            self.audio_pattern[i] = self.read_memory(self.index_register as usize + i)?;
+        }
+        Ok(())
+    }
+
+    fn pitch_vx(&mut self, x: u8) {
+        self.pitch = self.registers[x as usize];
+    }
+
     fn ld_vx_dt(&mut self, x: u8) {
-        self.logging(&format!("Fx07 - LD V{} DT", x));
         self.registers[x as usize] = self.delay_timer;
     }
 
-    fn ld_vx_k(&mut self, x: u8) {
-        self.logging(&format!("Fx0A - LD V{} K", x));
-        let mut pressed = false;
-
-        if let Some(key) = self.keyboard.get_key() {
-            self.registers[x as usize] = key;
-            pressed = true;
+    fn ld_vx_k(&mut self, x: u8) -> StepOutcome {
+        // COSMAC VIPと同様に、キーが押されてから離されるまで待つ
+        for event in std::mem::take(&mut self.key_events) {
+            match (self.key, event) {
+                (None, KeyEvent::Pressed(key)) => self.key = Some(key),
+                (Some(pending), KeyEvent::Released(key)) if pending == key => {
+                    self.key = None;
+                    self.registers[x as usize] = key;
+                    return StepOutcome::Executed;
+                }
+                _ => {}
+            }
         }
 
-        if !pressed {
-            self.program_counter -= 2; //キーが押されるまで待つ
-        }
+        self.program_counter -= 2; //キーが離されるまで待つ
+        StepOutcome::WaitingForKey
     }
 
     fn ld_dt_vx(&mut self, x: u8) {
-        self.logging(&format!("Fx15 - LD DT V{}", x));
         self.delay_timer = self.registers[x as usize];
     }
 
     fn ld_st_vx(&mut self, x: u8) {
-        self.logging(&format!("Fx18 - LD ST V{}", x));
         self.sound_timer = self.registers[x as usize];
+        self.update_sound();
     }
 
     fn add_i_vx(&mut self, x: u8) {
-        self.logging(&format!("Fx1E - ADD I V{}", x));
         let vx = self.registers[x as usize];
-        self.index_register += vx as u16;
+        self.index_register = self.index_register.wrapping_add(vx as u16);
     }
 
     fn ld_f_vx(&mut self, x: u8) {
-        self.logging(&format!("Fx29 - LD F V{}", x));
         self.index_register = self.registers[x as usize] as u16 * 5;
     }
 
-    fn ld_b_vx(&mut self, x: u8) {
-        self.logging(&format!("Fx33 - LD B V{}", x));
+    fn ld_hf_vx(&mut self, x: u8) {
+        let digit = (self.registers[x as usize] & 0x0F) as usize;
+        self.index_register = (BIG_FONT_ADDRESS + digit * 10) as u16;
+    }
+
+    fn ld_b_vx(&mut self, x: u8) -> Result<(), ExecutionError> {
         let vx = self.registers[x as usize];
-        self.memory[self.index_register as usize] = (vx / 100) % 10;
-        self.memory[self.index_register as usize + 1] = (vx / 10) % 10;
-        self.memory[self.index_register as usize + 2] = vx % 10;
+        let i = self.index_register as usize;
+        self.write_memory(i, (vx / 100) % 10)?;
+        self.write_memory(i + 1, (vx / 10) % 10)?;
+        self.write_memory(i + 2, vx % 10)
     }
 
-    fn ld_i_vx(&mut self, x: u8) {
-        self.logging(&format!("Fx55 - LD [I] V{}", x));
+    fn ld_i_vx(&mut self, x: u8) -> Result<(), ExecutionError> {
         for i in 0..=x {
-            self.memory[self.index_register as usize + i as usize] = self.registers[i as usize];
+            self.write_memory(
+                self.index_register as usize + i as usize,
+                self.registers[i as usize],
+            )?;
         }
+        self.increment_index(x);
+        Ok(())
     }
 
-    fn ld_vx_i(&mut self, x: u8) {
-        self.logging(&format!("Fx65 - LD V{} [I]", x));
+    fn ld_vx_i(&mut self, x: u8) -> Result<(), ExecutionError> {
         for i in 0..=x {
-            self.registers[i as usize] = self.memory[self.index_register as usize + i as usize];
+            self.registers[i as usize] =
+                self.read_memory(self.index_register as usize + i as usize)?;
         }
+        self.increment_index(x);
+        Ok(())
+    }
+
+    fn ld_r_vx(&mut self, x: u8) {
+        let count = x as usize + 1;
+        self.rpl_flags[..count].copy_from_slice(&self.registers[..count]);
+    }
+
+    fn ld_vx_r(&mut self, x: u8) {
+        let count = x as usize + 1;
+        self.registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+    }
+
+    fn increment_index(&mut self, x: u8) {
+        let amount = match self.quirks.index_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::X => x as u16,
+            IndexIncrement::XPlusOne => x as u16 + 1,
+        };
+        self.index_register = self.index_register.wrapping_add(amount);
     }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::keyboard::{InputEvent, KeyState};
     use std::sync::mpsc;
 
     // テスト用のモックキーボード構造体
+    #[derive(Default)]
     struct MockKeyboard {
-        key: Option<u8>,
+        state: KeyState,
     }
 
     impl KeyboardInput for MockKeyboard {
-        fn start_keyboard_thread(_sender: mpsc::Sender<u8>) {
+        fn start_keyboard_thread(_sender: mpsc::Sender<InputEvent>) {
             // テストでは何もしない
         }
 
-        fn get_key(&self) -> Option<u8> {
-            self.key
+        fn pressed_keys(&mut self) -> u16 {
+            self.state.pressed()
+        }
+
+        fn take_events(&mut self) -> Vec<KeyEvent> {
+            self.state.take_events()
         }
     }
 
     fn setup_cpu() -> Cpu<MockKeyboard> {
-        // テスト用の空ファイルを作成
-        let temp_path = "test.ch8";
+        // テスト用の空ファイルを作成（並列実行されるためスレッドごとに別名にする）
+        let temp_path =
+            std::env::temp_dir().join(format!("chip8-test-{:?}.ch8", std::thread::current().id()));
+        let temp_path = temp_path.to_str().unwrap();
         std::fs::write(temp_path, vec![0; 10]).expect("Failed to create test file");
-        
-        let keyboard = MockKeyboard { key: None };
+
+        let keyboard = MockKeyboard::default();
         let cpu = Cpu::new(temp_path, keyboard);
-        
+
         std::fs::remove_file(temp_path).expect("Failed to remove test file");
         cpu
     }
@@ -557,26 +1293,890 @@ mod tests {
     #[test]
     fn test_add_xy() {
         let mut cpu = setup_cpu();
-        
+
         // ケース1: 通常の加算（オーバーフローなし）
-        cpu.registers[0] = 0x10;  // 16
-        cpu.registers[1] = 0x20;  // 32
+        cpu.registers[0] = 0x10; // 16
+        cpu.registers[1] = 0x20; // 32
         cpu.add_xy(0, 1);
-        assert_eq!(cpu.registers[0], 0x30);  // 48
-        assert_eq!(cpu.registers[0xF], 0);   // キャリーフラグはセットされない
+        assert_eq!(cpu.registers[0], 0x30); // 48
+        assert_eq!(cpu.registers[0xF], 0); // キャリーフラグはセットされない
 
         // ケース2: オーバーフローする加算
-        cpu.registers[0] = 0xFF;  // 255
-        cpu.registers[1] = 0x02;  // 2
+        cpu.registers[0] = 0xFF; // 255
+        cpu.registers[1] = 0x02; // 2
         cpu.add_xy(0, 1);
-        assert_eq!(cpu.registers[0], 0x01);  // 257 % 256 = 1
-        assert_eq!(cpu.registers[0xF], 1);   // キャリーフラグがセットされる
+        assert_eq!(cpu.registers[0], 0x01); // 257 % 256 = 1
+        assert_eq!(cpu.registers[0xF], 1); // キャリーフラグがセットされる
 
         // ケース3: ちょうど256になる加算
-        cpu.registers[0] = 0xFE;  // 254
-        cpu.registers[1] = 0x02;  // 2
+        cpu.registers[0] = 0xFE; // 254
+        cpu.registers[1] = 0x02; // 2
         cpu.add_xy(0, 1);
-        assert_eq!(cpu.registers[0], 0x00);  // 256 % 256 = 0
-        assert_eq!(cpu.registers[0xF], 1);   // キャリーフラグがセットされる
+        assert_eq!(cpu.registers[0], 0x00); // 256 % 256 = 0
+        assert_eq!(cpu.registers[0xF], 1); // キャリーフラグがセットされる
+    }
+
+    #[test]
+    fn test_execute_decoded_instruction() {
+        let mut cpu = setup_cpu();
+        let instruction = Instruction::decode(0x6A42).unwrap();
+        assert_eq!(cpu.execute(instruction), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.registers[0xA], 0x42);
+
+        // 拡張命令が無効な場合、00Cn はSYSとして扱われる
+        let mut cpu = Cpu::from_bytes(&[0x00, 0xC3], MockKeyboard::default());
+        assert_eq!(cpu.update(), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.program_counter, 0x0C3);
+    }
+
+    #[test]
+    fn test_arithmetic_flag_overrides_vf_result() {
+        let mut cpu = setup_cpu();
+        cpu.registers[0xF] = 0x05;
+        cpu.registers[1] = 0x03;
+        cpu.sub_xy(0xF, 1); // 5 - 3: 借りなし
+        assert_eq!(cpu.registers[0xF], 1);
+
+        cpu.registers[0xF] = 0x05;
+        cpu.subn_xy(0xF, 1); // 3 - 5: 借りあり
+        assert_eq!(cpu.registers[0xF], 0);
+
+        cpu.registers[0xF] = 0xFF;
+        cpu.add_xy(0xF, 1);
+        assert_eq!(cpu.registers[0xF], 1);
+
+        // シフトも同じく、結果ではなくずれ出たビットが残る
+        cpu.registers[0xF] = 0x02;
+        cpu.shr_xy(0xF, 0xF);
+        assert_eq!(cpu.registers[0xF], 0);
+
+        cpu.registers[0xF] = 0x81;
+        cpu.shl_xy(0xF, 0xF);
+        assert_eq!(cpu.registers[0xF], 1);
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_bytes(&[0xE0, 0x00], keyboard);
+
+        assert_eq!(
+            cpu.update(),
+            Err(ExecutionError::UnknownOpcode {
+                address: 0x200,
+                opcode: 0xE000
+            })
+        );
+    }
+
+    #[test]
+    fn test_stack_errors() {
+        // RETのみ: スタックが空
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_bytes(&[0x00, 0xEE], keyboard);
+        assert_eq!(cpu.update(), Err(ExecutionError::StackUnderflow));
+
+        // 自分自身をCALLし続ける: 16段目まではOK、17段目でオーバーフロー
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_bytes(&[0x22, 0x00], keyboard);
+        for _ in 0..16 {
+            assert_eq!(cpu.update(), Ok(StepOutcome::Executed));
+        }
+        assert_eq!(cpu.update(), Err(ExecutionError::StackOverflow));
+    }
+
+    #[test]
+    fn test_memory_fault() {
+        let mut cpu = setup_cpu();
+
+        cpu.index_register = 0xFFE;
+        assert_eq!(
+            cpu.ld_i_vx(2),
+            Err(ExecutionError::MemoryFault {
+                address: 0x1000,
+                access: MemoryAccess::Write
+            })
+        );
+
+        cpu.index_register = 0xFFF;
+        assert_eq!(
+            cpu.ld_vx_i(1),
+            Err(ExecutionError::MemoryFault {
+                address: 0x1000,
+                access: MemoryAccess::Read
+            })
+        );
+
+        cpu.index_register = 0xFFC;
+        assert!(cpu.drw_xy(0, 0, 5).is_err());
+        assert!(cpu.ld_b_vx(0).is_ok());
+    }
+
+    #[test]
+    fn test_pc_out_of_range() {
+        let mut cpu = setup_cpu();
+        cpu.program_counter = 0xFFF;
+        assert_eq!(
+            cpu.update(),
+            Err(ExecutionError::PcOutOfRange { pc: 0xFFF })
+        );
+    }
+
+    #[test]
+    fn test_wait_for_key() {
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_bytes(&[0xF3, 0x0A], keyboard);
+        assert_eq!(cpu.update(), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(cpu.program_counter, 0x200);
+
+        // 押しただけでは進まない
+        cpu.keyboard.state.press(0x7);
+        assert_eq!(cpu.update(), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(cpu.program_counter, 0x200);
+
+        // 別のキーを離しても進まない
+        cpu.keyboard.state.press(0x2);
+        cpu.keyboard.state.release(0x2);
+        assert_eq!(cpu.update(), Ok(StepOutcome::WaitingForKey));
+
+        cpu.keyboard.state.release(0x7);
+        assert_eq!(cpu.update(), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.registers[3], 0x7);
+        assert_eq!(cpu.program_counter, 0x202);
+    }
+
+    #[test]
+    fn test_wait_for_key_ignores_earlier_presses() {
+        // LD V0 1; LD V3 K
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_bytes(&[0x60, 0x01, 0xF3, 0x0A], keyboard);
+        cpu.keyboard.state.press(0x5);
+        cpu.update().unwrap();
+
+        // Fx0A実行前に押されたキーを離しても入力とはみなさない
+        cpu.keyboard.state.release(0x5);
+        assert_eq!(cpu.update(), Ok(StepOutcome::WaitingForKey));
+
+        // 同じステップ内の押下と解放は受け付ける
+        cpu.keyboard.state.press(0x9);
+        cpu.keyboard.state.release(0x9);
+        assert_eq!(cpu.update(), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.registers[3], 0x9);
+    }
+
+    #[test]
+    fn test_skip_on_key_state() {
+        // SKP V0; (skipped); SKNP V1; (skipped)
+        let rom = [0xE0, 0x9E, 0x00, 0x00, 0xE1, 0xA1, 0x00, 0x00];
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_bytes(&rom, keyboard);
+        cpu.registers[0] = 0x4;
+        cpu.registers[1] = 0x6;
+
+        // 複数のキーを同時に押しても両方とも判定できる
+        cpu.keyboard.state.press(0x4);
+        cpu.keyboard.state.press(0x6);
+        cpu.update().unwrap();
+        assert_eq!(cpu.program_counter, 0x204);
+        cpu.update().unwrap();
+        assert_eq!(cpu.program_counter, 0x206);
+
+        // 何も押されていない場合、SKNPはスキップし、SKPはスキップしない
+        cpu.keyboard.state.release(0x4);
+        cpu.keyboard.state.release(0x6);
+        cpu.program_counter = 0x200;
+        cpu.update().unwrap();
+        assert_eq!(cpu.program_counter, 0x202);
+        cpu.program_counter = 0x204;
+        cpu.update().unwrap();
+        assert_eq!(cpu.program_counter, 0x208);
+    }
+
+    fn setup_cpu_with_quirks(rom: &[u8], quirks: Quirks) -> Cpu<MockKeyboard> {
+        let keyboard = MockKeyboard::default();
+        Cpu::with_quirks(rom, keyboard, quirks)
+    }
+
+    #[test]
+    fn test_quirk_shift_source() {
+        for shift_uses_vy in [false, true] {
+            let quirks = Quirks {
+                shift_uses_vy,
+                ..Quirks::default()
+            };
+            let mut cpu = setup_cpu_with_quirks(&[], quirks);
+
+            cpu.registers[1] = 0b0000_0110;
+            cpu.registers[2] = 0b1000_0001;
+            cpu.shr_xy(1, 2);
+            if shift_uses_vy {
+                assert_eq!(cpu.registers[1], 0b0100_0000);
+                assert_eq!(cpu.registers[0xF], 1);
+            } else {
+                assert_eq!(cpu.registers[1], 0b0000_0011);
+                assert_eq!(cpu.registers[0xF], 0);
+            }
+
+            cpu.registers[1] = 0b0000_0110;
+            cpu.shl_xy(1, 2);
+            if shift_uses_vy {
+                assert_eq!(cpu.registers[1], 0b0000_0010);
+                assert_eq!(cpu.registers[0xF], 1);
+            } else {
+                assert_eq!(cpu.registers[1], 0b0000_1100);
+                assert_eq!(cpu.registers[0xF], 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_quirk_index_increment() {
+        for (index_increment, expected) in [
+            (IndexIncrement::Unchanged, 0x300),
+            (IndexIncrement::X, 0x303),
+            (IndexIncrement::XPlusOne, 0x304),
+        ] {
+            let quirks = Quirks {
+                index_increment,
+                ..Quirks::default()
+            };
+            let mut cpu = setup_cpu_with_quirks(&[], quirks);
+
+            cpu.index_register = 0x300;
+            cpu.ld_i_vx(3).unwrap();
+            assert_eq!(cpu.index_register, expected);
+
+            cpu.index_register = 0x300;
+            cpu.ld_vx_i(3).unwrap();
+            assert_eq!(cpu.index_register, expected);
+        }
+    }
+
+    #[test]
+    fn test_quirk_vf_reset() {
+        for vf_reset in [false, true] {
+            let quirks = Quirks {
+                vf_reset,
+                ..Quirks::default()
+            };
+            let mut cpu = setup_cpu_with_quirks(&[], quirks);
+            let ops: [fn(&mut Cpu<MockKeyboard>, u8, u8); 3] =
+                [Cpu::or_xy, Cpu::and_xy, Cpu::xor_xy];
+
+            for op in ops {
+                cpu.registers[0xF] = 0x55;
+                op(&mut cpu, 0, 1);
+                assert_eq!(cpu.registers[0xF], if vf_reset { 0 } else { 0x55 });
+            }
+        }
+    }
+
+    #[test]
+    fn test_quirk_jump_source() {
+        for jump_uses_vx in [false, true] {
+            let quirks = Quirks {
+                jump_uses_vx,
+                ..Quirks::default()
+            };
+            let mut cpu = setup_cpu_with_quirks(&[], quirks);
+
+            cpu.registers[0] = 0x10;
+            cpu.registers[3] = 0x20;
+            cpu.jp_v0_addr(0x300);
+            assert_eq!(
+                cpu.program_counter,
+                if jump_uses_vx { 0x320 } else { 0x310 }
+            );
+        }
+    }
+
+    #[test]
+    fn test_quirk_sprite_clipping() {
+        for clip_sprites in [false, true] {
+            let quirks = Quirks {
+                clip_sprites,
+                ..Quirks::default()
+            };
+            let mut cpu = setup_cpu_with_quirks(&[], quirks);
+
+            // 右下の角に8x2のスプライトを描画
+            cpu.memory[0x300] = 0xFF;
+            cpu.memory[0x301] = 0xFF;
+            cpu.index_register = 0x300;
+            cpu.registers[0] = (DISPLAY_WIDTH - 4) as u8;
+            cpu.registers[1] = (DISPLAY_HEIGHT - 1) as u8;
+            cpu.drw_xy(0, 1, 2).unwrap();
+
+            assert!(cpu.display.get(DISPLAY_WIDTH - 1, DISPLAY_HEIGHT - 1));
+            assert_eq!(cpu.display.get(0, DISPLAY_HEIGHT - 1), !clip_sprites);
+            assert_eq!(cpu.display.get(DISPLAY_WIDTH - 4, 0), !clip_sprites);
+            assert_eq!(cpu.display.get(0, 0), !clip_sprites);
+        }
+
+        // 開始座標はクリップ時でも折り返す
+        let quirks = Quirks {
+            clip_sprites: true,
+            ..Quirks::default()
+        };
+        let mut cpu = setup_cpu_with_quirks(&[], quirks);
+        cpu.memory[0x300] = 0x80;
+        cpu.index_register = 0x300;
+        cpu.registers[0] = (DISPLAY_WIDTH + 2) as u8;
+        cpu.registers[1] = (DISPLAY_HEIGHT + 3) as u8;
+        cpu.drw_xy(0, 1, 1).unwrap();
+        assert!(cpu.display.get(2, 3));
+    }
+
+    #[test]
+    fn test_quirk_display_wait() {
+        // 同じスプライトを2回描画するROM
+        let rom = [0xD0, 0x01, 0xD0, 0x01];
+        for display_wait in [false, true] {
+            let quirks = Quirks {
+                display_wait,
+                ..Quirks::default()
+            };
+            let mut cpu = setup_cpu_with_quirks(&rom, quirks);
+
+            assert_eq!(cpu.update(), Ok(StepOutcome::Executed));
+            if display_wait {
+                assert_eq!(cpu.update(), Ok(StepOutcome::WaitingForVblank));
+                assert_eq!(cpu.program_counter, 0x202);
+                cpu.decrement_timers();
+            }
+            assert_eq!(cpu.update(), Ok(StepOutcome::Executed));
+            assert_eq!(cpu.program_counter, 0x204);
+        }
+    }
+
+    #[test]
+    fn test_quirk_sys_call() {
+        let rom = [0x03, 0x00];
+        for (sys_call, expected) in [
+            (SysCall::Jump, Ok(0x300)),
+            (SysCall::Ignore, Ok(0x202)),
+            (
+                SysCall::Unsupported,
+                Err(ExecutionError::UnknownOpcode {
+                    address: 0x200,
+                    opcode: 0x0300,
+                }),
+            ),
+        ] {
+            let quirks = Quirks {
+                sys_call,
+                ..Quirks::default()
+            };
+            let mut cpu = setup_cpu_with_quirks(&rom, quirks);
+            let result = cpu.update().map(|_| cpu.program_counter);
+            assert_eq!(result, expected);
+        }
+    }
+
+    #[test]
+    fn test_set_quirks_at_runtime() {
+        let mut cpu = setup_cpu();
+        assert_eq!(cpu.quirks(), Quirks::default());
+
+        let quirks = Quirks {
+            vf_reset: true,
+            ..Quirks::default()
+        };
+        cpu.set_quirks(quirks);
+        cpu.registers[0xF] = 1;
+        cpu.or_xy(0, 1);
+        assert_eq!(cpu.registers[0xF], 0);
+    }
+
+    #[test]
+    fn test_from_platform() {
+        let keyboard = MockKeyboard::default();
+        let cpu = Cpu::from_platform(&[0x12, 0x00], keyboard, Platform::CosmacVip);
+        assert_eq!(cpu.platform(), Some(Platform::CosmacVip));
+        assert_eq!(cpu.quirks(), Platform::CosmacVip.quirks());
+        assert_eq!(cpu.memory.len(), 0x1000);
+        assert_eq!(cpu.stack.len(), 12);
+        assert_eq!(&cpu.memory[..80], &crate::font::VIP_FONT[..]);
+        assert_eq!(&cpu.memory[0x200..0x202], &[0x12, 0x00]);
+
+        let keyboard = MockKeyboard::default();
+        let cpu = Cpu::from_platform(&[], keyboard, Platform::XoChip);
+        assert_eq!(cpu.memory.len(), 0x10000);
+        assert_eq!(cpu.stack.len(), 16);
+        assert_eq!(&cpu.memory[..80], &SMALL_FONT[..]);
+
+        let cpu = setup_cpu();
+        assert_eq!(cpu.platform(), None);
+    }
+
+    #[test]
+    fn test_vip_stack_depth() {
+        // 自分自身をCALLし続ける: VIPは12段まで
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_platform(&[0x22, 0x00], keyboard, Platform::CosmacVip);
+        for _ in 0..12 {
+            assert_eq!(cpu.update(), Ok(StepOutcome::Executed));
+        }
+        assert_eq!(cpu.update(), Err(ExecutionError::StackOverflow));
+    }
+
+    #[test]
+    fn test_oversized_rom_is_truncated() {
+        let keyboard = MockKeyboard::default();
+        let rom = vec![0xAA; 0x1000];
+        let cpu = Cpu::from_bytes(&rom, keyboard);
+        assert_eq!(cpu.memory.len(), 0x1000);
+        assert_eq!(cpu.memory[0xFFF], 0xAA);
+    }
+
+    fn setup_schip(rom: &[u8]) -> Cpu<MockKeyboard> {
+        let keyboard = MockKeyboard::default();
+        Cpu::from_platform(rom, keyboard, Platform::SuperChip)
+    }
+
+    #[test]
+    fn test_schip_opcodes_require_platform() {
+        // Fx30 はCHIP-8には無い命令
+        let mut cpu = Cpu::from_bytes(&[0xF0, 0x30], MockKeyboard::default());
+        assert!(matches!(
+            cpu.update(),
+            Err(ExecutionError::UnknownOpcode { .. })
+        ));
+
+        // 00FF はCHIP-8ではSYS（既定ではジャンプ）として扱い、解像度は変わらない
+        let mut cpu = Cpu::from_bytes(&[0x00, 0xFF], MockKeyboard::default());
+        assert_eq!(cpu.update(), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.program_counter, 0x0FF);
+        assert_eq!(cpu.display.width(), DISPLAY_WIDTH);
+
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_platform(&[0x00, 0xFF], keyboard, Platform::SuperChip);
+        assert_eq!(cpu.update(), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.display.width(), HIRES_DISPLAY_WIDTH);
+    }
+
+    #[test]
+    fn test_schip_resolution_switch() {
+        // HIGH, LOW, EXIT
+        let mut cpu = setup_schip(&[0x00, 0xFF, 0x00, 0xFE, 0x00, 0xFD]);
+
+        cpu.update().unwrap();
+        assert_eq!(cpu.display.width(), HIRES_DISPLAY_WIDTH);
+        assert_eq!(cpu.display.height(), HIRES_DISPLAY_HEIGHT);
+
+        cpu.update().unwrap();
+        assert_eq!(cpu.display.width(), DISPLAY_WIDTH);
+        assert_eq!(cpu.display.height(), DISPLAY_HEIGHT);
+
+        assert_eq!(cpu.update(), Ok(StepOutcome::Exited));
+        assert_eq!(cpu.update(), Ok(StepOutcome::Exited));
+        assert_eq!(cpu.program_counter, 0x204);
+    }
+
+    #[test]
+    fn test_schip_scroll() {
+        // SCD 3, SCR, SCL
+        let mut cpu = setup_schip(&[0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC]);
+        cpu.display.toggle(10, 5, 1);
+
+        cpu.update().unwrap();
+        assert!(cpu.display.get(10, 8));
+        cpu.update().unwrap();
+        assert!(cpu.display.get(14, 8));
+        cpu.update().unwrap();
+        assert!(cpu.display.get(10, 8));
+    }
+
+    #[test]
+    fn test_schip_16x16_sprite() {
+        // HIGH, DRW V0 V1 0
+        let mut cpu = setup_schip(&[0x00, 0xFF, 0xD0, 0x10]);
+        for i in 0..32 {
+            cpu.memory[0x300 + i] = 0xFF;
+        }
+        cpu.index_register = 0x300;
+        cpu.registers[0] = 100;
+        cpu.registers[1] = 40;
+
+        cpu.update().unwrap();
+        cpu.update().unwrap();
+        assert!(cpu.display.get(100, 40));
+        assert!(cpu.display.get(115, 55));
+        assert!(!cpu.display.get(116, 40));
+        assert!(!cpu.display.get(100, 56));
+        assert_eq!(cpu.registers[0xF], 0);
+
+        // 同じ場所にもう一度描くと衝突し、すべて消える
+        cpu.program_counter = 0x202;
+        cpu.update().unwrap();
+        assert_eq!(cpu.registers[0xF], 1);
+        assert!(cpu.display.is_blank());
+    }
+
+    #[test]
+    fn test_schip_big_font() {
+        let mut cpu = setup_schip(&[0xF3, 0x30]);
+        cpu.registers[3] = 0x7;
+        cpu.update().unwrap();
+        assert_eq!(cpu.index_register as usize, BIG_FONT_ADDRESS + 70);
+        assert_eq!(cpu.memory[cpu.index_register as usize], 0xFF);
+    }
+
+    #[test]
+    fn test_schip_rpl_flags() {
+        // LD R V3, LD V3 R
+        let mut cpu = setup_schip(&[0xF3, 0x75, 0xF3, 0x85]);
+        cpu.registers[..5].copy_from_slice(&[1, 2, 3, 4, 5]);
+        cpu.update().unwrap();
+
+        cpu.registers = [0; 16];
+        cpu.update().unwrap();
+        assert_eq!(&cpu.registers[..5], &[1, 2, 3, 4, 0]);
+    }
+
+    fn setup_xochip(rom: &[u8]) -> Cpu<MockKeyboard> {
+        let keyboard = MockKeyboard::default();
+        Cpu::from_platform(rom, keyboard, Platform::XoChip)
+    }
+
+    #[test]
+    fn test_xochip_long_load_and_memory() {
+        // LD I 0xF123; LD V0 0xAB; LD [I] V0
+        let mut cpu = setup_xochip(&[0xF0, 0x00, 0xF1, 0x23, 0x60, 0xAB, 0xF0, 0x55]);
+        cpu.update().unwrap();
+        assert_eq!(cpu.index_register, 0xF123);
+        assert_eq!(cpu.program_counter, 0x204);
+        cpu.update().unwrap();
+        cpu.update().unwrap();
+        assert_eq!(cpu.memory[0xF123], 0xAB);
+    }
+
+    #[test]
+    fn test_xochip_skip_over_long_instruction() {
+        // SE V0 0; LD I long; LD V1 1
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x61, 0x01];
+        let mut cpu = setup_xochip(&rom);
+        cpu.update().unwrap();
+        assert_eq!(cpu.program_counter, 0x206);
+
+        // XO-CHIP以外では2バイトだけ飛ばす
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_platform(&rom, keyboard, Platform::SuperChip);
+        cpu.update().unwrap();
+        assert_eq!(cpu.program_counter, 0x204);
+    }
+
+    #[test]
+    fn test_xochip_register_range() {
+        // SAVE V1 - V3; LOAD V6 - V4 (逆順)
+        let mut cpu = setup_xochip(&[0x51, 0x32, 0x56, 0x43]);
+        cpu.registers[1..4].copy_from_slice(&[7, 8, 9]);
+        cpu.index_register = 0x400;
+        cpu.update().unwrap();
+        assert_eq!(&cpu.memory[0x400..0x403], &[7, 8, 9]);
+        assert_eq!(cpu.index_register, 0x400);
+
+        cpu.update().unwrap();
+        assert_eq!(&cpu.registers[4..7], &[9, 8, 7]);
+    }
+
+    #[test]
+    fn test_xochip_planes() {
+        // PLANE 3; DRW V0 V0 1; PLANE 2; CLS
+        let mut cpu = setup_xochip(&[0xF3, 0x01, 0xD0, 0x01, 0xF2, 0x01, 0x00, 0xE0]);
+        cpu.memory[0x300] = 0x80; // プレーン1のデータ
+        cpu.memory[0x301] = 0xC0; // プレーン2のデータ
+        cpu.index_register = 0x300;
+
+        cpu.update().unwrap();
+        cpu.update().unwrap();
+        assert_eq!(cpu.display.pixel(0, 0), 3);
+        assert_eq!(cpu.display.pixel(1, 0), 2);
+
+        cpu.update().unwrap();
+        cpu.update().unwrap();
+        assert_eq!(cpu.display.pixel(0, 0), 1);
+        assert_eq!(cpu.display.pixel(1, 0), 0);
+    }
+
+    #[test]
+    fn test_xochip_scroll_up_audio_and_pitch() {
+        // SCU 2; AUDIO; PITCH V5
+        let mut cpu = setup_xochip(&[0x00, 0xD2, 0xF0, 0x02, 0xF5, 0x3A]);
+        cpu.display.toggle(3, 5, 1);
+        for i in 0..16 {
+            cpu.memory[0x300 + i] = i as u8;
+        }
+        cpu.index_register = 0x300;
+        cpu.registers[5] = 100;
+
+        cpu.update().unwrap();
+        assert!(cpu.display.get(3, 3));
+        cpu.update().unwrap();
+        assert_eq!(cpu.audio_pattern[15], 15);
+        cpu.update().unwrap();
+        assert_eq!(cpu.pitch, 100);
+    }
+
+    #[test]
+    fn test_sound_timer_notifies_audio_sink() {
+        use crate::audio::{BufferSink, SquareWave};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        struct RecordingSink(Rc<RefCell<Vec<bool>>>);
+        impl AudioSink for RecordingSink {
+            fn set_active(&mut self, active: bool) {
+                self.0.borrow_mut().push(active);
+            }
+        }
+
+        // LD V0 2; LD ST V0
+        let rom = [0x60, 0x02, 0xF0, 0x18];
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_bytes(&rom, keyboard);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        cpu.set_audio_sink(Box::new(RecordingSink(events.clone())));
+
+        cpu.update().unwrap();
+        cpu.update().unwrap();
+        assert!(cpu.is_sound_active());
+        cpu.decrement_timers();
+        assert!(cpu.is_sound_active());
+        cpu.decrement_timers();
+        assert!(!cpu.is_sound_active());
+        cpu.decrement_timers();
+        assert_eq!(*events.borrow(), vec![true, false]);
+
+        // BufferSinkにはサウンドタイマーが有効だったフレームだけ波形が入る
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_bytes(&rom, keyboard);
+        let sink = BufferSink::new(SquareWave::new(600.0, 0.5, 6000));
+        let samples = sink.samples();
+        cpu.set_audio_sink(Box::new(sink));
+        cpu.update().unwrap();
+        cpu.update().unwrap();
+        for _ in 0..4 {
+            cpu.decrement_timers();
+        }
+        let samples = samples.borrow();
+        assert_eq!(samples.len(), 400);
+        assert!(samples[..100].iter().any(|&s| s != 0.0));
+        assert!(samples[100..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_rnd_is_reproducible_with_seed() {
+        use crate::rng::RngAlgorithm;
+
+        // RND V0 0xFF; RND V1 0x0F
+        let rom = [0xC0, 0xFF, 0xC1, 0x0F];
+        let run = |rng: Rng| {
+            let keyboard = MockKeyboard::default();
+            let mut cpu = Cpu::with_rng(&rom, keyboard, rng);
+            cpu.update().unwrap();
+            cpu.update().unwrap();
+            (cpu.registers[0], cpu.registers[1], cpu.rng().clone())
+        };
+
+        let (a0, a1, rng_a) = run(Rng::seeded(7));
+        let (b0, b1, rng_b) = run(Rng::seeded(7));
+        assert_eq!((a0, a1), (b0, b1));
+        assert_eq!(rng_a, rng_b);
+        assert_eq!(a1 & 0xF0, 0);
+
+        let mut expected = Rng::seeded(7);
+        assert_eq!(a0, expected.next_byte());
+
+        let (v0, _, _) = run(Rng::new(RngAlgorithm::Timer, 7));
+        assert_eq!(v0, Rng::new(RngAlgorithm::Timer, 7).next_byte());
+
+        // VIPのRNDルーチン: R9=0x0104 → 0x69
+        let (v0, _, _) = run(Rng::new(RngAlgorithm::CosmacVip, 0x0104));
+        assert_eq!(v0, 0x69);
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        // RND V0 0xFF; LD ST V0; CALL 0x208; (unused); DRW V1 V2 5; JP 0x20A
+        let rom = [
+            0xC0, 0xFF, 0xF0, 0x18, 0x22, 0x08, 0x00, 0x00, 0xD1, 0x25, 0x12, 0x0A,
+        ];
+        let keyboard = MockKeyboard::default();
+        let rng = Rng::seeded(5);
+        let mut cpu = Cpu::from_platform_with_rng(&rom, keyboard, Platform::SuperChip, rng);
+        for _ in 0..4 {
+            cpu.update().unwrap();
+        }
+        cpu.decrement_timers();
+        cpu.key = Some(0xA);
+        cpu.memory[0x800] = 0x42;
+        let state = cpu.save_state();
+
+        // 別のCPUに読み込むと完全に同じ状態になる
+        let keyboard = MockKeyboard::default();
+        let mut restored = Cpu::from_platform(&rom, keyboard, Platform::SuperChip);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.registers, cpu.registers);
+        assert_eq!(restored.program_counter, cpu.program_counter);
+        assert_eq!(restored.stack, cpu.stack);
+        assert_eq!(restored.stack_pointer, 1);
+        assert_eq!(restored.display, cpu.display);
+        assert_eq!(restored.key, Some(0xA));
+        assert_eq!(restored.memory, cpu.memory);
+        assert_eq!(restored.rng(), cpu.rng());
+        assert_eq!(restored.is_sound_active(), cpu.is_sound_active());
+
+        // 以降の実行も一致する
+        cpu.program_counter = 0x200;
+        restored.program_counter = 0x200;
+        cpu.update().unwrap();
+        restored.update().unwrap();
+        assert_eq!(restored.registers[0], cpu.registers[0]);
+
+        // VIPの乱数もR9の値ごと復元される
+        let keyboard = MockKeyboard::default();
+        let rng = Rng::new(RngAlgorithm::CosmacVip, 0xABCD);
+        let mut vip = Cpu::from_platform_with_rng(&rom, keyboard, Platform::CosmacVip, rng);
+        vip.update().unwrap();
+        vip.decrement_timers();
+        let keyboard = MockKeyboard::default();
+        let mut restored = Cpu::from_platform(&rom, keyboard, Platform::CosmacVip);
+        restored.load_state(&vip.save_state()).unwrap();
+        assert_eq!(restored.rng(), vip.rng());
+        vip.program_counter = 0x200;
+        restored.program_counter = 0x200;
+        vip.update().unwrap();
+        restored.update().unwrap();
+        assert_eq!(restored.registers[0], vip.registers[0]);
+    }
+
+    #[test]
+    fn test_load_state_rejects_mismatches() {
+        let keyboard = MockKeyboard::default();
+        let cpu = Cpu::from_platform(&[0x12, 0x00], keyboard, Platform::Chip48);
+        let state = cpu.save_state();
+
+        // 別のROM
+        let keyboard = MockKeyboard::default();
+        let mut other = Cpu::from_platform(&[0x12, 0x02], keyboard, Platform::Chip48);
+        other.registers[0] = 0x99;
+        assert!(matches!(
+            other.load_state(&state),
+            Err(SaveStateError::RomMismatch { .. })
+        ));
+        assert_eq!(other.registers[0], 0x99);
+
+        // 別のプラットフォーム
+        let keyboard = MockKeyboard::default();
+        let mut other = Cpu::from_platform(&[0x12, 0x00], keyboard, Platform::CosmacVip);
+        assert!(matches!(
+            other.load_state(&state),
+            Err(SaveStateError::PlatformMismatch {
+                expected: 1,
+                found: 2
+            })
+        ));
+
+        // 壊れたデータ
+        let keyboard = MockKeyboard::default();
+        let mut same = Cpu::from_platform(&[0x12, 0x00], keyboard, Platform::Chip48);
+        assert_eq!(same.load_state(b"XXXX"), Err(SaveStateError::BadMagic));
+        assert_eq!(
+            same.load_state(&state[..state.len() - 1]),
+            Err(SaveStateError::Truncated)
+        );
+        let mut future = state.clone();
+        future[4] = 99;
+        assert_eq!(
+            same.load_state(&future),
+            Err(SaveStateError::UnsupportedVersion(99))
+        );
+        assert_eq!(same.load_state(&state), Ok(()));
+    }
+
+    #[test]
+    fn test_load_state_rejects_invalid_values() {
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_platform(&[0x12, 0x00], keyboard, Platform::Chip48);
+        cpu.set_rng(Rng::seeded(1));
+        let state = cpu.save_state();
+
+        // 末尾の画面（2プレーン×64x32ビット）とメモリから位置を求める
+        let display = state.len() - 0x1000 - 4 - 2 * 64 * 32 / 8;
+        let patched = |offset: usize, bytes: &[u8]| {
+            let mut state = state.clone();
+            state[offset..offset + bytes.len()].copy_from_slice(bytes);
+            state
+        };
+        let invalid = |what| Err(SaveStateError::Invalid(what));
+        // 幅0、巨大な画面、CHIP-48では使えない高解像度
+        assert_eq!(
+            cpu.load_state(&patched(display - 4, &[0, 0])),
+            invalid("display size")
+        );
+        assert_eq!(
+            cpu.load_state(&patched(display - 4, &[0xFF, 0xFF, 0xFF, 0xFF])),
+            invalid("display size")
+        );
+        assert_eq!(
+            cpu.load_state(&patched(display - 4, &[128, 0, 64, 0])),
+            invalid("display size")
+        );
+        assert_eq!(
+            cpu.load_state(&patched(display - 8, &[0; 4])),
+            invalid("rng state")
+        );
+        // VIPのR9は16ビット
+        assert_eq!(
+            cpu.load_state(&patched(display - 9, &[2, 0xFF, 0xFF, 0xFF, 0xFF])),
+            invalid("rng state")
+        );
+        assert_eq!(
+            cpu.load_state(&patched(display - 28, &[4])),
+            invalid("planes")
+        );
+        assert_eq!(cpu.load_state(&state), Ok(()));
+    }
+
+    #[test]
+    fn test_journal_undo_restores_every_instruction() {
+        // RND V0 0xFF; LD ST V0; HIGH; LD I 0x300; LD [I] V0; CALL 0x210; JP 0x20C; (unused)
+        // 210: LD I 0; DRW V1 V2 5; SCD 2; CLS; DRW V1 V2 5; RET
+        let rom = [
+            0xC0, 0xFF, 0xF0, 0x18, 0x00, 0xFF, 0xA3, 0x00, 0xF0, 0x55, 0x22, 0x10, 0x12, 0x0C,
+            0x00, 0x00, 0xA0, 0x00, 0xD1, 0x25, 0x00, 0xC2, 0x00, 0xE0, 0xD1, 0x25, 0x00, 0xEE,
+        ];
+        let keyboard = MockKeyboard::default();
+        let mut cpu = Cpu::from_platform(&rom, keyboard, Platform::XoChip);
+        cpu.set_rng(Rng::seeded(3));
+        cpu.set_journal(Some(100));
+        let mut states = Vec::new();
+        for step in 0..12 {
+            states.push(cpu.save_state());
+            cpu.update().unwrap();
+            if step % 3 == 2 {
+                cpu.decrement_timers();
+            }
+        }
+        // 同じ命令に留まるだけのジャンプは記録しない
+        cpu.update().unwrap();
+        cpu.decrement_timers();
+        assert_eq!(cpu.program_counter, 0x20C);
+        assert_eq!(cpu.journal().unwrap().len(), 12);
+
+        let writes = (0..12).rev().fold(Vec::new(), |mut writes, step| {
+            writes.extend(cpu.undo().unwrap());
+            assert_eq!(cpu.save_state(), states[step], "step {}", step);
+            writes
+        });
+        assert_eq!(writes, [(0x300, 0, cpu.rng().clone().next_byte())]);
+        assert_eq!(cpu.undo(), None);
+
+        // 無効にすると何も記録しない
+        cpu.set_journal(None);
+        cpu.update().unwrap();
+        assert_eq!(cpu.undo(), None);
     }
 }
@@ -0,0 +1,1676 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::instruction::Instruction;
+
+const PROGRAM_START: usize = 0x200;
+const MEMORY_SIZE: usize = 0x1000;
+// 変数に使えるのは V0 - VE。VFは 8xy4 / 8xy5 / シフトなどで書き換えられるので使わない
+const REGISTER_COUNT: usize = 15;
+const FLAG: u8 = 0xF;
+
+// C風の小さな言語をCHIP-8のROMにコンパイルする
+//
+//   const SPEED = 2;
+//   sprite ball = [0x60, 0xF0, 0x60];
+//   var score = 0;
+//
+//   fn step(x) { return x + SPEED; }
+//   fn main() {
+//       var x = 0;
+//       while (x < 60) { draw(ball, x, 10); x = step(x); }
+//   }
+//
+// 値はすべて8ビット。変数はレジスタに割り当て、関数は 2nnn / 00EE で呼び出す
+pub fn compile(source: &str) -> Result<Vec<u8>, CompileError> {
+    let tokens = tokenize(source)?;
+    let program = Parser {
+        tokens,
+        position: 0,
+    }
+    .program()?;
+    Compiler::new(&program)?.compile()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+impl Position {
+    fn error(&self, message: impl Into<String>) -> CompileError {
+        CompileError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Name(String),
+    Number(i64),
+    Symbol(&'static str),
+    End,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    position: Position,
+}
+
+// 長いものから順に照合する
+const SYMBOLS: [&str; 36] = [
+    "<<=", ">>=", "==", "!=", "<=", ">=", "&&", "||", "<<", ">>", "+=", "-=", "&=", "|=", "^=",
+    "(", ")", "{", "}", "[", "]", ",", ";", "=", "+", "-", "*", "/", "%", "&", "|", "^", "!", "<",
+    ">", "~",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, CompileError> {
+    let mut tokens = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or("");
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let position = Position {
+                line: number + 1,
+                column: i + 1,
+            };
+            let c = chars[i];
+            if c.is_whitespace() {
+                i += 1;
+                continue;
+            }
+            if c.is_ascii_alphanumeric() || c == '_' {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let kind = if c.is_ascii_digit() {
+                    TokenKind::Number(
+                        parse_number(&text)
+                            .ok_or_else(|| position.error(format!("invalid number '{}'", text)))?,
+                    )
+                } else {
+                    TokenKind::Name(text)
+                };
+                tokens.push(Token { kind, position });
+                continue;
+            }
+            let rest: String = chars[i..].iter().take(3).collect();
+            let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) else {
+                return Err(position.error(format!("unexpected character '{}'", c)));
+            };
+            tokens.push(Token {
+                kind: TokenKind::Symbol(symbol),
+                position,
+            });
+            i += symbol.chars().count();
+        }
+    }
+    let position = Position {
+        line: source.lines().count().max(1),
+        column: 1,
+    };
+    tokens.push(Token {
+        kind: TokenKind::End,
+        position,
+    });
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let text = text.replace('_', "");
+    if let Some(hex) = text.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    LogicalAnd,
+    LogicalOr,
+}
+
+impl BinaryOp {
+    fn is_condition(&self) -> bool {
+        use BinaryOp::*;
+        matches!(self, Eq | Ne | Lt | Gt | Le | Ge | LogicalAnd | LogicalOr)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone)]
+enum ExprKind {
+    Number(i64),
+    Name(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone)]
+struct Expr {
+    kind: ExprKind,
+    position: Position,
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Var(String, Option<Expr>, Position),
+    Assign(String, Option<BinaryOp>, Expr, Position),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Loop(Vec<Stmt>),
+    Break(Position),
+    Continue(Position),
+    Return(Option<Expr>, Position),
+    Expr(Expr),
+}
+
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+    position: Position,
+}
+
+#[derive(Default)]
+struct Program {
+    constants: Vec<(String, Expr)>,
+    sprites: Vec<(String, Vec<u8>, Position)>,
+    globals: Vec<(String, Option<Expr>, Position)>,
+    functions: Vec<Function>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Symbol(s) if *s == symbol)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Name(name) if name == keyword)
+    }
+
+    fn describe(token: &Token) -> String {
+        match &token.kind {
+            TokenKind::Name(name) => format!("'{}'", name),
+            TokenKind::Number(value) => format!("'{}'", value),
+            TokenKind::Symbol(symbol) => format!("'{}'", symbol),
+            TokenKind::End => "end of file".to_string(),
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<Position, CompileError> {
+        if self.is_symbol(symbol) {
+            return Ok(self.next().position);
+        }
+        let token = self.peek();
+        Err(token.position.error(format!(
+            "expected '{}' but found {}",
+            symbol,
+            Self::describe(token)
+        )))
+    }
+
+    fn name(&mut self) -> Result<(String, Position), CompileError> {
+        let token = self.next();
+        match token.kind {
+            TokenKind::Name(name) if !KEYWORDS.contains(&name.as_str()) => {
+                Ok((name, token.position))
+            }
+            _ => Err(token.position.error(format!(
+                "expected a name but found {}",
+                Self::describe(&token)
+            ))),
+        }
+    }
+
+    fn program(mut self) -> Result<Program, CompileError> {
+        let mut program = Program::default();
+        loop {
+            let token = self.next();
+            let TokenKind::Name(keyword) = &token.kind else {
+                if token.kind == TokenKind::End {
+                    return Ok(program);
+                }
+                return Err(token.position.error(format!(
+                    "expected 'fn', 'var', 'const' or 'sprite' but found {}",
+                    Self::describe(&token)
+                )));
+            };
+            match keyword.as_str() {
+                "const" => {
+                    let (name, _) = self.name()?;
+                    self.expect("=")?;
+                    let value = self.expression()?;
+                    self.expect(";")?;
+                    program.constants.push((name, value));
+                }
+                "var" => {
+                    let (name, position) = self.name()?;
+                    let value = if self.is_symbol("=") {
+                        self.next();
+                        Some(self.expression()?)
+                    } else {
+                        None
+                    };
+                    self.expect(";")?;
+                    program.globals.push((name, value, position));
+                }
+                "sprite" => {
+                    let (name, position) = self.name()?;
+                    self.expect("=")?;
+                    self.expect("[")?;
+                    let mut bytes = Vec::new();
+                    while !self.is_symbol("]") {
+                        let token = self.next();
+                        match token.kind {
+                            TokenKind::Number(value @ 0..=255) => bytes.push(value as u8),
+                            _ => {
+                                return Err(token.position.error(format!(
+                                    "expected a byte but found {}",
+                                    Self::describe(&token)
+                                )))
+                            }
+                        }
+                        if !self.is_symbol("]") {
+                            self.expect(",")?;
+                        }
+                    }
+                    self.expect("]")?;
+                    self.expect(";")?;
+                    if bytes.is_empty() || bytes.len() > 15 {
+                        return Err(position.error("a sprite must have 1 to 15 rows"));
+                    }
+                    program.sprites.push((name, bytes, position));
+                }
+                "fn" => {
+                    let (name, position) = self.name()?;
+                    self.expect("(")?;
+                    let mut params = Vec::new();
+                    while !self.is_symbol(")") {
+                        params.push(self.name()?.0);
+                        if !self.is_symbol(")") {
+                            self.expect(",")?;
+                        }
+                    }
+                    self.expect(")")?;
+                    let body = self.block()?;
+                    program.functions.push(Function {
+                        name,
+                        params,
+                        body,
+                        position,
+                    });
+                }
+                _ => {
+                    return Err(token.position.error(format!(
+                        "expected 'fn', 'var', 'const' or 'sprite' but found '{}'",
+                        keyword
+                    )))
+                }
+            }
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !self.is_symbol("}") {
+            if self.peek().kind == TokenKind::End {
+                return Err(self.peek().position.error("missing '}'"));
+            }
+            stmts.push(self.statement()?);
+        }
+        self.next();
+        Ok(stmts)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, CompileError> {
+        let position = self.peek().position;
+        if self.is_keyword("var") {
+            self.next();
+            let (name, position) = self.name()?;
+            let value = if self.is_symbol("=") {
+                self.next();
+                Some(self.expression()?)
+            } else {
+                None
+            };
+            self.expect(";")?;
+            return Ok(Stmt::Var(name, value, position));
+        }
+        if self.is_keyword("if") {
+            self.next();
+            self.expect("(")?;
+            let condition = self.expression()?;
+            self.expect(")")?;
+            let then = self.block()?;
+            let otherwise = if self.is_keyword("else") {
+                self.next();
+                if self.is_keyword("if") {
+                    vec![self.statement()?]
+                } else {
+                    self.block()?
+                }
+            } else {
+                Vec::new()
+            };
+            return Ok(Stmt::If(condition, then, otherwise));
+        }
+        if self.is_keyword("while") {
+            self.next();
+            self.expect("(")?;
+            let condition = self.expression()?;
+            self.expect(")")?;
+            return Ok(Stmt::While(condition, self.block()?));
+        }
+        if self.is_keyword("loop") {
+            self.next();
+            return Ok(Stmt::Loop(self.block()?));
+        }
+        if self.is_keyword("break") || self.is_keyword("continue") {
+            let keyword = self.next();
+            self.expect(";")?;
+            return Ok(match keyword.kind {
+                TokenKind::Name(name) if name == "break" => Stmt::Break(position),
+                _ => Stmt::Continue(position),
+            });
+        }
+        if self.is_keyword("return") {
+            self.next();
+            let value = if self.is_symbol(";") {
+                None
+            } else {
+                Some(self.expression()?)
+            };
+            self.expect(";")?;
+            return Ok(Stmt::Return(value, position));
+        }
+
+        // 代入か式
+        if let TokenKind::Name(name) = &self.peek().kind {
+            let operator = match &self.tokens[self.position + 1].kind {
+                TokenKind::Symbol("=") => Some(None),
+                TokenKind::Symbol("+=") => Some(Some(BinaryOp::Add)),
+                TokenKind::Symbol("-=") => Some(Some(BinaryOp::Sub)),
+                TokenKind::Symbol("&=") => Some(Some(BinaryOp::And)),
+                TokenKind::Symbol("|=") => Some(Some(BinaryOp::Or)),
+                TokenKind::Symbol("^=") => Some(Some(BinaryOp::Xor)),
+                TokenKind::Symbol("<<=") => Some(Some(BinaryOp::Shl)),
+                TokenKind::Symbol(">>=") => Some(Some(BinaryOp::Shr)),
+                _ => None,
+            };
+            if let Some(operator) = operator {
+                let name = name.clone();
+                self.next();
+                self.next();
+                let value = self.expression()?;
+                self.expect(";")?;
+                return Ok(Stmt::Assign(name, operator, value, position));
+            }
+        }
+        let expr = self.expression()?;
+        self.expect(";")?;
+        Ok(Stmt::Expr(expr))
+    }
+
+    fn expression(&mut self) -> Result<Expr, CompileError> {
+        self.binary(0)
+    }
+
+    // 優先順位の低いものから順に並べる
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        const LEVELS: [&[(&str, BinaryOp)]; 10] = [
+            &[("||", BinaryOp::LogicalOr)],
+            &[("&&", BinaryOp::LogicalAnd)],
+            &[("|", BinaryOp::Or)],
+            &[("^", BinaryOp::Xor)],
+            &[("&", BinaryOp::And)],
+            &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+            &[
+                ("<=", BinaryOp::Le),
+                (">=", BinaryOp::Ge),
+                ("<", BinaryOp::Lt),
+                (">", BinaryOp::Gt),
+            ],
+            &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            &[
+                ("*", BinaryOp::Mul),
+                ("/", BinaryOp::Div),
+                ("%", BinaryOp::Rem),
+            ],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        'operators: loop {
+            for &(symbol, op) in LEVELS[level] {
+                if self.is_symbol(symbol) {
+                    let position = self.next().position;
+                    let right = self.binary(level + 1)?;
+                    left = Expr {
+                        kind: ExprKind::Binary(op, Box::new(left), Box::new(right)),
+                        position,
+                    };
+                    continue 'operators;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        let op = [
+            ("-", UnaryOp::Neg),
+            ("!", UnaryOp::Not),
+            ("~", UnaryOp::BitNot),
+        ]
+        .into_iter()
+        .find(|(symbol, _)| self.is_symbol(symbol));
+        if let Some((_, op)) = op {
+            let position = self.next().position;
+            let operand = self.unary()?;
+            return Ok(Expr {
+                kind: ExprKind::Unary(op, Box::new(operand)),
+                position,
+            });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        let token = self.next();
+        let position = token.position;
+        let kind = match token.kind {
+            TokenKind::Number(value) => ExprKind::Number(value),
+            TokenKind::Symbol("(") => {
+                let expr = self.expression()?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            TokenKind::Name(name) if !KEYWORDS.contains(&name.as_str()) => {
+                if self.is_symbol("(") {
+                    self.next();
+                    let mut args = Vec::new();
+                    while !self.is_symbol(")") {
+                        args.push(self.expression()?);
+                        if !self.is_symbol(")") {
+                            self.expect(",")?;
+                        }
+                    }
+                    self.expect(")")?;
+                    ExprKind::Call(name, args)
+                } else {
+                    ExprKind::Name(name)
+                }
+            }
+            _ => {
+                return Err(position.error(format!(
+                    "expected an expression but found {}",
+                    Self::describe(&token)
+                )))
+            }
+        };
+        Ok(Expr { kind, position })
+    }
+}
+
+const KEYWORDS: [&str; 11] = [
+    "fn", "var", "const", "sprite", "if", "else", "while", "loop", "break", "continue", "return",
+];
+
+// 組み込み関数と、値を返すかどうか
+const BUILTINS: [(&str, usize, bool); 9] = [
+    ("clear", 0, false),
+    ("draw", 3, true),
+    ("draw_digit", 3, true),
+    ("key", 1, true),
+    ("wait_key", 0, true),
+    ("random", 1, true),
+    ("delay", 0, true),
+    ("set_delay", 1, false),
+    ("sound", 1, false),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Label {
+    Function(usize),
+    Sprite(usize),
+    Local(usize),
+}
+
+// 関数ごとの情報
+struct FunctionInfo {
+    params: usize,
+    returns_value: bool,
+    base: u8,       // 最初の引数・ローカル変数のレジスタ
+    frame_size: u8, // 引数・ローカル変数・一時変数に使うレジスタの数
+}
+
+struct Compiler<'a> {
+    program: &'a Program,
+    constants: HashMap<String, i64>,
+    sprites: HashMap<String, usize>,
+    globals: HashMap<String, u8>,
+    functions: HashMap<String, usize>,
+    info: Vec<FunctionInfo>,
+}
+
+// コード片（ラベルの位置は後で決まる）
+#[derive(Default)]
+struct Code {
+    bytes: Vec<u8>,
+    labels: HashMap<Label, usize>, // コード片の先頭からのオフセット
+    fixups: Vec<(usize, Label)>,   // nnn を書き換える位置
+}
+
+impl<'a> Compiler<'a> {
+    fn new(program: &'a Program) -> Result<Self, CompileError> {
+        let mut compiler = Compiler {
+            program,
+            constants: HashMap::new(),
+            sprites: HashMap::new(),
+            globals: HashMap::new(),
+            functions: HashMap::new(),
+            info: Vec::new(),
+        };
+
+        let defined = |name: &str, position: Position, compiler: &Compiler| {
+            let taken = compiler.constants.contains_key(name)
+                || compiler.sprites.contains_key(name)
+                || compiler.globals.contains_key(name)
+                || compiler.functions.contains_key(name)
+                || BUILTINS.iter().any(|(builtin, ..)| *builtin == name);
+            match taken {
+                true => Err(position.error(format!("'{}' is already defined", name))),
+                false => Ok(()),
+            }
+        };
+
+        for (name, value) in &program.constants {
+            defined(name, value.position, &compiler)?;
+            let value = compiler.constant(value).ok_or_else(|| {
+                value
+                    .position
+                    .error("constant value must be known at compile time")
+            })?;
+            compiler.constants.insert(name.clone(), value);
+        }
+        for (index, (name, _, position)) in program.sprites.iter().enumerate() {
+            defined(name, *position, &compiler)?;
+            compiler.sprites.insert(name.clone(), index);
+        }
+        for (name, _, position) in &program.globals {
+            defined(name, *position, &compiler)?;
+            if compiler.globals.len() == REGISTER_COUNT {
+                return Err(position.error("too many global variables"));
+            }
+            compiler
+                .globals
+                .insert(name.clone(), compiler.globals.len() as u8);
+        }
+        for (index, function) in program.functions.iter().enumerate() {
+            defined(&function.name, function.position, &compiler)?;
+            compiler.functions.insert(function.name.clone(), index);
+            compiler.info.push(FunctionInfo {
+                params: function.params.len(),
+                returns_value: returns_value(&function.body),
+                base: 0,
+                frame_size: 0,
+            });
+        }
+        Ok(compiler)
+    }
+
+    // コンパイル時に値が決まる式
+    fn constant(&self, expr: &Expr) -> Option<i64> {
+        use BinaryOp::*;
+        Some(match &expr.kind {
+            ExprKind::Number(value) => *value,
+            ExprKind::Name(name) => *self.constants.get(name)?,
+            ExprKind::Unary(UnaryOp::Neg, operand) => -self.constant(operand)?,
+            ExprKind::Unary(UnaryOp::BitNot, operand) => !self.constant(operand)? & 0xFF,
+            ExprKind::Unary(UnaryOp::Not, operand) => (self.constant(operand)? & 0xFF == 0) as i64,
+            ExprKind::Binary(op, left, right) => {
+                let (a, b) = (self.constant(left)?, self.constant(right)?);
+                match op {
+                    Add => a + b,
+                    Sub => a - b,
+                    Mul => a * b,
+                    Div => a.checked_div(b)?,
+                    Rem => a.checked_rem(b)?,
+                    And => a & b,
+                    Or => a | b,
+                    Xor => a ^ b,
+                    Shl => a << b.clamp(0, 8),
+                    Shr => (a & 0xFF) >> b.clamp(0, 8),
+                    Eq => (a & 0xFF == b & 0xFF) as i64,
+                    Ne => (a & 0xFF != b & 0xFF) as i64,
+                    Lt => ((a & 0xFF) < (b & 0xFF)) as i64,
+                    Gt => ((a & 0xFF) > (b & 0xFF)) as i64,
+                    Le => ((a & 0xFF) <= (b & 0xFF)) as i64,
+                    Ge => ((a & 0xFF) >= (b & 0xFF)) as i64,
+                    LogicalAnd => (a & 0xFF != 0 && b & 0xFF != 0) as i64,
+                    LogicalOr => (a & 0xFF != 0 || b & 0xFF != 0) as i64,
+                }
+            }
+            ExprKind::Call(..) => return None,
+        })
+    }
+
+    fn compile(mut self) -> Result<Vec<u8>, CompileError> {
+        let Some(&main) = self.functions.get("main") else {
+            return Err(Position { line: 1, column: 1 }.error("missing 'fn main()'"));
+        };
+        let main_function = &self.program.functions[main];
+        if !main_function.params.is_empty() {
+            return Err(main_function
+                .position
+                .error("'main' must not take parameters"));
+        }
+
+        // 1. 各関数をレジスタ0から割り当てて、使うレジスタの数と呼び出し先を調べる
+        let mut calls = Vec::new();
+        for index in 0..self.program.functions.len() {
+            let mut generator = Generator::new(&self, Some(index));
+            generator.function()?;
+            let (frame_size, callees) = (generator.frame_size(), generator.calls);
+            self.info[index].frame_size = frame_size;
+            calls.push(callees);
+        }
+
+        // 2. 呼び出し元のレジスタと重ならないように、呼び出し先のレジスタを後ろにずらす
+        let order = self.call_order(&calls)?;
+        let globals = self.globals.len() as u8;
+        for &index in &order {
+            self.info[index].base = self.info[index].base.max(globals);
+            let end = self.info[index].base + self.info[index].frame_size;
+            if end as usize > REGISTER_COUNT {
+                let function = &self.program.functions[index];
+                return Err(function.position.error(format!(
+                    "'{}' needs {} registers but only {} are free (V0-VE)",
+                    function.name,
+                    self.info[index].frame_size,
+                    REGISTER_COUNT.saturating_sub(self.info[index].base as usize)
+                )));
+            }
+            for &(callee, _) in &calls[index] {
+                self.info[callee].base = self.info[callee].base.max(end);
+            }
+        }
+
+        // 3. 起動処理（グローバル変数の初期化と main の呼び出し）、関数、スプライトの順に並べる
+        let mut startup = Generator::new(&self, None);
+        startup.startup(main)?;
+        let mut pieces = vec![(None, startup.code)];
+        for index in 0..self.program.functions.len() {
+            let mut generator = Generator::new(&self, Some(index));
+            generator.function()?;
+            pieces.push((Some(Label::Function(index)), generator.code));
+        }
+
+        let mut rom = Vec::new();
+        let mut addresses: HashMap<Label, usize> = HashMap::new();
+        let mut fixups = Vec::new();
+        for (label, code) in pieces {
+            let offset = rom.len();
+            if let Some(label) = label {
+                addresses.insert(label, PROGRAM_START + offset);
+            }
+            for (label, at) in code.labels {
+                addresses.insert(label, PROGRAM_START + offset + at);
+            }
+            fixups.extend(
+                code.fixups
+                    .into_iter()
+                    .map(|(at, label)| (offset + at, label)),
+            );
+            rom.extend(code.bytes);
+        }
+        for (index, (_, bytes, _)) in self.program.sprites.iter().enumerate() {
+            addresses.insert(Label::Sprite(index), PROGRAM_START + rom.len());
+            rom.extend(bytes);
+        }
+        if PROGRAM_START + rom.len() > MEMORY_SIZE {
+            return Err(Position { line: 1, column: 1 }.error(format!(
+                "program is {} bytes but only {} fit in memory",
+                rom.len(),
+                MEMORY_SIZE - PROGRAM_START
+            )));
+        }
+        for (at, label) in fixups {
+            let address = addresses[&label] as u16;
+            rom[at] = (rom[at] & 0xF0) | (address >> 8) as u8;
+            rom[at + 1] = address as u8;
+        }
+        Ok(rom)
+    }
+
+    // 呼び出し元が先に来る順番。再帰呼び出しはレジスタを割り当てられないのでエラー
+    fn call_order(&self, calls: &[Vec<(usize, Position)>]) -> Result<Vec<usize>, CompileError> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            New,
+            Active,
+            Done,
+        }
+        fn visit(
+            index: usize,
+            calls: &[Vec<(usize, Position)>],
+            marks: &mut [Mark],
+            order: &mut Vec<usize>,
+            names: &[Function],
+        ) -> Result<(), CompileError> {
+            marks[index] = Mark::Active;
+            for &(callee, position) in &calls[index] {
+                match marks[callee] {
+                    Mark::Active => {
+                        return Err(position.error(format!(
+                            "recursive call to '{}' is not supported",
+                            names[callee].name
+                        )))
+                    }
+                    Mark::New => visit(callee, calls, marks, order, names)?,
+                    Mark::Done => {}
+                }
+            }
+            marks[index] = Mark::Done;
+            order.push(index);
+            Ok(())
+        }
+
+        let mut marks = vec![Mark::New; calls.len()];
+        let mut order = Vec::new();
+        for index in 0..calls.len() {
+            if marks[index] == Mark::New {
+                visit(
+                    index,
+                    calls,
+                    &mut marks,
+                    &mut order,
+                    &self.program.functions,
+                )?;
+            }
+        }
+        order.reverse();
+        Ok(order)
+    }
+}
+
+fn returns_value(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Return(value, _) => value.is_some(),
+        Stmt::If(_, then, otherwise) => returns_value(then) || returns_value(otherwise),
+        Stmt::While(_, body) | Stmt::Loop(body) => returns_value(body),
+        _ => false,
+    })
+}
+
+// 条件が成り立つときに飛ばす命令と、成り立たないときに飛ばす命令
+struct Test {
+    when_true: Instruction,
+    when_false: Instruction,
+}
+
+// 1つの関数（または起動処理）のコード生成
+struct Generator<'a> {
+    compiler: &'a Compiler<'a>,
+    function: Option<usize>,
+    code: Code,
+    scopes: Vec<HashMap<String, u8>>,
+    next_register: u8,
+    max_register: u8,
+    base: u8,
+    loops: Vec<(Label, Label)>, // (continue, break)
+    calls: Vec<(usize, Position)>,
+    next_label: usize,
+}
+
+impl<'a> Generator<'a> {
+    fn new(compiler: &'a Compiler<'a>, function: Option<usize>) -> Self {
+        let base = match function {
+            Some(index) => compiler.info[index].base,
+            None => compiler.globals.len() as u8,
+        };
+        Generator {
+            compiler,
+            function,
+            code: Code::default(),
+            scopes: vec![HashMap::new()],
+            next_register: base,
+            max_register: base,
+            base,
+            loops: Vec::new(),
+            calls: Vec::new(),
+            // ラベル番号は関数ごとに別の範囲を使う
+            next_label: function.map_or(0, |index| (index + 1) << 16),
+        }
+    }
+
+    fn frame_size(&self) -> u8 {
+        self.max_register - self.base
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.code.bytes.extend(instruction.to_bytes());
+    }
+
+    fn emit_to(&mut self, instruction: Instruction, label: Label) {
+        self.code.fixups.push((self.code.bytes.len(), label));
+        self.emit(instruction);
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.next_label += 1;
+        Label::Local(self.next_label)
+    }
+
+    fn place(&mut self, label: Label) {
+        self.code.labels.insert(label, self.code.bytes.len());
+    }
+
+    // 一時変数やローカル変数のレジスタを確保する
+    // 上限はフレームの大きさを測ってから Compiler::compile で調べる
+    fn allocate(&mut self, position: Position) -> Result<u8, CompileError> {
+        let register = self.next_register;
+        if register == u8::MAX {
+            return Err(position.error("expression is too complex"));
+        }
+        self.next_register += 1;
+        self.max_register = self.max_register.max(self.next_register);
+        Ok(register)
+    }
+
+    fn variable(&self, name: &str) -> Option<u8> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+            .or_else(|| self.compiler.globals.get(name).copied())
+    }
+
+    fn startup(&mut self, main: usize) -> Result<(), CompileError> {
+        for (name, value, _) in &self.compiler.program.globals {
+            let register = self.compiler.globals[name];
+            match value {
+                Some(value) => {
+                    if let Some(position) = find_call(value) {
+                        return Err(position.error("global initializers cannot call functions"));
+                    }
+                    self.expr(value, register)?;
+                }
+                None => self.emit(Instruction::LdByte(register, 0)),
+            }
+        }
+        if self.max_register as usize > REGISTER_COUNT {
+            let position = self.compiler.program.globals[0].2;
+            return Err(position.error("global initializers need too many registers"));
+        }
+        self.emit_to(Instruction::Call(0), Label::Function(main));
+        // main から戻ったら停止する
+        let halt = self.new_label();
+        self.place(halt);
+        self.emit_to(Instruction::Jp(0), halt);
+        Ok(())
+    }
+
+    fn function(&mut self) -> Result<(), CompileError> {
+        let function = &self.compiler.program.functions[self.function.unwrap()];
+        for param in &function.params {
+            let register = self.allocate(function.position)?;
+            self.scopes[0].insert(param.clone(), register);
+        }
+        // 戻り値を入れるレジスタもフレームに含める
+        if self.compiler.info[self.function.unwrap()].returns_value {
+            self.max_register = self.max_register.max(self.base + 1);
+        }
+        self.block(&function.body)?;
+        self.emit(Instruction::Ret);
+        Ok(())
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), CompileError> {
+        let saved = self.next_register;
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.statement(stmt)?;
+        }
+        self.scopes.pop();
+        self.next_register = saved;
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        use Instruction::*;
+        // 文の中で使った一時変数は文の終わりで解放する
+        let saved = self.next_register;
+        match stmt {
+            Stmt::Var(name, value, position) => {
+                let register = self.allocate(*position)?;
+                match value {
+                    Some(value) => self.expr(value, register)?,
+                    None => self.emit(LdByte(register, 0)),
+                }
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert(name.clone(), register);
+                self.next_register = register + 1;
+                return Ok(());
+            }
+            Stmt::Assign(name, op, value, position) => {
+                let Some(register) = self.variable(name) else {
+                    return Err(position.error(self.unknown_variable(name)));
+                };
+                match op {
+                    // x op= e は x = x op e として、x を最初に読むので直接計算できる
+                    Some(op) => {
+                        let target = Expr {
+                            kind: ExprKind::Name(name.clone()),
+                            position: *position,
+                        };
+                        let expr = Expr {
+                            kind: ExprKind::Binary(*op, Box::new(target), Box::new(value.clone())),
+                            position: *position,
+                        };
+                        self.expr(&expr, register)?;
+                    }
+                    // 途中で自分自身を書き換えてしまう場合は一時変数で計算してから代入する
+                    None if self.clobbers(value, register)
+                        && self.compiler.constant(value).is_none() =>
+                    {
+                        let temp = self.allocate(*position)?;
+                        self.expr(value, temp)?;
+                        self.emit(LdXy(register, temp));
+                    }
+                    None => self.expr(value, register)?,
+                }
+            }
+            Stmt::If(condition, then, otherwise) => {
+                let else_label = self.new_label();
+                self.branch(condition, false, else_label)?;
+                self.next_register = saved;
+                self.block(then)?;
+                if otherwise.is_empty() {
+                    self.place(else_label);
+                } else {
+                    let end = self.new_label();
+                    self.emit_to(Jp(0), end);
+                    self.place(else_label);
+                    self.block(otherwise)?;
+                    self.place(end);
+                }
+            }
+            Stmt::While(condition, body) => {
+                let (top, end) = (self.new_label(), self.new_label());
+                self.place(top);
+                self.branch(condition, false, end)?;
+                self.next_register = saved;
+                self.loops.push((top, end));
+                self.block(body)?;
+                self.loops.pop();
+                self.emit_to(Jp(0), top);
+                self.place(end);
+            }
+            Stmt::Loop(body) => {
+                let (top, end) = (self.new_label(), self.new_label());
+                self.place(top);
+                self.loops.push((top, end));
+                self.block(body)?;
+                self.loops.pop();
+                self.emit_to(Jp(0), top);
+                self.place(end);
+            }
+            Stmt::Break(position) | Stmt::Continue(position) => {
+                let Some(&(top, end)) = self.loops.last() else {
+                    return Err(position.error("'break' or 'continue' outside of a loop"));
+                };
+                let target = if matches!(stmt, Stmt::Break(_)) {
+                    end
+                } else {
+                    top
+                };
+                self.emit_to(Jp(0), target);
+            }
+            Stmt::Return(value, position) => {
+                if let Some(value) = value {
+                    // 戻り値は関数の最初のレジスタに入れる
+                    if self.clobbers(value, self.base) {
+                        let temp = self.allocate(*position)?;
+                        self.expr(value, temp)?;
+                        self.emit(LdXy(self.base, temp));
+                    } else {
+                        self.expr(value, self.base)?;
+                    }
+                }
+                self.emit(Ret);
+            }
+            Stmt::Expr(expr) => match &expr.kind {
+                ExprKind::Call(name, args) => self.call(name, args, None, expr.position)?,
+                _ => return Err(expr.position.error("expression result is unused")),
+            },
+        }
+        self.next_register = saved;
+        Ok(())
+    }
+
+    // 式がレジスタの変数を読むか
+    fn reads(&self, expr: &Expr, register: u8) -> bool {
+        match &expr.kind {
+            ExprKind::Number(_) => false,
+            ExprKind::Name(name) => self.variable(name) == Some(register),
+            ExprKind::Unary(_, operand) => self.reads(operand, register),
+            ExprKind::Binary(_, left, right) => {
+                self.reads(left, register) || self.reads(right, register)
+            }
+            ExprKind::Call(_, args) => args.iter().any(|arg| self.reads(arg, register)),
+        }
+    }
+
+    // 式を直接 register に計算すると、register の変数を読む前に書き換えてしまうか
+    fn clobbers(&self, expr: &Expr, register: u8) -> bool {
+        match &expr.kind {
+            ExprKind::Unary(UnaryOp::Neg | UnaryOp::BitNot, operand) => {
+                self.clobbers(operand, register)
+            }
+            ExprKind::Binary(op, left, right) if !op.is_condition() => {
+                let unchanged = matches!(&left.kind, ExprKind::Name(name) if self.variable(name) == Some(register));
+                self.clobbers(left, register) || (!unchanged && self.reads(right, register))
+            }
+            // 条件式と呼び出しは最後に書き込む
+            _ => false,
+        }
+    }
+
+    fn unknown_variable(&self, name: &str) -> String {
+        if self.compiler.constants.contains_key(name) || self.compiler.sprites.contains_key(name) {
+            format!("cannot assign to '{}'", name)
+        } else {
+            format!("unknown variable '{}'", name)
+        }
+    }
+
+    // 式の値をレジスタ target に計算する
+    fn expr(&mut self, expr: &Expr, target: u8) -> Result<(), CompileError> {
+        use Instruction::*;
+
+        if let Some(value) = self.compiler.constant(expr) {
+            self.emit(LdByte(target, byte(value, expr.position)?));
+            return Ok(());
+        }
+        // 式の中で使った一時変数は計算が終われば解放する
+        let (position, saved) = (expr.position, self.next_register);
+        match &expr.kind {
+            ExprKind::Number(_) => unreachable!(),
+            ExprKind::Name(name) => {
+                let Some(register) = self.variable(name) else {
+                    return Err(position.error(format!("unknown variable '{}'", name)));
+                };
+                if register != target {
+                    self.emit(LdXy(target, register));
+                }
+            }
+            ExprKind::Unary(UnaryOp::Neg, operand) => {
+                // 0 - x
+                self.expr(operand, target)?;
+                let zero = self.allocate(position)?;
+                self.emit(LdByte(zero, 0));
+                self.emit(SubnXy(target, zero));
+            }
+            ExprKind::Unary(UnaryOp::BitNot, operand) => {
+                self.expr(operand, target)?;
+                let mask = self.allocate(position)?;
+                self.emit(LdByte(mask, 0xFF));
+                self.emit(XorXy(target, mask));
+            }
+            ExprKind::Unary(UnaryOp::Not, _) => self.boolean(expr, target)?,
+            ExprKind::Binary(op, ..) if op.is_condition() => self.boolean(expr, target)?,
+            ExprKind::Binary(op, left, right) => {
+                self.arithmetic(*op, left, right, target, position)?
+            }
+            ExprKind::Call(name, args) => self.call(name, args, Some(target), position)?,
+        }
+        self.next_register = saved;
+        Ok(())
+    }
+
+    // 条件式の値を 0 / 1 にする
+    fn boolean(&mut self, expr: &Expr, target: u8) -> Result<(), CompileError> {
+        let (otherwise, end) = (self.new_label(), self.new_label());
+        self.branch(expr, false, otherwise)?;
+        self.emit(Instruction::LdByte(target, 1));
+        self.emit_to(Instruction::Jp(0), end);
+        self.place(otherwise);
+        self.emit(Instruction::LdByte(target, 0));
+        self.place(end);
+        Ok(())
+    }
+
+    fn arithmetic(
+        &mut self,
+        op: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+        target: u8,
+        position: Position,
+    ) -> Result<(), CompileError> {
+        use Instruction::*;
+
+        self.expr(left, target)?;
+        let constant = self.compiler.constant(right);
+
+        // 2の累乗との乗除算はシフトにする
+        let power = |value: i64| {
+            (value > 0 && value & (value - 1) == 0).then(|| value.trailing_zeros() as i64)
+        };
+        let (op, constant) = match (op, constant) {
+            (BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem, Some(value)) => {
+                match (op, power(value)) {
+                    (BinaryOp::Mul, Some(shift)) => (BinaryOp::Shl, Some(shift)),
+                    (BinaryOp::Div, Some(shift)) => (BinaryOp::Shr, Some(shift)),
+                    (BinaryOp::Rem, Some(_)) => (BinaryOp::And, Some(value - 1)),
+                    _ => {
+                        return Err(position.error(
+                            "only multiplication and division by powers of two are supported",
+                        ))
+                    }
+                }
+            }
+            (BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem, None) => {
+                return Err(
+                    position.error("only multiplication and division by constants are supported")
+                )
+            }
+            _ => (op, constant),
+        };
+
+        match (op, constant) {
+            // 7xkk はVFを変えない
+            (BinaryOp::Add, Some(value)) => self.emit(AddByte(target, byte(value, position)?)),
+            (BinaryOp::Sub, Some(value)) => {
+                self.emit(AddByte(target, byte(value, position)?.wrapping_neg()))
+            }
+            (BinaryOp::Shl | BinaryOp::Shr, Some(amount)) => {
+                if amount >= 8 {
+                    self.emit(LdByte(target, 0));
+                } else {
+                    // VXとVYを同じにすると、シフトのQuirksに関係なく同じ結果になる
+                    for _ in 0..amount.max(0) {
+                        self.emit(match op {
+                            BinaryOp::Shl => ShlXy(target, target),
+                            _ => ShrXy(target, target),
+                        });
+                    }
+                }
+            }
+            (BinaryOp::Shl | BinaryOp::Shr, None) => {
+                return Err(position.error("shift amount must be a constant"))
+            }
+            _ => {
+                let operand = match constant {
+                    Some(value) => {
+                        let operand = self.allocate(position)?;
+                        self.emit(LdByte(operand, byte(value, position)?));
+                        operand
+                    }
+                    None => self.operand(right)?,
+                };
+                self.emit(match op {
+                    BinaryOp::Add => AddXy(target, operand),
+                    BinaryOp::Sub => SubXy(target, operand),
+                    BinaryOp::And => AndXy(target, operand),
+                    BinaryOp::Or => OrXy(target, operand),
+                    BinaryOp::Xor => XorXy(target, operand),
+                    _ => unreachable!(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // 変数ならそのレジスタ、それ以外は一時変数に計算したレジスタ
+    fn operand(&mut self, expr: &Expr) -> Result<u8, CompileError> {
+        if let ExprKind::Name(name) = &expr.kind {
+            if let Some(register) = self.variable(name) {
+                return Ok(register);
+            }
+        }
+        let register = self.allocate(expr.position)?;
+        self.expr(expr, register)?;
+        Ok(register)
+    }
+
+    // 条件が jump_if のときに target へジャンプする
+    fn branch(&mut self, expr: &Expr, jump_if: bool, target: Label) -> Result<(), CompileError> {
+        use BinaryOp::*;
+
+        if let Some(value) = self.compiler.constant(expr) {
+            if (value & 0xFF != 0) == jump_if {
+                self.emit_to(Instruction::Jp(0), target);
+            }
+            return Ok(());
+        }
+        let saved = self.next_register;
+        match &expr.kind {
+            ExprKind::Unary(UnaryOp::Not, operand) => self.branch(operand, !jump_if, target)?,
+            ExprKind::Binary(op @ (LogicalAnd | LogicalOr), left, right) => {
+                // && で偽へ、|| で真へ飛ぶ場合は両辺とも同じ飛び先
+                if (*op == LogicalAnd) != jump_if {
+                    self.branch(left, jump_if, target)?;
+                    self.branch(right, jump_if, target)?;
+                } else {
+                    let skip = self.new_label();
+                    self.branch(left, !jump_if, skip)?;
+                    self.branch(right, jump_if, target)?;
+                    self.place(skip);
+                }
+            }
+            _ => {
+                let test = self.test(expr)?;
+                self.emit(if jump_if {
+                    test.when_false
+                } else {
+                    test.when_true
+                });
+                self.emit_to(Instruction::Jp(0), target);
+            }
+        }
+        self.next_register = saved;
+        Ok(())
+    }
+
+    fn test(&mut self, expr: &Expr) -> Result<Test, CompileError> {
+        use BinaryOp::*;
+        use Instruction::*;
+
+        let test = |when_true, when_false| Test {
+            when_true,
+            when_false,
+        };
+        match &expr.kind {
+            ExprKind::Binary(op @ (Eq | Ne), left, right) => {
+                let x = self.operand(left)?;
+                let (equal, not_equal) = match self.compiler.constant(right) {
+                    Some(value) => {
+                        let value = byte(value, right.position)?;
+                        (SeByte(x, value), SneByte(x, value))
+                    }
+                    None => {
+                        let y = self.operand(right)?;
+                        (SeXy(x, y), SneXy(x, y))
+                    }
+                };
+                Ok(match op {
+                    Eq => test(equal, not_equal),
+                    _ => test(not_equal, equal),
+                })
+            }
+            ExprKind::Binary(op @ (Lt | Ge | Gt | Le), left, right) => {
+                // 引き算の結果は捨てるので左辺は一時変数に計算する
+                let x = self.allocate(expr.position)?;
+                self.expr(left, x)?;
+                let y = self.operand(right)?;
+                // 8xy5: VF = (x >= y)、8xy7: VF = (y >= x)。VFは演算の直後に読む
+                self.emit(match op {
+                    Lt | Ge => SubXy(x, y),
+                    _ => SubnXy(x, y),
+                });
+                let flag_when_true = match op {
+                    Lt | Gt => 0,
+                    _ => 1,
+                };
+                Ok(test(
+                    SeByte(FLAG, flag_when_true),
+                    SneByte(FLAG, flag_when_true),
+                ))
+            }
+            ExprKind::Call(name, args) if name == "key" => {
+                check_arity(name, args, 1, expr.position)?;
+                let x = self.operand(&args[0])?;
+                Ok(test(Skp(x), Sknp(x)))
+            }
+            _ => {
+                let x = self.operand(expr)?;
+                Ok(test(SneByte(x, 0), SeByte(x, 0)))
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        target: Option<u8>,
+        position: Position,
+    ) -> Result<(), CompileError> {
+        use Instruction::*;
+
+        if let Some(&(_, arity, returns)) = BUILTINS.iter().find(|(builtin, ..)| *builtin == name) {
+            check_arity(name, args, arity, position)?;
+            if target.is_some() && !returns {
+                return Err(position.error(format!("'{}' does not return a value", name)));
+            }
+            return self.builtin(name, args, target, position);
+        }
+
+        let Some(&index) = self.compiler.functions.get(name) else {
+            return Err(position.error(format!("unknown function '{}'", name)));
+        };
+        let info = &self.compiler.info[index];
+        check_arity(name, args, info.params, position)?;
+        if target.is_some() && !info.returns_value {
+            return Err(position.error(format!("'{}' does not return a value", name)));
+        }
+        self.calls.push((index, position));
+
+        // 引数をすべて計算してから呼び出し先のレジスタに移す（引数の中の呼び出しで壊されないように）
+        let base = info.base;
+        let mut values = Vec::new();
+        for arg in args {
+            let register = self.allocate(arg.position)?;
+            self.expr(arg, register)?;
+            values.push(register);
+        }
+        for (i, value) in values.into_iter().enumerate() {
+            self.emit(LdXy(base + i as u8, value));
+        }
+        self.emit_to(Call(0), Label::Function(index));
+        if let Some(target) = target {
+            self.emit(LdXy(target, base));
+        }
+        Ok(())
+    }
+
+    fn builtin(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        target: Option<u8>,
+        position: Position,
+    ) -> Result<(), CompileError> {
+        use Instruction::*;
+
+        match name {
+            "clear" => self.emit(Cls),
+            "draw" | "draw_digit" => {
+                let rows = if name == "draw" {
+                    let sprite = match &args[0].kind {
+                        ExprKind::Name(sprite) => self.compiler.sprites.get(sprite).copied(),
+                        _ => None,
+                    };
+                    let Some(sprite) = sprite else {
+                        return Err(args[0].position.error("expected a sprite name"));
+                    };
+                    self.emit_to(LdI(0), Label::Sprite(sprite));
+                    self.compiler.program.sprites[sprite].1.len() as u8
+                } else {
+                    let digit = self.operand(&args[0])?;
+                    self.emit(LdFVx(digit));
+                    5
+                };
+                let x = self.operand(&args[1])?;
+                let y = self.operand(&args[2])?;
+                self.emit(Drw(x, y, rows));
+                // 衝突フラグ（VF）は描画の直後に読む
+                if let Some(target) = target {
+                    self.emit(LdXy(target, FLAG));
+                }
+            }
+            "key" => {
+                if let Some(target) = target {
+                    let expr = Expr {
+                        kind: ExprKind::Call(name.to_string(), args.to_vec()),
+                        position,
+                    };
+                    self.boolean(&expr, target)?;
+                }
+            }
+            "wait_key" => {
+                let register = target.map_or_else(|| self.allocate(position), Ok)?;
+                self.emit(LdVxK(register));
+            }
+            "random" => {
+                let Some(mask) = self.compiler.constant(&args[0]) else {
+                    return Err(args[0].position.error("random mask must be a constant"));
+                };
+                let mask = byte(mask, args[0].position)?;
+                if let Some(target) = target {
+                    self.emit(Rnd(target, mask));
+                }
+            }
+            "delay" => {
+                if let Some(target) = target {
+                    self.emit(LdVxDt(target));
+                }
+            }
+            "set_delay" => {
+                let value = self.operand(&args[0])?;
+                self.emit(LdDtVx(value));
+            }
+            "sound" => {
+                let value = self.operand(&args[0])?;
+                self.emit(LdStVx(value));
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+fn check_arity(
+    name: &str,
+    args: &[Expr],
+    arity: usize,
+    position: Position,
+) -> Result<(), CompileError> {
+    if args.len() != arity {
+        return Err(position.error(format!(
+            "'{}' takes {} argument(s) but {} were given",
+            name,
+            arity,
+            args.len()
+        )));
+    }
+    Ok(())
+}
+
+fn find_call(expr: &Expr) -> Option<Position> {
+    match &expr.kind {
+        ExprKind::Call(name, args) => {
+            if BUILTINS.iter().any(|(builtin, ..)| builtin == name) {
+                args.iter().find_map(find_call)
+            } else {
+                Some(expr.position)
+            }
+        }
+        ExprKind::Unary(_, operand) => find_call(operand),
+        ExprKind::Binary(_, left, right) => find_call(left).or_else(|| find_call(right)),
+        _ => None,
+    }
+}
+
+// -128 から 255 までを1バイトにする
+fn byte(value: i64, position: Position) -> Result<u8, CompileError> {
+    if (-128..=255).contains(&value) {
+        Ok(value as u8)
+    } else {
+        Err(position.error(format!("value {} does not fit in a byte", value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Cpu;
+    use crate::keyboard::NoKeyboard;
+
+    // コンパイルして実行し、グローバル変数（V0から順に割り当てられる）を返す
+    fn run(source: &str, globals: usize) -> Vec<u8> {
+        let rom = compile(source).unwrap_or_else(|e| panic!("{}", e));
+        let mut cpu = Cpu::from_bytes(&rom, NoKeyboard);
+        for step in 0..5000 {
+            cpu.update().unwrap();
+            if step % 10 == 0 {
+                cpu.decrement_timers();
+            }
+        }
+        cpu.registers()[..globals].to_vec()
+    }
+
+    fn error(source: &str) -> String {
+        compile(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_arithmetic_and_conditions() {
+        let source = "
+            const BASE = 0x10;
+            var sum = 0;
+            var flags = 0;
+            var wrapped = 250;
+            var mixed = 0;
+            fn main() {
+                var i = 1;
+                while (i <= 10) { sum += i; i += 1; }
+                wrapped += 10;
+                if (sum > 50 && !(sum == 56)) { flags |= 1; }
+                if (wrapped < 5 || sum < 3) { flags |= 2; }
+                if (sum >= 100) { flags |= 4; } else if (sum != 0) { flags |= 8; }
+                mixed = (BASE * 4 + sum / 2 - (sum % 8)) ^ ~0;
+                mixed = mixed >> 1;
+            }
+        ";
+        let sum = 55u8;
+        let mixed = ((0x40 + sum / 2 - sum % 8) ^ 0xFF) >> 1;
+        assert_eq!(run(source, 4), vec![sum, 1 | 2 | 8, 4, mixed]);
+    }
+
+    #[test]
+    fn test_functions_and_register_frames() {
+        let source = "
+            var result = 0;
+            var count = 0;
+            fn add3(a, b, c) { return a + b + c; }
+            fn double(x) { count += 1; return add3(x, x, 0); }
+            fn fib(n) {
+                var a = 0;
+                var b = 1;
+                loop {
+                    if (n == 0) { break; }
+                    var next = a + b;
+                    a = b;
+                    b = next;
+                    n -= 1;
+                }
+                return a;
+            }
+            fn main() {
+                result = add3(double(3), fib(10), double(fib(5)) - 1);
+            }
+        ";
+        assert_eq!(run(source, 2), vec![6 + 55 + 10 - 1, 2]);
+    }
+
+    #[test]
+    fn test_vf_is_never_allocated() {
+        // VFを書き換える加算・比較・描画が続いても、変数の値は壊れない
+        let source = "
+            sprite dot = [0x80];
+            var a = 200;
+            var b = 100;
+            var hit = 0;
+            var carry = 0;
+            fn main() {
+                var c = a + b;
+                if (c < a) { carry = 1; }
+                draw(dot, 0, 0);
+                hit = draw(dot, 0, 0);
+                b = a - b - c;
+            }
+        ";
+        assert_eq!(run(source, 4), vec![200, 56, 1, 1]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error("fn main() { x = 1; }"), "1:13: unknown variable 'x'");
+        assert_eq!(
+            error("fn f() { g(); }\nfn g() { f(); }\nfn main() { f(); }"),
+            "2:10: recursive call to 'f' is not supported"
+        );
+        assert_eq!(
+            error("fn main() { var a = 1; a = a * 3; }"),
+            "1:30: only multiplication and division by powers of two are supported"
+        );
+        let locals: String = (0..16).map(|i| format!("var v{} = {};", i, i)).collect();
+        assert!(error(&format!("fn main() {{ {} }}", locals)).contains("'main' needs 16 registers"));
+        assert_eq!(error("fn start() {}"), "1:1: missing 'fn main()'");
+    }
+}
@@ -0,0 +1,1222 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::assembler::{analyze, SourceInstruction, SymbolKind};
+use crate::chip8::Cpu;
+use crate::debugger::{Condition, Debugger, Register, StopReason};
+use crate::expression::{Expression, LogMessage};
+use crate::instruction::Instruction;
+use crate::journal;
+use crate::json::Json;
+use crate::keyboard::NoKeyboard;
+use crate::lsp::{read_message, write_message};
+use crate::platform::Platform;
+use crate::rng::{Rng, RngAlgorithm};
+
+// エディタから使うためのDebug Adapter Protocolのサーバ
+//
+// - launch: ROM（.ch8）かOcto形式のソース（.8o）を読み込む。ソースの場合は
+//   アセンブラの出力から行とアドレスを対応させ、ソース上でブレークポイントを置ける
+// - attach: launch と同じ引数に加えて、state のセーブステートから再開する
+// - 変数: Registers（V0〜VF, I, PC, SP, DT, ST）と Display（画面の各行）
+// - readMemory / disassemble / setInstructionBreakpoints でアドレス単位のデバッグもできる
+// - ブレークポイントの condition・hitCondition は条件式（expression.rs）、
+//   logMessage はログポイントのメッセージとして扱い、出力は output イベントで送る
+//
+// スレッドは1つ（id 1）だけで、キー入力はCHIP-8に渡さない
+
+const THREAD_ID: usize = 1;
+
+// variablesReference
+const REGISTERS: usize = 1;
+const DISPLAY: usize = 2;
+
+const TIMER_FREQUENCY: u64 = 60;
+const DEFAULT_FREQUENCY: u64 = 600; // 600命令/秒
+
+// 入力を読むスレッドと、実行中に1/60秒ごとにフレームを進めるループで処理する。
+// disconnect を受け取るか入力が終わると返る
+pub fn run(input: impl BufRead + Send + 'static, mut output: impl Write) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        loop {
+            let message = read_message(&mut input);
+            let end = !matches!(message, Ok(Some(_)));
+            if sender.send(message).is_err() || end {
+                break;
+            }
+        }
+    });
+
+    let frame = Duration::from_nanos(1_000_000_000 / TIMER_FREQUENCY);
+    let mut server = Server::new();
+    let mut next_frame = Instant::now();
+    while !server.exited() {
+        let message = if server.is_running() {
+            let now = Instant::now();
+            if now >= next_frame {
+                // 遅れた分はまとめて取り戻さない
+                next_frame = (next_frame + frame).max(now);
+                for event in &server.tick() {
+                    write_message(&mut output, event)?;
+                }
+                continue;
+            }
+            match receiver.recv_timeout(next_frame - now) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            next_frame = Instant::now();
+            match receiver.recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            }
+        };
+        let Some(body) = message? else { break };
+        // DAPには解析できないメッセージへの応答が無いので読み捨てる
+        let Ok(message) = Json::parse(&body) else {
+            continue;
+        };
+        for reply in &server.handle(&message) {
+            write_message(&mut output, reply)?;
+        }
+    }
+    Ok(())
+}
+
+// アセンブルしたソースと、命令ごとの位置
+struct Source {
+    path: String,
+    listing: Vec<SourceInstruction>,
+    labels: Vec<(usize, String)>,
+}
+
+impl Source {
+    // line 以降で最初に命令がある行とそのアドレス
+    fn address_of(&self, line: usize) -> Option<&SourceInstruction> {
+        self.listing
+            .iter()
+            .filter(|entry| entry.line >= line)
+            .min_by_key(|entry| (entry.line, entry.address))
+    }
+
+    fn line_of(&self, address: usize) -> Option<&SourceInstruction> {
+        self.listing.iter().find(|entry| entry.address == address)
+    }
+
+    fn label_of(&self, address: usize) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(label, _)| *label == address)
+            .map(|(_, name)| name.as_str())
+    }
+
+    fn is(&self, path: &str) -> bool {
+        Path::new(&self.path) == Path::new(path)
+    }
+}
+
+struct Program {
+    debugger: Debugger<NoKeyboard>,
+    source: Option<Source>,
+    instructions_per_frame: usize,
+}
+
+#[derive(Default)]
+pub struct Server {
+    seq: usize,
+    program: Option<Program>,
+    stop_on_entry: bool,
+    source_breakpoints: BTreeMap<usize, Condition>,
+    instruction_breakpoints: BTreeMap<usize, Condition>,
+    exited: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.program
+            .as_ref()
+            .is_some_and(|program| !program.debugger.is_paused())
+    }
+
+    // 要求を1つ処理し、応答とその後に送るイベントを返す
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        if message.get("type").and_then(Json::as_str) != Some("request") {
+            return Vec::new();
+        }
+        let command = message.get("command").and_then(Json::as_str).unwrap_or("");
+        let arguments = message.get("arguments").unwrap_or(&Json::Null);
+        let request_seq = message.get("seq").and_then(Json::as_u64).unwrap_or(0) as usize;
+
+        let stop_on_entry = self.stop_on_entry;
+        let mut events = Vec::new();
+        let result = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" | "attach" => self.launch(arguments, command == "attach").map(|()| {
+                events.push(("initialized", Json::Null));
+                Json::Null
+            }),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+            "configurationDone" => self.program_mut().map(|program| {
+                if stop_on_entry {
+                    events.push(("stopped", stopped("entry", None)));
+                } else {
+                    program.debugger.resume();
+                }
+                Json::Null
+            }),
+            "threads" => Ok(Json::object([(
+                "threads",
+                vec![Json::object([
+                    ("id", THREAD_ID.into()),
+                    ("name", "CHIP-8".into()),
+                ])]
+                .into(),
+            )])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(scopes()),
+            "variables" => self.variables(arguments),
+            "setVariable" => self.set_variable(arguments),
+            "readMemory" => self.read_memory(arguments),
+            "disassemble" => self.disassemble(arguments),
+            "continue" => self.program_mut().map(|program| {
+                program.debugger.resume();
+                Json::object([("allThreadsContinued", true.into())])
+            }),
+            "next" => self.program_mut().map(|program| {
+                program.debugger.step_over();
+                Json::Null
+            }),
+            "stepIn" => self.program_mut().map(|program| {
+                program.debugger.step();
+                Json::Null
+            }),
+            "stepOut" => self.program_mut().map(|program| {
+                program.debugger.step_out();
+                Json::Null
+            }),
+            // 逆実行はその場で終わるので、応答の後にすぐ stopped を送る
+            "stepBack" | "reverseContinue" => self
+                .program_mut()
+                .and_then(|program| {
+                    if program.debugger.cpu().journal().is_none() {
+                        return Err("reverse debugging is disabled".to_string());
+                    }
+                    Ok(match command {
+                        "stepBack" => program.debugger.reverse_step(),
+                        _ => program.debugger.reverse_continue(),
+                    })
+                })
+                .map(|reason| {
+                    events.push(("stopped", self.stopped_event(&reason)));
+                    Json::Null
+                }),
+            "pause" => self.program_mut().map(|program| {
+                program.debugger.pause();
+                events.push(("stopped", stopped("pause", None)));
+                Json::Null
+            }),
+            "terminate" => {
+                events.push(("terminated", Json::Null));
+                self.program = None;
+                Ok(Json::Null)
+            }
+            "disconnect" => {
+                self.exited = true;
+                Ok(Json::Null)
+            }
+            _ => Err(format!("unknown command '{}'", command)),
+        };
+
+        let response = match result {
+            Ok(body) => Json::object([
+                ("seq", self.next_seq().into()),
+                ("type", "response".into()),
+                ("request_seq", request_seq.into()),
+                ("success", true.into()),
+                ("command", command.into()),
+                ("body", body),
+            ]),
+            Err(message) => Json::object([
+                ("seq", self.next_seq().into()),
+                ("type", "response".into()),
+                ("request_seq", request_seq.into()),
+                ("success", false.into()),
+                ("command", command.into()),
+                ("message", message.into()),
+            ]),
+        };
+        let mut replies = vec![response];
+        for (name, body) in events {
+            replies.push(self.event(name, body));
+        }
+        replies
+    }
+
+    // 実行中なら1フレーム分の命令を実行し、止まった場合のイベントを返す
+    pub fn tick(&mut self) -> Vec<Json> {
+        let Some(program) = self.program.as_mut() else {
+            return Vec::new();
+        };
+        if program.debugger.is_paused() {
+            return Vec::new();
+        }
+        let reason = program.debugger.run(program.instructions_per_frame);
+        if reason.is_none() {
+            program.debugger.cpu_mut().decrement_timers();
+        }
+        let logs = program.debugger.take_logs();
+        let mut events: Vec<Json> = logs
+            .into_iter()
+            .map(|line| {
+                self.event(
+                    "output",
+                    Json::object([
+                        ("category", "console".into()),
+                        ("output", format!("{}\n", line).into()),
+                    ]),
+                )
+            })
+            .collect();
+        let Some(reason) = reason else {
+            return events;
+        };
+        if reason == StopReason::Exited {
+            self.program = None;
+            events.push(self.event("exited", Json::object([("exitCode", 0usize.into())])));
+            events.push(self.event("terminated", Json::Null));
+            return events;
+        }
+        let event = self.stopped_event(&reason);
+        events.push(self.event("stopped", event));
+        events
+    }
+
+    // stopped イベントの内容。ソースの行に置いたものはブレークポイント、それ以外は命令ブレークポイント
+    fn stopped_event(&self, reason: &StopReason) -> Json {
+        let pc = self
+            .program
+            .as_ref()
+            .map_or(0, |program| program.debugger.cpu().program_counter());
+        match reason {
+            StopReason::Step => stopped("step", None),
+            StopReason::Breakpoint if self.source_breakpoints.contains_key(&pc) => {
+                stopped("breakpoint", None)
+            }
+            StopReason::Breakpoint => stopped("instruction breakpoint", None),
+            StopReason::Memory { .. } | StopReason::Register { .. } => {
+                stopped("data breakpoint", Some(reason.to_string()))
+            }
+            StopReason::StartOfHistory => stopped("step", Some(reason.to_string())),
+            StopReason::Fault(_) | StopReason::Exited => {
+                stopped("exception", Some(reason.to_string()))
+            }
+        }
+    }
+
+    fn next_seq(&mut self) -> usize {
+        self.seq += 1;
+        self.seq
+    }
+
+    fn event(&mut self, name: &str, body: Json) -> Json {
+        Json::object([
+            ("seq", self.next_seq().into()),
+            ("type", "event".into()),
+            ("event", name.into()),
+            ("body", body),
+        ])
+    }
+
+    fn program(&self) -> Result<&Program, String> {
+        self.program
+            .as_ref()
+            .ok_or_else(|| "no program is loaded".to_string())
+    }
+
+    fn program_mut(&mut self) -> Result<&mut Program, String> {
+        self.program
+            .as_mut()
+            .ok_or_else(|| "no program is loaded".to_string())
+    }
+
+    fn launch(&mut self, arguments: &Json, attach: bool) -> Result<(), String> {
+        let path = arguments
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or("missing 'program'")?;
+        let platform = match arguments.get("platform").and_then(Json::as_str) {
+            Some(name) => Some(name.parse::<Platform>().map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let (rom, source) = if path.ends_with(".8o") {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            let analysis = analyze(&text);
+            let rom = analysis.result.map_err(|e| format!("{}:{}", path, e))?;
+            let labels = analysis
+                .symbols
+                .into_iter()
+                .filter_map(|symbol| match symbol.kind {
+                    SymbolKind::Label(address) => Some((address, symbol.name)),
+                    _ => None,
+                })
+                .collect();
+            let source = Source {
+                path: path.to_string(),
+                listing: analysis.listing,
+                labels,
+            };
+            (rom, Some(source))
+        } else {
+            (
+                std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?,
+                None,
+            )
+        };
+
+        let rng = match arguments.get("seed").and_then(Json::as_u64) {
+            Some(seed) => Rng::new(RngAlgorithm::Xorshift, seed as u32),
+            None => Rng::default(),
+        };
+        let (mut cpu, frequency) = match platform {
+            Some(platform) => (
+                Cpu::from_platform_with_rng(&rom, NoKeyboard, platform, rng),
+                platform.clock_speed() as u64,
+            ),
+            None => (Cpu::with_rng(&rom, NoKeyboard, rng), DEFAULT_FREQUENCY),
+        };
+        if attach {
+            let state = arguments
+                .get("state")
+                .and_then(Json::as_str)
+                .ok_or("missing 'state'")?;
+            let data = std::fs::read(state).map_err(|e| format!("{}: {}", state, e))?;
+            cpu.load_state(&data)
+                .map_err(|e| format!("{}: {}", state, e))?;
+        }
+
+        // 逆実行のための記録は既定で有効にする
+        if arguments.get("reverseDebugging").and_then(Json::as_bool) != Some(false) {
+            cpu.set_journal(Some(journal::DEFAULT_CAPACITY));
+        }
+
+        self.stop_on_entry = arguments
+            .get("stopOnEntry")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+        self.source_breakpoints.clear();
+        self.instruction_breakpoints.clear();
+        self.program = Some(Program {
+            debugger: Debugger::new(cpu),
+            source,
+            instructions_per_frame: (frequency / TIMER_FREQUENCY).max(1) as usize,
+        });
+        Ok(())
+    }
+
+    // ソースの行は命令のある次の行にずらし、ずらした後の行を返す
+    fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments
+            .get("source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+            .unwrap_or("");
+        let source = self
+            .program
+            .as_ref()
+            .and_then(|program| program.source.as_ref())
+            .filter(|source| source.is(path));
+        let mut addresses = BTreeMap::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or(&[])
+        {
+            let line = breakpoint.get("line").and_then(Json::as_u64).unwrap_or(0) as usize;
+            let condition = condition(breakpoint);
+            let breakpoint = match (source.and_then(|source| source.address_of(line)), condition) {
+                (_, Err(message)) => Json::object([
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", message.into()),
+                ]),
+                (Some(entry), Ok(condition)) => {
+                    addresses.insert(entry.address, condition);
+                    Json::object([
+                        ("verified", true.into()),
+                        ("line", entry.line.into()),
+                        ("instructionReference", reference(entry.address).into()),
+                    ])
+                }
+                (None, _) if source.is_some() => Json::object([
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", "no instruction at or after this line".into()),
+                ]),
+                (None, _) => Json::object([
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", "source is not loaded".into()),
+                ]),
+            };
+            breakpoints.push(breakpoint);
+        }
+        self.source_breakpoints = addresses;
+        self.update_breakpoints();
+        Ok(Json::object([("breakpoints", breakpoints.into())]))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let mut addresses = BTreeMap::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or(&[])
+        {
+            let address = breakpoint
+                .get("instructionReference")
+                .and_then(Json::as_str)
+                .and_then(|text| address(text, breakpoint.get("offset")));
+            breakpoints.push(match (address, condition(breakpoint)) {
+                (_, Err(message)) => {
+                    Json::object([("verified", false.into()), ("message", message.into())])
+                }
+                (Some(address), Ok(condition)) => {
+                    addresses.insert(address, condition);
+                    Json::object([
+                        ("verified", true.into()),
+                        ("instructionReference", reference(address).into()),
+                    ])
+                }
+                (None, _) => Json::object([
+                    ("verified", false.into()),
+                    ("message", "invalid instruction reference".into()),
+                ]),
+            });
+        }
+        self.instruction_breakpoints = addresses;
+        self.update_breakpoints();
+        Ok(Json::object([("breakpoints", breakpoints.into())]))
+    }
+
+    // ソースとアドレスのブレークポイントを合わせたものを Debugger に設定する
+    // 同じアドレスにある場合はソースの方を使う。条件が変わらなければ到達した回数は引き継がれる
+    fn update_breakpoints(&mut self) {
+        let Some(program) = self.program.as_mut() else {
+            return;
+        };
+        let mut wanted = self.instruction_breakpoints.clone();
+        wanted.extend(self.source_breakpoints.clone());
+        let stale: Vec<usize> = program
+            .debugger
+            .breakpoints()
+            .map(|(address, _)| address)
+            .filter(|address| !wanted.contains_key(address))
+            .collect();
+        for address in stale {
+            program.debugger.remove_breakpoint(address);
+        }
+        for (address, condition) in wanted {
+            program.debugger.set_breakpoint(address, condition);
+        }
+    }
+
+    fn stack_trace(&self) -> Result<Json, String> {
+        let program = self.program()?;
+        let source = program.source.as_ref();
+        let frames: Vec<Json> = program
+            .debugger
+            .call_stack()
+            .iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let name = match frame.function {
+                    Some(function) => source
+                        .and_then(|source| source.label_of(function))
+                        .map_or_else(|| format!("sub_{:03X}", function), str::to_string),
+                    None => "???".to_string(),
+                };
+                let mut members = vec![
+                    ("id", id.into()),
+                    ("name", name.into()),
+                    ("line", 0usize.into()),
+                    ("column", 0usize.into()),
+                    ("instructionPointerReference", reference(frame.pc).into()),
+                ];
+                if let Some((source, entry)) =
+                    source.and_then(|source| Some((source, source.line_of(frame.pc)?)))
+                {
+                    members[2].1 = entry.line.into();
+                    members[3].1 = entry.column.into();
+                    members.push(("source", source_json(&source.path)));
+                }
+                Json::object(members)
+            })
+            .collect();
+        let total = frames.len();
+        Ok(Json::object([
+            ("stackFrames", frames.into()),
+            ("totalFrames", total.into()),
+        ]))
+    }
+
+    fn variables(&self, arguments: &Json) -> Result<Json, String> {
+        let cpu = self.program()?.debugger.cpu();
+        let reference = arguments
+            .get("variablesReference")
+            .and_then(Json::as_u64)
+            .unwrap_or(0) as usize;
+        let variables: Vec<Json> = match reference {
+            REGISTERS => {
+                let mut variables: Vec<Json> = (0..16)
+                    .map(|x| {
+                        variable(
+                            &Register::V(x).to_string(),
+                            format!("0x{:02X}", cpu.registers()[x as usize]),
+                            None,
+                        )
+                    })
+                    .collect();
+                let index = cpu.index_register() as usize;
+                let pc = cpu.program_counter();
+                variables.push(variable("I", format!("0x{:03X}", index), Some(index)));
+                variables.push(variable("PC", format!("0x{:03X}", pc), Some(pc)));
+                variables.push(variable("SP", cpu.call_stack().len().to_string(), None));
+                variables.push(variable("DT", format!("0x{:02X}", cpu.delay_timer()), None));
+                variables.push(variable("ST", format!("0x{:02X}", cpu.sound_timer()), None));
+                variables
+            }
+            DISPLAY => {
+                let display = cpu.get_display();
+                (0..display.height())
+                    .map(|y| {
+                        let row: String = (0..display.width())
+                            .map(|x| if display.pixel(x, y) != 0 { '#' } else { '.' })
+                            .collect();
+                        variable(&format!("{:02}", y), row, None)
+                    })
+                    .collect()
+            }
+            _ => return Err(format!("unknown variablesReference {}", reference)),
+        };
+        Ok(Json::object([("variables", variables.into())]))
+    }
+
+    // V0〜VF, I, PC を書き換える
+    fn set_variable(&mut self, arguments: &Json) -> Result<Json, String> {
+        let program = self.program_mut()?;
+        if arguments.get("variablesReference").and_then(Json::as_u64) != Some(REGISTERS as u64) {
+            return Err("only registers can be changed".to_string());
+        }
+        let name = arguments.get("name").and_then(Json::as_str).unwrap_or("");
+        let text = arguments.get("value").and_then(Json::as_str).unwrap_or("");
+        let value = number(text).ok_or_else(|| format!("invalid value '{}'", text))?;
+        let register = match name {
+            "PC" => None,
+            _ => Some(name.parse::<Register>().map_err(|e| e.to_string())?),
+        };
+        // 端末のデバッガの set と同じく、入りきらない値は切り詰めずにエラーにする
+        let limit = match register {
+            Some(Register::V(_)) => 0xFF,
+            _ => 0xFFFF,
+        };
+        if value > limit {
+            return Err(format!("value 0x{:X} is out of range", value));
+        }
+        let cpu = program.debugger.cpu_mut();
+        // 書き換える前の状態へは逆実行で戻れなくする
+        cpu.clear_journal();
+        let value = match register {
+            None => {
+                cpu.set_program_counter(value);
+                format!("0x{:03X}", value)
+            }
+            Some(Register::V(x)) => {
+                cpu.registers_mut()[x as usize] = value as u8;
+                format!("0x{:02X}", value)
+            }
+            Some(Register::I) => {
+                cpu.set_index_register(value as u16);
+                format!("0x{:03X}", value)
+            }
+        };
+        Ok(Json::object([("value", value.into())]))
+    }
+
+    fn read_memory(&self, arguments: &Json) -> Result<Json, String> {
+        let memory = self.program()?.debugger.cpu().memory();
+        let start = arguments
+            .get("memoryReference")
+            .and_then(Json::as_str)
+            .and_then(|text| address(text, arguments.get("offset")))
+            .ok_or("invalid memory reference")?;
+        let count = arguments.get("count").and_then(Json::as_u64).unwrap_or(0) as usize;
+        let start = start.min(memory.len());
+        let end = start.saturating_add(count).min(memory.len());
+        Ok(Json::object([
+            ("address", reference(start).into()),
+            ("data", base64(&memory[start..end]).into()),
+            ("unreadableBytes", (count - (end - start)).into()),
+        ]))
+    }
+
+    // 命令は2バイトとして instructionOffset を数える
+    fn disassemble(&self, arguments: &Json) -> Result<Json, String> {
+        let program = self.program()?;
+        let memory = program.debugger.cpu().memory();
+        let base = arguments
+            .get("memoryReference")
+            .and_then(Json::as_str)
+            .and_then(|text| address(text, arguments.get("offset")))
+            .ok_or("invalid memory reference")?;
+        let offset = arguments
+            .get("instructionOffset")
+            .and_then(Json::as_f64)
+            .unwrap_or(0.0) as i64;
+        let count = arguments
+            .get("instructionCount")
+            .and_then(Json::as_u64)
+            .unwrap_or(0) as usize;
+        // 範囲外の分も1命令ずつ返すので、メモリ全体の命令数までに抑える
+        let count = count.min(memory.len() / 2);
+
+        let mut address = (base as i64).saturating_add(offset.saturating_mul(2));
+        let mut instructions = Vec::with_capacity(count);
+        for _ in 0..count {
+            let decoded = usize::try_from(address)
+                .ok()
+                .filter(|&address| address < memory.len())
+                .map(|address| (address, Instruction::fetch(memory, address)));
+            let Some((at, decoded)) = decoded else {
+                instructions.push(Json::object([
+                    ("address", format!("0x{:X}", address).into()),
+                    ("instruction", "??".into()),
+                    ("presentationHint", "invalid".into()),
+                ]));
+                address += 2;
+                continue;
+            };
+            let (text, size) = match decoded {
+                Ok(instruction) => (instruction.to_string(), instruction.size()),
+                Err(_) => ("??".to_string(), 2),
+            };
+            let bytes: Vec<String> = memory[at..(at + size).min(memory.len())]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            let mut members = vec![
+                ("address", reference(at).into()),
+                ("instructionBytes", bytes.join(" ").into()),
+                ("instruction", text.into()),
+            ];
+            if let Some(source) = &program.source {
+                if let Some(label) = source.label_of(at) {
+                    members.push(("symbol", label.into()));
+                }
+                if let Some(entry) = source.line_of(at) {
+                    members.push(("location", source_json(&source.path)));
+                    members.push(("line", entry.line.into()));
+                }
+            }
+            instructions.push(Json::object(members));
+            address += size as i64;
+        }
+        Ok(Json::object([("instructions", instructions.into())]))
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        ("supportsConfigurationDoneRequest", true.into()),
+        ("supportsSetVariable", true.into()),
+        ("supportsReadMemoryRequest", true.into()),
+        ("supportsDisassembleRequest", true.into()),
+        ("supportsInstructionBreakpoints", true.into()),
+        ("supportsSteppingGranularity", false.into()),
+        ("supportsTerminateRequest", true.into()),
+        ("supportsConditionalBreakpoints", true.into()),
+        ("supportsHitConditionalBreakpoints", true.into()),
+        ("supportsLogPoints", true.into()),
+        ("supportsStepBack", true.into()),
+    ])
+}
+
+// ブレークポイントの condition・hitCondition・logMessage を読む
+// hitCondition は "5"（5回目以降）か、">= 5" "== 3" "% 2 == 0" のように hit_count に続く式
+fn condition(breakpoint: &Json) -> Result<Condition, String> {
+    let text = |name: &str| {
+        breakpoint
+            .get(name)
+            .and_then(Json::as_str)
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+    };
+    let mut expressions = Vec::new();
+    if let Some(condition) = text("condition") {
+        Expression::condition(condition).map_err(|e| format!("condition: {}", e))?;
+        expressions.push(format!("({})", condition));
+    }
+    if let Some(hits) = text("hitCondition") {
+        let hits = match hits.starts_with(|c: char| c.is_ascii_digit()) {
+            true => format!("hit_count >= {}", hits),
+            false => format!("hit_count {}", hits),
+        };
+        Expression::condition(&hits).map_err(|e| format!("hitCondition: {}", e))?;
+        expressions.push(format!("({})", hits));
+    }
+    let expression = match expressions.is_empty() {
+        true => None,
+        false => Some(Expression::condition(&expressions.join(" && ")).map_err(|e| e.to_string())?),
+    };
+    let log = text("logMessage")
+        .map(LogMessage::parse)
+        .transpose()
+        .map_err(|e| format!("logMessage: {}", e))?;
+    Ok(Condition { expression, log })
+}
+
+fn scopes() -> Json {
+    let scope = |name: &str, reference: usize| {
+        Json::object([
+            ("name", name.into()),
+            ("variablesReference", reference.into()),
+            ("expensive", false.into()),
+        ])
+    };
+    Json::object([(
+        "scopes",
+        vec![scope("Registers", REGISTERS), scope("Display", DISPLAY)].into(),
+    )])
+}
+
+fn stopped(reason: &str, description: Option<String>) -> Json {
+    let mut members = vec![
+        ("reason", reason.into()),
+        ("threadId", THREAD_ID.into()),
+        ("allThreadsStopped", true.into()),
+    ];
+    if let Some(description) = description {
+        members.push(("description", description.as_str().into()));
+        members.push(("text", description.into()));
+    }
+    Json::object(members)
+}
+
+fn variable(name: &str, value: String, memory: Option<usize>) -> Json {
+    let mut members = vec![
+        ("name", name.into()),
+        ("value", value.into()),
+        ("variablesReference", 0usize.into()),
+    ];
+    if let Some(address) = memory {
+        members.push(("memoryReference", reference(address).into()));
+    }
+    Json::object(members)
+}
+
+fn source_json(path: &str) -> Json {
+    let name = Path::new(path)
+        .file_name()
+        .map_or(path.to_string(), |name| name.to_string_lossy().into_owned());
+    Json::object([("name", name.into()), ("path", path.into())])
+}
+
+// メモリやアドレスの参照は "0x200" の形で渡す
+fn reference(address: usize) -> String {
+    format!("0x{:03X}", address)
+}
+
+// "0x" で始まれば16進数、それ以外は10進数
+fn number(text: &str) -> Option<usize> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// 参照に offset（負の場合もある）を足したアドレス
+fn address(reference: &str, offset: Option<&Json>) -> Option<usize> {
+    let offset = offset.and_then(Json::as_f64).unwrap_or(0.0) as i64;
+    let reference = i64::try_from(number(reference)?).ok()?;
+    usize::try_from(reference.checked_add(offset)?).ok()
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(bits >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(seq: usize, command: &str, arguments: Json) -> Json {
+        Json::object([
+            ("seq", seq.into()),
+            ("type", "request".into()),
+            ("command", command.into()),
+            ("arguments", arguments),
+        ])
+    }
+
+    fn find<'a>(replies: &'a [Json], event: &str) -> Option<&'a Json> {
+        replies
+            .iter()
+            .find(|reply| reply.get("event").and_then(Json::as_str) == Some(event))
+    }
+
+    fn temp_file(name: &str, contents: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("chip8-dap-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn replies_body<'a>(replies: &'a [Json], key: &str) -> &'a [Json] {
+        replies[0]
+            .get("body")
+            .unwrap()
+            .get(key)
+            .unwrap()
+            .as_array()
+            .unwrap()
+    }
+
+    // 止まるまでフレームを進める
+    fn run_until_stopped(server: &mut Server) -> Json {
+        for _ in 0..100 {
+            if let Some(event) = find(&server.tick(), "stopped") {
+                return event.get("body").unwrap().clone();
+            }
+        }
+        panic!("did not stop");
+    }
+
+    #[test]
+    fn test_source_level_session() {
+        let path = temp_file(
+            "session.8o",
+            b": main\n  v0 := 1\n\n  count\n  loop again\n: count\n  v1 += 2\n  return\n",
+        );
+        let mut server = Server::new();
+        let replies = server.handle(&request(1, "initialize", Json::Null));
+        assert_eq!(
+            replies[0]
+                .get("body")
+                .and_then(|body| body.get("supportsReadMemoryRequest"))
+                .and_then(Json::as_bool),
+            Some(true)
+        );
+        let replies = server.handle(&request(
+            2,
+            "launch",
+            Json::object([("program", path.as_str().into())]),
+        ));
+        assert_eq!(
+            replies[0].get("success").and_then(Json::as_bool),
+            Some(true)
+        );
+        assert!(find(&replies, "initialized").is_some());
+
+        // 空行の3行目は次の命令がある4行目にずれる
+        let replies = server.handle(&request(
+            3,
+            "setBreakpoints",
+            Json::object([
+                ("source", Json::object([("path", path.as_str().into())])),
+                (
+                    "breakpoints",
+                    vec![Json::object([("line", 3usize.into())])].into(),
+                ),
+            ]),
+        ));
+        let breakpoint = &replies[0]
+            .get("body")
+            .unwrap()
+            .get("breakpoints")
+            .unwrap()
+            .as_array()
+            .unwrap()[0];
+        assert_eq!(
+            breakpoint.get("verified").and_then(Json::as_bool),
+            Some(true)
+        );
+        assert_eq!(breakpoint.get("line").and_then(Json::as_u64), Some(4));
+
+        server.handle(&request(4, "configurationDone", Json::Null));
+        assert!(server.is_running());
+        let stopped = run_until_stopped(&mut server);
+        assert_eq!(
+            stopped.get("reason").and_then(Json::as_str),
+            Some("breakpoint")
+        );
+
+        // count の中に入って1命令実行する
+        server.handle(&request(5, "stepIn", Json::Null));
+        run_until_stopped(&mut server);
+        server.handle(&request(6, "next", Json::Null));
+        assert_eq!(
+            run_until_stopped(&mut server)
+                .get("reason")
+                .and_then(Json::as_str),
+            Some("step")
+        );
+        let replies = server.handle(&request(7, "stackTrace", Json::Null));
+        let frames = replies[0]
+            .get("body")
+            .unwrap()
+            .get("stackFrames")
+            .unwrap()
+            .as_array()
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].get("name").and_then(Json::as_str), Some("count"));
+        assert_eq!(frames[0].get("line").and_then(Json::as_u64), Some(8));
+        assert_eq!(frames[1].get("line").and_then(Json::as_u64), Some(4));
+
+        let replies = server.handle(&request(
+            8,
+            "variables",
+            Json::object([("variablesReference", REGISTERS.into())]),
+        ));
+        let variables = replies[0]
+            .get("body")
+            .unwrap()
+            .get("variables")
+            .unwrap()
+            .as_array()
+            .unwrap();
+        assert_eq!(
+            variables[0].get("value").and_then(Json::as_str),
+            Some("0x01")
+        );
+        assert_eq!(
+            variables[1].get("value").and_then(Json::as_str),
+            Some("0x02")
+        );
+
+        // 逆実行は応答の直後に stopped を返す
+        let replies = server.handle(&request(9, "stepBack", Json::Null));
+        let stopped = find(&replies, "stopped").unwrap().get("body").unwrap();
+        assert_eq!(stopped.get("reason").and_then(Json::as_str), Some("step"));
+        assert_eq!(server.program().unwrap().debugger.cpu().registers()[1], 0);
+        let replies = server.handle(&request(10, "reverseContinue", Json::Null));
+        let stopped = find(&replies, "stopped").unwrap().get("body").unwrap();
+        assert_eq!(
+            stopped.get("reason").and_then(Json::as_str),
+            Some("breakpoint")
+        );
+
+        server.handle(&request(
+            11,
+            "setVariable",
+            Json::object([
+                ("variablesReference", REGISTERS.into()),
+                ("name", "V1".into()),
+                ("value", "0x7F".into()),
+            ]),
+        ));
+        assert_eq!(
+            server.program().unwrap().debugger.cpu().registers()[1],
+            0x7F
+        );
+
+        // 書き換えた後は、それより前には戻れない
+        let replies = server.handle(&request(12, "stepBack", Json::Null));
+        let stopped = find(&replies, "stopped").unwrap().get("body").unwrap();
+        assert_eq!(
+            stopped.get("description").and_then(Json::as_str),
+            Some("start of history")
+        );
+        assert_eq!(
+            server.program().unwrap().debugger.cpu().registers()[1],
+            0x7F
+        );
+
+        let replies = server.handle(&request(13, "disconnect", Json::Null));
+        assert_eq!(
+            replies[0].get("success").and_then(Json::as_bool),
+            Some(true)
+        );
+        assert!(server.exited());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rom_memory_and_instruction_breakpoints() {
+        // 00E0 (CLS), 6005 (LD V0, 5), 1202 (JP 0x202)
+        let path = temp_file("rom.ch8", &[0x00, 0xE0, 0x60, 0x05, 0x12, 0x02]);
+        let mut server = Server::new();
+        server.handle(&request(
+            1,
+            "launch",
+            Json::object([
+                ("program", path.as_str().into()),
+                ("platform", "chip-8".into()),
+                ("stopOnEntry", true.into()),
+            ]),
+        ));
+        let replies = server.handle(&request(2, "configurationDone", Json::Null));
+        assert_eq!(
+            find(&replies, "stopped")
+                .and_then(|event| event.get("body").unwrap().get("reason"))
+                .and_then(Json::as_str),
+            Some("entry")
+        );
+
+        let replies = server.handle(&request(
+            3,
+            "readMemory",
+            Json::object([
+                ("memoryReference", "0x200".into()),
+                ("offset", 2usize.into()),
+                ("count", 4usize.into()),
+            ]),
+        ));
+        let body = replies[0].get("body").unwrap();
+        assert_eq!(body.get("address").and_then(Json::as_str), Some("0x202"));
+        assert_eq!(body.get("data").and_then(Json::as_str), Some("YAUSAg=="));
+
+        let replies = server.handle(&request(
+            4,
+            "disassemble",
+            Json::object([
+                ("memoryReference", "0x200".into()),
+                ("instructionCount", 2usize.into()),
+            ]),
+        ));
+        let instructions = replies[0]
+            .get("body")
+            .unwrap()
+            .get("instructions")
+            .unwrap()
+            .as_array()
+            .unwrap();
+        assert_eq!(
+            instructions[1].get("instruction").and_then(Json::as_str),
+            Some("LD V0, 0x05")
+        );
+
+        let replies = server.handle(&request(
+            5,
+            "setInstructionBreakpoints",
+            Json::object([(
+                "breakpoints",
+                vec![
+                    Json::object([
+                        ("instructionReference", "0x204".into()),
+                        ("hitCondition", "3".into()),
+                    ]),
+                    Json::object([
+                        ("instructionReference", "0x202".into()),
+                        ("logMessage", "v0={v0} #{hit_count}".into()),
+                    ]),
+                    Json::object([
+                        ("instructionReference", "0x200".into()),
+                        ("condition", "v0 +".into()),
+                    ]),
+                ]
+                .into(),
+            )]),
+        ));
+        let breakpoints = replies_body(&replies, "breakpoints");
+        assert_eq!(
+            breakpoints[2].get("message").and_then(Json::as_str),
+            Some("condition: column 5: expected a value, found end of expression")
+        );
+
+        // ログポイントは止まらずに出力し、3回目に到達したところで止まる
+        server.handle(&request(6, "continue", Json::Null));
+        let mut output = String::new();
+        let stopped = loop {
+            let events = server.tick();
+            for event in &events {
+                let body = event.get("body").unwrap();
+                if let Some(text) = body.get("output").and_then(Json::as_str) {
+                    output.push_str(text);
+                }
+            }
+            if let Some(event) = find(&events, "stopped") {
+                break event.get("body").unwrap().clone();
+            }
+        };
+        assert_eq!(
+            stopped.get("reason").and_then(Json::as_str),
+            Some("instruction breakpoint")
+        );
+        assert_eq!(output, "v0=0 #1\nv0=5 #2\nv0=5 #3\n");
+        assert_eq!(
+            server.program().unwrap().debugger.cpu().program_counter(),
+            0x204
+        );
+
+        // 大きすぎる命令数はメモリ全体の命令数に抑える
+        let replies = server.handle(&request(
+            7,
+            "disassemble",
+            Json::object([
+                ("memoryReference", "0x0".into()),
+                ("instructionCount", (u64::MAX as f64).into()),
+            ]),
+        ));
+        let instructions = replies_body(&replies, "instructions");
+        assert_eq!(instructions.len(), 0x1000 / 2);
+
+        // レジスタに入りきらない値は切り詰めずにエラーにする
+        let replies = server.handle(&request(
+            8,
+            "setVariable",
+            Json::object([
+                ("variablesReference", REGISTERS.into()),
+                ("name", "V0".into()),
+                ("value", "0x1234".into()),
+            ]),
+        ));
+        assert_eq!(
+            replies[0].get("success").and_then(Json::as_bool),
+            Some(false)
+        );
+        assert_eq!(server.program().unwrap().debugger.cpu().registers()[0], 5);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
@@ -0,0 +1,659 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::debugger::{Condition, Debugger, Register, StopReason, WatchKind, Watchpoint};
+use crate::expression::{Expression, LogMessage};
+use crate::instruction::Instruction;
+use crate::journal;
+use crate::keyboard::KeyboardInput;
+
+// 逆アセンブルで表示する命令の数（PCより前は4命令）
+const DISASSEMBLY_LINES: usize = 12;
+const DISASSEMBLY_BEFORE: usize = 4;
+const MEMORY_ROWS: usize = 8;
+const BYTES_PER_ROW: usize = 16;
+const LOG_LINES: usize = 4; // ログポイントの出力を表示する行数
+
+pub const HELP: &str = "b/break ADDR [if EXPR]  log ADDR MESSAGE  d/delete ADDR  \
+w/watch [read|write|access] ADDR|REG [if EXPR]  unwatch ...  s/step  n/next  f/finish  c/continue  rs/rstep  rc/rcontinue  history on|off  poke ADDR BYTE...  set REG|pc VALUE  x ADDR  q/quit  (numbers are hex)";
+
+// set で書き換える対象
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Register(Register),
+    ProgramCounter,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Break(usize, Condition), // log ADDR MESSAGE は log 付きの Break
+    Delete(usize),
+    Watch(Watchpoint, Condition),
+    Unwatch(Watchpoint),
+    Step,
+    Next,   // call を飛ばす
+    Finish, // サブルーチンから戻るまで
+    Continue,
+    ReverseStep,
+    ReverseContinue,
+    History(bool), // 逆実行のための記録を有効・無効にする
+    Poke { address: usize, bytes: Vec<u8> },
+    Set { target: Target, value: u16 },
+    Memory(usize), // メモリ表示の先頭を移す
+    Help,
+    Quit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandError(pub String);
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+fn hex(text: Option<&str>, what: &str) -> Result<usize, CommandError> {
+    let text = text.ok_or_else(|| CommandError(format!("missing {}", what)))?;
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    usize::from_str_radix(digits, 16)
+        .map_err(|_| CommandError(format!("invalid {} '{}' (expected hex)", what, text)))
+}
+
+fn watchpoint<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<Watchpoint, CommandError> {
+    let first = words
+        .next()
+        .ok_or_else(|| CommandError("missing address or register".to_string()))?;
+    let kind = match first {
+        "read" => Some(WatchKind::Read),
+        "write" => Some(WatchKind::Write),
+        "access" => Some(WatchKind::ReadWrite),
+        _ => None,
+    };
+    if let Some(kind) = kind {
+        let address = hex(words.next(), "address")?;
+        return Ok(Watchpoint::Memory { address, kind });
+    }
+    // レジスタ名でなければ書き込みを監視するアドレス
+    if let Ok(register) = first.parse() {
+        return Ok(Watchpoint::Register(register));
+    }
+    Ok(Watchpoint::Memory {
+        address: hex(Some(first), "address")?,
+        kind: WatchKind::Write,
+    })
+}
+
+impl FromStr for Command {
+    type Err = CommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // メッセージには空白や " if " が含まれることがあるので、log は先に分ける
+        if let Some(rest) = s.trim_start().strip_prefix("log ") {
+            let (address, message) = rest
+                .trim_start()
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| CommandError("missing message".to_string()))?;
+            let log = LogMessage::parse(message.trim())
+                .map_err(|e| CommandError(format!("message: {}", e)))?;
+            let condition = Condition {
+                expression: None,
+                log: Some(log),
+            };
+            return Ok(Command::Break(hex(Some(address), "address")?, condition));
+        }
+        // "if" 以降は条件式
+        let (s, expression) = match s.split_once(" if ") {
+            Some((command, expression)) => {
+                let expression = Expression::condition(expression)
+                    .map_err(|e| CommandError(format!("condition: {}", e)))?;
+                (command, Some(expression))
+            }
+            None => (s, None),
+        };
+        let condition = Condition {
+            expression,
+            log: None,
+        };
+
+        let mut words = s.split_whitespace();
+        let Some(name) = words.next() else {
+            return Err(CommandError("empty command".to_string()));
+        };
+        let command = match name {
+            "b" | "break" => Command::Break(hex(words.next(), "address")?, condition.clone()),
+            "d" | "delete" => Command::Delete(hex(words.next(), "address")?),
+            "w" | "watch" => Command::Watch(watchpoint(&mut words)?, condition.clone()),
+            "unwatch" => Command::Unwatch(watchpoint(&mut words)?),
+            "s" | "step" => Command::Step,
+            "n" | "next" => Command::Next,
+            "f" | "finish" => Command::Finish,
+            "c" | "continue" => Command::Continue,
+            "rs" | "rstep" => Command::ReverseStep,
+            "rc" | "rcontinue" => Command::ReverseContinue,
+            "history" => match words.next() {
+                Some("on") => Command::History(true),
+                Some("off") => Command::History(false),
+                _ => {
+                    return Err(CommandError(
+                        "expected 'history on' or 'history off'".to_string(),
+                    ))
+                }
+            },
+            "poke" => {
+                // メモリは最大でも64KB（XO-CHIP）
+                let address = hex(words.next(), "address")?;
+                if address > 0xFFFF {
+                    return Err(CommandError(format!(
+                        "address {:X} is out of range",
+                        address
+                    )));
+                }
+                let bytes = words
+                    .by_ref()
+                    .map(|word| {
+                        hex(Some(word), "byte")?
+                            .try_into()
+                            .map_err(|_| CommandError(format!("'{}' does not fit in a byte", word)))
+                    })
+                    .collect::<Result<Vec<u8>, _>>()?;
+                if bytes.is_empty() {
+                    return Err(CommandError("missing byte".to_string()));
+                }
+                Command::Poke { address, bytes }
+            }
+            "set" => {
+                let name = words
+                    .next()
+                    .ok_or_else(|| CommandError("missing register".to_string()))?;
+                let target = match name.to_ascii_lowercase().as_str() {
+                    "pc" => Target::ProgramCounter,
+                    _ => {
+                        Target::Register(name.parse().map_err(|e| CommandError(format!("{}", e)))?)
+                    }
+                };
+                let value = hex(words.next(), "value")?;
+                let limit = match target {
+                    Target::Register(Register::V(_)) => 0xFF,
+                    _ => 0xFFFF,
+                };
+                if value > limit {
+                    return Err(CommandError(format!("value {:X} is out of range", value)));
+                }
+                Command::Set {
+                    target,
+                    value: value as u16,
+                }
+            }
+            "x" => Command::Memory(hex(words.next(), "address")?),
+            "h" | "help" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            _ => return Err(CommandError(format!("unknown command '{}'", name))),
+        };
+        if let Some(extra) = words.next() {
+            return Err(CommandError(format!("unexpected '{}'", extra)));
+        }
+        if !condition.is_empty() && !matches!(command, Command::Break(..) | Command::Watch(..)) {
+            return Err(CommandError(
+                "'if' can only be used with break and watch".to_string(),
+            ));
+        }
+        Ok(command)
+    }
+}
+
+// 画面以外に表示する状態
+#[derive(Debug, Clone, Default)]
+pub struct View {
+    pub memory_address: usize, // メモリ表示の先頭
+    pub message: String,
+    pub input: String,    // 入力中のコマンド
+    pub log: Vec<String>, // ログポイントの出力（新しいものが後ろ）
+}
+
+impl View {
+    pub fn push_log(&mut self, line: String) {
+        if self.log.len() == LOG_LINES {
+            self.log.remove(0);
+        }
+        self.log.push(line);
+    }
+}
+
+// コマンドを実行し、結果を view.message に書く（Quit は呼び出し側で扱う）
+pub fn execute<T: KeyboardInput>(debugger: &mut Debugger<T>, view: &mut View, command: &Command) {
+    let memory_size = debugger.cpu().memory().len();
+    view.message = match *command {
+        Command::Break(address, ref condition) => {
+            debugger.set_breakpoint(address, condition.clone());
+            let kind = if condition.log.is_some() {
+                "logpoint"
+            } else {
+                "breakpoint"
+            };
+            format!("{} at {:03X} {}", kind, address, condition)
+                .trim_end()
+                .to_string()
+        }
+        Command::Delete(address) => match debugger.remove_breakpoint(address) {
+            true => format!("deleted breakpoint at {:03X}", address),
+            false => format!("no breakpoint at {:03X}", address),
+        },
+        Command::Watch(watchpoint, ref condition) => {
+            debugger.set_watchpoint(watchpoint, condition.clone());
+            format!("watching {} {}", watchpoint, condition)
+                .trim_end()
+                .to_string()
+        }
+        Command::Unwatch(watchpoint) => match debugger.remove_watchpoint(&watchpoint) {
+            true => format!("stopped watching {}", watchpoint),
+            false => format!("not watching {}", watchpoint),
+        },
+        Command::Step => {
+            debugger.step();
+            String::new()
+        }
+        Command::Next => {
+            debugger.step_over();
+            String::new()
+        }
+        Command::Finish => {
+            debugger.step_out();
+            String::new()
+        }
+        Command::Continue => {
+            debugger.resume();
+            "running (Esc to pause)".to_string()
+        }
+        Command::ReverseStep | Command::ReverseContinue if debugger.cpu().journal().is_none() => {
+            "history is off (use 'history on')".to_string()
+        }
+        Command::ReverseStep | Command::ReverseContinue => {
+            let reason = match command {
+                Command::ReverseStep => debugger.reverse_step(),
+                _ => debugger.reverse_continue(),
+            };
+            match reason {
+                StopReason::Step => String::new(),
+                reason => format!("stopped: {}", reason),
+            }
+        }
+        Command::History(enabled) => {
+            debugger
+                .cpu_mut()
+                .set_journal(enabled.then_some(journal::DEFAULT_CAPACITY));
+            match enabled {
+                true => format!(
+                    "recording history (up to {} records)",
+                    journal::DEFAULT_CAPACITY
+                ),
+                false => "history off".to_string(),
+            }
+        }
+        Command::Poke { address, ref bytes } => match address.checked_add(bytes.len()) {
+            Some(end) if end <= memory_size => {
+                // 書き換える前の値は記録に無いので、それより前には戻れなくする
+                let cpu = debugger.cpu_mut();
+                cpu.memory_mut()[address..end].copy_from_slice(bytes);
+                cpu.clear_journal();
+                format!("wrote {} bytes at {:03X}", bytes.len(), address)
+            }
+            _ => format!(
+                "{:03X} is outside memory",
+                address.saturating_add(bytes.len().saturating_sub(1))
+            ),
+        },
+        Command::Set { target, value } => {
+            let cpu = debugger.cpu_mut();
+            match target {
+                Target::Register(Register::V(x)) => cpu.registers_mut()[x as usize] = value as u8,
+                Target::Register(Register::I) => cpu.set_index_register(value),
+                Target::ProgramCounter => cpu.set_program_counter(value as usize),
+            }
+            // poke と同じく、書き換える前の状態へは戻れなくする
+            cpu.clear_journal();
+            match target {
+                Target::Register(register) => format!("{} = {:X}", register, value),
+                Target::ProgramCounter => format!("PC = {:03X}", value),
+            }
+        }
+        Command::Memory(address) => {
+            view.memory_address = address.min(memory_size.saturating_sub(1));
+            String::new()
+        }
+        Command::Help => HELP.to_string(),
+        Command::Quit => String::new(),
+    };
+}
+
+// 枠で囲む。幅は文字数で数え、はみ出す部分は切り捨てる
+fn boxed(title: &str, lines: &[String], width: usize) -> Vec<String> {
+    let mut out = Vec::with_capacity(lines.len() + 2);
+    out.push(format!(
+        "+- {} {}+",
+        title,
+        "-".repeat(width.saturating_sub(title.len() + 3))
+    ));
+    for line in lines {
+        let line: String = line.chars().take(width).collect();
+        let padding = width - line.chars().count();
+        out.push(format!("|{}{}|", line, " ".repeat(padding)));
+    }
+    out.push(format!("+{}+", "-".repeat(width)));
+    out
+}
+
+// 2つの列を横に並べる。短い方は空白で埋める
+fn side_by_side(left: Vec<String>, right: Vec<String>) -> Vec<String> {
+    let width = left
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let rows = left.len().max(right.len());
+    (0..rows)
+        .map(|row| {
+            let l = left.get(row).map_or("", String::as_str);
+            let r = right.get(row).map_or("", String::as_str);
+            let padding = width - l.chars().count();
+            format!("{}{} {}", l, " ".repeat(padding), r)
+        })
+        .collect()
+}
+
+// 上下2ピクセルを1文字にまとめて表示する
+fn screen_lines<T: KeyboardInput>(debugger: &Debugger<T>) -> Vec<String> {
+    let rows: Vec<&[u8]> = debugger.cpu().get_display().rows().collect();
+    rows.chunks(2)
+        .map(|pair| {
+            let bottom = pair.get(1).copied().unwrap_or(&[]);
+            pair[0]
+                .iter()
+                .enumerate()
+                .map(
+                    |(x, &top)| match (top != 0, bottom.get(x).is_some_and(|&pixel| pixel != 0)) {
+                        (false, false) => ' ',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (true, true) => '█',
+                    },
+                )
+                .collect()
+        })
+        .collect()
+}
+
+fn register_lines<T: KeyboardInput>(debugger: &Debugger<T>) -> Vec<String> {
+    let cpu = debugger.cpu();
+    let mut lines: Vec<String> = cpu
+        .registers()
+        .chunks(4)
+        .enumerate()
+        .map(|(row, values)| {
+            let cells: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| format!("V{:X} {:02X}", row * 4 + i, value))
+                .collect();
+            cells.join("  ")
+        })
+        .collect();
+    lines.push(format!(
+        "I  {:04X}   PC {:03X}",
+        cpu.index_register(),
+        cpu.program_counter()
+    ));
+    lines.push(format!(
+        "SP {:X}  DT {:02X}  ST {:02X}",
+        cpu.call_stack().len(),
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    ));
+    lines
+}
+
+fn stack_lines<T: KeyboardInput>(debugger: &Debugger<T>) -> Vec<String> {
+    let mut lines: Vec<String> = debugger
+        .call_stack()
+        .iter()
+        .enumerate()
+        .map(|(depth, frame)| {
+            let function = frame
+                .function
+                .map_or("???".to_string(), |function| format!("{:03X}", function));
+            format!("#{:<2} {:03X} in {}", depth, frame.pc, function)
+        })
+        .collect();
+    // 条件の無いものは1行にまとめる
+    let breakpoints: Vec<String> = debugger
+        .breakpoints()
+        .filter(|(_, condition)| condition.is_empty())
+        .map(|(address, _)| format!("{:03X}", address))
+        .collect();
+    if !breakpoints.is_empty() {
+        lines.push(format!("break: {}", breakpoints.join(" ")));
+    }
+    for (address, condition) in debugger.breakpoints() {
+        if !condition.is_empty() {
+            lines.push(format!("break: {:03X} {}", address, condition));
+        }
+    }
+    for (watchpoint, condition) in debugger.watchpoints() {
+        let line = format!("watch: {} {}", watchpoint, condition);
+        lines.push(line.trim_end().to_string());
+    }
+    if let Some(journal) = debugger.cpu().journal() {
+        lines.push(format!("history: {} steps", journal.len()));
+    }
+    lines
+}
+
+fn disassembly_lines<T: KeyboardInput>(debugger: &Debugger<T>) -> Vec<String> {
+    let cpu = debugger.cpu();
+    let memory = cpu.memory();
+    let pc = cpu.program_counter();
+    let mut address = pc.saturating_sub(DISASSEMBLY_BEFORE * 2);
+    let mut lines = Vec::with_capacity(DISASSEMBLY_LINES);
+    while lines.len() < DISASSEMBLY_LINES && address + 1 < memory.len() {
+        let marker = match (address == pc, debugger.has_breakpoint(address)) {
+            (true, true) => ">*",
+            (true, false) => "> ",
+            (false, true) => " *",
+            (false, false) => "  ",
+        };
+        let opcode = u16::from_be_bytes([memory[address], memory[address + 1]]);
+        let (text, size) = match Instruction::fetch(memory, address) {
+            Ok(instruction) => (instruction.to_string(), instruction.size()),
+            Err(_) => ("???".to_string(), 2),
+        };
+        lines.push(format!(
+            "{}{:03X}: {:04X}  {}",
+            marker, address, opcode, text
+        ));
+        address += size;
+    }
+    lines
+}
+
+fn memory_lines<T: KeyboardInput>(debugger: &Debugger<T>, start: usize) -> Vec<String> {
+    let memory = debugger.cpu().memory();
+    let start = start - start % BYTES_PER_ROW;
+    (0..MEMORY_ROWS)
+        .map(|row| start + row * BYTES_PER_ROW)
+        .take_while(|&address| address < memory.len())
+        .map(|address| {
+            let end = (address + BYTES_PER_ROW).min(memory.len());
+            let bytes: Vec<String> = memory[address..end]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            format!("{:04X}: {}", address, bytes.join(" "))
+        })
+        .collect()
+}
+
+// 端末に出す1画面分の行
+pub fn render<T: KeyboardInput>(debugger: &Debugger<T>, view: &View) -> Vec<String> {
+    let screen = screen_lines(debugger);
+    let width = screen.first().map_or(0, |line| line.chars().count());
+    let mut right = boxed("registers", &register_lines(debugger), 28);
+    right.extend(boxed("stack", &stack_lines(debugger), 28));
+    let mut lines = side_by_side(boxed("screen", &screen, width), right);
+
+    let disassembly = boxed("disassembly", &disassembly_lines(debugger), 32);
+    let memory = boxed(
+        "memory",
+        &memory_lines(debugger, view.memory_address),
+        6 + BYTES_PER_ROW * 3,
+    );
+    lines.extend(side_by_side(disassembly, memory));
+    if !view.log.is_empty() {
+        let width = lines.last().map_or(0, |line| line.chars().count() - 2);
+        lines.extend(boxed("log", &view.log, width));
+    }
+
+    lines.push(view.message.clone());
+    if debugger.is_paused() {
+        lines.push(format!("(chip8) {}", view.input));
+    } else {
+        lines.push("(running) Esc: pause".to_string());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::chip8::Cpu;
+    use crate::keyboard::NoKeyboard;
+
+    #[test]
+    fn test_parse_commands() {
+        let parse = |text: &str| text.parse::<Command>();
+        assert_eq!(
+            parse("b 2a0"),
+            Ok(Command::Break(0x2A0, Condition::default()))
+        );
+        assert_eq!(
+            parse("watch v3"),
+            Ok(Command::Watch(
+                Watchpoint::Register(Register::V(3)),
+                Condition::default()
+            ))
+        );
+        assert_eq!(
+            parse("w read 0x300 if mem[0x300] == 0"),
+            Ok(Command::Watch(
+                Watchpoint::Memory {
+                    address: 0x300,
+                    kind: WatchKind::Read
+                },
+                Condition {
+                    expression: Some(Expression::condition("mem[0x300] == 0").unwrap()),
+                    log: None
+                }
+            ))
+        );
+        let Ok(Command::Break(0x2A4, condition)) = parse("log 2a4 v3 is {v3} if set") else {
+            panic!("expected a logpoint");
+        };
+        assert_eq!(condition.to_string(), "log v3 is {v3} if set");
+        assert!(parse("b 2a0 if v3").is_err());
+        assert!(parse("step if v3 == 1").is_err());
+        assert_eq!(
+            parse("poke 300 1 ff"),
+            Ok(Command::Poke {
+                address: 0x300,
+                bytes: vec![1, 0xFF]
+            })
+        );
+        assert_eq!(
+            parse("set pc 208"),
+            Ok(Command::Set {
+                target: Target::ProgramCounter,
+                value: 0x208
+            })
+        );
+        assert!(parse("set v0 100").is_err());
+        assert!(parse("poke 300 100").is_err());
+        assert!(parse("poke ffffffffffffffff 1").is_err());
+        assert!(parse("step 2").is_err());
+        assert!(parse("jump").is_err());
+        assert_eq!(parse("rs"), Ok(Command::ReverseStep));
+        assert_eq!(parse("history off"), Ok(Command::History(false)));
+        assert!(parse("history").is_err());
+    }
+
+    #[test]
+    fn test_set_clears_history() {
+        let rom = assemble("v0 := 1\nv1 := 2\nloop again").unwrap();
+        let mut debugger = Debugger::new(Cpu::from_bytes(&rom, NoKeyboard));
+        let mut view = View::default();
+        execute(&mut debugger, &mut view, &Command::History(true));
+        for _ in 0..2 {
+            execute(&mut debugger, &mut view, &Command::Step);
+            debugger.run(100);
+        }
+        execute(&mut debugger, &mut view, &"set v0 7".parse().unwrap());
+        // 取り消すと set の前の状態に戻ってしまうので、逆実行はできない
+        execute(&mut debugger, &mut view, &Command::ReverseStep);
+        assert_eq!(view.message, "stopped: start of history");
+        assert_eq!(debugger.cpu().registers()[0], 7);
+        assert_eq!(debugger.cpu().program_counter(), 0x204);
+    }
+
+    #[test]
+    fn test_execute_and_render() {
+        let rom = assemble("v0 := 5\ni := 0x300\nsprite v0 v0 5\nloop again").unwrap();
+        let mut debugger = Debugger::new(Cpu::from_bytes(&rom, NoKeyboard));
+        let mut view = View::default();
+        for command in [
+            "b 206",
+            "log 204 v0={v0} #{hit_count}",
+            "b 202 if v0 != 5",
+            "set v1 2a",
+            "poke 300 f0 90",
+            "history on",
+            "c",
+        ] {
+            execute(&mut debugger, &mut view, &command.parse().unwrap());
+        }
+        debugger.run(100);
+        assert_eq!(debugger.cpu().program_counter(), 0x206);
+        for line in debugger.take_logs() {
+            view.push_log(line);
+        }
+
+        let lines = render(&debugger, &view);
+        let text = lines.join("\n");
+        assert!(text.contains("V0 05  V1 2A"));
+        assert!(text.contains("PC 206"));
+        assert!(text.contains(">*206: 1206  JP 0x206"));
+        assert!(text.contains("break: 206"));
+        assert!(text.contains("break: 202 if v0 != 5"));
+        assert!(text.contains("break: 204 log v0={v0} #{hit|"));
+        assert!(text.contains("|v0=5 #1 "));
+        // 画面は64x32を上下2ピクセルずつ、枠付きで表示する
+        assert!(lines[0].starts_with(&format!("+- screen {}+", "-".repeat(55))));
+        assert_eq!(lines[3].chars().take(10).collect::<String>(), "|     ▄▄▄▄");
+        assert_eq!(lines[4].chars().take(10).collect::<String>(), "|     ▀  ▀");
+        assert_eq!(lines.last().unwrap(), "(chip8) ");
+
+        // 逆実行ではログポイントと条件が偽のブレークポイントでは止まらない
+        execute(&mut debugger, &mut view, &Command::ReverseStep);
+        assert_eq!(debugger.cpu().program_counter(), 0x204);
+        assert!(render(&debugger, &view)
+            .join("\n")
+            .contains("history: 2 steps"));
+        execute(&mut debugger, &mut view, &Command::ReverseContinue);
+        assert_eq!(debugger.cpu().program_counter(), 0x200);
+        assert_eq!(debugger.cpu().registers()[0], 0);
+        assert_eq!(view.message, "stopped: start of history");
+    }
+}
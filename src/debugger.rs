@@ -0,0 +1,832 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chip8::{Cpu, ExecutionError, MemoryAccess, StepOutcome};
+use crate::expression::{Expression, LogMessage};
+use crate::instruction::Instruction;
+use crate::keyboard::KeyboardInput;
+
+const PROGRAM_START: usize = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+}
+
+impl Register {
+    pub fn value<T: KeyboardInput>(&self, cpu: &Cpu<T>) -> u16 {
+        match *self {
+            Register::V(x) => cpu.registers()[x as usize & 0xF] as u16,
+            Register::I => cpu.index_register(),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownRegister(pub String);
+
+impl fmt::Display for UnknownRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown register '{}' (expected v0-vf or i)", self.0)
+    }
+}
+
+impl std::error::Error for UnknownRegister {}
+
+// "v3" "VF" "i" のように大文字・小文字を区別しない
+impl FromStr for Register {
+    type Err = UnknownRegister;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        if lower == "i" {
+            return Ok(Register::I);
+        }
+        lower
+            .strip_prefix('v')
+            .filter(|digit| digit.len() == 1)
+            .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+            .map(Register::V)
+            .ok_or_else(|| UnknownRegister(s.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(&self, access: MemoryAccess) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::ReadWrite, _)
+                | (WatchKind::Read, MemoryAccess::Read)
+                | (WatchKind::Write, MemoryAccess::Write)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    // 命令による読み書き（命令の取り出しは含まない）
+    Memory { address: usize, kind: WatchKind },
+    // 値が変わったとき
+    Register(Register),
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchpoint::Memory { address, kind } => {
+                let kind = match kind {
+                    WatchKind::Read => "read",
+                    WatchKind::Write => "write",
+                    WatchKind::ReadWrite => "access",
+                };
+                write!(f, "{} {:03X}", kind, address)
+            }
+            Watchpoint::Register(register) => write!(f, "{}", register),
+        }
+    }
+}
+
+// ブレークポイント・ウォッチポイントに付ける条件
+// expression が偽の間は止まらない。log があれば止まる代わりにメッセージを出す（ログポイント）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Condition {
+    pub expression: Option<Expression>,
+    pub log: Option<LogMessage>,
+}
+
+impl Condition {
+    pub fn is_empty(&self) -> bool {
+        self.expression.is_none() && self.log.is_none()
+    }
+}
+
+// "if v3 > 0x10 log v3={v3}" の形
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(expression) = &self.expression {
+            write!(f, "if {}", expression)?;
+            if self.log.is_some() {
+                write!(f, " ")?;
+            }
+        }
+        if let Some(log) = &self.log {
+            write!(f, "log {}", log)?;
+        }
+        Ok(())
+    }
+}
+
+// 条件と、そこに到達した回数（式の hit_count）
+#[derive(Debug, Clone)]
+struct Trigger {
+    condition: Condition,
+    hits: usize,
+}
+
+impl Trigger {
+    fn new(condition: Condition) -> Self {
+        Trigger { condition, hits: 0 }
+    }
+
+    // 到達するたびに呼ぶ。止まる場合は true
+    fn hit<T: KeyboardInput>(&mut self, cpu: &Cpu<T>, logs: &mut Vec<String>) -> bool {
+        self.hits += 1;
+        if let Some(expression) = &self.condition.expression {
+            if !expression.is_true(cpu, self.hits) {
+                return false;
+            }
+        }
+        match &self.condition.log {
+            Some(log) => {
+                logs.push(log.format(cpu, self.hits));
+                false
+            }
+            None => true,
+        }
+    }
+
+    // 逆実行で止まるか。到達した回数は数えず、ログポイントでは止まらない
+    fn holds<T: KeyboardInput>(&self, cpu: &Cpu<T>) -> bool {
+        self.condition.log.is_none()
+            && self
+                .condition
+                .expression
+                .as_ref()
+                .is_none_or(|expression| expression.is_true(cpu, self.hits))
+    }
+}
+
+// 実行が止まった理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Step, // step / step_over / step_out が終わった
+    Breakpoint,
+    Memory {
+        at: usize, // 読み書きした命令のアドレス
+        address: usize,
+        access: MemoryAccess,
+        old: u8,
+        new: u8,
+    },
+    Register {
+        at: usize,
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+    Exited,
+    Fault(ExecutionError),
+    StartOfHistory, // 逆実行で、取り消せる命令がなくなった
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "step"),
+            StopReason::Breakpoint => write!(f, "breakpoint"),
+            StopReason::Memory {
+                at,
+                address,
+                access: MemoryAccess::Read,
+                old,
+                ..
+            } => write!(f, "{:03X} read {:03X} ({:02X})", at, address, old),
+            StopReason::Memory {
+                at,
+                address,
+                access: MemoryAccess::Write,
+                old,
+                new,
+            } => write!(
+                f,
+                "{:03X} wrote {:03X} ({:02X} -> {:02X})",
+                at, address, old, new
+            ),
+            StopReason::Register {
+                at,
+                register,
+                old,
+                new,
+            } => write!(
+                f,
+                "{:03X} changed {} ({:02X} -> {:02X})",
+                at, register, old, new
+            ),
+            StopReason::Exited => write!(f, "exited"),
+            StopReason::Fault(error) => write!(f, "{}", error),
+            StopReason::StartOfHistory => write!(f, "start of history"),
+        }
+    }
+}
+
+// コールスタックの1段。function はサブルーチンの先頭（呼び出し元の命令から求める）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub function: Option<usize>,
+    pub pc: usize, // 実行中の位置。呼び出し元の段では呼び出した命令
+}
+
+// 命令がキー入力や画面の更新を待っていて、PCが同じ命令に留まっている
+struct Waiting;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Paused,
+    Running,
+    Step,
+    StepOver { depth: usize }, // 呼び出しの深さがここまで戻ったら止まる
+    StepOut { depth: usize },  // この深さより浅くなったら止まる
+}
+
+// Cpu を包んでブレークポイントやウォッチポイントで止める
+//
+// step() や resume() などで動き方を決め、run() で実際に命令を実行する。
+// run() は指定した数の命令を実行するか、止まる理由があった時点で返る
+pub struct Debugger<T: KeyboardInput> {
+    cpu: Cpu<T>,
+    breakpoints: BTreeMap<usize, Trigger>,
+    watchpoints: Vec<(Watchpoint, Trigger)>,
+    logs: Vec<String>, // ログポイントが出したメッセージ
+    mode: Mode,
+    resuming: bool, // 再開した直後の命令はブレークポイントで止めない
+}
+
+impl<T: KeyboardInput> Debugger<T> {
+    // 一時停止した状態で始める
+    pub fn new(cpu: Cpu<T>) -> Self {
+        Debugger {
+            cpu,
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            logs: Vec::new(),
+            mode: Mode::Paused,
+            resuming: false,
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu<T> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu<T> {
+        &mut self.cpu
+    }
+
+    pub fn into_inner(self) -> Cpu<T> {
+        self.cpu
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, &Condition)> + '_ {
+        self.breakpoints
+            .iter()
+            .map(|(&address, trigger)| (address, &trigger.condition))
+    }
+
+    pub fn has_breakpoint(&self, address: usize) -> bool {
+        self.breakpoints.contains_key(&address)
+    }
+
+    // 新しく追加した場合は true
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.set_breakpoint(address, Condition::default())
+    }
+
+    // 既にある場合は条件を置き換える。条件が変わらなければ到達した回数はそのまま
+    pub fn set_breakpoint(&mut self, address: usize, condition: Condition) -> bool {
+        match self.breakpoints.get_mut(&address) {
+            Some(trigger) => {
+                if trigger.condition != condition {
+                    *trigger = Trigger::new(condition);
+                }
+                false
+            }
+            None => {
+                self.breakpoints.insert(address, Trigger::new(condition));
+                true
+            }
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (&Watchpoint, &Condition)> + '_ {
+        self.watchpoints
+            .iter()
+            .map(|(watchpoint, trigger)| (watchpoint, &trigger.condition))
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.set_watchpoint(watchpoint, Condition::default())
+    }
+
+    pub fn set_watchpoint(&mut self, watchpoint: Watchpoint, condition: Condition) -> bool {
+        if let Some((_, trigger)) = self
+            .watchpoints
+            .iter_mut()
+            .find(|(other, _)| *other == watchpoint)
+        {
+            if trigger.condition != condition {
+                *trigger = Trigger::new(condition);
+            }
+            return false;
+        }
+        self.watchpoints.push((watchpoint, Trigger::new(condition)));
+        self.update_memory_trace();
+        true
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|(other, _)| other != watchpoint);
+        self.update_memory_trace();
+        self.watchpoints.len() != len
+    }
+
+    // ログポイントが出したメッセージを取り出す
+    pub fn take_logs(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.logs)
+    }
+
+    // メモリのウォッチポイントがある間だけ読み書きを記録させる
+    fn update_memory_trace(&mut self) {
+        let memory = self
+            .watchpoints
+            .iter()
+            .any(|(watchpoint, _)| matches!(watchpoint, Watchpoint::Memory { .. }));
+        self.cpu.set_memory_trace(memory);
+    }
+
+    // 内側から順に並べたコールスタック
+    pub fn call_stack(&self) -> Vec<Frame> {
+        let stack = self.cpu.call_stack();
+        let memory = self.cpu.memory();
+        let mut frames = Vec::with_capacity(stack.len() + 1);
+        let mut pc = self.cpu.program_counter();
+        for &return_address in stack.iter().rev() {
+            let call_site = (return_address as usize).wrapping_sub(2);
+            let function = match Instruction::fetch(memory, call_site) {
+                Ok(Instruction::Call(nnn)) => Some(nnn as usize),
+                _ => None,
+            };
+            frames.push(Frame { function, pc });
+            pc = call_site;
+        }
+        frames.push(Frame {
+            function: Some(PROGRAM_START),
+            pc,
+        });
+        frames
+    }
+
+    fn start(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.resuming = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.start(Mode::Running);
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused;
+        self.resuming = false;
+    }
+
+    // 1命令だけ実行する
+    pub fn step(&mut self) {
+        self.start(Mode::Step);
+    }
+
+    // call の場合は戻ってくるまで実行する
+    pub fn step_over(&mut self) {
+        let depth = self.cpu.call_stack().len();
+        match Instruction::fetch(self.cpu.memory(), self.cpu.program_counter()) {
+            Ok(Instruction::Call(_)) => self.start(Mode::StepOver { depth }),
+            _ => self.start(Mode::Step),
+        }
+    }
+
+    // 実行中のサブルーチンから戻るまで実行する。最も外側では resume と同じ
+    pub fn step_out(&mut self) {
+        let depth = self.cpu.call_stack().len();
+        if depth == 0 {
+            self.resume();
+        } else {
+            self.start(Mode::StepOut { depth });
+        }
+    }
+
+    // 最大 budget 命令を実行する。止まった場合はその理由を返し、一時停止の状態になる
+    // 一時停止中は何もしない。キー入力や画面の更新を待つ命令では、
+    // タイマーを進めてもらうために途中で返る
+    pub fn run(&mut self, budget: usize) -> Option<StopReason> {
+        for _ in 0..budget {
+            if self.mode == Mode::Paused {
+                return None;
+            }
+            match self.execute_one() {
+                Ok(None) => {}
+                Ok(Some(reason)) => {
+                    self.pause();
+                    return Some(reason);
+                }
+                Err(Waiting) => return None,
+            }
+        }
+        None
+    }
+
+    // 監視しているレジスタの (watchpoints の位置, レジスタ, 今の値)
+    fn watched_registers(&self) -> Vec<(usize, Register, u16)> {
+        self.watchpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (watchpoint, _))| match watchpoint {
+                Watchpoint::Register(register) => {
+                    Some((index, *register, register.value(&self.cpu)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    // 直前の命令を1つ取り消す。Cpu の取り消し記録が有効な場合だけ戻れる
+    pub fn reverse_step(&mut self) -> StopReason {
+        self.pause();
+        self.resuming = true;
+        match self.cpu.undo() {
+            Some(_) => StopReason::Step,
+            None => StopReason::StartOfHistory,
+        }
+    }
+
+    // 命令を取り消していき、ブレークポイントかウォッチポイントに当たったら止まる
+    // メモリは書き込みだけを見る。止まった位置は、その命令を実行する直前になる
+    pub fn reverse_continue(&mut self) -> StopReason {
+        self.pause();
+        self.resuming = true;
+        loop {
+            let registers = self.watched_registers();
+            let Some(writes) = self.cpu.undo() else {
+                return StopReason::StartOfHistory;
+            };
+            let pc = self.cpu.program_counter();
+
+            for &(address, old, new) in &writes {
+                let watched = self.watchpoints.iter().any(|(watchpoint, trigger)| {
+                    matches!(watchpoint, Watchpoint::Memory { address: a, kind }
+                        if *a == address && kind.matches(MemoryAccess::Write))
+                        && trigger.holds(&self.cpu)
+                });
+                if watched {
+                    return StopReason::Memory {
+                        at: pc,
+                        address,
+                        access: MemoryAccess::Write,
+                        old,
+                        new,
+                    };
+                }
+            }
+            for (index, register, new) in registers {
+                let old = register.value(&self.cpu);
+                if old != new && self.watchpoints[index].1.holds(&self.cpu) {
+                    return StopReason::Register {
+                        at: pc,
+                        register,
+                        old,
+                        new,
+                    };
+                }
+            }
+            if self
+                .breakpoints
+                .get(&pc)
+                .is_some_and(|trigger| trigger.holds(&self.cpu))
+            {
+                return StopReason::Breakpoint;
+            }
+        }
+    }
+
+    fn execute_one(&mut self) -> Result<Option<StopReason>, Waiting> {
+        let pc = self.cpu.program_counter();
+        if !self.resuming {
+            if let Some(trigger) = self.breakpoints.get_mut(&pc) {
+                if trigger.hit(&self.cpu, &mut self.logs) {
+                    return Ok(Some(StopReason::Breakpoint));
+                }
+            }
+        }
+
+        let registers = self.watched_registers();
+        let memory: Vec<(usize, u8)> = self
+            .watchpoints
+            .iter()
+            .filter_map(|(watchpoint, _)| match watchpoint {
+                Watchpoint::Memory { address, .. } => {
+                    Some((*address, self.cpu.memory().get(*address).copied()?))
+                }
+                _ => None,
+            })
+            .collect();
+
+        match self.cpu.update() {
+            Ok(StepOutcome::Executed) => self.resuming = false,
+            // PCは同じ命令に留まっているので、次の run() でブレークポイントに止まらないようにする
+            Ok(StepOutcome::WaitingForKey | StepOutcome::WaitingForVblank) => {
+                self.resuming = true;
+                return Err(Waiting);
+            }
+            Ok(StepOutcome::Exited) => return Ok(Some(StopReason::Exited)),
+            Err(error) => return Ok(Some(StopReason::Fault(error))),
+        }
+
+        for &(address, access) in self.cpu.memory_trace() {
+            let mut stop = false;
+            for (watchpoint, trigger) in &mut self.watchpoints {
+                let watched = matches!(watchpoint, Watchpoint::Memory { address: a, kind }
+                    if *a == address && kind.matches(access));
+                // 条件が偽でも到達した回数は数える
+                if watched && trigger.hit(&self.cpu, &mut self.logs) {
+                    stop = true;
+                }
+            }
+            if !stop {
+                continue;
+            }
+            let new = self.cpu.memory()[address];
+            let old = match access {
+                MemoryAccess::Read => new,
+                MemoryAccess::Write => memory
+                    .iter()
+                    .find(|(a, _)| *a == address)
+                    .map_or(new, |&(_, value)| value),
+            };
+            return Ok(Some(StopReason::Memory {
+                at: pc,
+                address,
+                access,
+                old,
+                new,
+            }));
+        }
+        for (index, register, old) in registers {
+            let new = register.value(&self.cpu);
+            if new != old && self.watchpoints[index].1.hit(&self.cpu, &mut self.logs) {
+                return Ok(Some(StopReason::Register {
+                    at: pc,
+                    register,
+                    old,
+                    new,
+                }));
+            }
+        }
+
+        let depth = self.cpu.call_stack().len();
+        let done = match self.mode {
+            Mode::Step => true,
+            Mode::StepOver { depth: target } => depth <= target,
+            Mode::StepOut { depth: target } => depth < target,
+            Mode::Running | Mode::Paused => false,
+        };
+        Ok(done.then_some(StopReason::Step))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::keyboard::NoKeyboard;
+
+    // 200: v0 := 1 / 202: sub / 204: v1 := 2 / 206: loop again
+    // 208: sub  v2 := 3 / 20A: sub2 / 20C: return
+    // 20E: sub2 i := buf / 210: save v0 / 212: return / 214: buf
+    const PROGRAM: &str = "
+        v0 := 1
+        sub
+        v1 := 2
+        loop again
+        : sub
+        v2 := 3
+        sub2
+        return
+        : sub2
+        i := buf
+        save v0
+        return
+        : buf 0";
+
+    fn debugger() -> Debugger<NoKeyboard> {
+        let rom = assemble(PROGRAM).unwrap();
+        Debugger::new(Cpu::from_bytes(&rom, NoKeyboard))
+    }
+
+    fn pc(debugger: &Debugger<NoKeyboard>) -> usize {
+        debugger.cpu().program_counter()
+    }
+
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.run(10), None);
+        assert_eq!(pc(&debugger), 0x200);
+
+        debugger.add_breakpoint(0x20E);
+        debugger.resume();
+        assert_eq!(debugger.run(100), Some(StopReason::Breakpoint));
+        assert_eq!(pc(&debugger), 0x20E);
+        assert_eq!(
+            debugger.call_stack(),
+            [
+                Frame {
+                    function: Some(0x20E),
+                    pc: 0x20E
+                },
+                Frame {
+                    function: Some(0x208),
+                    pc: 0x20A
+                },
+                Frame {
+                    function: Some(0x200),
+                    pc: 0x202
+                },
+            ]
+        );
+
+        debugger.step();
+        assert_eq!(debugger.run(100), Some(StopReason::Step));
+        assert_eq!(pc(&debugger), 0x210);
+        debugger.step_out();
+        assert_eq!(debugger.run(100), Some(StopReason::Step));
+        assert_eq!(pc(&debugger), 0x20C);
+        debugger.step_out();
+        assert_eq!(debugger.run(100), Some(StopReason::Step));
+        assert_eq!(pc(&debugger), 0x204);
+
+        // 最も外側では止まらずに走り続ける
+        debugger.step_out();
+        assert_eq!(debugger.run(100), None);
+        assert_eq!(pc(&debugger), 0x206);
+    }
+
+    #[test]
+    fn test_step_over() {
+        let mut debugger = debugger();
+        debugger.step();
+        debugger.run(1);
+        debugger.step_over();
+        assert_eq!(debugger.run(100), Some(StopReason::Step));
+        assert_eq!(pc(&debugger), 0x204);
+        assert_eq!(debugger.cpu().registers()[2], 3);
+
+        // 呼び出し先のブレークポイントでは止まる
+        let mut debugger = self::debugger();
+        debugger.add_breakpoint(0x210);
+        debugger.step();
+        debugger.run(1);
+        debugger.step_over();
+        assert_eq!(debugger.run(100), Some(StopReason::Breakpoint));
+        assert_eq!(pc(&debugger), 0x210);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut debugger = debugger();
+        debugger.add_watchpoint(Watchpoint::Memory {
+            address: 0x214,
+            kind: WatchKind::Write,
+        });
+        debugger.add_watchpoint(Watchpoint::Register("v1".parse().unwrap()));
+        debugger.resume();
+        let stop = debugger.run(100).unwrap();
+        assert_eq!(stop.to_string(), "210 wrote 214 (00 -> 01)");
+        debugger.resume();
+        let stop = debugger.run(100).unwrap();
+        assert_eq!(stop.to_string(), "204 changed V1 (00 -> 02)");
+        debugger.resume();
+        assert_eq!(debugger.run(100), None);
+
+        // 読み出しのウォッチポイントは書き込みでは止まらない
+        let mut debugger = self::debugger();
+        debugger.add_watchpoint(Watchpoint::Memory {
+            address: 0x214,
+            kind: WatchKind::Read,
+        });
+        debugger.resume();
+        assert_eq!(debugger.run(100), None);
+    }
+
+    #[test]
+    fn test_conditions_and_logpoints() {
+        let condition = |expression: &str, log: &str| Condition {
+            expression: (!expression.is_empty())
+                .then(|| Expression::condition(expression).unwrap()),
+            log: (!log.is_empty()).then(|| LogMessage::parse(log).unwrap()),
+        };
+        let mut debugger = debugger();
+        debugger.set_breakpoint(0x206, condition("hit_count == 4 && v1 == 2", ""));
+        debugger.set_breakpoint(0x208, condition("v0 == 7", ""));
+        debugger.set_breakpoint(0x20E, condition("", "sub2 from {sp} levels, v2={v2:x}"));
+        debugger.set_watchpoint(
+            Watchpoint::Memory {
+                address: 0x214,
+                kind: WatchKind::Write,
+            },
+            condition("mem[0x214] == 1", "wrote {mem[i]}"),
+        );
+        debugger.resume();
+        assert_eq!(debugger.run(100), Some(StopReason::Breakpoint));
+        assert_eq!(pc(&debugger), 0x206);
+        assert_eq!(debugger.cpu().registers()[1], 2);
+        assert_eq!(
+            debugger.take_logs(),
+            ["sub2 from 2 levels, v2=3", "wrote 1"]
+        );
+        assert!(debugger.take_logs().is_empty());
+
+        // 同じ条件を設定し直しても到達した回数は保たれる
+        debugger.set_breakpoint(0x206, condition("hit_count == 4 && v1 == 2", ""));
+        debugger.resume();
+        assert_eq!(debugger.run(100), None);
+        let breakpoints: Vec<String> = debugger
+            .breakpoints()
+            .map(|(address, condition)| format!("{:03X} {}", address, condition))
+            .collect();
+        assert_eq!(
+            breakpoints,
+            [
+                "206 if hit_count == 4 && v1 == 2",
+                "208 if v0 == 7",
+                "20E log sub2 from {sp} levels, v2={v2:x}"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reverse_execution() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.reverse_step(), StopReason::StartOfHistory);
+        debugger.cpu_mut().set_journal(Some(100));
+        debugger.add_breakpoint(0x20E);
+        debugger.add_watchpoint(Watchpoint::Memory {
+            address: 0x214,
+            kind: WatchKind::Write,
+        });
+        debugger.resume();
+        assert_eq!(debugger.run(100), Some(StopReason::Breakpoint));
+        debugger.resume();
+        assert!(debugger.run(100).is_some());
+        debugger.resume();
+        assert_eq!(debugger.run(100), None);
+        assert_eq!(pc(&debugger), 0x206);
+
+        // 書き込んだ命令の直前まで戻る
+        let stop = debugger.reverse_continue();
+        assert_eq!(stop.to_string(), "210 wrote 214 (00 -> 01)");
+        assert_eq!(pc(&debugger), 0x210);
+        assert_eq!(debugger.cpu().memory()[0x214], 0);
+        assert_eq!(debugger.reverse_continue(), StopReason::Breakpoint);
+        assert_eq!(pc(&debugger), 0x20E);
+        assert_eq!(debugger.reverse_step(), StopReason::Step);
+        assert_eq!(pc(&debugger), 0x20A);
+        assert_eq!(debugger.cpu().call_stack().len(), 1);
+
+        debugger.add_watchpoint(Watchpoint::Register(Register::V(0)));
+        let stop = debugger.reverse_continue();
+        assert_eq!(stop.to_string(), "200 changed V0 (00 -> 01)");
+        assert_eq!(debugger.reverse_step(), StopReason::StartOfHistory);
+        assert!(debugger.is_paused());
+
+        // 戻った位置から普通に実行を続けられる
+        debugger.step();
+        assert!(debugger.run(100).is_some());
+        assert_eq!(pc(&debugger), 0x202);
+        assert_eq!(debugger.cpu().registers()[0], 1);
+    }
+}
@@ -0,0 +1,834 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::cfg::analyze;
+use crate::disassembler::{disassemble, to_octo, Line, LineKind};
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+
+const PROGRAM_START: usize = 0x200;
+
+// よく使われる命令列をまとめるマクロ。使われたものだけ出力の先頭で定義する
+const IDIOMS: [(&str, &str); 2] = [
+    (
+        "wait_delay",
+        ":macro wait_delay REG { delay := REG loop REG := delay if REG != 0 then again }",
+    ),
+    (
+        "bcd_digits",
+        ":macro bcd_digits REG BUFFER { i := BUFFER bcd REG load v2 }",
+    ),
+];
+
+// レジスタの使われ方。1つの使われ方しかないレジスタに名前を付ける
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Role {
+    PositionX,
+    PositionY,
+    KeyPress,
+    Timer,
+    Number,
+    Digit,
+    Random,
+    Scratch, // Fx65 などでまとめて読み込まれる
+}
+
+impl Role {
+    fn name(&self) -> Option<&'static str> {
+        match self {
+            Role::PositionX => Some("pos_x"),
+            Role::PositionY => Some("pos_y"),
+            Role::KeyPress => Some("keypress"),
+            Role::Timer => Some("timer"),
+            Role::Number => Some("number"),
+            Role::Digit => Some("digit"),
+            Role::Random => Some("rand"),
+            Role::Scratch => None,
+        }
+    }
+}
+
+enum Stmt {
+    Op(usize),   // lines の添字
+    Data(usize), // lines の添字
+    // if 条件 then 文（文が範囲外ならNone）
+    IfThen {
+        at: usize,
+        then: Option<Box<Stmt>>,
+    },
+    IfBlock {
+        at: usize,
+        then: Vec<Stmt>,
+        otherwise: Option<Vec<Stmt>>,
+    },
+    Loop {
+        at: usize,
+        body: Vec<Stmt>,
+        close: Option<usize>,
+        sprites: bool,
+    },
+    While(usize),
+    Idiom {
+        at: usize,
+        name: &'static str,
+        args: Vec<Arg>,
+    },
+}
+
+enum Arg {
+    Register(u8),
+    Address(u16),
+}
+
+struct LoopContext {
+    exit: usize,
+}
+
+struct Decompiler<'a> {
+    lines: &'a [Line],
+    index: BTreeMap<usize, usize>, // アドレス -> lines の添字
+    labels: BTreeMap<usize, String>,
+    references: BTreeMap<usize, usize>,
+    entries: BTreeMap<usize, BTreeSet<usize>>, // 関数の入口と呼び出し先
+    region_end: Vec<usize>,
+}
+
+// ROMを構造化したOcto風のソースに変換する
+// 制御フローグラフの関数ごとに、スキップとジャンプの組を if / loop / while に戻す
+// アドレス順に出力するので、結果をアセンブルすると元のROMと同じになる
+pub fn decompile(rom: &[u8], platform: Option<Platform>) -> String {
+    let disassembly = disassemble(rom, platform);
+    let graph = analyze(rom, platform);
+    let lines = disassembly.lines();
+
+    let mut labels = disassembly.labels().clone();
+    labels.insert(PROGRAM_START, "main".to_string());
+    let entries = graph
+        .functions()
+        .values()
+        .map(|function| (function.entry, function.callees.clone()))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut references: BTreeMap<usize, usize> = BTreeMap::new();
+    for line in lines {
+        if let LineKind::Code(instruction) = line.kind {
+            use Instruction::*;
+            if let Jp(nnn) | JpV0(nnn) | Call(nnn) | LdI(nnn) | LdILong(nnn) = instruction {
+                *references.entry(nnn as usize).or_default() += 1;
+            }
+        }
+    }
+
+    // 構造は関数の入口やデータをまたがない
+    let mut region_end = vec![lines.len(); lines.len()];
+    let mut end = lines.len();
+    for (i, line) in lines.iter().enumerate().rev() {
+        region_end[i] = end;
+        if line.kind == LineKind::Data || entries.contains_key(&line.address) {
+            end = i;
+        }
+    }
+
+    let decompiler = Decompiler {
+        lines,
+        index: lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| (line.address, i))
+            .collect(),
+        labels,
+        references,
+        entries,
+        region_end,
+    };
+    let mut program = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i].kind == LineKind::Data {
+            program.push(Stmt::Data(i));
+            i += 1;
+            continue;
+        }
+        let end = decompiler.region_end[i];
+        program.extend(decompiler.parse(i, end, None));
+        i = end;
+    }
+    decompiler.render(&program)
+}
+
+impl Decompiler<'_> {
+    fn instruction(&self, i: usize) -> Option<Instruction> {
+        match self.lines.get(i)?.kind {
+            LineKind::Code(instruction) => Some(instruction),
+            LineKind::Data => None,
+        }
+    }
+
+    fn is_skip(&self, i: usize) -> bool {
+        use Instruction::*;
+        matches!(
+            self.instruction(i),
+            Some(SeByte(..) | SneByte(..) | SeXy(..) | SneXy(..) | Skp(_) | Sknp(_))
+        )
+    }
+
+    fn jump_target(&self, i: usize) -> Option<usize> {
+        match self.instruction(i)? {
+            Instruction::Jp(nnn) => Some(nnn as usize),
+            _ => None,
+        }
+    }
+
+    // 構造の途中に置く命令は、他から参照されていてはいけない
+    fn unreferenced(&self, i: usize) -> bool {
+        let address = self.lines[i].address;
+        !self.references.contains_key(&address) && !self.entries.contains_key(&address)
+    }
+
+    // start..end の命令列を構造化する
+    fn parse(&self, mut i: usize, end: usize, context: Option<&LoopContext>) -> Vec<Stmt> {
+        let mut stmts = Vec::new();
+        while i < end {
+            if let Some((stmt, next)) = self.parse_loop(i, end) {
+                stmts.push(stmt);
+                i = next;
+                continue;
+            }
+            if let Some((stmt, next)) = self.parse_idiom(i, end) {
+                stmts.push(stmt);
+                i = next;
+                continue;
+            }
+            if self.is_skip(i) {
+                let (stmt, next) = self.parse_skip(i, end, context);
+                stmts.push(stmt);
+                i = next;
+                continue;
+            }
+            stmts.push(Stmt::Op(i));
+            i += 1;
+        }
+        stmts
+    }
+
+    // 先頭に戻るジャンプのうち一番後ろのものまでをループにする
+    // 同じ先頭に戻るジャンプが他にもあれば、内側のループになる
+    fn parse_loop(&self, i: usize, end: usize) -> Option<(Stmt, usize)> {
+        let header = self.lines[i].address;
+        let j = (i..end)
+            .rev()
+            .find(|&j| self.jump_target(j) == Some(header) && (j == i || self.unreferenced(j)))?;
+        // 直前がスキップなら条件付きで戻る（if 条件 then again）
+        let conditional = j > i && self.is_skip(j - 1) && !(j > i + 1 && self.is_skip(j - 2));
+        let (body_end, close) = if conditional {
+            (j - 1, Some(j - 1))
+        } else {
+            (j, None)
+        };
+        let context = LoopContext {
+            exit: self
+                .lines
+                .get(j + 1)
+                .map_or(usize::MAX, |line| line.address),
+        };
+        let body = self.parse(i, body_end, Some(&context));
+        let sprites = body.iter().any(|stmt| match stmt {
+            Stmt::Op(k) => matches!(self.instruction(*k), Some(Instruction::Drw(..))),
+            _ => false,
+        }) && body.iter().any(|stmt| match stmt {
+            Stmt::Op(k) => matches!(self.instruction(*k), Some(Instruction::AddIVx(_))),
+            _ => false,
+        });
+        Some((
+            Stmt::Loop {
+                at: i,
+                body,
+                close,
+                sprites,
+            },
+            j + 1,
+        ))
+    }
+
+    fn parse_skip(&self, i: usize, end: usize, context: Option<&LoopContext>) -> (Stmt, usize) {
+        let next = i + 1;
+        // 飛ばす命令が構造の外にあるか、ジャンプ先になっている場合は、
+        // その命令を if の後ろにそのまま置く（if 条件 then の次の行が飛ばす命令）
+        if next >= end || !self.unreferenced(next) {
+            return (Stmt::IfThen { at: i, then: None }, next);
+        }
+
+        if let Some(target) = self.jump_target(next).filter(|_| self.unreferenced(next)) {
+            // ループを抜けるジャンプを飛ばす: while 条件
+            if context.is_some_and(|context| context.exit == target) {
+                return (Stmt::While(i), next + 1);
+            }
+            // 前方へのジャンプを飛ばす: if 条件 begin ... [else ...] end
+            if target > self.lines[next].address {
+                if let Some(stmt) = self.parse_block(i, target, end, context) {
+                    return stmt;
+                }
+            }
+        }
+
+        let (then, after) = if self.is_skip(next) {
+            self.parse_skip(next, end, context)
+        } else {
+            (Stmt::Op(next), next + 1)
+        };
+        (
+            Stmt::IfThen {
+                at: i,
+                then: Some(Box::new(then)),
+            },
+            after,
+        )
+    }
+
+    fn parse_block(
+        &self,
+        i: usize,
+        target: usize,
+        end: usize,
+        context: Option<&LoopContext>,
+    ) -> Option<(Stmt, usize)> {
+        let e = *self.index.get(&target)?;
+        // 節の最後のスキップは節の外の命令を飛ばすので、end の後ろに回ってしまう
+        if e > end || self.is_skip(e - 1) {
+            return None;
+        }
+        // then 節の最後が else の先へのジャンプなら if ... else ... end
+        let otherwise = (e > i + 2 && self.unreferenced(e - 1) && !self.is_skip(e - 2))
+            .then(|| self.jump_target(e - 1))
+            .flatten()
+            .filter(|&after| after > target)
+            .and_then(|after| self.index.get(&after).copied())
+            .filter(|&f| f <= end && !self.is_skip(f - 1));
+
+        Some(match otherwise {
+            Some(f) => (
+                Stmt::IfBlock {
+                    at: i,
+                    then: self.parse(i + 2, e - 1, context),
+                    otherwise: Some(self.parse(e, f, context)),
+                },
+                f,
+            ),
+            None => (
+                Stmt::IfBlock {
+                    at: i,
+                    then: self.parse(i + 2, e, context),
+                    otherwise: None,
+                },
+                e,
+            ),
+        })
+    }
+
+    fn parse_idiom(&self, i: usize, end: usize) -> Option<(Stmt, usize)> {
+        use Instruction::*;
+        let at = |k: usize| (k < end).then(|| self.instruction(k)).flatten();
+
+        // delay := vX / loop vX := delay if vX != 0 then again
+        if let Some(LdDtVx(x)) = at(i) {
+            let wait = i + 3 < end
+                && at(i + 1) == Some(LdVxDt(x))
+                && at(i + 2) == Some(SeByte(x, 0))
+                && self.jump_target(i + 3) == Some(self.lines[i + 1].address)
+                && self.references.get(&self.lines[i + 1].address) == Some(&1)
+                && !self.entries.contains_key(&self.lines[i + 1].address)
+                && self.unreferenced(i + 2)
+                && self.unreferenced(i + 3);
+            if wait {
+                let args = vec![Arg::Register(x)];
+                return Some((
+                    Stmt::Idiom {
+                        at: i,
+                        name: "wait_delay",
+                        args,
+                    },
+                    i + 4,
+                ));
+            }
+        }
+
+        // i := BUFFER / bcd vX / load v2 で10進数の各桁を v0 - v2 に読み込む
+        if let Some(LdI(buffer)) = at(i) {
+            if let (Some(LdBVx(x)), Some(LdVxI(2))) = (at(i + 1), at(i + 2)) {
+                if self.unreferenced(i + 1) && self.unreferenced(i + 2) {
+                    let args = vec![Arg::Register(x), Arg::Address(buffer)];
+                    return Some((
+                        Stmt::Idiom {
+                            at: i,
+                            name: "bcd_digits",
+                            args,
+                        },
+                        i + 3,
+                    ));
+                }
+            }
+        }
+        None
+    }
+
+    // レジスタの使われ方を調べて別名を決める
+    fn aliases(&self) -> BTreeMap<u8, String> {
+        use Instruction::*;
+        let mut roles: BTreeMap<u8, BTreeSet<Role>> = BTreeMap::new();
+        let mut add = |register: u8, role| {
+            roles.entry(register).or_default().insert(role);
+        };
+        for line in self.lines {
+            let LineKind::Code(instruction) = line.kind else {
+                continue;
+            };
+            match instruction {
+                Drw(x, y, _) => {
+                    add(x, Role::PositionX);
+                    add(y, Role::PositionY);
+                }
+                LdVxK(x) | Skp(x) | Sknp(x) => add(x, Role::KeyPress),
+                LdVxDt(x) | LdDtVx(x) => add(x, Role::Timer),
+                LdBVx(x) => add(x, Role::Number),
+                LdFVx(x) | LdHfVx(x) => add(x, Role::Digit),
+                Rnd(x, _) => add(x, Role::Random),
+                LdVxI(x) | LdVxR(x) => (0..=x).for_each(|register| add(register, Role::Scratch)),
+                LoadXy(x, y) => {
+                    (x.min(y)..=x.max(y)).for_each(|register| add(register, Role::Scratch))
+                }
+                _ => {}
+            }
+        }
+
+        let single: Vec<(u8, Role)> = roles
+            .into_iter()
+            .filter(|(register, roles)| *register != 0xF && roles.len() == 1)
+            .filter_map(|(register, roles)| Some((register, *roles.first()?)))
+            .collect();
+        single
+            .iter()
+            .filter_map(|&(register, role)| {
+                let name = role.name()?;
+                // 同じ使われ方のレジスタが複数あればレジスタ番号を付ける
+                let shared = single.iter().filter(|(_, other)| *other == role).count() > 1;
+                if shared {
+                    Some((register, format!("{}{:x}", name, register)))
+                } else {
+                    Some((register, name.to_string()))
+                }
+            })
+            .collect()
+    }
+
+    fn render(&self, program: &[Stmt]) -> String {
+        let aliases = self.aliases();
+        let used_labels = RefCell::new(BTreeSet::new());
+        let used_idioms = RefCell::new(BTreeSet::new());
+        let renderer = Renderer {
+            decompiler: self,
+            aliases: &aliases,
+            used_labels: &used_labels,
+            used_idioms: &used_idioms,
+            placed_labels: RefCell::new(BTreeSet::new()),
+        };
+
+        let mut body = Vec::new();
+        for stmt in program {
+            renderer.stmt(stmt, 1, &mut body);
+        }
+
+        let mut out = String::new();
+        for (register, name) in &aliases {
+            writeln!(out, ":alias {} v{:x}", name, register).unwrap();
+        }
+        for (name, definition) in IDIOMS {
+            if used_idioms.borrow().contains(name) {
+                writeln!(out, "{}", definition).unwrap();
+            }
+        }
+
+        // 参照されたラベルと関数の入口だけを出力する
+        let used_labels = used_labels.into_inner();
+        for line in body {
+            match line {
+                Output::Label(address) => {
+                    let entry = self.entries.get(&address);
+                    if entry.is_none()
+                        && address != PROGRAM_START
+                        && !used_labels.contains(&address)
+                    {
+                        continue;
+                    }
+                    if entry.is_some() {
+                        out.push('\n');
+                    }
+                    write!(out, ": {}", self.labels[&address]).unwrap();
+                    if let Some(callees) = entry.filter(|callees| !callees.is_empty()) {
+                        let names: Vec<String> = callees
+                            .iter()
+                            .map(|callee| self.label_name(*callee))
+                            .collect();
+                        write!(out, " # calls {}", names.join(", ")).unwrap();
+                    }
+                    out.push('\n');
+                }
+                Output::Text(depth, text) => {
+                    writeln!(out, "{}{}", "\t".repeat(depth), text).unwrap();
+                }
+            }
+        }
+        out
+    }
+
+    fn label_name(&self, address: usize) -> String {
+        match self.labels.get(&address) {
+            Some(name) => name.clone(),
+            None => format!("0x{:03X}", address),
+        }
+    }
+}
+
+enum Output {
+    Label(usize),
+    Text(usize, String),
+}
+
+struct Renderer<'a> {
+    decompiler: &'a Decompiler<'a>,
+    aliases: &'a BTreeMap<u8, String>,
+    used_labels: &'a RefCell<BTreeSet<usize>>,
+    used_idioms: &'a RefCell<BTreeSet<&'static str>>,
+    placed_labels: RefCell<BTreeSet<usize>>,
+}
+
+impl Renderer<'_> {
+    fn register(&self, x: u8) -> String {
+        match self.aliases.get(&x) {
+            Some(name) => name.clone(),
+            None => format!("v{:x}", x),
+        }
+    }
+
+    fn address(&self, nnn: u16) -> String {
+        let address = nnn as usize;
+        match self.decompiler.labels.get(&address) {
+            Some(name) => {
+                self.used_labels.borrow_mut().insert(address);
+                name.clone()
+            }
+            None => format!("0x{:03X}", nnn),
+        }
+    }
+
+    // 命令1つ分のテキスト（レジスタは別名に置き換える）
+    fn text(&self, i: usize) -> String {
+        let line = &self.decompiler.lines[i];
+        let octo = match line.kind {
+            LineKind::Code(instruction) => to_octo(&instruction, |nnn| {
+                self.decompiler
+                    .labels
+                    .contains_key(&(nnn as usize))
+                    .then(|| self.address(nnn))
+            }),
+            LineKind::Data => None,
+        };
+        match octo {
+            Some(text) => text
+                .split(' ')
+                .map(|token| match parse_register(token) {
+                    Some(x) => self.register(x),
+                    None => token.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            None => {
+                let bytes: Vec<String> = line
+                    .bytes
+                    .iter()
+                    .map(|byte| format!("0x{:02X}", byte))
+                    .collect();
+                bytes.join(" ")
+            }
+        }
+    }
+
+    // スキップ命令が飛ばすとき（skipped = true）に成り立つ条件
+    fn condition(&self, i: usize, skipped: bool) -> String {
+        use Instruction::*;
+        let (x, operand, equal) = match self.decompiler.instruction(i) {
+            Some(SeByte(x, kk)) => (x, format!("0x{:02X}", kk), true),
+            Some(SneByte(x, kk)) => (x, format!("0x{:02X}", kk), false),
+            Some(SeXy(x, y)) => (x, self.register(y), true),
+            Some(SneXy(x, y)) => (x, self.register(y), false),
+            Some(Skp(x)) => {
+                return format!(
+                    "{} {}",
+                    self.register(x),
+                    if skipped { "key" } else { "-key" }
+                )
+            }
+            Some(Sknp(x)) => {
+                return format!(
+                    "{} {}",
+                    self.register(x),
+                    if skipped { "-key" } else { "key" }
+                )
+            }
+            _ => unreachable!(),
+        };
+        let operator = if equal == skipped { "==" } else { "!=" };
+        format!("{} {} {}", self.register(x), operator, operand)
+    }
+
+    // 同じ位置から始まる構造が重なっている場合も、ラベルは一度だけ置く
+    fn label(&self, i: usize, out: &mut Vec<Output>) {
+        let address = self.decompiler.lines[i].address;
+        if self.decompiler.labels.contains_key(&address)
+            && self.placed_labels.borrow_mut().insert(address)
+        {
+            out.push(Output::Label(address));
+        }
+    }
+
+    // 1行にまとめた文（if ... then の後ろに続ける）
+    fn inline(&self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::IfThen { at, then } => {
+                let mut text = format!("if {} then", self.condition(*at, false));
+                if let Some(then) = then {
+                    text.push(' ');
+                    text.push_str(&self.inline(then));
+                }
+                text
+            }
+            Stmt::Op(i) => self.text(*i),
+            _ => unreachable!(),
+        }
+    }
+
+    fn stmt(&self, stmt: &Stmt, depth: usize, out: &mut Vec<Output>) {
+        match stmt {
+            Stmt::Op(i) | Stmt::Data(i) => {
+                self.label(*i, out);
+                out.push(Output::Text(depth, self.text(*i)));
+            }
+            Stmt::IfThen { at, .. } => {
+                self.label(*at, out);
+                out.push(Output::Text(depth, self.inline(stmt)));
+            }
+            Stmt::IfBlock {
+                at,
+                then,
+                otherwise,
+            } => {
+                self.label(*at, out);
+                out.push(Output::Text(
+                    depth,
+                    format!("if {} begin", self.condition(*at, true)),
+                ));
+                for stmt in then {
+                    self.stmt(stmt, depth + 1, out);
+                }
+                if let Some(otherwise) = otherwise {
+                    out.push(Output::Text(depth, "else".to_string()));
+                    for stmt in otherwise {
+                        self.stmt(stmt, depth + 1, out);
+                    }
+                }
+                out.push(Output::Text(depth, "end".to_string()));
+            }
+            Stmt::Loop {
+                at,
+                body,
+                close,
+                sprites,
+            } => {
+                self.label(*at, out);
+                let header = if *sprites {
+                    "loop # sprite loop"
+                } else {
+                    "loop"
+                };
+                out.push(Output::Text(depth, header.to_string()));
+                for stmt in body {
+                    self.stmt(stmt, depth + 1, out);
+                }
+                let close = match close {
+                    Some(skip) => {
+                        self.label(*skip, out);
+                        format!("if {} then again", self.condition(*skip, false))
+                    }
+                    None => "again".to_string(),
+                };
+                out.push(Output::Text(depth, close));
+            }
+            Stmt::While(i) => {
+                self.label(*i, out);
+                out.push(Output::Text(
+                    depth,
+                    format!("while {}", self.condition(*i, true)),
+                ));
+            }
+            Stmt::Idiom { at, name, args } => {
+                self.label(*at, out);
+                self.used_idioms.borrow_mut().insert(name);
+                let args: Vec<String> = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Arg::Register(x) => self.register(*x),
+                        Arg::Address(nnn) => self.address(*nnn),
+                    })
+                    .collect();
+                out.push(Output::Text(depth, format!("{} {}", name, args.join(" "))));
+            }
+        }
+    }
+}
+
+fn parse_register(token: &str) -> Option<u8> {
+    let digit = token.strip_prefix('v')?;
+    (digit.len() == 1)
+        .then(|| u8::from_str_radix(digit, 16).ok())
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn statements(text: &str) -> Vec<String> {
+        text.lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| {
+                !line.is_empty() && !line.starts_with(":alias") && !line.starts_with(":macro")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_structured_control_flow() {
+        let source = "
+            : main
+                v1 := 0
+                loop
+                    v1 += 1
+                    while v1 != 8
+                    if v1 == 3 begin
+                        v2 := 1
+                    else
+                        v2 := 2
+                    end
+                    if v2 == 1 then v3 += 1
+                again
+                loop
+                    v1 += 1
+                    if v1 != 0 then again
+                loop again
+        ";
+        let rom = assemble(source).unwrap();
+        let text = decompile(&rom, None);
+        assert_eq!(
+            statements(&text),
+            vec![
+                ": main",
+                "v1 := 0x00",
+                "loop",
+                "v1 += 0x01",
+                "while v1 != 0x08",
+                "if v1 == 0x03 begin",
+                "v2 := 0x01",
+                "else",
+                "v2 := 0x02",
+                "end",
+                "if v2 == 0x01 then v3 += 0x01",
+                "again",
+                "loop",
+                "v1 += 0x01",
+                "if v1 != 0x00 then again",
+                "loop",
+                "again",
+            ]
+        );
+        assert_eq!(assemble(&text).unwrap(), rom);
+    }
+
+    #[test]
+    fn test_skip_leaving_block() {
+        // ブロックの最後のスキップはブロックの後ろの命令を飛ばす
+        let source = "
+            : main
+                if v1 == 1 begin
+                    v2 := 1
+                    if v3 != 2 then
+                end
+                v4 := 1
+                loop again
+        ";
+        let rom = assemble(source).unwrap();
+        let text = decompile(&rom, None);
+        assert_eq!(
+            statements(&text),
+            vec![
+                ": main",
+                "if v1 != 0x01 then jump label_208",
+                "v2 := 0x01",
+                "if v3 != 0x02 then",
+                ": label_208",
+                "v4 := 0x01",
+                "loop",
+                "again",
+            ]
+        );
+        assert_eq!(assemble(&text).unwrap(), rom);
+    }
+
+    #[test]
+    fn test_idioms_and_register_names() {
+        let source = "
+            : main
+                v6 := random 0x3F
+                v5 := 42
+                v0 := 10
+                delay := v0
+                loop
+                    v0 := delay
+                    if v0 != 0 then
+                again
+                i := digits
+                bcd v5
+                load v2
+                show
+                loop again
+            : show
+                i := hex v0
+                sprite v3 v4 5
+                return
+            : digits
+                0 0 0
+        ";
+        let rom = assemble(source).unwrap();
+        let text = decompile(&rom, None);
+        let lines = statements(&text);
+        assert!(text.contains(":alias rand v6"));
+        assert!(text.contains(":alias pos_x v3"));
+        assert!(text.contains(":macro wait_delay"));
+        assert!(lines.contains(&"wait_delay v0".to_string()));
+        assert!(lines.contains(&"bcd_digits number data_21E".to_string()));
+        assert!(lines.contains(&": sub_218".to_string()));
+        assert_eq!(assemble(&text).unwrap(), rom);
+    }
+
+    #[test]
+    fn test_bundled_roms_round_trip() {
+        for rom in [
+            &include_bytes!("../rom/BRIX")[..],
+            &include_bytes!("../rom/INVADERS")[..],
+            &include_bytes!("../rom/GUESS")[..],
+        ] {
+            let text = decompile(rom, None);
+            assert_eq!(assemble(&text).unwrap(), rom, "{}", text);
+        }
+    }
+}
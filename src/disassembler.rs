@@ -0,0 +1,365 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::instruction::{Instruction, LONG_PREFIX};
+use crate::platform::Platform;
+
+const PROGRAM_START: usize = 0x200;
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    Code(Instruction),
+    Data, // 実行が到達しないバイト
+}
+
+// リスティングの1行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    pub kind: LineKind,
+}
+
+// ラベルの種類（名前の付け方と優先順位）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Jump,
+    Subroutine,
+}
+
+pub struct Disassembly {
+    lines: Vec<Line>,
+    labels: BTreeMap<usize, String>,
+}
+
+impl Disassembly {
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    // アドレスとラベル名の対応（行の先頭に置けたものだけ）
+    pub fn labels(&self) -> &BTreeMap<usize, String> {
+        &self.labels
+    }
+
+    pub fn label(&self, address: usize) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+}
+
+// 0x200 に読み込まれるROMを逆アセンブルする
+// 0x200 から実行を辿り、到達した命令だけをコード、それ以外をデータとして扱う
+pub fn disassemble(rom: &[u8], platform: Option<Platform>) -> Disassembly {
+    let mut code: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut targets: BTreeMap<usize, LabelKind> = BTreeMap::new();
+    let mut pending = vec![0];
+
+    let offset_of = |address: u16| {
+        let address = address as usize;
+        (address >= PROGRAM_START && address - PROGRAM_START < rom.len())
+            .then(|| address - PROGRAM_START)
+    };
+    let add_target = |targets: &mut BTreeMap<usize, LabelKind>, offset: usize, kind| {
+        let entry = targets.entry(offset).or_insert(kind);
+        *entry = (*entry).max(kind);
+    };
+
+    while let Some(offset) = pending.pop() {
+        if offset >= rom.len() || code.contains_key(&offset) {
+            continue;
+        }
+        let Some(instruction) = decode_at(rom, offset, platform) else {
+            continue;
+        };
+        code.insert(offset, instruction);
+        let next = offset + instruction.size();
+
+        use Instruction::*;
+        match instruction {
+            Jp(nnn) | JpV0(nnn) => {
+                if let Some(target) = offset_of(nnn) {
+                    add_target(&mut targets, target, LabelKind::Jump);
+                    pending.push(target);
+                }
+            }
+            Call(nnn) => {
+                if let Some(target) = offset_of(nnn) {
+                    add_target(&mut targets, target, LabelKind::Subroutine);
+                    pending.push(target);
+                }
+                pending.push(next);
+            }
+            Ret | Exit => {}
+            SeByte(..) | SneByte(..) | SeXy(..) | SneXy(..) | Skp(_) | Sknp(_) => {
+                pending.push(next);
+                pending.push(next + skipped_size(rom, next, platform));
+            }
+            LdI(nnn) | LdILong(nnn) => {
+                if let Some(target) = offset_of(nnn) {
+                    add_target(&mut targets, target, LabelKind::Data);
+                }
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        if let Some(instruction) = code.get(&offset) {
+            let size = instruction.size();
+            lines.push(Line {
+                address: PROGRAM_START + offset,
+                bytes: rom[offset..offset + size].to_vec(),
+                kind: LineKind::Code(*instruction),
+            });
+            offset += size;
+            continue;
+        }
+
+        // 次の命令かラベルの位置までをデータとしてまとめる
+        let start = offset;
+        offset += 1;
+        while offset < rom.len()
+            && offset - start < DATA_BYTES_PER_LINE
+            && !code.contains_key(&offset)
+            && !targets.contains_key(&offset)
+        {
+            offset += 1;
+        }
+        lines.push(Line {
+            address: PROGRAM_START + start,
+            bytes: rom[start..offset].to_vec(),
+            kind: LineKind::Data,
+        });
+    }
+
+    // 命令の途中を指すラベルは置けないので、数値のまま出力する
+    let line_starts: BTreeSet<usize> = lines.iter().map(|line| line.address).collect();
+    let labels = targets
+        .into_iter()
+        .map(|(offset, kind)| (PROGRAM_START + offset, kind))
+        .filter(|(address, _)| line_starts.contains(address))
+        .map(|(address, kind)| {
+            let prefix = match kind {
+                LabelKind::Subroutine => "sub",
+                LabelKind::Jump => "label",
+                LabelKind::Data => "data",
+            };
+            (address, format!("{}_{:03X}", prefix, address))
+        })
+        .collect();
+
+    Disassembly { lines, labels }
+}
+
+// プラットフォームで使えない命令はNone（0から始まるものはSYSとして扱う）
+pub(crate) fn decode_at(
+    rom: &[u8],
+    offset: usize,
+    platform: Option<Platform>,
+) -> Option<Instruction> {
+    let instruction = Instruction::fetch(rom, offset).ok()?;
+    if Platform::supports(platform, instruction.instruction_set()) {
+        Some(instruction)
+    } else if instruction.encode() & 0xF000 == 0 {
+        Some(Instruction::Sys(instruction.encode()))
+    } else {
+        None
+    }
+}
+
+// スキップ命令で飛ばされるバイト数（XO-CHIPでは F000 NNNN をまとめて飛ばす）
+pub(crate) fn skipped_size(rom: &[u8], offset: usize, platform: Option<Platform>) -> usize {
+    let long = platform == Some(Platform::XoChip)
+        && rom.get(offset..offset + 2) == Some(&LONG_PREFIX.to_be_bytes()[..]);
+    if long {
+        4
+    } else {
+        2
+    }
+}
+
+// 命令をOcto構文で書く。Octoで書けない命令（0nnn）はNone
+pub fn to_octo(instruction: &Instruction, label: impl Fn(u16) -> Option<String>) -> Option<String> {
+    use Instruction::*;
+
+    let address = |nnn: u16| label(nnn).unwrap_or_else(|| format!("0x{:03X}", nnn));
+    let text = match *instruction {
+        Sys(_) => return None,
+        Cls => "clear".to_string(),
+        Ret => "return".to_string(),
+        Scd(n) => format!("scroll-down {}", n),
+        Scu(n) => format!("scroll-up {}", n),
+        Scr => "scroll-right".to_string(),
+        Scl => "scroll-left".to_string(),
+        Exit => "exit".to_string(),
+        Low => "lores".to_string(),
+        High => "hires".to_string(),
+        Jp(nnn) => format!("jump {}", address(nnn)),
+        Call(nnn) => match label(nnn) {
+            Some(name) => name,
+            None => format!(":call 0x{:03X}", nnn),
+        },
+        // Octoの if は条件が成り立つときに次の命令を実行するので、スキップ条件とは逆になる
+        SeByte(x, kk) => format!("if v{:x} != 0x{:02X} then", x, kk),
+        SneByte(x, kk) => format!("if v{:x} == 0x{:02X} then", x, kk),
+        SeXy(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        SaveXy(x, y) => format!("save v{:x} - v{:x}", x, y),
+        LoadXy(x, y) => format!("load v{:x} - v{:x}", x, y),
+        LdByte(x, kk) => format!("v{:x} := 0x{:02X}", x, kk),
+        AddByte(x, kk) => format!("v{:x} += 0x{:02X}", x, kk),
+        LdXy(x, y) => format!("v{:x} := v{:x}", x, y),
+        OrXy(x, y) => format!("v{:x} |= v{:x}", x, y),
+        AndXy(x, y) => format!("v{:x} &= v{:x}", x, y),
+        XorXy(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        AddXy(x, y) => format!("v{:x} += v{:x}", x, y),
+        SubXy(x, y) => format!("v{:x} -= v{:x}", x, y),
+        ShrXy(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        SubnXy(x, y) => format!("v{:x} =- v{:x}", x, y),
+        ShlXy(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        SneXy(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        LdI(nnn) => format!("i := {}", address(nnn)),
+        JpV0(nnn) => format!("jump0 {}", address(nnn)),
+        Rnd(x, kk) => format!("v{:x} := random 0x{:02X}", x, kk),
+        Drw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Skp(x) => format!("if v{:x} -key then", x),
+        Sknp(x) => format!("if v{:x} key then", x),
+        LdILong(nnnn) => match label(nnnn) {
+            Some(name) => format!("i := long {}", name),
+            None => format!("i := long 0x{:04X}", nnnn),
+        },
+        Plane(n) => format!("plane {}", n),
+        Audio => "audio".to_string(),
+        LdVxDt(x) => format!("v{:x} := delay", x),
+        LdVxK(x) => format!("v{:x} := key", x),
+        LdDtVx(x) => format!("delay := v{:x}", x),
+        LdStVx(x) => format!("buzzer := v{:x}", x),
+        AddIVx(x) => format!("i += v{:x}", x),
+        LdFVx(x) => format!("i := hex v{:x}", x),
+        LdHfVx(x) => format!("i := bighex v{:x}", x),
+        LdBVx(x) => format!("bcd v{:x}", x),
+        Pitch(x) => format!("pitch := v{:x}", x),
+        LdIVx(x) => format!("save v{:x}", x),
+        LdVxI(x) => format!("load v{:x}", x),
+        LdRVx(x) => format!("saveflags v{:x}", x),
+        LdVxR(x) => format!("loadflags v{:x}", x),
+    };
+    Some(text)
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+// Octoのソースとして出力する（各行のコメントにアドレスと元のバイト列を付ける）
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = |address: u16| self.label(address as usize).map(str::to_string);
+
+        for line in &self.lines {
+            if let Some(name) = self.label(line.address) {
+                writeln!(f, ": {}", name)?;
+            }
+
+            let octo = match &line.kind {
+                LineKind::Code(instruction) => to_octo(instruction, label),
+                LineKind::Data => None,
+            };
+            let (text, note) = match (octo, &line.kind) {
+                (Some(text), _) => (text, String::new()),
+                (None, kind) => {
+                    let bytes: Vec<String> = line
+                        .bytes
+                        .iter()
+                        .map(|byte| format!("0x{:02X}", byte))
+                        .collect();
+                    let note = match kind {
+                        LineKind::Code(instruction) => format!(" ({})", instruction),
+                        LineKind::Data => String::new(),
+                    };
+                    (bytes.join(" "), note)
+                }
+            };
+            writeln!(
+                f,
+                "\t{:<27} # {:03X}: {}{}",
+                text,
+                line.address,
+                hex_bytes(&line.bytes),
+                note
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels_and_data() {
+        let rom = [
+            0xA2, 0x0A, // 200: i := data_20A
+            0x22, 0x08, // 202: sub_208
+            0x12, 0x06, // 204: jump label_206
+            0x12, 0x06, // 206: jump label_206
+            0x00, 0xEE, // 208: return
+            0xF0, 0x90, 0x90, // 20A: データ
+        ];
+        let disassembly = disassemble(&rom, None);
+
+        assert_eq!(disassembly.label(0x208), Some("sub_208"));
+        assert_eq!(disassembly.label(0x206), Some("label_206"));
+        assert_eq!(disassembly.label(0x20A), Some("data_20A"));
+        assert_eq!(disassembly.lines().len(), 6);
+        assert_eq!(disassembly.lines()[5].kind, LineKind::Data);
+
+        let text = disassembly.to_string();
+        let statements: Vec<&str> = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap().trim())
+            .collect();
+        assert_eq!(
+            statements,
+            vec![
+                "i := data_20A",
+                "sub_208",
+                "jump label_206",
+                ": label_206",
+                "jump label_206",
+                ": sub_208",
+                "return",
+                ": data_20A",
+                "0xF0 0x90 0x90",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skip_and_platform_specific_instructions() {
+        // SUPER-CHIPの命令は拡張の無いプラットフォームではデータ扱い
+        let rom = [0x30, 0x01, 0x00, 0xFF, 0x00, 0xFD];
+        let chip8 = disassemble(&rom, None);
+        assert_eq!(
+            chip8.lines()[1].kind,
+            LineKind::Code(Instruction::Sys(0x0FF))
+        );
+        assert!(chip8.to_string().contains("0x00 0xFF"));
+
+        let schip = disassemble(&rom, Some(Platform::SuperChip));
+        let kinds: Vec<&LineKind> = schip.lines().iter().map(|line| &line.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &LineKind::Code(Instruction::SeByte(0, 1)),
+                &LineKind::Code(Instruction::High),
+                &LineKind::Code(Instruction::Exit),
+            ]
+        );
+    }
+}
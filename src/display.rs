@@ -1,27 +1,37 @@
 use std::io::Write;
 
-use crate::chip8::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::chip8::ExecutionError;
+use crate::framebuffer::FrameBuffer;
 
 pub trait Draw {
-    fn draw(&self, display: &[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT]);
+    fn draw(&self, display: &FrameBuffer);
+    fn draw_fault(&self, error: &ExecutionError);
 }
 
 pub struct CUIDraw;
 
 impl Draw for CUIDraw {
-    fn draw(&self, display: &[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT]) {
+    fn draw(&self, display: &FrameBuffer) {
         // カーソルを非表示にし、画面の一番上に移動
         print!("\x1b[?25l\x1b[H");
 
         // 描画用のバッファを準備
-        let mut buffer = String::with_capacity(DISPLAY_HEIGHT * (DISPLAY_WIDTH + 1));
+        let mut buffer = String::with_capacity(display.height() * (display.width() + 4));
 
-        for row in display {
+        for row in display.rows() {
             for &pixel in row {
-                buffer.push(if pixel { '#' } else { ' ' });
+                // XO-CHIPのプレーンの組み合わせごとに文字を変える
+                buffer.push(match pixel {
+                    0 => ' ',
+                    1 => '#',
+                    2 => '+',
+                    _ => '@',
+                });
             }
-            buffer.push('\n');
+            // 解像度が下がった場合に備えて行末の残りを消す
+            buffer.push_str("\x1b[K\n");
         }
+        buffer.push_str("\x1b[J");
 
         // バッファの内容を一度に出力
         print!("{}", buffer);
@@ -30,4 +40,13 @@ impl Draw for CUIDraw {
         print!("\x1b[?25h");
         std::io::stdout().flush().unwrap();
     }
+
+    fn draw_fault(&self, error: &ExecutionError) {
+        // 最後の画面は残したまま、その下にエラー内容を表示
+        println!();
+        println!("*** CHIP-8 FAULT ***");
+        println!("{}", error);
+        println!("Press ESC to quit");
+        std::io::stdout().flush().unwrap();
+    }
 }
@@ -0,0 +1,725 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chip8::Cpu;
+use crate::keyboard::KeyboardInput;
+
+// ブレークポイント・ウォッチポイントの条件式と、ログポイントのメッセージ
+//
+// 式はCpuの状態を読む小さな言語で、構文解析と型検査を済ませた木を評価する
+// （評価では文字列の処理やメモリの確保をしないので、毎命令評価しても軽い）
+//
+// - 値: 数値（10進数、0x〜の16進数、0b〜の2進数）、true / false
+// - 変数: v0〜vf, i, pc, sp（コールスタックの深さ）, dt, st, hit_count（到達した回数）
+// - メモリ: mem[式]（1バイト。範囲外は0）
+// - 演算子（優先順位の低い順）: || / && / == != / < <= > >= / | / ^ / & / << >> / + - / * / % / 単項 ! - ~
+//
+// 数値は64ビットの符号付き整数で計算する。0での除算と範囲外のシフトは0になる
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Number,
+    Bool,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Number => write!(f, "number"),
+            Type::Bool => write!(f, "boolean"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Number(i64),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+// column は1始まりの文字位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpressionError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+fn error(column: usize, message: String) -> ExpressionError {
+    ExpressionError { column, message }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    HitCount,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Variable> {
+        let variable = match name {
+            "i" => Variable::I,
+            "pc" => Variable::Pc,
+            "sp" => Variable::Sp,
+            "dt" => Variable::Dt,
+            "st" => Variable::St,
+            "hit_count" => Variable::HitCount,
+            _ => {
+                let digit = name.strip_prefix('v')?;
+                if digit.len() != 1 {
+                    return None;
+                }
+                Variable::V(u8::from_str_radix(digit, 16).ok()?)
+            }
+        };
+        Some(variable)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Unary {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Binary {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+// 2項演算子と優先順位（大きいほど強く結びつく）
+const BINARY: &[(&str, Binary, u8)] = &[
+    ("||", Binary::Or, 1),
+    ("&&", Binary::And, 2),
+    ("==", Binary::Eq, 3),
+    ("!=", Binary::Ne, 3),
+    ("<", Binary::Lt, 4),
+    ("<=", Binary::Le, 4),
+    (">", Binary::Gt, 4),
+    (">=", Binary::Ge, 4),
+    ("|", Binary::BitOr, 5),
+    ("^", Binary::BitXor, 6),
+    ("&", Binary::BitAnd, 7),
+    ("<<", Binary::Shl, 8),
+    (">>", Binary::Shr, 8),
+    ("+", Binary::Add, 9),
+    ("-", Binary::Sub, 9),
+    ("*", Binary::Mul, 10),
+    ("/", Binary::Div, 10),
+    ("%", Binary::Rem, 10),
+];
+
+impl Binary {
+    fn symbol(self) -> &'static str {
+        BINARY.iter().find(|(_, op, _)| *op == self).unwrap().0
+    }
+}
+
+// 記号は長いものから順に試す
+const SYMBOLS: &[&str] = &[
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "(", ")", "[", "]", "+", "-", "*", "/", "%",
+    "&", "|", "^", "!", "~", "<", ">",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Bool(bool),
+    Variable(Variable),
+    Memory {
+        address: Box<Node>,
+        column: usize,
+    },
+    Unary {
+        op: Unary,
+        operand: Box<Node>,
+        column: usize,
+    },
+    Binary {
+        op: Binary,
+        left: Box<Node>,
+        right: Box<Node>,
+        column: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "'{}'", n),
+            Token::Name(name) => write!(f, "'{}'", name),
+            Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+            Token::End => write!(f, "end of expression"),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, ExpressionError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while position < chars.len() {
+        let c = chars[position];
+        let column = position + 1;
+        if c.is_whitespace() {
+            position += 1;
+            continue;
+        }
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let start = position;
+            while position < chars.len()
+                && (chars[position].is_ascii_alphanumeric() || chars[position] == '_')
+            {
+                position += 1;
+            }
+            let word: String = chars[start..position]
+                .iter()
+                .collect::<String>()
+                .to_ascii_lowercase();
+            if !c.is_ascii_digit() {
+                tokens.push((Token::Name(word), column));
+                continue;
+            }
+            let (digits, radix) = match word.get(..2) {
+                Some("0x") => (&word[2..], 16),
+                Some("0b") => (&word[2..], 2),
+                _ => (word.as_str(), 10),
+            };
+            let number = i64::from_str_radix(digits, radix)
+                .map_err(|_| error(column, format!("invalid number '{}'", word)))?;
+            tokens.push((Token::Number(number), column));
+            continue;
+        }
+        let rest: String = chars[position..chars.len().min(position + 2)]
+            .iter()
+            .collect();
+        let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) else {
+            return Err(error(column, format!("unexpected character '{}'", c)));
+        };
+        tokens.push((Token::Symbol(symbol), column));
+        position += symbol.len();
+    }
+    tokens.push((Token::End, chars.len() + 1));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &(Token, usize) {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> (Token, usize) {
+        let token = self.tokens[self.position].clone();
+        if token.0 != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), ExpressionError> {
+        match self.next() {
+            (Token::Symbol(s), _) if s == symbol => Ok(()),
+            (token, column) => Err(error(
+                column,
+                format!("expected '{}', found {}", symbol, token),
+            )),
+        }
+    }
+
+    // 優先順位が min 以上の2項演算子だけを読む
+    fn expression(&mut self, min: u8) -> Result<Node, ExpressionError> {
+        let mut left = self.unary()?;
+        while let (Token::Symbol(symbol), column) = *self.peek() {
+            let Some(&(_, op, precedence)) = BINARY.iter().find(|(s, _, _)| *s == symbol) else {
+                break;
+            };
+            if precedence < min {
+                break;
+            }
+            self.next();
+            let right = self.expression(precedence + 1)?;
+            left = Node::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+                column,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, ExpressionError> {
+        let op = match self.peek() {
+            (Token::Symbol("!"), _) => Unary::Not,
+            (Token::Symbol("-"), _) => Unary::Negate,
+            (Token::Symbol("~"), _) => Unary::Complement,
+            _ => return self.primary(),
+        };
+        let (_, column) = self.next();
+        Ok(Node::Unary {
+            op,
+            operand: Box::new(self.unary()?),
+            column,
+        })
+    }
+
+    fn primary(&mut self) -> Result<Node, ExpressionError> {
+        match self.next() {
+            (Token::Number(n), _) => Ok(Node::Number(n)),
+            (Token::Symbol("("), _) => {
+                let node = self.expression(1)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            (Token::Name(name), column) => match name.as_str() {
+                "true" => Ok(Node::Bool(true)),
+                "false" => Ok(Node::Bool(false)),
+                "mem" => {
+                    self.expect("[")?;
+                    let address = self.expression(1)?;
+                    self.expect("]")?;
+                    Ok(Node::Memory {
+                        address: Box::new(address),
+                        column,
+                    })
+                }
+                _ => Variable::from_name(&name)
+                    .map(Node::Variable)
+                    .ok_or_else(|| error(column, format!("unknown name '{}'", name))),
+            },
+            (token, column) => Err(error(column, format!("expected a value, found {}", token))),
+        }
+    }
+}
+
+// 式の型を求める。演算子と値の型が合わなければエラー
+fn check(node: &Node) -> Result<Type, ExpressionError> {
+    let expect = |node: &Node, expected: Type, symbol: &str, column: usize| {
+        let found = check(node)?;
+        if found != expected {
+            return Err(error(
+                column,
+                format!("'{}' expects a {}, found a {}", symbol, expected, found),
+            ));
+        }
+        Ok(())
+    };
+    match node {
+        Node::Number(_) | Node::Variable(_) => Ok(Type::Number),
+        Node::Bool(_) => Ok(Type::Bool),
+        Node::Memory { address, column } => {
+            expect(address, Type::Number, "mem[]", *column)?;
+            Ok(Type::Number)
+        }
+        Node::Unary {
+            op: Unary::Not,
+            operand,
+            column,
+        } => {
+            expect(operand, Type::Bool, "!", *column)?;
+            Ok(Type::Bool)
+        }
+        Node::Unary {
+            op,
+            operand,
+            column,
+        } => {
+            let symbol = if *op == Unary::Negate { "-" } else { "~" };
+            expect(operand, Type::Number, symbol, *column)?;
+            Ok(Type::Number)
+        }
+        Node::Binary {
+            op,
+            left,
+            right,
+            column,
+        } => {
+            let (operand, result) = match op {
+                Binary::Or | Binary::And => (Type::Bool, Type::Bool),
+                Binary::Eq | Binary::Ne => {
+                    // 同じ型どうしなら比べられる
+                    let operand = check(left)?;
+                    expect(right, operand, op.symbol(), *column)?;
+                    return Ok(Type::Bool);
+                }
+                Binary::Lt | Binary::Le | Binary::Gt | Binary::Ge => (Type::Number, Type::Bool),
+                _ => (Type::Number, Type::Number),
+            };
+            expect(left, operand, op.symbol(), *column)?;
+            expect(right, operand, op.symbol(), *column)?;
+            Ok(result)
+        }
+    }
+}
+
+// 真偽値は0と1で表す（型検査を済ませているので混ざらない）
+fn evaluate<T: KeyboardInput>(node: &Node, cpu: &Cpu<T>, hit_count: usize) -> i64 {
+    let eval = |node: &Node| evaluate(node, cpu, hit_count);
+    match node {
+        Node::Number(n) => *n,
+        Node::Bool(b) => *b as i64,
+        Node::Variable(variable) => match *variable {
+            Variable::V(x) => cpu.registers()[x as usize] as i64,
+            Variable::I => cpu.index_register() as i64,
+            Variable::Pc => cpu.program_counter() as i64,
+            Variable::Sp => cpu.call_stack().len() as i64,
+            Variable::Dt => cpu.delay_timer() as i64,
+            Variable::St => cpu.sound_timer() as i64,
+            Variable::HitCount => hit_count as i64,
+        },
+        Node::Memory { address, .. } => usize::try_from(eval(address))
+            .ok()
+            .and_then(|address| cpu.memory().get(address))
+            .map_or(0, |&byte| byte as i64),
+        Node::Unary { op, operand, .. } => {
+            let value = eval(operand);
+            match op {
+                Unary::Not => (value == 0) as i64,
+                Unary::Negate => value.wrapping_neg(),
+                Unary::Complement => !value,
+            }
+        }
+        Node::Binary {
+            op: Binary::And,
+            left,
+            right,
+            ..
+        } => (eval(left) != 0 && eval(right) != 0) as i64,
+        Node::Binary {
+            op: Binary::Or,
+            left,
+            right,
+            ..
+        } => (eval(left) != 0 || eval(right) != 0) as i64,
+        Node::Binary {
+            op, left, right, ..
+        } => {
+            let (a, b) = (eval(left), eval(right));
+            let shift = |b: i64| u32::try_from(b).ok();
+            match op {
+                Binary::Eq => (a == b) as i64,
+                Binary::Ne => (a != b) as i64,
+                Binary::Lt => (a < b) as i64,
+                Binary::Le => (a <= b) as i64,
+                Binary::Gt => (a > b) as i64,
+                Binary::Ge => (a >= b) as i64,
+                Binary::BitOr => a | b,
+                Binary::BitXor => a ^ b,
+                Binary::BitAnd => a & b,
+                Binary::Shl => shift(b).and_then(|b| a.checked_shl(b)).unwrap_or(0),
+                Binary::Shr => shift(b).and_then(|b| a.checked_shr(b)).unwrap_or(0),
+                Binary::Add => a.wrapping_add(b),
+                Binary::Sub => a.wrapping_sub(b),
+                Binary::Mul => a.wrapping_mul(b),
+                Binary::Div => a.checked_div(b).unwrap_or(0),
+                Binary::Rem => a.checked_rem(b).unwrap_or(0),
+                Binary::And | Binary::Or => unreachable!(),
+            }
+        }
+    }
+}
+
+// 型検査を済ませた式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    text: String,
+    root: Node,
+    ty: Type,
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Expression, ExpressionError> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+        let root = parser.expression(1)?;
+        match parser.next() {
+            (Token::End, _) => {}
+            (token, column) => return Err(error(column, format!("unexpected {}", token))),
+        }
+        let ty = check(&root)?;
+        Ok(Expression {
+            text: text.trim().to_string(),
+            root,
+            ty,
+        })
+    }
+
+    // ブレークポイントの条件。真偽値の式でなければエラー
+    pub fn condition(text: &str) -> Result<Expression, ExpressionError> {
+        let expression = Expression::parse(text)?;
+        if expression.ty != Type::Bool {
+            return Err(error(
+                1,
+                format!("condition must be a boolean, found a {}", expression.ty),
+            ));
+        }
+        Ok(expression)
+    }
+
+    pub fn ty(&self) -> Type {
+        self.ty
+    }
+
+    pub fn evaluate<T: KeyboardInput>(&self, cpu: &Cpu<T>, hit_count: usize) -> Value {
+        let value = evaluate(&self.root, cpu, hit_count);
+        match self.ty {
+            Type::Number => Value::Number(value),
+            Type::Bool => Value::Bool(value != 0),
+        }
+    }
+
+    // 条件として評価する（数値の式は0以外を真とする）
+    pub fn is_true<T: KeyboardInput>(&self, cpu: &Cpu<T>, hit_count: usize) -> bool {
+        evaluate(&self.root, cpu, hit_count) != 0
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ExpressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Expression::parse(s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Value { expression: Expression, hex: bool },
+}
+
+// ログポイントのメッセージ。"{式}" を値に置き換え、"{式:x}" は16進数で書く
+// "{{" と "}}" は波括弧そのもの
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogMessage {
+    text: String,
+    parts: Vec<Part>,
+}
+
+impl LogMessage {
+    pub fn parse(text: &str) -> Result<LogMessage, ExpressionError> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut position = 0;
+        while position < chars.len() {
+            match (chars[position], chars.get(position + 1)) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    literal.push(chars[position]);
+                    position += 2;
+                }
+                ('{', _) => {
+                    let start = position + 1;
+                    let Some(length) = chars[start..].iter().position(|&c| c == '}') else {
+                        return Err(error(position + 1, "unclosed '{'".to_string()));
+                    };
+                    let inner: String = chars[start..start + length].iter().collect();
+                    let (source, hex) = match inner.strip_suffix(":x") {
+                        Some(source) => (source, true),
+                        None => (inner.as_str(), false),
+                    };
+                    // 位置はメッセージ全体の中の位置に直す
+                    let expression = Expression::parse(source)
+                        .map_err(|e| error(e.column + start, e.message))?;
+                    if hex && expression.ty != Type::Number {
+                        return Err(error(start + 1, "':x' needs a number".to_string()));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Value { expression, hex });
+                    position = start + length + 1;
+                }
+                ('}', _) => return Err(error(position + 1, "unmatched '}'".to_string())),
+                (c, _) => {
+                    literal.push(c);
+                    position += 1;
+                }
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Ok(LogMessage {
+            text: text.to_string(),
+            parts,
+        })
+    }
+
+    pub fn format<T: KeyboardInput>(&self, cpu: &Cpu<T>, hit_count: usize) -> String {
+        let mut text = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(literal) => text.push_str(literal),
+                Part::Value { expression, hex } => match expression.evaluate(cpu, hit_count) {
+                    Value::Number(n) if *hex => text.push_str(&format!("{:X}", n)),
+                    value => text.push_str(&value.to_string()),
+                },
+            }
+        }
+        text
+    }
+}
+
+impl fmt::Display for LogMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl FromStr for LogMessage {
+    type Err = ExpressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LogMessage::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::keyboard::NoKeyboard;
+
+    // v3 := 0x20 / i := data を実行した後の状態
+    fn cpu() -> Cpu<NoKeyboard> {
+        let rom = assemble("v3 := 0x20\ni := data\nloop again\n: data 0 7").unwrap();
+        let mut cpu = Cpu::from_bytes(&rom, NoKeyboard);
+        cpu.update().unwrap();
+        cpu.update().unwrap();
+        cpu
+    }
+
+    fn eval(text: &str, hit_count: usize) -> Value {
+        Expression::parse(text).unwrap().evaluate(&cpu(), hit_count)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(eval("pc == 0x204 && v3 > 0x10", 0), Value::Bool(true));
+        assert_eq!(eval("mem[i] != 0", 0), Value::Bool(false));
+        assert_eq!(eval("mem[i + 1]", 0), Value::Number(7));
+        assert_eq!(eval("dt == 0 && sp == 0", 0), Value::Bool(true));
+        assert_eq!(eval("hit_count >= 5", 4), Value::Bool(false));
+        assert_eq!(eval("hit_count >= 5", 5), Value::Bool(true));
+        assert_eq!(eval("1 + 2 * 3 << 1", 0), Value::Number(14));
+        assert_eq!(eval("-(V3 - 0b1) & 0xFF | ~0 ^ ~0", 0), Value::Number(0xE1));
+        assert_eq!(eval("!(1 > 2) || mem[-1] == 0", 0), Value::Bool(true));
+        assert_eq!(eval("v3 / 0 + v3 % 0 + (1 << 100)", 0), Value::Number(0));
+        assert_eq!(eval("true == (pc != 0)", 0), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |text: &str| Expression::parse(text).unwrap_err().to_string();
+        assert_eq!(
+            err("v3 > "),
+            "column 6: expected a value, found end of expression"
+        );
+        assert_eq!(err("v3 $ 1"), "column 4: unexpected character '$'");
+        assert_eq!(err("vg == 1"), "column 1: unknown name 'vg'");
+        assert_eq!(err("0x1G"), "column 1: invalid number '0x1g'");
+        assert_eq!(
+            err("(v1 + 2"),
+            "column 8: expected ')', found end of expression"
+        );
+        assert_eq!(err("v1 2"), "column 4: unexpected '2'");
+        assert_eq!(
+            err("v1 && true"),
+            "column 4: '&&' expects a boolean, found a number"
+        );
+        assert_eq!(
+            err("!v1"),
+            "column 1: '!' expects a boolean, found a number"
+        );
+        assert_eq!(
+            err("v1 == true"),
+            "column 4: '==' expects a number, found a boolean"
+        );
+        assert_eq!(
+            err("mem[v1 > 0]"),
+            "column 1: 'mem[]' expects a number, found a boolean"
+        );
+        assert_eq!(
+            Expression::condition("v1 + 1").unwrap_err().to_string(),
+            "column 1: condition must be a boolean, found a number"
+        );
+    }
+
+    #[test]
+    fn test_log_message() {
+        let message =
+            LogMessage::parse("v3={v3:x} i={i} {{set={mem[i] == 0}}} #{hit_count}").unwrap();
+        assert_eq!(message.format(&cpu(), 3), "v3=20 i=518 {set=true} #3");
+        assert_eq!(
+            LogMessage::parse("x={v1 +}").unwrap_err().to_string(),
+            "column 8: expected a value, found end of expression"
+        );
+        assert_eq!(
+            LogMessage::parse("{pc > 0:x}").unwrap_err().to_string(),
+            "column 2: ':x' needs a number"
+        );
+        assert!(LogMessage::parse("{pc").is_err());
+        assert!(LogMessage::parse("pc}").is_err());
+    }
+}
@@ -0,0 +1,196 @@
+// 解像度を切り替えられる画面バッファ
+// 各ピクセルはプレーンごとのビットを持つ（bit0がプレーン1、bit1がプレーン2）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+pub const PLANE_COUNT: usize = 2;
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    // 保存しておいたピクセル列から復元する
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<u8>) -> Option<Self> {
+        if pixels.len() != width * height || pixels.iter().any(|&p| p > 3) {
+            return None;
+        }
+        Some(FrameBuffer {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // いずれかのプレーンで点灯しているか
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixel(x, y) != 0
+    }
+
+    // ピクセルのプレーンビット（0〜3）
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    // 指定したプレーンのピクセルを反転し、点灯していたピクセルが消えた場合はtrueを返す
+    pub fn toggle(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let pixel = &mut self.pixels[y * self.width + x];
+        *pixel ^= plane;
+        *pixel & plane == 0
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels.chunks(self.width)
+    }
+
+    pub fn row(&self, y: usize) -> &[u8] {
+        &self.pixels[y * self.width..(y + 1) * self.width]
+    }
+
+    // 保存しておいた1行分のピクセルを書き戻す
+    pub fn set_row(&mut self, y: usize, pixels: &[u8]) {
+        self.pixels[y * self.width..(y + 1) * self.width].copy_from_slice(pixels);
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.pixels.iter().all(|&p| p == 0)
+    }
+
+    // 選択したプレーンだけを消去する
+    pub fn clear(&mut self, planes: u8) {
+        for pixel in &mut self.pixels {
+            *pixel &= !planes;
+        }
+    }
+
+    // 解像度を切り替える（画面はクリアされる）
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; width * height];
+    }
+
+    // 選択したプレーンのビットだけを src から dst へ移す
+    fn move_pixel(&mut self, src: Option<usize>, dst: usize, planes: u8) {
+        let value = src.map_or(0, |i| self.pixels[i]) & planes;
+        self.pixels[dst] = (self.pixels[dst] & !planes) | value;
+    }
+
+    pub fn scroll_down(&mut self, n: usize, planes: u8) {
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let src = y.checked_sub(n).map(|sy| sy * self.width + x);
+                self.move_pixel(src, y * self.width + x, planes);
+            }
+        }
+    }
+
+    pub fn scroll_up(&mut self, n: usize, planes: u8) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src = Some(y + n)
+                    .filter(|&sy| sy < self.height)
+                    .map(|sy| sy * self.width + x);
+                self.move_pixel(src, y * self.width + x, planes);
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self, n: usize, planes: u8) {
+        for y in 0..self.height {
+            for x in (0..self.width).rev() {
+                let src = x.checked_sub(n).map(|sx| y * self.width + sx);
+                self.move_pixel(src, y * self.width + x, planes);
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self, n: usize, planes: u8) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src = Some(x + n)
+                    .filter(|&sx| sx < self.width)
+                    .map(|sx| y * self.width + sx);
+                self.move_pixel(src, y * self.width + x, planes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scroll() {
+        let mut fb = FrameBuffer::new(8, 4);
+        fb.toggle(0, 0, 1);
+        fb.toggle(7, 3, 1);
+
+        fb.scroll_down(1, 1);
+        assert!(fb.get(0, 1));
+        assert!(!fb.get(0, 0));
+        assert!(!fb.get(7, 3)); // 下端から押し出される
+
+        fb.scroll_right(4, 1);
+        assert!(fb.get(4, 1));
+        assert!(!fb.get(0, 1));
+
+        fb.scroll_left(4, 1);
+        assert!(fb.get(0, 1));
+        assert!(!fb.get(4, 1));
+
+        fb.scroll_up(1, 1);
+        assert!(fb.get(0, 0));
+        assert!(!fb.get(0, 1));
+
+        fb.scroll_left(100, 1);
+        assert!(fb.is_blank());
+    }
+
+    #[test]
+    fn test_toggle_reports_collision() {
+        let mut fb = FrameBuffer::new(4, 4);
+        assert!(!fb.toggle(1, 2, 1));
+        assert!(fb.get(1, 2));
+        assert!(fb.toggle(1, 2, 1));
+        assert!(!fb.get(1, 2));
+    }
+
+    #[test]
+    fn test_planes_are_independent() {
+        let mut fb = FrameBuffer::new(4, 4);
+        fb.toggle(0, 0, 1);
+        fb.toggle(0, 0, 2);
+        assert_eq!(fb.pixel(0, 0), 3);
+
+        // プレーン2だけをスクロール・消去する
+        fb.scroll_right(1, 2);
+        assert_eq!(fb.pixel(0, 0), 1);
+        assert_eq!(fb.pixel(1, 0), 2);
+
+        fb.clear(1);
+        assert_eq!(fb.pixel(0, 0), 0);
+        assert_eq!(fb.pixel(1, 0), 2);
+    }
+}
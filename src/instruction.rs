@@ -0,0 +1,470 @@
+use std::fmt;
+
+// 命令がどの命令セットで追加されたものか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionSet {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOpcode(u16),
+    MissingOperand, // F000 NNNN の後半が無い
+    Truncated,      // メモリの終端で命令が途切れている
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode(opcode) => write!(f, "unknown opcode {:04X}", opcode),
+            DecodeError::MissingOperand => write!(f, "F000 is missing its 16-bit operand"),
+            DecodeError::Truncated => write!(f, "instruction runs past the end of memory"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// CHIP-8 / SUPER-CHIP / XO-CHIP の命令
+// x, y はレジスタ番号、kk は即値、nnn はアドレス、n は4ビットの値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Sys(u16),        // 0nnn
+    Cls,             // 00E0
+    Ret,             // 00EE
+    Scd(u8),         // 00Cn (SUPER-CHIP)
+    Scu(u8),         // 00Dn (XO-CHIP)
+    Scr,             // 00FB (SUPER-CHIP)
+    Scl,             // 00FC (SUPER-CHIP)
+    Exit,            // 00FD (SUPER-CHIP)
+    Low,             // 00FE (SUPER-CHIP)
+    High,            // 00FF (SUPER-CHIP)
+    Jp(u16),         // 1nnn
+    Call(u16),       // 2nnn
+    SeByte(u8, u8),  // 3xkk
+    SneByte(u8, u8), // 4xkk
+    SeXy(u8, u8),    // 5xy0
+    SaveXy(u8, u8),  // 5xy2 (XO-CHIP)
+    LoadXy(u8, u8),  // 5xy3 (XO-CHIP)
+    LdByte(u8, u8),  // 6xkk
+    AddByte(u8, u8), // 7xkk
+    LdXy(u8, u8),    // 8xy0
+    OrXy(u8, u8),    // 8xy1
+    AndXy(u8, u8),   // 8xy2
+    XorXy(u8, u8),   // 8xy3
+    AddXy(u8, u8),   // 8xy4
+    SubXy(u8, u8),   // 8xy5
+    ShrXy(u8, u8),   // 8xy6
+    SubnXy(u8, u8),  // 8xy7
+    ShlXy(u8, u8),   // 8xyE
+    SneXy(u8, u8),   // 9xy0
+    LdI(u16),        // Annn
+    JpV0(u16),       // Bnnn
+    Rnd(u8, u8),     // Cxkk
+    Drw(u8, u8, u8), // Dxyn
+    Skp(u8),         // Ex9E
+    Sknp(u8),        // ExA1
+    LdILong(u16),    // F000 NNNN (XO-CHIP)
+    Plane(u8),       // Fn01 (XO-CHIP)
+    Audio,           // F002 (XO-CHIP)
+    LdVxDt(u8),      // Fx07
+    LdVxK(u8),       // Fx0A
+    LdDtVx(u8),      // Fx15
+    LdStVx(u8),      // Fx18
+    AddIVx(u8),      // Fx1E
+    LdFVx(u8),       // Fx29
+    LdHfVx(u8),      // Fx30 (SUPER-CHIP)
+    LdBVx(u8),       // Fx33
+    Pitch(u8),       // Fx3A (XO-CHIP)
+    LdIVx(u8),       // Fx55
+    LdVxI(u8),       // Fx65
+    LdRVx(u8),       // Fx75 (SUPER-CHIP)
+    LdVxR(u8),       // Fx85 (SUPER-CHIP)
+}
+
+// F000 NNNN の前半
+pub const LONG_PREFIX: u16 = 0xF000;
+
+impl Instruction {
+    // 2バイトの命令を解釈する。F000 は後半が必要なので decode_long を使う
+    pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+        use Instruction::*;
+
+        let c = (opcode >> 12) as u8;
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nnn = opcode & 0x0FFF;
+        let kk = (opcode & 0x00FF) as u8;
+
+        let instruction = match (c, x, y, n) {
+            (0, 0, 0xE, 0) => Cls,
+            (0, 0, 0xE, 0xE) => Ret,
+            (0, 0, 0xC, _) => Scd(n),
+            (0, 0, 0xD, _) => Scu(n),
+            (0, 0, 0xF, 0xB) => Scr,
+            (0, 0, 0xF, 0xC) => Scl,
+            (0, 0, 0xF, 0xD) => Exit,
+            (0, 0, 0xF, 0xE) => Low,
+            (0, 0, 0xF, 0xF) => High,
+            (0, _, _, _) => Sys(nnn),
+            (0x1, _, _, _) => Jp(nnn),
+            (0x2, _, _, _) => Call(nnn),
+            (0x3, _, _, _) => SeByte(x, kk),
+            (0x4, _, _, _) => SneByte(x, kk),
+            (0x5, _, _, 0) => SeXy(x, y),
+            (0x5, _, _, 2) => SaveXy(x, y),
+            (0x5, _, _, 3) => LoadXy(x, y),
+            (0x6, _, _, _) => LdByte(x, kk),
+            (0x7, _, _, _) => AddByte(x, kk),
+            (0x8, _, _, 0) => LdXy(x, y),
+            (0x8, _, _, 1) => OrXy(x, y),
+            (0x8, _, _, 2) => AndXy(x, y),
+            (0x8, _, _, 3) => XorXy(x, y),
+            (0x8, _, _, 4) => AddXy(x, y),
+            (0x8, _, _, 5) => SubXy(x, y),
+            (0x8, _, _, 6) => ShrXy(x, y),
+            (0x8, _, _, 7) => SubnXy(x, y),
+            (0x8, _, _, 0xE) => ShlXy(x, y),
+            (0x9, _, _, 0) => SneXy(x, y),
+            (0xA, _, _, _) => LdI(nnn),
+            (0xB, _, _, _) => JpV0(nnn),
+            (0xC, _, _, _) => Rnd(x, kk),
+            (0xD, _, _, _) => Drw(x, y, n),
+            (0xE, _, 9, 0xE) => Skp(x),
+            (0xE, _, 0xA, 1) => Sknp(x),
+            (0xF, 0, 0, 0) => return Err(DecodeError::MissingOperand),
+            (0xF, _, 0, 1) => Plane(x),
+            (0xF, 0, 0, 2) => Audio,
+            (0xF, _, 0, 7) => LdVxDt(x),
+            (0xF, _, 0, 0xA) => LdVxK(x),
+            (0xF, _, 1, 5) => LdDtVx(x),
+            (0xF, _, 1, 8) => LdStVx(x),
+            (0xF, _, 1, 0xE) => AddIVx(x),
+            (0xF, _, 2, 9) => LdFVx(x),
+            (0xF, _, 3, 0) => LdHfVx(x),
+            (0xF, _, 3, 3) => LdBVx(x),
+            (0xF, _, 3, 0xA) => Pitch(x),
+            (0xF, _, 5, 5) => LdIVx(x),
+            (0xF, _, 6, 5) => LdVxI(x),
+            (0xF, _, 7, 5) => LdRVx(x),
+            (0xF, _, 8, 5) => LdVxR(x),
+            _ => return Err(DecodeError::UnknownOpcode(opcode)),
+        };
+        Ok(instruction)
+    }
+
+    // 4バイト命令にも対応した解釈。operand は次の2バイト
+    pub fn decode_long(opcode: u16, operand: u16) -> Result<Instruction, DecodeError> {
+        if opcode == LONG_PREFIX {
+            Ok(Instruction::LdILong(operand))
+        } else {
+            Self::decode(opcode)
+        }
+    }
+
+    // メモリ上の address から1命令を読み取る
+    pub fn fetch(memory: &[u8], address: usize) -> Result<Instruction, DecodeError> {
+        let word = |at: usize| -> Option<u16> {
+            let high = *memory.get(at)?;
+            let low = *memory.get(at + 1)?;
+            Some(u16::from_be_bytes([high, low]))
+        };
+        let opcode = word(address).ok_or(DecodeError::Truncated)?;
+        if opcode == LONG_PREFIX {
+            let operand = word(address + 2).ok_or(DecodeError::MissingOperand)?;
+            return Self::decode_long(opcode, operand);
+        }
+        Self::decode(opcode)
+    }
+
+    // 命令の最初の2バイト
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        let xy = |c: u16, x: u8, y: u8, n: u16| {
+            c << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | n
+        };
+        let xkk = |c: u16, x: u8, kk: u8| c << 12 | (x as u16 & 0xF) << 8 | kk as u16;
+        let fx = |x: u8, low: u16| 0xF000 | (x as u16 & 0xF) << 8 | low;
+
+        match *self {
+            Sys(nnn) => nnn & 0x0FFF,
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            Scd(n) => 0x00C0 | (n as u16 & 0xF),
+            Scu(n) => 0x00D0 | (n as u16 & 0xF),
+            Scr => 0x00FB,
+            Scl => 0x00FC,
+            Exit => 0x00FD,
+            Low => 0x00FE,
+            High => 0x00FF,
+            Jp(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            SeByte(x, kk) => xkk(0x3, x, kk),
+            SneByte(x, kk) => xkk(0x4, x, kk),
+            SeXy(x, y) => xy(0x5, x, y, 0),
+            SaveXy(x, y) => xy(0x5, x, y, 2),
+            LoadXy(x, y) => xy(0x5, x, y, 3),
+            LdByte(x, kk) => xkk(0x6, x, kk),
+            AddByte(x, kk) => xkk(0x7, x, kk),
+            LdXy(x, y) => xy(0x8, x, y, 0),
+            OrXy(x, y) => xy(0x8, x, y, 1),
+            AndXy(x, y) => xy(0x8, x, y, 2),
+            XorXy(x, y) => xy(0x8, x, y, 3),
+            AddXy(x, y) => xy(0x8, x, y, 4),
+            SubXy(x, y) => xy(0x8, x, y, 5),
+            ShrXy(x, y) => xy(0x8, x, y, 6),
+            SubnXy(x, y) => xy(0x8, x, y, 7),
+            ShlXy(x, y) => xy(0x8, x, y, 0xE),
+            SneXy(x, y) => xy(0x9, x, y, 0),
+            LdI(nnn) => 0xA000 | (nnn & 0x0FFF),
+            JpV0(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Rnd(x, kk) => xkk(0xC, x, kk),
+            Drw(x, y, n) => xy(0xD, x, y, n as u16 & 0xF),
+            Skp(x) => xkk(0xE, x, 0x9E),
+            Sknp(x) => xkk(0xE, x, 0xA1),
+            LdILong(_) => LONG_PREFIX,
+            Plane(n) => fx(n, 0x01),
+            Audio => 0xF002,
+            LdVxDt(x) => fx(x, 0x07),
+            LdVxK(x) => fx(x, 0x0A),
+            LdDtVx(x) => fx(x, 0x15),
+            LdStVx(x) => fx(x, 0x18),
+            AddIVx(x) => fx(x, 0x1E),
+            LdFVx(x) => fx(x, 0x29),
+            LdHfVx(x) => fx(x, 0x30),
+            LdBVx(x) => fx(x, 0x33),
+            Pitch(x) => fx(x, 0x3A),
+            LdIVx(x) => fx(x, 0x55),
+            LdVxI(x) => fx(x, 0x65),
+            LdRVx(x) => fx(x, 0x75),
+            LdVxR(x) => fx(x, 0x85),
+        }
+    }
+
+    // メモリに書き込むバイト列（F000 NNNN は4バイト）
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let Instruction::LdILong(address) = self {
+            bytes.extend_from_slice(&address.to_be_bytes());
+        }
+        bytes
+    }
+
+    // 命令のバイト数
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LdILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    pub fn instruction_set(&self) -> InstructionSet {
+        use Instruction::*;
+
+        match self {
+            Scd(_) | Scr | Scl | Exit | Low | High | LdHfVx(_) | LdRVx(_) | LdVxR(_) => {
+                InstructionSet::SuperChip
+            }
+            Scu(_) | SaveXy(..) | LoadXy(..) | LdILong(_) | Plane(_) | Audio | Pitch(_) => {
+                InstructionSet::XoChip
+            }
+            _ => InstructionSet::Chip8,
+        }
+    }
+
+    // 命令の動作の説明。Quirksで変わる部分はデフォルトの動作を書き、括弧で補足する
+    pub fn semantics(&self) -> String {
+        use Instruction::*;
+
+        match *self {
+            Sys(nnn) => format!("call machine code at 0x{:03X} (treated as a jump or ignored)", nnn),
+            Cls => "clear the display".to_string(),
+            Ret => "return from a subroutine (PC = popped address)".to_string(),
+            Scd(n) => format!("scroll the display down {} pixels", n),
+            Scu(n) => format!("scroll the display up {} pixels", n),
+            Scr => "scroll the display right 4 pixels".to_string(),
+            Scl => "scroll the display left 4 pixels".to_string(),
+            Exit => "exit the interpreter".to_string(),
+            Low => "switch to low resolution (64x32)".to_string(),
+            High => "switch to high resolution (128x64)".to_string(),
+            Jp(nnn) => format!("PC = 0x{:03X}", nnn),
+            Call(nnn) => format!("push PC, PC = 0x{:03X}", nnn),
+            SeByte(x, kk) => format!("skip the next instruction if V{:X} == 0x{:02X}", x, kk),
+            SneByte(x, kk) => format!("skip the next instruction if V{:X} != 0x{:02X}", x, kk),
+            SeXy(x, y) => format!("skip the next instruction if V{:X} == V{:X}", x, y),
+            SaveXy(x, y) => format!("store V{:X}..V{:X} at I (I unchanged)", x, y),
+            LoadXy(x, y) => format!("load V{:X}..V{:X} from I (I unchanged)", x, y),
+            LdByte(x, kk) => format!("V{:X} = 0x{:02X}", x, kk),
+            AddByte(x, kk) => format!("V{:X} += 0x{:02X} (VF unchanged)", x, kk),
+            LdXy(x, y) => format!("V{:X} = V{:X}", x, y),
+            OrXy(x, y) => format!("V{:X} |= V{:X} (VF = 0 with the vf_reset quirk)", x, y),
+            AndXy(x, y) => format!("V{:X} &= V{:X} (VF = 0 with the vf_reset quirk)", x, y),
+            XorXy(x, y) => format!("V{:X} ^= V{:X} (VF = 0 with the vf_reset quirk)", x, y),
+            AddXy(x, y) => format!("V{:X} += V{:X}, VF = carry", x, y),
+            SubXy(x, y) => format!("V{:X} -= V{:X}, VF = 1 if V{:X} >= V{:X} (no borrow)", x, y, x, y),
+            ShrXy(x, y) => format!(
+                "V{:X} >>= 1, VF = bit shifted out (V{:X} = V{:X} >> 1 with the shift_uses_vy quirk)",
+                x, x, y
+            ),
+            SubnXy(x, y) => format!(
+                "V{:X} = V{:X} - V{:X}, VF = 1 if V{:X} >= V{:X} (no borrow)",
+                x, y, x, y, x
+            ),
+            ShlXy(x, y) => format!(
+                "V{:X} <<= 1, VF = bit shifted out (V{:X} = V{:X} << 1 with the shift_uses_vy quirk)",
+                x, x, y
+            ),
+            SneXy(x, y) => format!("skip the next instruction if V{:X} != V{:X}", x, y),
+            LdI(nnn) => format!("I = 0x{:03X}", nnn),
+            JpV0(nnn) => format!("PC = V0 + 0x{:03X}", nnn),
+            Rnd(x, kk) => format!("V{:X} = random byte & 0x{:02X}", x, kk),
+            Drw(x, y, 0) => format!(
+                "draw a 16x16 sprite from I at (V{:X}, V{:X}), VF = collision",
+                x, y
+            ),
+            Drw(x, y, n) => format!(
+                "draw a {}-row sprite from I at (V{:X}, V{:X}), VF = collision",
+                n, x, y
+            ),
+            Skp(x) => format!("skip the next instruction if key V{:X} is pressed", x),
+            Sknp(x) => format!("skip the next instruction if key V{:X} is not pressed", x),
+            LdILong(nnnn) => format!("I = 0x{:04X}", nnnn),
+            Plane(n) => format!("select drawing planes {}", n),
+            Audio => "load a 16-byte audio pattern from I".to_string(),
+            LdVxDt(x) => format!("V{:X} = delay timer", x),
+            LdVxK(x) => format!("wait for a key press, V{:X} = key", x),
+            LdDtVx(x) => format!("delay timer = V{:X}", x),
+            LdStVx(x) => format!("sound timer = V{:X}", x),
+            AddIVx(x) => format!("I += V{:X}", x),
+            LdFVx(x) => format!("I = address of the font glyph for V{:X}", x),
+            LdHfVx(x) => format!("I = address of the big font glyph for V{:X}", x),
+            LdBVx(x) => format!("store the BCD digits of V{:X} at I, I+1, I+2", x),
+            Pitch(x) => format!("audio pitch = V{:X}", x),
+            LdIVx(x) => format!("store V0..V{:X} at I (I advances with the index_increment quirk)", x),
+            LdVxI(x) => format!("load V0..V{:X} from I (I advances with the index_increment quirk)", x),
+            LdRVx(x) => format!("save V0..V{:X} to the flag registers", x),
+            LdVxR(x) => format!("load V0..V{:X} from the flag registers", x),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            Sys(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Scd(n) => write!(f, "SCD {}", n),
+            Scu(n) => write!(f, "SCU {}", n),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            Jp(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            SeByte(x, kk) => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            SneByte(x, kk) => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            SeXy(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            SaveXy(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            LoadXy(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            LdByte(x, kk) => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            AddByte(x, kk) => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            LdXy(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            OrXy(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            AndXy(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            XorXy(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddXy(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            SubXy(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShrXy(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubnXy(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShlXy(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneXy(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            JpV0(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Rnd(x, kk) => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp(x) => write!(f, "SKP V{:X}", x),
+            Sknp(x) => write!(f, "SKNP V{:X}", x),
+            LdILong(nnnn) => write!(f, "LD I, LONG 0x{:04X}", nnnn),
+            Plane(n) => write!(f, "PLANE {}", n),
+            Audio => write!(f, "AUDIO"),
+            LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            AddIVx(x) => write!(f, "ADD I, V{:X}", x),
+            LdFVx(x) => write!(f, "LD F, V{:X}", x),
+            LdHfVx(x) => write!(f, "LD HF, V{:X}", x),
+            LdBVx(x) => write!(f, "LD B, V{:X}", x),
+            Pitch(x) => write!(f, "PITCH V{:X}", x),
+            LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            LdVxR(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_encode_round_trip() {
+        for opcode in 0..=u16::MAX {
+            match Instruction::decode(opcode) {
+                Ok(instruction) => assert_eq!(instruction.encode(), opcode, "{}", instruction),
+                Err(DecodeError::MissingOperand) => assert_eq!(opcode, LONG_PREFIX),
+                Err(DecodeError::UnknownOpcode(unknown)) => assert_eq!(unknown, opcode),
+                Err(DecodeError::Truncated) => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_and_display() {
+        let cases = [
+            (0x00E0, "CLS"),
+            (0x00C3, "SCD 3"),
+            (0x0123, "SYS 0x123"),
+            (0x1234, "JP 0x234"),
+            (0x3A7F, "SE VA, 0x7F"),
+            (0x5AB2, "SAVE VA, VB"),
+            (0x8126, "SHR V1, V2"),
+            (0xD125, "DRW V1, V2, 5"),
+            (0xE3A1, "SKNP V3"),
+            (0xF201, "PLANE 2"),
+            (0xF455, "LD [I], V4"),
+        ];
+        for (opcode, text) in cases {
+            assert_eq!(Instruction::decode(opcode).unwrap().to_string(), text);
+        }
+        assert_eq!(
+            Instruction::decode(0x5121),
+            Err(DecodeError::UnknownOpcode(0x5121))
+        );
+    }
+
+    #[test]
+    fn test_fetch_long_instruction() {
+        let memory = [0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0];
+        let instruction = Instruction::fetch(&memory, 0).unwrap();
+        assert_eq!(instruction, Instruction::LdILong(0x1234));
+        assert_eq!(instruction.size(), 4);
+        assert_eq!(instruction.to_bytes(), memory[..4]);
+        assert_eq!(instruction.instruction_set(), InstructionSet::XoChip);
+        assert_eq!(Instruction::fetch(&memory, 4), Ok(Instruction::Cls));
+        assert_eq!(
+            Instruction::fetch(&memory[..3], 0),
+            Err(DecodeError::MissingOperand)
+        );
+        assert_eq!(Instruction::fetch(&memory, 5), Err(DecodeError::Truncated));
+    }
+}
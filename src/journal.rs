@@ -0,0 +1,243 @@
+use crate::framebuffer::FrameBuffer;
+use crate::rng::Rng;
+use std::collections::VecDeque;
+
+// 命令単位の取り消し記録（デバッガの逆実行用）
+// 命令を実行する前のレジスタやタイマーと、命令が書き換えたメモリ・スタック・画面の行の
+// 元の値を残す。タイマーの更新も別の記録として残し、命令と一緒に取り消す。
+// 記録の数が capacity を超えると古いものから捨てる
+pub struct Journal {
+    capacity: usize,
+    records: VecDeque<Record>,
+    instructions: usize, // records のうち命令の記録の数
+    open: bool,          // 実行中の命令の変更を最新の記録に追加している
+}
+
+// 既定では最大50000件（画面を描き換える命令が多くても数MB程度）を保持する
+pub const DEFAULT_CAPACITY: usize = 50_000;
+
+// 命令の前にまるごと保存する小さな状態
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct State {
+    pub registers: [u8; 16],
+    pub program_counter: usize,
+    pub index_register: u16,
+    pub stack_pointer: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub rpl_flags: [u8; 16],
+    pub planes: u8,
+    pub audio_pattern: [u8; 16],
+    pub pitch: u8,
+    pub key: Option<u8>,
+    pub vblank: bool,
+    pub rng: Rng,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Record {
+    pub state: State,
+    pub instruction: bool,            // false はタイマーの更新
+    pub memory: Vec<(usize, u8)>,     // 書き換えたアドレスと元の値（書き換えた順）
+    pub stack: Option<(usize, u16)>,  // 書き換えたスタックの段と元の値
+    pub rows: Vec<(usize, Vec<u8>)>,  // 描画で変わった行と元のピクセル
+    pub display: Option<FrameBuffer>, // 消去・スクロール・解像度の切り替え前の画面
+}
+
+impl Record {
+    fn is_unchanged(&self, state: &State) -> bool {
+        self.memory.is_empty()
+            && self.stack.is_none()
+            && self.rows.is_empty()
+            && self.display.is_none()
+            && self.state == *state
+    }
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        Journal {
+            capacity: capacity.max(1),
+            records: VecDeque::new(),
+            instructions: 0,
+            open: false,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // 取り消せる命令の数
+    pub fn len(&self) -> usize {
+        self.instructions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.instructions = 0;
+        self.open = false;
+    }
+
+    // 命令の実行（instruction が false ならタイマーの更新）の前に呼ぶ
+    pub(crate) fn begin(&mut self, state: State, instruction: bool) {
+        if self.records.len() >= self.capacity {
+            if let Some(record) = self.records.pop_front() {
+                self.instructions -= record.instruction as usize;
+            }
+        }
+        self.records.push_back(Record {
+            state,
+            instruction,
+            memory: Vec::new(),
+            stack: None,
+            rows: Vec::new(),
+            display: None,
+        });
+        self.instructions += instruction as usize;
+        self.open = instruction;
+    }
+
+    // 命令の実行後に呼ぶ。何も変えなかった命令（キー入力待ちなど）の記録は捨てる
+    pub(crate) fn end(&mut self, state: &State) {
+        self.open = false;
+        let unchanged = self
+            .records
+            .back()
+            .is_some_and(|record| record.instruction && record.is_unchanged(state));
+        if unchanged {
+            self.records.pop_back();
+            self.instructions -= 1;
+        }
+    }
+
+    fn current(&mut self) -> Option<&mut Record> {
+        match self.open {
+            true => self.records.back_mut(),
+            false => None,
+        }
+    }
+
+    pub(crate) fn memory(&mut self, address: usize, old: u8) {
+        if let Some(record) = self.current() {
+            record.memory.push((address, old));
+        }
+    }
+
+    // 1命令で書き換えるスタックの段は1つだけ
+    pub(crate) fn stack(&mut self, slot: usize, old: u16) {
+        if let Some(record) = self.current() {
+            record.stack.get_or_insert((slot, old));
+        }
+    }
+
+    // 行を変える前に呼ぶ。同じ命令で既に保存した行は保存しない
+    pub(crate) fn row(&mut self, y: usize, display: &FrameBuffer) {
+        if let Some(record) = self.current() {
+            if record.display.is_none() && !record.rows.iter().any(|(row, _)| *row == y) {
+                record.rows.push((y, display.row(y).to_vec()));
+            }
+        }
+    }
+
+    // 画面全体を変える前に呼ぶ
+    pub(crate) fn display(&mut self, display: &FrameBuffer) {
+        if let Some(record) = self.current() {
+            record.display.get_or_insert_with(|| display.clone());
+        }
+    }
+
+    // 最後の命令の記録と、その後のタイマーの更新の記録を新しい順に取り出す
+    pub(crate) fn undo(&mut self) -> Option<Vec<Record>> {
+        if self.instructions == 0 {
+            return None;
+        }
+        self.open = false;
+        let mut records = Vec::new();
+        while let Some(record) = self.records.pop_back() {
+            let instruction = record.instruction;
+            records.push(record);
+            if instruction {
+                self.instructions -= 1;
+                break;
+            }
+        }
+        Some(records)
+    }
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(pc: usize) -> State {
+        State {
+            registers: [0; 16],
+            program_counter: pc,
+            index_register: 0,
+            stack_pointer: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            rpl_flags: [0; 16],
+            planes: 1,
+            audio_pattern: [0; 16],
+            pitch: 64,
+            key: None,
+            vblank: false,
+            rng: Rng::seeded(1),
+        }
+    }
+
+    #[test]
+    fn test_undo_takes_frames_with_instruction() {
+        let mut journal = Journal::new(4);
+        journal.begin(state(0x200), true);
+        journal.memory(0x300, 7);
+        journal.end(&state(0x202));
+        journal.begin(state(0x202), false);
+        // 記録が閉じている間の変更は残さない
+        journal.memory(0x301, 8);
+        journal.begin(state(0x202), true);
+        journal.end(&state(0x202));
+        assert_eq!(journal.len(), 1);
+
+        let records = journal.undo().unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(!records[0].instruction && records[0].memory.is_empty());
+        assert_eq!(records[1].memory, [(0x300, 7)]);
+        assert!(journal.is_empty());
+        assert!(journal.undo().is_none());
+    }
+
+    #[test]
+    fn test_capacity_drops_oldest() {
+        let mut journal = Journal::new(2);
+        let mut display = FrameBuffer::new(8, 2);
+        for pc in [0x200, 0x202, 0x204] {
+            journal.begin(state(pc), true);
+            journal.row(1, &display);
+            display.toggle(0, 1, 1);
+            journal.row(1, &display);
+            journal.end(&state(pc + 2));
+        }
+        assert_eq!(journal.len(), 2);
+        let records = journal.undo().unwrap();
+        assert_eq!(records[0].state.program_counter, 0x204);
+        assert_eq!(records[0].rows, [(1, vec![0; 8])]);
+        assert_eq!(
+            journal.undo().unwrap()[0].rows,
+            [(1, [1, 0, 0, 0, 0, 0, 0, 0].to_vec())]
+        );
+        assert!(journal.undo().is_none());
+    }
+}
@@ -1,49 +1,244 @@
 #[cfg(not(target_arch = "wasm32"))]
 use getch_rs::{Getch, Key};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
 use std::{sync::mpsc, thread};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    Pressed(u8),
+    Released(u8),
+}
+
+// CHIP-8のキーパッド以外にフロントエンドが使う操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    QuickSave(u8),
+    QuickLoad(u8),
+}
+
+// キーボードスレッドから送られる入力
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(u8),
+    Hotkey(Hotkey),
+    Rewind, // 押している間は巻き戻す
+}
+
 pub trait KeyboardInput {
-    fn start_keyboard_thread(sender: mpsc::Sender<u8>);
-    fn get_key(&self) -> Option<u8>;
+    fn start_keyboard_thread(sender: mpsc::Sender<InputEvent>);
+    // 現在押されているキーのビットマップ（bit n がキーnに対応）
+    fn pressed_keys(&mut self) -> u16;
+    // 前回の呼び出し以降に発生した押下・解放イベント
+    fn take_events(&mut self) -> Vec<KeyEvent>;
+    // 前回の呼び出し以降に押されたホットキー
+    fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        Vec::new()
+    }
+    // 巻き戻しキーが押されているか
+    fn rewind_held(&mut self) -> bool {
+        false
+    }
+}
+
+// QWERTYキーボードの文字をCHIP-8のキーに変換する
+pub fn map_key(c: char) -> Option<u8> {
+    match c.to_ascii_lowercase() {
+        '1' => Some(0x1),
+        '2' => Some(0x2),
+        '3' => Some(0x3),
+        '4' => Some(0xC),
+        'q' => Some(0x4),
+        'w' => Some(0x5),
+        'e' => Some(0x6),
+        'r' => Some(0xD),
+        'a' => Some(0x7),
+        's' => Some(0x8),
+        'd' => Some(0x9),
+        'f' => Some(0xE),
+        'z' => Some(0xA),
+        'x' => Some(0x0),
+        'c' => Some(0xB),
+        'v' => Some(0xF),
+        _ => None,
+    }
+}
+
+// 16キーの押下状態とイベントを管理する
+#[derive(Debug, Default)]
+pub struct KeyState {
+    pressed: u16,
+    events: Vec<KeyEvent>,
+}
+
+impl KeyState {
+    pub fn press(&mut self, key: u8) {
+        let bit = 1 << (key & 0xF);
+        if self.pressed & bit == 0 {
+            self.pressed |= bit;
+            self.events.push(KeyEvent::Pressed(key & 0xF));
+        }
+    }
+
+    pub fn release(&mut self, key: u8) {
+        let bit = 1 << (key & 0xF);
+        if self.pressed & bit != 0 {
+            self.pressed &= !bit;
+            self.events.push(KeyEvent::Released(key & 0xF));
+        }
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.pressed & (1 << (key & 0xF)) != 0
+    }
+
+    pub fn pressed(&self) -> u16 {
+        self.pressed
+    }
+
+    pub fn take_events(&mut self) -> Vec<KeyEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+// キー入力を受け付けない（デバッグアダプタやテスト用）
+pub struct NoKeyboard;
+
+impl KeyboardInput for NoKeyboard {
+    fn start_keyboard_thread(_sender: mpsc::Sender<InputEvent>) {}
+
+    fn pressed_keys(&mut self) -> u16 {
+        0
+    }
+
+    fn take_events(&mut self) -> Vec<KeyEvent> {
+        Vec::new()
+    }
+}
+
+// 端末ではキーを離したことを検出できないため、最後の入力からしばらく経ったら離したとみなす
+// 押し続けると最初のキーリピートまで250〜600ms程度かかるので、それまでは長めに待ち、
+// リピートが始まった後はリピートの間隔（30〜100ms程度）より少し長い時間で判定する
+#[cfg(not(target_arch = "wasm32"))]
+const KEY_REPEAT_DELAY: Duration = Duration::from_millis(650);
+#[cfg(not(target_arch = "wasm32"))]
+const KEY_REPEAT_INTERVAL: Duration = Duration::from_millis(150);
+
+// 端末から入力が届き続けている間は押されているとみなすキー
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy)]
+struct HeldKey {
+    seen: Instant,
+    repeating: bool, // 2回目以降の入力（キーリピート）が届いている
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl HeldKey {
+    // 入力が届いたときに呼ぶ。押されていなければ新しく押されたものとして扱う
+    fn update(held: &mut Option<HeldKey>, now: Instant) {
+        *held = Some(HeldKey {
+            seen: now,
+            repeating: held.is_some(),
+        });
+    }
+
+    fn is_released(&self, now: Instant) -> bool {
+        let timeout = if self.repeating {
+            KEY_REPEAT_INTERVAL
+        } else {
+            KEY_REPEAT_DELAY
+        };
+        now.duration_since(self.seen) >= timeout
+    }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 pub struct GetchKeyboard {
-    receiver: mpsc::Receiver<u8>,
+    receiver: mpsc::Receiver<InputEvent>,
+    state: KeyState,
+    hotkeys: Vec<Hotkey>,
+    held: [Option<HeldKey>; 16],
+    rewind: Option<HeldKey>,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 impl GetchKeyboard {
     pub fn new() -> Self {
-        let (sender, receiver) = mpsc::channel::<u8>();
+        let (sender, receiver) = mpsc::channel::<InputEvent>();
         Self::start_keyboard_thread(sender);
-        GetchKeyboard { receiver }
+        Self::with_receiver(receiver)
+    }
+
+    // 端末からの入力を自分で読まず、他のスレッド（デバッガなど）から受け取る
+    pub fn with_receiver(receiver: mpsc::Receiver<InputEvent>) -> Self {
+        GetchKeyboard {
+            receiver,
+            state: KeyState::default(),
+            hotkeys: Vec::new(),
+            held: [None; 16],
+            rewind: None,
+        }
+    }
+
+    // チャンネルに届いた入力と経過時間から押下状態を更新する
+    fn poll(&mut self) {
+        let now = Instant::now();
+        while let Ok(input) = self.receiver.try_recv() {
+            match input {
+                InputEvent::Key(key) => {
+                    self.state.press(key);
+                    HeldKey::update(&mut self.held[key as usize], now);
+                }
+                InputEvent::Hotkey(hotkey) => self.hotkeys.push(hotkey),
+                InputEvent::Rewind => HeldKey::update(&mut self.rewind, now),
+            }
+        }
+
+        for key in 0..16u8 {
+            if self.held[key as usize].is_some_and(|held| held.is_released(now)) {
+                self.held[key as usize] = None;
+                self.state.release(key);
+            }
+        }
+
+        if self.rewind.is_some_and(|held| held.is_released(now)) {
+            self.rewind = None;
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for GetchKeyboard {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 impl KeyboardInput for GetchKeyboard {
-    fn start_keyboard_thread(sender: mpsc::Sender<u8>) {
+    fn start_keyboard_thread(sender: mpsc::Sender<InputEvent>) {
         thread::spawn(move || {
             let g = Getch::new();
             loop {
                 match g.getch() {
-                    Ok(Key::Char('1')) => sender.send(0x1).unwrap(),
-                    Ok(Key::Char('2')) => sender.send(0x2).unwrap(),
-                    Ok(Key::Char('3')) => sender.send(0x3).unwrap(),
-                    Ok(Key::Char('4')) => sender.send(0xC).unwrap(),
-                    Ok(Key::Char('q')) => sender.send(0x4).unwrap(),
-                    Ok(Key::Char('w')) => sender.send(0x5).unwrap(),
-                    Ok(Key::Char('e')) => sender.send(0x6).unwrap(),
-                    Ok(Key::Char('r')) => sender.send(0xD).unwrap(),
-                    Ok(Key::Char('a')) => sender.send(0x7).unwrap(),
-                    Ok(Key::Char('s')) => sender.send(0x8).unwrap(),
-                    Ok(Key::Char('d')) => sender.send(0x9).unwrap(),
-                    Ok(Key::Char('f')) => sender.send(0xE).unwrap(),
-                    Ok(Key::Char('z')) => sender.send(0xA).unwrap(),
-                    Ok(Key::Char('x')) => sender.send(0x0).unwrap(),
-                    Ok(Key::Char('c')) => sender.send(0xB).unwrap(),
-                    Ok(Key::Char('v')) => sender.send(0xF).unwrap(),
+                    Ok(Key::Char(c)) => {
+                        if let Some(key) = map_key(c) {
+                            sender.send(InputEvent::Key(key)).unwrap();
+                        }
+                    }
+                    // F1〜F4でスロット1〜4にセーブ、F5〜F8でロード
+                    Ok(Key::F(n @ 1..=4)) => {
+                        sender
+                            .send(InputEvent::Hotkey(Hotkey::QuickSave(n)))
+                            .unwrap();
+                    }
+                    Ok(Key::F(n @ 5..=8)) => {
+                        sender
+                            .send(InputEvent::Hotkey(Hotkey::QuickLoad(n - 4)))
+                            .unwrap();
+                    }
+                    // Backspaceを押している間は巻き戻す
+                    Ok(Key::Backspace) => sender.send(InputEvent::Rewind).unwrap(),
                     Ok(Key::Esc) => std::process::exit(0),
                     _ => {}
                 }
@@ -51,7 +246,76 @@ impl KeyboardInput for GetchKeyboard {
         });
     }
 
-    fn get_key(&self) -> Option<u8> {
-        self.receiver.try_recv().ok()
+    fn pressed_keys(&mut self) -> u16 {
+        self.poll();
+        self.state.pressed()
+    }
+
+    fn take_events(&mut self) -> Vec<KeyEvent> {
+        self.poll();
+        self.state.take_events()
+    }
+
+    fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        self.poll();
+        std::mem::take(&mut self.hotkeys)
+    }
+
+    fn rewind_held(&mut self) -> bool {
+        self.poll();
+        self.rewind.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_state_tracks_multiple_keys() {
+        let mut state = KeyState::default();
+        state.press(0x4);
+        state.press(0x6);
+        state.press(0x4); // 押しっぱなしの間はイベントを出さない
+        assert_eq!(state.pressed(), 0b0101_0000);
+        assert!(state.is_pressed(0x6));
+
+        state.release(0x4);
+        state.release(0x4);
+        assert_eq!(state.pressed(), 0b0100_0000);
+        assert_eq!(
+            state.take_events(),
+            vec![
+                KeyEvent::Pressed(0x4),
+                KeyEvent::Pressed(0x6),
+                KeyEvent::Released(0x4)
+            ]
+        );
+        assert!(state.take_events().is_empty());
+    }
+
+    #[test]
+    fn test_held_key_waits_for_autorepeat() {
+        let start = Instant::now();
+        let after = |ms| start + Duration::from_millis(ms);
+        let mut held = None;
+        HeldKey::update(&mut held, start);
+        let key = held.unwrap();
+        // 最初のリピートが届くまでは離したとみなさない
+        assert!(!key.is_released(after(500)));
+        assert!(key.is_released(after(700)));
+
+        HeldKey::update(&mut held, after(500));
+        let key = held.unwrap();
+        assert!(key.repeating);
+        assert!(!key.is_released(after(600)));
+        assert!(key.is_released(after(700)));
+    }
+
+    #[test]
+    fn test_map_key() {
+        assert_eq!(map_key('Q'), Some(0x4));
+        assert_eq!(map_key('v'), Some(0xF));
+        assert_eq!(map_key('p'), None);
     }
 }
@@ -1,6 +1,31 @@
-mod chip8;
-mod display;
-mod keyboard;
+pub mod assembler;
+pub mod audio;
+pub mod cfg;
+pub mod chip8;
+pub mod compiler;
+pub mod dap;
+pub mod debug_ui;
+pub mod debugger;
+pub mod decompiler;
+pub mod disassembler;
+pub mod display;
+pub mod expression;
+pub mod font;
+pub mod framebuffer;
+pub mod instruction;
+pub mod journal;
+pub mod json;
+pub mod keyboard;
+pub mod linter;
+pub mod lsp;
+pub mod platform;
+pub mod png;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod savestate;
+pub mod sprites;
+pub mod verifier;
 mod web_display;
 mod web_keyboard;
 
@@ -8,8 +33,11 @@ use wasm_bindgen::prelude::*;
 use web_sys::console;
 use std::cell::RefCell;
 
-use chip8::Cpu;
+use chip8::{Cpu, ExecutionError, StepOutcome};
 use display::Draw;
+use keyboard::KeyboardInput;
+use platform::Platform;
+use rewind::RewindBuffer;
 use web_display::WebDraw;
 use web_keyboard::WebKeyboard;
 
@@ -27,15 +55,22 @@ pub struct GameState {
     last_cpu_time: f64,
     last_timer_time: f64,
     current_rom: Vec<u8>, // 現在のROMデータを保持
+    platform: Option<Platform>, // Noneの場合は従来の挙動
+    cpu_frequency: f64,
+    fault: Option<ExecutionError>, // 実行エラーで停止中の場合はその内容
+    save_slots: [Option<Vec<u8>>; SAVE_SLOT_COUNT], // クイックセーブの保存先
+    rewind: RewindBuffer,
 }
 
+const SAVE_SLOT_COUNT: usize = 4;
+
 thread_local! {
-    static GAME_STATE: RefCell<Option<GameState>> = RefCell::new(None);
+    static GAME_STATE: RefCell<Option<GameState>> = const { RefCell::new(None) };
 }
 
 // ログ初期化の状態を管理
 thread_local! {
-    static LOGGER_INITIALIZED: RefCell<bool> = RefCell::new(false);
+    static LOGGER_INITIALIZED: RefCell<bool> = const { RefCell::new(false) };
 }
 
 #[wasm_bindgen]
@@ -53,15 +88,28 @@ pub fn init_wasm() -> Result<(), JsValue> {
     })
 }
 
+fn create_cpu(rom_data: &[u8], platform: Option<Platform>) -> Cpu<WebKeyboard> {
+    let keyboard = WebKeyboard::new();
+    match platform {
+        Some(platform) => Cpu::from_platform(rom_data, keyboard, platform),
+        None => Cpu::from_bytes(rom_data, keyboard),
+    }
+}
+
+// platformには "vip", "chip48", "schip", "xochip" などの名前を指定する
 #[wasm_bindgen]
-pub fn init_game(canvas_id: &str, rom_data: &[u8]) -> Result<(), JsValue> {
+pub fn init_game(canvas_id: &str, rom_data: &[u8], platform: Option<String>) -> Result<(), JsValue> {
     log!("Initializing CHIP-8 emulator");
     
-    let keyboard = WebKeyboard::new();
+    let platform = platform
+        .map(|name| name.parse::<Platform>())
+        .transpose()
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
     let drawer = WebDraw::new(canvas_id)?;
     
     // CPUを初期化（ROM データを直接渡す）
-    let cpu = Cpu::from_bytes(rom_data, keyboard);
+    let cpu = create_cpu(rom_data, platform);
+    let cpu_frequency = platform.map_or(600.0, |p| p.clock_speed() as f64); // 既定は600命令/秒
     
     let now = js_sys::Date::now();
     let game_state = GameState {
@@ -70,6 +118,11 @@ pub fn init_game(canvas_id: &str, rom_data: &[u8]) -> Result<(), JsValue> {
         last_cpu_time: now,
         last_timer_time: now,
         current_rom: rom_data.to_vec(), // ROMデータを保存
+        platform,
+        cpu_frequency,
+        fault: None,
+        save_slots: Default::default(),
+        rewind: RewindBuffer::default(),
     };
     
     GAME_STATE.with(|state| {
@@ -84,24 +137,62 @@ pub fn init_game(canvas_id: &str, rom_data: &[u8]) -> Result<(), JsValue> {
 pub fn game_loop() {
     GAME_STATE.with(|state_cell| {
         if let Some(ref mut state) = *state_cell.borrow_mut() {
-            const CPU_FREQUENCY: f64 = 600.0; // 600命令/秒
             const TIMER_FREQUENCY: f64 = 60.0; // 60Hz固定
             
-            let cpu_interval = 1000.0 / CPU_FREQUENCY; // ミリ秒
+            let cpu_interval = 1000.0 / state.cpu_frequency; // ミリ秒
             let timer_interval = 1000.0 / TIMER_FREQUENCY; // ミリ秒
             
             let now = js_sys::Date::now();
+            let rewinding = state.cpu.keyboard_mut().rewind_held();
+
+            // 巻き戻し中は1フレームごとに1スナップショット戻る（エラーで停止中でも戻れる）
+            if rewinding {
+                if now - state.last_timer_time >= timer_interval {
+                    if state.rewind.step_back(&mut state.cpu) {
+                        state.fault = None;
+                        state.drawer.draw(state.cpu.get_display());
+                    }
+                    state.last_timer_time = now;
+                }
+                state.last_cpu_time = now;
+                return;
+            }
+
+            // エラーで停止中はリセットされるまで何もしない
+            if state.fault.is_some() {
+                return;
+            }
             
-            // CPU命令実行（600Hz）
-            if now - state.last_cpu_time >= cpu_interval {
-                state.cpu.update();
-                state.drawer.draw(state.cpu.get_display());
+            // CPU命令実行。前回から経過した時間の分をまとめて実行し、画面は最後に1回だけ描く
+            let instructions_per_frame = (state.cpu_frequency / TIMER_FREQUENCY).max(1.0) as usize;
+            let mut executed = 0;
+            while executed < instructions_per_frame && now - state.last_cpu_time >= cpu_interval {
+                match state.cpu.update() {
+                    // 00FD (EXIT) 後は最後の画面のまま何もしない
+                    Ok(StepOutcome::Exited) => break,
+                    Ok(_) => {}
+                    Err(error) => {
+                        log!("CHIP-8 fault: {}", error);
+                        state.drawer.draw_fault(&error);
+                        state.fault = Some(error);
+                        return;
+                    }
+                }
+                state.last_cpu_time += cpu_interval;
+                executed += 1;
+            }
+            // 1フレーム分より遅れた場合は追いつこうとせず、残りを捨てる
+            if executed == instructions_per_frame {
                 state.last_cpu_time = now;
             }
+            if executed > 0 {
+                state.drawer.draw(state.cpu.get_display());
+            }
             
             // タイマー減算（60Hz）
             if now - state.last_timer_time >= timer_interval {
                 state.cpu.decrement_timers();
+                state.rewind.record_frame(&state.cpu);
                 state.last_timer_time = now;
             }
         }
@@ -113,13 +204,14 @@ pub fn reset_current_game() -> Result<(), JsValue> {
     GAME_STATE.with(|state_cell| {
         if let Some(ref mut state) = *state_cell.borrow_mut() {
             // 現在のROMデータを使ってCPUを再初期化
-            let keyboard = WebKeyboard::new();
             let rom_data = state.current_rom.clone();
             
             // CPUを完全にリセット
-            state.cpu = Cpu::from_bytes(&rom_data, keyboard);
+            state.cpu = create_cpu(&rom_data, state.platform);
             state.last_cpu_time = js_sys::Date::now();
             state.last_timer_time = js_sys::Date::now();
+            state.fault = None;
+            state.rewind.clear();
             
             // 画面をクリア
             state.drawer.draw(state.cpu.get_display());
@@ -140,6 +232,62 @@ pub fn stop_game() {
     log!("Game stopped");
 }
 
+fn slot_index(slot: u8) -> Result<usize, JsValue> {
+    match slot {
+        1..=4 => Ok(slot as usize - 1),
+        _ => Err(JsValue::from_str(&format!("Invalid save slot: {}", slot))),
+    }
+}
+
+// スロット(1〜4)に現在の状態を保存する
+#[wasm_bindgen]
+pub fn quick_save(slot: u8) -> Result<(), JsValue> {
+    let index = slot_index(slot)?;
+    GAME_STATE.with(|state_cell| {
+        if let Some(ref mut state) = *state_cell.borrow_mut() {
+            state.save_slots[index] = Some(state.cpu.save_state());
+            log!("Saved state to slot {}", slot);
+            Ok(())
+        } else {
+            Err(JsValue::from_str("No game is currently loaded"))
+        }
+    })
+}
+
+// スロット(1〜4)の状態を復元する（エラーで停止中でも再開できる）
+#[wasm_bindgen]
+pub fn quick_load(slot: u8) -> Result<(), JsValue> {
+    let index = slot_index(slot)?;
+    GAME_STATE.with(|state_cell| {
+        if let Some(ref mut state) = *state_cell.borrow_mut() {
+            let data = state.save_slots[index]
+                .as_ref()
+                .ok_or_else(|| JsValue::from_str(&format!("Slot {} is empty", slot)))?;
+            state
+                .cpu
+                .load_state(data)
+                .map_err(|e| JsValue::from_str(&e.to_string()))?;
+            state.fault = None;
+            state.drawer.draw(state.cpu.get_display());
+            log!("Loaded state from slot {}", slot);
+            Ok(())
+        } else {
+            Err(JsValue::from_str("No game is currently loaded"))
+        }
+    })
+}
+
+// 実行エラーで停止している場合はそのメッセージを返す
+#[wasm_bindgen]
+pub fn current_fault() -> Option<String> {
+    GAME_STATE.with(|state_cell| {
+        state_cell
+            .borrow()
+            .as_ref()
+            .and_then(|state| state.fault.as_ref().map(|error| error.to_string()))
+    })
+}
+
 #[wasm_bindgen]
 pub fn is_game_running() -> bool {
     GAME_STATE.with(|state_cell| {
@@ -154,15 +302,15 @@ const GUESS_ROM: &[u8] = include_bytes!("../rom/GUESS");
 
 #[wasm_bindgen]
 pub fn load_brix(canvas_id: &str) -> Result<(), JsValue> {
-    init_game(canvas_id, BRIX_ROM)
+    init_game(canvas_id, BRIX_ROM, None)
 }
 
 #[wasm_bindgen]
 pub fn load_invaders(canvas_id: &str) -> Result<(), JsValue> {
-    init_game(canvas_id, INVADERS_ROM)
+    init_game(canvas_id, INVADERS_ROM, None)
 }
 
 #[wasm_bindgen]
 pub fn load_guess(canvas_id: &str) -> Result<(), JsValue> {
-    init_game(canvas_id, GUESS_ROM)
+    init_game(canvas_id, GUESS_ROM, None)
 }
\ No newline at end of file
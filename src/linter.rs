@@ -0,0 +1,765 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::mpsc;
+
+use crate::cfg::analyze;
+use crate::chip8::{Cpu, ExecutionError, StepOutcome};
+use crate::instruction::{Instruction, InstructionSet};
+use crate::keyboard::{InputEvent, KeyEvent, KeyState, KeyboardInput};
+use crate::platform::Platform;
+use crate::quirks::{IndexIncrement, Quirks, SysCall};
+use crate::rng::Rng;
+
+// 600命令/秒で約100秒分
+pub const DEFAULT_STEPS: usize = 60_000;
+const STEPS_PER_FRAME: usize = 10;
+const SEED: u32 = 0xC8C8;
+// 入力を待つROMも先に進むように、一定の間隔でキーを順番に押す
+const FRAMES_PER_KEY: usize = 30;
+const FRAMES_HELD: usize = 10;
+
+// ROMの動作に影響しうるQuirks（display_wait はタイミングだけなので対象外）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Quirk {
+    ShiftUsesVy,
+    IndexIncrement,
+    VfReset,
+    JumpUsesVx,
+    ClipSprites,
+    SysCall,
+}
+
+impl Quirk {
+    pub const ALL: [Quirk; 6] = [
+        Quirk::ShiftUsesVy,
+        Quirk::IndexIncrement,
+        Quirk::VfReset,
+        Quirk::JumpUsesVx,
+        Quirk::ClipSprites,
+        Quirk::SysCall,
+    ];
+
+    // Quirks のフィールド名
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quirk::ShiftUsesVy => "shift_uses_vy",
+            Quirk::IndexIncrement => "index_increment",
+            Quirk::VfReset => "vf_reset",
+            Quirk::JumpUsesVx => "jump_uses_vx",
+            Quirk::ClipSprites => "clip_sprites",
+            Quirk::SysCall => "sys_call",
+        }
+    }
+
+    // "vf_reset = true" のような設定値の表記
+    pub fn setting(&self, quirks: &Quirks) -> String {
+        let value = match self {
+            Quirk::ShiftUsesVy => quirks.shift_uses_vy.to_string(),
+            Quirk::IndexIncrement => match quirks.index_increment {
+                IndexIncrement::Unchanged => "unchanged".to_string(),
+                IndexIncrement::X => "x".to_string(),
+                IndexIncrement::XPlusOne => "x+1".to_string(),
+            },
+            Quirk::VfReset => quirks.vf_reset.to_string(),
+            Quirk::JumpUsesVx => quirks.jump_uses_vx.to_string(),
+            Quirk::ClipSprites => quirks.clip_sprites.to_string(),
+            Quirk::SysCall => match quirks.sys_call {
+                SysCall::Jump => "jump".to_string(),
+                SysCall::Ignore => "ignore".to_string(),
+                SysCall::Unsupported => "unsupported".to_string(),
+            },
+        };
+        format!("{} = {}", self.name(), value)
+    }
+
+    // この項目だけを変えた設定
+    fn variants(&self, base: Quirks) -> Vec<Quirks> {
+        let with = |change: &dyn Fn(&mut Quirks)| {
+            let mut quirks = base;
+            change(&mut quirks);
+            quirks
+        };
+        let candidates = match self {
+            Quirk::ShiftUsesVy => vec![with(&|q| q.shift_uses_vy = !base.shift_uses_vy)],
+            Quirk::IndexIncrement => [
+                IndexIncrement::Unchanged,
+                IndexIncrement::X,
+                IndexIncrement::XPlusOne,
+            ]
+            .into_iter()
+            .map(|value| with(&|q| q.index_increment = value))
+            .collect(),
+            Quirk::VfReset => vec![with(&|q| q.vf_reset = !base.vf_reset)],
+            Quirk::JumpUsesVx => vec![with(&|q| q.jump_uses_vx = !base.jump_uses_vx)],
+            Quirk::ClipSprites => vec![with(&|q| q.clip_sprites = !base.clip_sprites)],
+            // Unsupported はエラーにするだけなので動作の違いとしては比べない
+            Quirk::SysCall => [SysCall::Jump, SysCall::Ignore]
+                .into_iter()
+                .map(|value| with(&|q| q.sys_call = value))
+                .collect(),
+        };
+        candidates
+            .into_iter()
+            .filter(|quirks| *quirks != base)
+            .collect()
+    }
+}
+
+impl fmt::Display for Quirk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// 命令を見ただけでわかるQuirksへの依存
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticFinding {
+    pub address: usize,
+    pub instruction: Instruction,
+    pub quirk: Quirk,
+    pub certain: bool, // false の場合は基本ブロックの外で使われるかもしれない
+    pub reason: &'static str,
+}
+
+impl fmt::Display for StaticFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:03X}: {}: {} ({})",
+            self.address, self.instruction, self.quirk, self.reason
+        )
+    }
+}
+
+// 2つの設定で同時に実行したときに最初に見つかった違い
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Register {
+        register: u8,
+        baseline: u8,
+        variant: u8,
+    },
+    Index {
+        baseline: u16,
+        variant: u16,
+    },
+    ProgramCounter {
+        baseline: usize,
+        variant: usize,
+    },
+    Display,
+    Fault {
+        baseline: Option<ExecutionError>,
+        variant: Option<ExecutionError>,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fault = |error: &Option<ExecutionError>| match error {
+            Some(error) => error.to_string(),
+            None => "runs".to_string(),
+        };
+        match self {
+            Difference::Register {
+                register,
+                baseline,
+                variant,
+            } => write!(
+                f,
+                "V{:X} = 0x{:02X} instead of 0x{:02X}",
+                register, variant, baseline
+            ),
+            Difference::Index { baseline, variant } => {
+                write!(f, "I = 0x{:03X} instead of 0x{:03X}", variant, baseline)
+            }
+            Difference::ProgramCounter { baseline, variant } => {
+                write!(f, "PC = 0x{:03X} instead of 0x{:03X}", variant, baseline)
+            }
+            Difference::Display => write!(f, "the display differs"),
+            Difference::Fault { baseline, variant } => {
+                write!(f, "{} instead of {}", fault(variant), fault(baseline))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub quirk: Quirk,
+    pub variant: Quirks,
+    pub step: usize,
+    pub address: usize, // 違いが出た命令
+    pub instruction: Option<Instruction>,
+    pub difference: Difference,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: step {} at {:03X}",
+            self.quirk.setting(&self.variant),
+            self.step,
+            self.address
+        )?;
+        if let Some(instruction) = self.instruction {
+            write!(f, " ({})", instruction)?;
+        }
+        write!(f, ": {}", self.difference)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recommendation {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub adjusted: Vec<Quirk>, // プラットフォームの設定ではエラーになるので変えた項目
+    pub undecided: Vec<Quirk>, // 依存しているが、実行してもどちらが正しいか決められない項目
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintReport {
+    pub findings: Vec<StaticFinding>,
+    pub baseline: Quirks,
+    pub divergences: Vec<Divergence>,
+    pub steps: usize,
+    pub recommendation: Recommendation,
+}
+
+impl LintReport {
+    // 実行して違いが出たか、命令から確実に依存するとわかるQuirks
+    pub fn sensitive(&self) -> BTreeSet<Quirk> {
+        sensitive(&self.findings, &self.divergences)
+    }
+
+    // 基本ブロックの外まで追えなかったもの
+    pub fn possibly_sensitive(&self) -> BTreeSet<Quirk> {
+        let sensitive = self.sensitive();
+        self.findings
+            .iter()
+            .map(|finding| finding.quirk)
+            .filter(|quirk| !sensitive.contains(quirk))
+            .collect()
+    }
+}
+
+fn sensitive(findings: &[StaticFinding], divergences: &[Divergence]) -> BTreeSet<Quirk> {
+    findings
+        .iter()
+        .filter(|finding| finding.certain)
+        .map(|finding| finding.quirk)
+        .chain(divergences.iter().map(|divergence| divergence.quirk))
+        .collect()
+}
+
+// ROMがどのQuirksに依存しているかを調べ、合うプラットフォームを薦める
+//
+// 静的: 到達する命令を調べる（x != y の 8xy6 など）
+// 動的: 基準の設定と1項目だけ変えた設定で同時に実行し、最初の違いを報告する
+pub fn lint(rom: &[u8], platform: Option<Platform>, steps: usize) -> LintReport {
+    // プラットフォームの指定が無い場合は全ての命令を解釈できる XO-CHIP として辿る
+    let graph = analyze(rom, platform.or(Some(Platform::XoChip)));
+    let instructions: Vec<&[(usize, Instruction)]> = graph
+        .blocks()
+        .values()
+        .map(|block| block.instructions.as_slice())
+        .collect();
+
+    let mut findings = Vec::new();
+    for block in &instructions {
+        for (i, &(address, instruction)) in block.iter().enumerate() {
+            let rest = &block[i + 1..];
+            for (quirk, certain, reason) in static_dependencies(instruction, rest) {
+                findings.push(StaticFinding {
+                    address,
+                    instruction,
+                    quirk,
+                    certain,
+                    reason,
+                });
+            }
+        }
+    }
+    findings.sort_by_key(|finding| finding.address);
+
+    let baseline = platform.map_or_else(Quirks::default, |platform| platform.quirks());
+    let mut divergences = Vec::new();
+    for quirk in Quirk::ALL {
+        for variant in quirk.variants(baseline) {
+            if let Some((step, address, instruction, difference)) =
+                lockstep(rom, platform, baseline, variant, steps)
+            {
+                divergences.push(Divergence {
+                    quirk,
+                    variant,
+                    step,
+                    address,
+                    instruction,
+                    difference,
+                });
+            }
+        }
+    }
+
+    let mut sets = Vec::new();
+    for block in &instructions {
+        for (_, instruction) in block.iter() {
+            if !sets.contains(&instruction.instruction_set()) {
+                sets.push(instruction.instruction_set());
+            }
+        }
+    }
+    let sensitive = sensitive(&findings, &divergences);
+    let recommendation = recommend(rom, platform, &sets, &sensitive, steps);
+
+    LintReport {
+        findings,
+        baseline,
+        divergences,
+        steps,
+        recommendation,
+    }
+}
+
+// 使っている命令セットに対応するプラットフォームのうち、依存しているQuirksの設定で
+// エラーにならないものを選ぶ。古いインタプリタ向けのROMほど後の拡張を前提にしないので、古い順に試す。
+// どのプラットフォームの設定でもエラーになる場合は、エラーにならない値に変えて薦める
+fn recommend(
+    rom: &[u8],
+    platform: Option<Platform>,
+    sets: &[InstructionSet],
+    sensitive: &BTreeSet<Quirk>,
+    steps: usize,
+) -> Recommendation {
+    let candidates: Vec<Platform> = match platform {
+        Some(platform) => vec![platform],
+        None => Platform::ALL
+            .into_iter()
+            .filter(|&platform| {
+                sets.iter()
+                    .all(|&set| Platform::supports(Some(platform), set))
+            })
+            .collect(),
+    };
+    let recommendations: Vec<Recommendation> = candidates
+        .iter()
+        .map(|&platform| settle(rom, platform, sensitive, steps))
+        .collect();
+    let runs = |recommendation: &&Recommendation| {
+        runs_without_errors(rom, recommendation.platform, recommendation.quirks, steps)
+    };
+    recommendations
+        .iter()
+        .filter(|recommendation| recommendation.adjusted.is_empty())
+        .find(runs)
+        .or_else(|| recommendations.iter().find(runs))
+        .or(recommendations.first())
+        .cloned()
+        .unwrap_or_else(|| settle(rom, Platform::XoChip, sensitive, steps))
+}
+
+// 依存している項目ごとに、プラットフォームの設定と他の値でエラー無く動くかを調べる
+// プラットフォームの設定でエラーになり、他の値なら動く場合はその値に変える
+// どの値でも動く（または動かない）項目は決められないものとして残す
+fn settle(
+    rom: &[u8],
+    platform: Platform,
+    sensitive: &BTreeSet<Quirk>,
+    steps: usize,
+) -> Recommendation {
+    let mut quirks = platform.quirks();
+    let mut adjusted = Vec::new();
+    let mut undecided = Vec::new();
+    for &quirk in sensitive {
+        let working: Vec<Quirks> = quirk
+            .variants(quirks)
+            .into_iter()
+            .filter(|&variant| runs_without_errors(rom, platform, variant, steps))
+            .collect();
+        if runs_without_errors(rom, platform, quirks, steps) {
+            if !working.is_empty() {
+                undecided.push(quirk);
+            }
+        } else if let Some(&variant) = working.first() {
+            quirks = variant;
+            adjusted.push(quirk);
+        } else {
+            undecided.push(quirk);
+        }
+    }
+    Recommendation {
+        platform,
+        quirks,
+        adjusted,
+        undecided,
+    }
+}
+
+fn runs_without_errors(rom: &[u8], platform: Platform, quirks: Quirks, steps: usize) -> bool {
+    let mut cpu = machine(rom, Some(platform), quirks);
+    for step in 0..steps {
+        tick(&mut cpu, step);
+        match cpu.update() {
+            Err(_) => return false,
+            Ok(StepOutcome::Exited) => return true,
+            Ok(_) => {}
+        }
+    }
+    true
+}
+
+// ---- 静的な検査 ----
+
+// 命令の後に続く同じ基本ブロック内の命令 rest を見て、依存するQuirksと確実かどうか、その理由を返す
+fn static_dependencies(
+    instruction: Instruction,
+    rest: &[(usize, Instruction)],
+) -> Vec<(Quirk, bool, &'static str)> {
+    use Instruction::*;
+
+    match instruction {
+        ShrXy(x, y) | ShlXy(x, y) if x != y => {
+            vec![(Quirk::ShiftUsesVy, true, "shifts a register other than VX")]
+        }
+        LdIVx(_) | LdVxI(_) => match next_use(rest, index_access) {
+            Use::Read => vec![(Quirk::IndexIncrement, true, "I is used afterwards")],
+            Use::Unknown => vec![(Quirk::IndexIncrement, false, "I may be used afterwards")],
+            Use::Overwritten => Vec::new(),
+        },
+        OrXy(..) | AndXy(..) | XorXy(..) => match next_use(rest, flag_access) {
+            Use::Read => vec![(Quirk::VfReset, true, "VF is read afterwards")],
+            Use::Unknown => vec![(Quirk::VfReset, false, "VF may be read afterwards")],
+            Use::Overwritten => Vec::new(),
+        },
+        // B0nn は どちらの解釈でも V0 を使う
+        JpV0(nnn) if nnn >> 8 != 0 => {
+            vec![(Quirk::JumpUsesVx, true, "jumps relative to V0 or VX")]
+        }
+        Sys(_) => vec![(
+            Quirk::SysCall,
+            true,
+            "machine code call is a jump or ignored",
+        )],
+        _ => Vec::new(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Use {
+    Read,
+    Overwritten,
+    Unknown,
+}
+
+// (読むか, 上書きするか)
+type Access = fn(Instruction) -> (bool, bool);
+
+// 値を上書きされる前に読む命令があるか
+fn next_use(rest: &[(usize, Instruction)], access: Access) -> Use {
+    for &(_, instruction) in rest {
+        // 呼び出し先で使われるかはわからない
+        if matches!(instruction, Instruction::Call(_)) {
+            return Use::Unknown;
+        }
+        match access(instruction) {
+            (true, _) => return Use::Read,
+            (false, true) => return Use::Overwritten,
+            _ => {}
+        }
+    }
+    Use::Unknown
+}
+
+fn index_access(instruction: Instruction) -> (bool, bool) {
+    use Instruction::*;
+
+    match instruction {
+        Drw(..) | LdBVx(_) | LdIVx(_) | LdVxI(_) | AddIVx(_) | SaveXy(..) | LoadXy(..) | Audio => {
+            (true, true)
+        }
+        LdI(_) | LdILong(_) | LdFVx(_) | LdHfVx(_) => (false, true),
+        _ => (false, false),
+    }
+}
+
+fn flag_access(instruction: Instruction) -> (bool, bool) {
+    use Instruction::*;
+
+    const F: u8 = 0xF;
+    let range = |x: u8, y: u8| x.min(y) <= F && F <= x.max(y);
+    match instruction {
+        SeByte(x, _)
+        | SneByte(x, _)
+        | Skp(x)
+        | Sknp(x)
+        | LdDtVx(x)
+        | LdStVx(x)
+        | AddIVx(x)
+        | LdFVx(x)
+        | LdHfVx(x)
+        | LdBVx(x)
+        | Pitch(x) => (x == F, false),
+        SeXy(x, y) | SneXy(x, y) => (x == F || y == F, false),
+        AddByte(x, _) => (x == F, x == F),
+        LdByte(x, _) | Rnd(x, _) | LdVxDt(x) | LdVxK(x) => (false, x == F),
+        LdXy(x, y) => (y == F, x == F),
+        OrXy(x, y) | AndXy(x, y) | XorXy(x, y) => (x == F || y == F, x == F),
+        // フラグを設定する演算
+        AddXy(x, y) | SubXy(x, y) | SubnXy(x, y) | ShrXy(x, y) | ShlXy(x, y) => {
+            (x == F || y == F, true)
+        }
+        Drw(x, y, _) => (x == F || y == F, true),
+        LdIVx(x) | LdRVx(x) => (x == F, false),
+        LdVxI(x) | LdVxR(x) => (false, x == F),
+        SaveXy(x, y) => (range(x, y), false),
+        LoadXy(x, y) => (false, range(x, y)),
+        _ => (false, false),
+    }
+}
+
+// ---- 動的な検査 ----
+
+// 決まった順番でキーを押すキーボード
+#[derive(Default)]
+struct ScriptedKeyboard {
+    keys: KeyState,
+}
+
+impl KeyboardInput for ScriptedKeyboard {
+    fn start_keyboard_thread(_sender: mpsc::Sender<InputEvent>) {}
+
+    fn pressed_keys(&mut self) -> u16 {
+        self.keys.pressed()
+    }
+
+    fn take_events(&mut self) -> Vec<KeyEvent> {
+        self.keys.take_events()
+    }
+}
+
+fn machine(rom: &[u8], platform: Option<Platform>, quirks: Quirks) -> Cpu<ScriptedKeyboard> {
+    let keyboard = ScriptedKeyboard::default();
+    let mut cpu = match platform {
+        Some(platform) => Cpu::from_platform_with_rng(rom, keyboard, platform, Rng::seeded(SEED)),
+        None => Cpu::with_rng(rom, keyboard, Rng::seeded(SEED)),
+    };
+    cpu.set_quirks(quirks);
+    cpu
+}
+
+// 命令数から決まるタイマーと入力を与える（同じ step なら同じ状態になる）
+fn tick(cpu: &mut Cpu<ScriptedKeyboard>, step: usize) {
+    if !step.is_multiple_of(STEPS_PER_FRAME) {
+        return;
+    }
+    let frame = step / STEPS_PER_FRAME;
+    if frame > 0 {
+        cpu.decrement_timers();
+    }
+    let key = (frame / FRAMES_PER_KEY % 16) as u8;
+    match frame % FRAMES_PER_KEY {
+        0 => cpu.keyboard_mut().keys.press(key),
+        FRAMES_HELD => cpu.keyboard_mut().keys.release(key),
+        _ => {}
+    }
+}
+
+fn changes_display(instruction: Option<Instruction>) -> bool {
+    use Instruction::*;
+
+    matches!(
+        instruction,
+        Some(Drw(..) | Cls | Scd(_) | Scu(_) | Scr | Scl | Low | High)
+    )
+}
+
+fn lockstep(
+    rom: &[u8],
+    platform: Option<Platform>,
+    baseline: Quirks,
+    variant: Quirks,
+    steps: usize,
+) -> Option<(usize, usize, Option<Instruction>, Difference)> {
+    let mut a = machine(rom, platform, baseline);
+    let mut b = machine(rom, platform, variant);
+    for step in 0..steps {
+        tick(&mut a, step);
+        tick(&mut b, step);
+        let address = a.program_counter();
+        let instruction = Instruction::fetch(a.memory(), address).ok();
+        let (result_a, result_b) = (a.update(), b.update());
+        let difference = match (result_a, result_b) {
+            (Err(x), Err(y)) if x == y => return None,
+            (Ok(StepOutcome::Exited), Ok(StepOutcome::Exited)) => return None,
+            (Err(x), Ok(_)) => Some(Difference::Fault {
+                baseline: Some(x),
+                variant: None,
+            }),
+            (Ok(_), Err(y)) => Some(Difference::Fault {
+                baseline: None,
+                variant: Some(y),
+            }),
+            (Err(x), Err(y)) => Some(Difference::Fault {
+                baseline: Some(x),
+                variant: Some(y),
+            }),
+            _ => compare(&a, &b, changes_display(instruction)),
+        };
+        if let Some(difference) = difference {
+            return Some((step, address, instruction, difference));
+        }
+    }
+    None
+}
+
+fn compare(
+    a: &Cpu<ScriptedKeyboard>,
+    b: &Cpu<ScriptedKeyboard>,
+    display: bool,
+) -> Option<Difference> {
+    let registers = a.registers().iter().zip(b.registers()).enumerate();
+    for (register, (&baseline, &variant)) in registers {
+        if baseline != variant {
+            return Some(Difference::Register {
+                register: register as u8,
+                baseline,
+                variant,
+            });
+        }
+    }
+    if a.index_register() != b.index_register() {
+        return Some(Difference::Index {
+            baseline: a.index_register(),
+            variant: b.index_register(),
+        });
+    }
+    if a.program_counter() != b.program_counter() {
+        return Some(Difference::ProgramCounter {
+            baseline: a.program_counter(),
+            variant: b.program_counter(),
+        });
+    }
+    if display && a.get_display() != b.get_display() {
+        return Some(Difference::Display);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn test_static_findings() {
+        let rom = assemble(
+            "
+            v1 >>= v2      # x != y
+            v3 <<= v3      # x == y は影響しない
+            v1 |= v2
+            if vf == 1 then v0 := 0
+            v1 &= v2
+            vf := 0        # 上書きされるので影響しない
+            i := 0x300
+            save v2
+            sprite v0 v0 1
+            loop again
+            ",
+        )
+        .unwrap();
+        let report = lint(&rom, None, 0);
+        let findings: Vec<(usize, Quirk, bool)> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.address, finding.quirk, finding.certain))
+            .collect();
+        assert_eq!(
+            findings,
+            vec![
+                (0x200, Quirk::ShiftUsesVy, true),
+                (0x204, Quirk::VfReset, true),
+                (0x210, Quirk::IndexIncrement, true),
+            ]
+        );
+        assert!(report.divergences.is_empty());
+    }
+
+    #[test]
+    fn test_lockstep_divergence() {
+        let rom = assemble(
+            "
+            i := data
+            load v1
+            v0 := 60
+            sprite v0 v1 1   # 右端からはみ出す
+            loop again
+            : data
+            0xFF 0
+            ",
+        )
+        .unwrap();
+        let report = lint(&rom, None, 100);
+        let divergences: Vec<String> = report.divergences.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            divergences,
+            vec![
+                "index_increment = x: step 1 at 202 (LD V1, [I]): I = 0x20B instead of 0x20A",
+                "index_increment = x+1: step 1 at 202 (LD V1, [I]): I = 0x20C instead of 0x20A",
+                "clip_sprites = true: step 3 at 206 (DRW V0, V1, 1): the display differs",
+            ]
+        );
+        assert_eq!(
+            report.sensitive(),
+            BTreeSet::from([Quirk::IndexIncrement, Quirk::ClipSprites])
+        );
+    }
+
+    #[test]
+    fn test_recommendation_uses_instruction_sets() {
+        let rom = assemble("hires\nloop again").unwrap();
+        let report = lint(&rom, None, 100);
+        assert_eq!(report.recommendation.platform, Platform::SuperChip);
+
+        let rom = assemble("v0 := 1\nloop again").unwrap();
+        let report = lint(&rom, None, 100);
+        assert!(report.sensitive().is_empty());
+        assert_eq!(report.recommendation.platform, Platform::CosmacVip);
+    }
+
+    #[test]
+    fn test_recommendation_follows_dependencies() {
+        // shift_uses_vy でなければ v1 = 0 になり、空のスタックから戻ろうとする
+        let rom = assemble(
+            "
+            v2 := 4
+            v1 >>= v2
+            if v1 == 0 then return
+            loop again
+            ",
+        )
+        .unwrap();
+        let recommendation = lint(&rom, None, 100).recommendation;
+        assert_eq!(recommendation.platform, Platform::CosmacVip);
+        assert!(recommendation.adjusted.is_empty() && recommendation.undecided.is_empty());
+
+        // 逆の場合は shift_uses_vy = false の CHIP-48 を薦める
+        let rom = assemble(
+            "
+            v2 := 4
+            v1 >>= v2
+            if v1 != 0 then return
+            loop again
+            ",
+        )
+        .unwrap();
+        let recommendation = lint(&rom, None, 100).recommendation;
+        assert_eq!(recommendation.platform, Platform::Chip48);
+        assert!(!recommendation.quirks.shift_uses_vy);
+
+        // どちらでも動く場合は決められない項目として残す
+        let rom = assemble("v2 := 4\nv1 >>= v2\nloop again").unwrap();
+        let recommendation = lint(&rom, None, 100).recommendation;
+        assert_eq!(recommendation.undecided, vec![Quirk::ShiftUsesVy]);
+    }
+}
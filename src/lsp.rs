@@ -0,0 +1,664 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::assembler::{analyze, Analysis, Symbol, SymbolKind};
+use crate::instruction::InstructionSet;
+use crate::json::Json;
+
+// Octo形式のソースのためのLanguage Server（標準入出力でJSON-RPCをやり取りする）
+//
+// - 診断: アセンブルのエラー
+// - 定義へ移動: ラベル・定数・エイリアス・マクロ
+// - ホバー: その行が出力する命令と動作（Instruction::semantics）、名前の値
+// - 補完: 命令・ディレクティブ・定義済みの名前
+//
+// 位置の列は文字単位で数える（コメント以外はASCIIなのでUTF-16の位置と一致する）
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// 補完に出す命令と書き方
+const MNEMONICS: &[(&str, &str)] = &[
+    ("clear", "clear"),
+    ("return", "return"),
+    ("exit", "exit"),
+    ("hires", "hires"),
+    ("lores", "lores"),
+    ("scroll-down", "scroll-down n"),
+    ("scroll-up", "scroll-up n"),
+    ("scroll-left", "scroll-left"),
+    ("scroll-right", "scroll-right"),
+    ("bcd", "bcd vx"),
+    ("save", "save vx / save vx - vy"),
+    ("load", "load vx / load vx - vy"),
+    ("saveflags", "saveflags vx"),
+    ("loadflags", "loadflags vx"),
+    ("sprite", "sprite vx vy n"),
+    ("jump", "jump label"),
+    ("jump0", "jump0 label"),
+    ("native", "native address"),
+    ("plane", "plane n"),
+    ("audio", "audio"),
+    ("i", "i := label / i += vx"),
+    ("delay", "delay := vx"),
+    ("buzzer", "buzzer := vx"),
+    ("pitch", "pitch := vx"),
+    ("if", "if vx == n then / if vx == n begin"),
+    ("then", "if ... then"),
+    ("begin", "if ... begin ... end"),
+    ("else", "if ... begin ... else ... end"),
+    ("end", "if ... begin ... end"),
+    ("loop", "loop ... again"),
+    ("while", "while vx != n"),
+    ("again", "loop ... again"),
+    ("key", "vx := key / if vx key then"),
+    ("random", "vx := random mask"),
+    ("hex", "i := hex vx"),
+    ("bighex", "i := bighex vx"),
+    ("long", "i := long label"),
+];
+
+const DIRECTIVES: &[(&str, &str)] = &[
+    (":", ": label"),
+    (":const", ":const name value"),
+    (":alias", ":alias name vx"),
+    (":macro", ":macro name params { ... }"),
+    (":calc", ":calc name { expression }"),
+    (":byte", ":byte value"),
+    (":org", ":org address"),
+    (":call", ":call label"),
+    (":unpack", ":unpack n label"),
+];
+
+// LSPの CompletionItemKind
+const KIND_FUNCTION: usize = 3;
+const KIND_VARIABLE: usize = 6;
+const KIND_KEYWORD: usize = 14;
+const KIND_SNIPPET: usize = 15;
+const KIND_CONSTANT: usize = 21;
+
+// Content-Length ヘッダ付きのメッセージを1つ読む。入力が終われば None
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing Content-Length header",
+        ));
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+// exit 通知を受け取るか入力が終わるまで処理する
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut server = Server::new();
+    while let Some(body) = read_message(&mut input)? {
+        let replies = match Json::parse(&body) {
+            Ok(message) => server.handle(&message),
+            Err(e) => vec![error_response(Json::Null, PARSE_ERROR, &e.to_string())],
+        };
+        for reply in &replies {
+            write_message(&mut output, reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    exited: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    // メッセージを1つ処理し、送り返すメッセージ（応答と通知）を返す
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let Some(id) = message.get("id").cloned() else {
+            return self.notification(method, params);
+        };
+        if self.shutdown {
+            return vec![error_response(id, INVALID_REQUEST, "server is shut down")];
+        }
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+        };
+        match result {
+            Ok(result) => vec![Json::object([
+                ("jsonrpc", "2.0".into()),
+                ("id", id),
+                ("result", result),
+            ])],
+            Err((code, message)) => vec![error_response(id, code, &message)],
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .map(str::to_string);
+        match (method, uri) {
+            ("exit", _) => {
+                self.exited = true;
+                Vec::new()
+            }
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|document| document.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+                vec![self.diagnostics(&uri)]
+            }
+            // 同期は全文（textDocumentSync = 1）なので最後の変更がそのまま新しい内容
+            ("textDocument/didChange", Some(uri)) => {
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str);
+                let Some(text) = text else {
+                    return Vec::new();
+                };
+                self.documents.insert(uri.clone(), text.to_string());
+                vec![self.diagnostics(&uri)]
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(&uri);
+                vec![publish_diagnostics(&uri, Vec::new())]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn diagnostics(&self, uri: &str) -> Json {
+        let text = &self.documents[uri];
+        let diagnostics = match analyze(text).result {
+            Ok(_) => Vec::new(),
+            Err(error) => {
+                let (line, column) = (error.line - 1, error.column - 1);
+                let length = word_length(text, line, column).max(1);
+                vec![Json::object([
+                    ("range", range(line, column, column + length)),
+                    ("severity", 1usize.into()),
+                    ("source", "chip8".into()),
+                    ("message", error.message.into()),
+                ])]
+            }
+        };
+        publish_diagnostics(uri, diagnostics)
+    }
+
+    // 位置パラメータから、文書の内容・その解析結果・カーソル位置のトークンを取り出す
+    fn lookup<'a>(&self, params: &'a Json) -> Result<Lookup<'a>, (i64, String)> {
+        let invalid = || {
+            (
+                INVALID_PARAMS,
+                "missing textDocument or position".to_string(),
+            )
+        };
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .ok_or_else(invalid)?;
+        let position = params.get("position").ok_or_else(invalid)?;
+        let line = position
+            .get("line")
+            .and_then(Json::as_u64)
+            .ok_or_else(invalid)? as usize;
+        let character = position
+            .get("character")
+            .and_then(Json::as_u64)
+            .ok_or_else(invalid)? as usize;
+        let Some(text) = self.documents.get(uri) else {
+            return Err((INVALID_PARAMS, format!("document '{}' is not open", uri)));
+        };
+        Ok(Lookup {
+            uri,
+            line,
+            analysis: analyze(text),
+            token: token_at(text, line, character),
+        })
+    }
+
+    fn definition(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let lookup = self.lookup(params)?;
+        let Some(symbol) = lookup.symbol() else {
+            return Ok(Json::Null);
+        };
+        let (line, column) = (symbol.line - 1, symbol.column - 1);
+        Ok(Json::object([
+            ("uri", lookup.uri.into()),
+            (
+                "range",
+                range(line, column, column + symbol.name.chars().count()),
+            ),
+        ]))
+    }
+
+    fn hover(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let lookup = self.lookup(params)?;
+        let Some((text, start)) = &lookup.token else {
+            return Ok(Json::Null);
+        };
+        let contents = match lookup.symbol() {
+            Some(symbol) => describe_symbol(symbol),
+            None => {
+                // その行から出力された命令（マクロや if の展開では複数になる）
+                let lines: Vec<String> = lookup
+                    .analysis
+                    .listing
+                    .iter()
+                    .filter(|listed| listed.line == lookup.line + 1)
+                    .map(|listed| {
+                        let bytes: String = listed
+                            .instruction
+                            .to_bytes()
+                            .iter()
+                            .map(|byte| format!("{:02X}", byte))
+                            .collect();
+                        let set = match listed.instruction.instruction_set() {
+                            InstructionSet::Chip8 => "",
+                            InstructionSet::SuperChip => " *(SUPER-CHIP)*",
+                            InstructionSet::XoChip => " *(XO-CHIP)*",
+                        };
+                        format!(
+                            "`{:03X}: {}` **{}**{} — {}",
+                            listed.address,
+                            bytes,
+                            listed.instruction,
+                            set,
+                            listed.instruction.semantics()
+                        )
+                    })
+                    .collect();
+                if lines.is_empty() {
+                    return Ok(Json::Null);
+                }
+                lines.join("\n\n")
+            }
+        };
+        Ok(Json::object([
+            (
+                "contents",
+                Json::object([("kind", "markdown".into()), ("value", contents.into())]),
+            ),
+            (
+                "range",
+                range(lookup.line, *start, start + text.chars().count()),
+            ),
+        ]))
+    }
+
+    fn completion(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let lookup = self.lookup(params)?;
+        let item = |label: &str, kind: usize, detail: String| {
+            Json::object([
+                ("label", label.into()),
+                ("kind", kind.into()),
+                ("detail", detail.into()),
+            ])
+        };
+        let mut items: Vec<Json> = MNEMONICS
+            .iter()
+            .map(|(name, syntax)| item(name, KIND_KEYWORD, syntax.to_string()))
+            .chain(
+                DIRECTIVES
+                    .iter()
+                    .map(|(name, syntax)| item(name, KIND_KEYWORD, syntax.to_string())),
+            )
+            .collect();
+        let mut seen = Vec::new();
+        for symbol in &lookup.analysis.symbols {
+            if seen.contains(&&symbol.name) {
+                continue;
+            }
+            seen.push(&symbol.name);
+            let kind = match symbol.kind {
+                SymbolKind::Label(_) => KIND_FUNCTION,
+                SymbolKind::Constant(_) => KIND_CONSTANT,
+                SymbolKind::Alias(_) => KIND_VARIABLE,
+                SymbolKind::Macro(_) => KIND_SNIPPET,
+            };
+            items.push(item(&symbol.name, kind, describe_symbol(symbol)));
+        }
+        Ok(Json::Array(items))
+    }
+}
+
+struct Lookup<'a> {
+    uri: &'a str,
+    line: usize,
+    analysis: Analysis,
+    token: Option<(String, usize)>,
+}
+
+impl Lookup<'_> {
+    fn symbol(&self) -> Option<&Symbol> {
+        let (text, _) = self.token.as_ref()?;
+        self.analysis
+            .symbols
+            .iter()
+            .find(|symbol| &symbol.name == text)
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        (
+            "capabilities",
+            Json::object([
+                ("textDocumentSync", 1usize.into()),
+                ("definitionProvider", true.into()),
+                ("hoverProvider", true.into()),
+                (
+                    "completionProvider",
+                    Json::object([("triggerCharacters", vec![Json::from(":")].into())]),
+                ),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object([
+                ("name", "chip8-lsp".into()),
+                ("version", env!("CARGO_PKG_VERSION").into()),
+            ]),
+        ),
+    ])
+}
+
+fn error_response(id: Json, code: i64, message: &str) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id),
+        (
+            "error",
+            Json::object([("code", code.into()), ("message", message.into())]),
+        ),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            Json::object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        ),
+    ])
+}
+
+// 1行の中の範囲（行・列は0始まり）
+fn range(line: usize, start: usize, end: usize) -> Json {
+    let position =
+        |character: usize| Json::object([("line", line.into()), ("character", character.into())]);
+    Json::object([("start", position(start)), ("end", position(end))])
+}
+
+fn describe_symbol(symbol: &Symbol) -> String {
+    match &symbol.kind {
+        SymbolKind::Label(address) => format!("label `{}` = 0x{:03X}", symbol.name, address),
+        SymbolKind::Constant(value) => format!("constant `{}` = {}", symbol.name, value),
+        SymbolKind::Alias(register) => format!("alias `{}` = v{:X}", symbol.name, register),
+        SymbolKind::Macro(params) => format!("macro `{}` {}", symbol.name, params.join(" "))
+            .trim_end()
+            .to_string(),
+    }
+}
+
+// カーソル位置にある空白区切りのトークンと、その開始列。アセンブラと同様に # 以降はコメント
+fn token_at(text: &str, line: usize, character: usize) -> Option<(String, usize)> {
+    let chars: Vec<char> = text.lines().nth(line)?.chars().collect();
+    let mut start = 0;
+    while start < chars.len() {
+        if chars[start].is_whitespace() {
+            start += 1;
+            continue;
+        }
+        if chars[start] == '#' {
+            return None;
+        }
+        let end = (start..chars.len())
+            .find(|&i| chars[i].is_whitespace())
+            .unwrap_or(chars.len());
+        // 単語の直後にあるカーソルも単語の上とみなす
+        if (start..=end).contains(&character) {
+            return Some((chars[start..end].iter().collect(), start));
+        }
+        start = end;
+    }
+    None
+}
+
+fn word_length(text: &str, line: usize, column: usize) -> usize {
+    text.lines().nth(line).map_or(0, |line| {
+        line.chars()
+            .skip(column)
+            .take_while(|c| !c.is_whitespace())
+            .count()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::KEYWORDS;
+
+    fn request(id: usize, method: &str, params: Json) -> Json {
+        Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ])
+    }
+
+    fn notification(method: &str, params: Json) -> Json {
+        Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ])
+    }
+
+    fn position(line: usize, character: usize) -> Json {
+        Json::object([
+            (
+                "textDocument",
+                Json::object([("uri", "file:///a.8o".into())]),
+            ),
+            (
+                "position",
+                Json::object([("line", line.into()), ("character", character.into())]),
+            ),
+        ])
+    }
+
+    fn open(text: &str) -> Json {
+        notification(
+            "textDocument/didOpen",
+            Json::object([(
+                "textDocument",
+                Json::object([
+                    ("uri", "file:///a.8o".into()),
+                    ("languageId", "octo".into()),
+                    ("version", 1usize.into()),
+                    ("text", text.into()),
+                ]),
+            )]),
+        )
+    }
+
+    #[test]
+    fn test_stdio_session_with_diagnostics() {
+        let messages = [
+            request(1, "initialize", Json::object([])),
+            notification("initialized", Json::object([])),
+            open("clear\nv0 := 300\n"),
+            request(2, "shutdown", Json::Null),
+            notification("exit", Json::Null),
+        ];
+        let mut input = Vec::new();
+        for message in &messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        run(io::Cursor::new(input), &mut output).unwrap();
+
+        let mut reader = io::Cursor::new(output);
+        let mut replies = Vec::new();
+        while let Some(body) = read_message(&mut reader).unwrap() {
+            replies.push(Json::parse(&body).unwrap());
+        }
+        assert_eq!(replies.len(), 3);
+        let capabilities = replies[0]
+            .get("result")
+            .unwrap()
+            .get("capabilities")
+            .unwrap();
+        assert_eq!(
+            capabilities.get("hoverProvider").and_then(Json::as_bool),
+            Some(true)
+        );
+        let diagnostic = &replies[1]
+            .get("params")
+            .unwrap()
+            .get("diagnostics")
+            .unwrap()
+            .as_array()
+            .unwrap()[0];
+        assert_eq!(
+            diagnostic.get("range").unwrap().to_string(),
+            r#"{"start":{"line":1,"character":6},"end":{"line":1,"character":9}}"#
+        );
+        assert!(diagnostic
+            .get("message")
+            .and_then(Json::as_str)
+            .unwrap()
+            .contains("out of range"));
+        assert_eq!(replies[2].get("id").and_then(Json::as_u64), Some(2));
+    }
+
+    #[test]
+    fn test_definition_hover_and_completion() {
+        let source = ":const SPEED 3\n: main\n  v1 := SPEED\n  jump main # loop\n  vf += v2\n";
+        let mut server = Server::new();
+        assert_eq!(server.handle(&open(source)).len(), 1);
+
+        // jump main の main から定義へ
+        let reply = server.handle(&request(1, "textDocument/definition", position(3, 8)));
+        assert_eq!(
+            reply[0]
+                .get("result")
+                .unwrap()
+                .get("range")
+                .unwrap()
+                .to_string(),
+            r#"{"start":{"line":1,"character":2},"end":{"line":1,"character":6}}"#
+        );
+
+        let hover = |server: &mut Server, line, character| {
+            let reply = server.handle(&request(2, "textDocument/hover", position(line, character)));
+            reply[0]
+                .get("result")
+                .and_then(|result| result.get("contents"))
+                .and_then(|contents| contents.get("value"))
+                .and_then(Json::as_str)
+                .map(str::to_string)
+        };
+        assert_eq!(
+            hover(&mut server, 2, 2).unwrap(),
+            "`200: 6103` **LD V1, 0x03** — V1 = 0x03"
+        );
+        assert_eq!(hover(&mut server, 2, 10).unwrap(), "constant `SPEED` = 3");
+        assert!(hover(&mut server, 4, 3).unwrap().contains("VF = carry"));
+        assert_eq!(hover(&mut server, 3, 13), None);
+
+        let reply = server.handle(&request(3, "textDocument/completion", position(4, 0)));
+        let labels: Vec<&str> = reply[0]
+            .get("result")
+            .and_then(Json::as_array)
+            .unwrap()
+            .iter()
+            .filter_map(|item| item.get("label").and_then(Json::as_str))
+            .collect();
+        for expected in ["sprite", ":const", "SPEED", "main"] {
+            assert!(labels.contains(&expected), "{}", expected);
+        }
+
+        let reply = server.handle(&request(4, "textDocument/unknown", Json::Null));
+        assert_eq!(
+            reply[0]
+                .get("error")
+                .unwrap()
+                .get("code")
+                .and_then(Json::as_f64),
+            Some(METHOD_NOT_FOUND as f64)
+        );
+    }
+
+    #[test]
+    fn test_every_keyword_has_a_completion() {
+        for keyword in KEYWORDS {
+            assert!(
+                MNEMONICS.iter().any(|(name, _)| name == keyword),
+                "{}",
+                keyword
+            );
+        }
+    }
+}
@@ -1,59 +1,617 @@
 #[cfg(not(target_arch = "wasm32"))]
-mod chip8;
+use chip8::assembler::assemble;
 #[cfg(not(target_arch = "wasm32"))]
-mod display;
+use chip8::audio::AudioSink;
 #[cfg(not(target_arch = "wasm32"))]
-mod keyboard;
-
+use chip8::cfg::analyze;
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::chip8::{Cpu, StepOutcome};
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::compiler::compile;
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::dap;
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::debug_ui::{execute, render, Command, View, HELP};
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::debugger::{Debugger, StopReason};
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::decompiler::decompile;
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::disassembler::disassemble;
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::display::{CUIDraw, Draw};
 #[cfg(not(target_arch = "wasm32"))]
-use chip8::Cpu;
+use chip8::journal;
 #[cfg(not(target_arch = "wasm32"))]
-use display::{CUIDraw, Draw};
+use chip8::keyboard::{map_key, GetchKeyboard, Hotkey, InputEvent, KeyboardInput};
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::linter::{lint, Quirk, DEFAULT_STEPS};
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::platform::Platform;
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::rewind::RewindBuffer;
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::rng::{Rng, RngAlgorithm};
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::sprites::{find_sprites, memory_image, to_ascii, to_png, ByteRange};
+#[cfg(not(target_arch = "wasm32"))]
+use chip8::verifier::verify;
 #[cfg(not(target_arch = "wasm32"))]
 use getch_rs::{Getch, Key};
 #[cfg(not(target_arch = "wasm32"))]
-use keyboard::{GetchKeyboard, KeyboardInput};
+use log::error;
 #[cfg(not(target_arch = "wasm32"))]
 use simplelog::*;
 #[cfg(not(target_arch = "wasm32"))]
 use std::fs::File;
 #[cfg(not(target_arch = "wasm32"))]
+use std::io::Write;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc;
+#[cfg(not(target_arch = "wasm32"))]
 use std::time::{Duration, Instant};
 
+// 端末のベルで鳴らす（音の長さは表現できない）
 #[cfg(not(target_arch = "wasm32"))]
-fn start<T: KeyboardInput, D: Draw>(mut cpu: Cpu<T>, drawer: D) {
-    const CPU_FREQUENCY: u64 = 600; // 600命令/秒
+struct TerminalBell;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AudioSink for TerminalBell {
+    fn set_active(&mut self, active: bool) {
+        if active {
+            print!("\x07");
+            let _ = std::io::stdout().flush();
+        }
+    }
+}
+
+// セーブスロットのファイル名（例: saves/BRIX.1.state）
+#[cfg(not(target_arch = "wasm32"))]
+fn slot_path(save_dir: &Path, rom_name: &str, slot: u8) -> PathBuf {
+    save_dir.join(format!("{}.{}.state", rom_name, slot))
+}
+
+// 状態をロードできた場合はtrueを返す
+#[cfg(not(target_arch = "wasm32"))]
+fn handle_hotkey<T: KeyboardInput>(cpu: &mut Cpu<T>, hotkey: Hotkey, rom_name: &str) -> bool {
+    let save_dir = Path::new("saves");
+    match hotkey {
+        Hotkey::QuickSave(slot) => {
+            let path = slot_path(save_dir, rom_name, slot);
+            let result = std::fs::create_dir_all(save_dir)
+                .and_then(|_| std::fs::write(&path, cpu.save_state()));
+            match result {
+                Ok(()) => log::info!("Saved state to {}", path.display()),
+                Err(e) => error!("Failed to save {}: {}", path.display(), e),
+            }
+            false
+        }
+        Hotkey::QuickLoad(slot) => {
+            let path = slot_path(save_dir, rom_name, slot);
+            match std::fs::read(&path) {
+                Ok(data) => match cpu.load_state(&data) {
+                    Ok(()) => {
+                        log::info!("Loaded state from {}", path.display());
+                        return true;
+                    }
+                    Err(e) => error!("Failed to load {}: {}", path.display(), e),
+                },
+                Err(e) => error!("Failed to read {}: {}", path.display(), e),
+            }
+            false
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn start<T: KeyboardInput, D: Draw>(
+    mut cpu: Cpu<T>,
+    drawer: D,
+    cpu_frequency: u64,
+    rom_name: &str,
+) {
     const TIMER_FREQUENCY: u64 = 60; // 60Hz固定
-    
-    let cpu_interval = Duration::from_nanos(1_000_000_000 / CPU_FREQUENCY);
+
+    let cpu_interval = Duration::from_nanos(1_000_000_000 / cpu_frequency);
     let timer_interval = Duration::from_nanos(1_000_000_000 / TIMER_FREQUENCY);
-    
+    // 1回のループで実行する命令の上限（スリープが長引いても1フレーム分まで）
+    let instructions_per_frame = (cpu_frequency / TIMER_FREQUENCY).max(1);
+
     let mut last_cpu_time = Instant::now();
     let mut last_timer_time = Instant::now();
-    
+    let mut rewind = RewindBuffer::default();
+    // エラーで停止中はロードか巻き戻しをするまで実行しない（ESCで終了）
+    let mut faulted = false;
+
     loop {
         let now = Instant::now();
-        
-        // CPU命令実行（600Hz）
-        if now.duration_since(last_cpu_time) >= cpu_interval {
-            cpu.update();
-            drawer.draw(cpu.get_display());
-            last_cpu_time = now;
-        }
-        
-        // タイマー減算（60Hz）
+
+        for hotkey in cpu.keyboard_mut().take_hotkeys() {
+            // ロードに失敗した場合はエラーで停止したまま
+            if handle_hotkey(&mut cpu, hotkey, rom_name) {
+                faulted = false;
+                drawer.draw(cpu.get_display());
+            }
+        }
+        let rewinding = cpu.keyboard_mut().rewind_held();
+
+        // CPU命令実行。前回から経過した時間の分をまとめて実行し、画面は最後に1回だけ描く
+        if !faulted && !rewinding {
+            let mut executed = 0;
+            while executed < instructions_per_frame
+                && now.duration_since(last_cpu_time) >= cpu_interval
+            {
+                match cpu.update() {
+                    Ok(StepOutcome::Exited) => return, // 00FD (EXIT)
+                    Ok(_) => {}
+                    Err(error) => {
+                        error!("CHIP-8 fault: {}", error);
+                        drawer.draw_fault(&error);
+                        faulted = true;
+                        break;
+                    }
+                }
+                last_cpu_time += cpu_interval;
+                executed += 1;
+            }
+            // 1フレーム分より遅れた場合は追いつこうとせず、残りを捨てる
+            if executed == instructions_per_frame {
+                last_cpu_time = now;
+            }
+            if executed > 0 && !faulted {
+                drawer.draw(cpu.get_display());
+            }
+        }
+
+        // タイマー減算（60Hz）。巻き戻し中は1フレームごとに1スナップショット戻る
         if now.duration_since(last_timer_time) >= timer_interval {
-            cpu.decrement_timers();
+            if rewinding {
+                if rewind.step_back(&mut cpu) {
+                    faulted = false;
+                    drawer.draw(cpu.get_display());
+                }
+            } else if !faulted {
+                cpu.decrement_timers();
+                rewind.record_frame(&cpu);
+            }
             last_timer_time = now;
         }
-        
+
         // CPU使用率を下げるため短時間スリープ
         std::thread::sleep(Duration::from_micros(100));
     }
 }
 
+// --debug: 画面・レジスタ・スタック・逆アセンブル・メモリを並べて表示し、コマンドで操作する
+// 端末の入力はこのスレッドで読み、一時停止中はコマンド、実行中はCHIP-8のキーとして扱う
+#[cfg(not(target_arch = "wasm32"))]
+fn start_debugger<T: KeyboardInput>(
+    mut cpu: Cpu<T>,
+    keys: mpsc::Sender<InputEvent>,
+    cpu_frequency: u64,
+) {
+    const TIMER_FREQUENCY: u64 = 60;
+
+    let frame_interval = Duration::from_nanos(1_000_000_000 / TIMER_FREQUENCY);
+    let instructions_per_frame = (cpu_frequency / TIMER_FREQUENCY).max(1) as usize;
+
+    let (sender, input) = mpsc::channel::<Key>();
+    std::thread::spawn(move || {
+        let g = Getch::new();
+        while let Ok(key) = g.getch() {
+            if sender.send(key).is_err() {
+                break;
+            }
+        }
+    });
+
+    // 逆実行できるよう、取り消し記録は最初から有効にしておく（history off で止められる）
+    cpu.set_journal(Some(journal::DEFAULT_CAPACITY));
+    let mut debugger = Debugger::new(cpu);
+    let mut view = View {
+        memory_address: 0x200,
+        message: HELP.to_string(),
+        ..View::default()
+    };
+    let mut last_command = Command::Step;
+    let mut last_frame_time = Instant::now();
+    print!("\x1b[2J");
+
+    loop {
+        while let Ok(key) = input.try_recv() {
+            if !debugger.is_paused() {
+                match key {
+                    Key::Esc => {
+                        debugger.pause();
+                        view.message = "paused".to_string();
+                    }
+                    Key::Char(c) => {
+                        if let Some(key) = map_key(c) {
+                            let _ = keys.send(InputEvent::Key(key));
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            match key {
+                Key::Char('\n' | '\r') => {
+                    // 空行は直前のコマンドを繰り返す
+                    let text = std::mem::take(&mut view.input);
+                    let command = match text.trim() {
+                        "" => Ok(last_command.clone()),
+                        text => text.parse::<Command>(),
+                    };
+                    match command {
+                        Ok(Command::Quit) => {
+                            print!("\x1b[2J\x1b[H");
+                            return;
+                        }
+                        Ok(command) => {
+                            execute(&mut debugger, &mut view, &command);
+                            last_command = command;
+                        }
+                        Err(e) => view.message = e.to_string(),
+                    }
+                }
+                Key::Char(c) => view.input.push(c),
+                Key::Backspace | Key::Delete => {
+                    view.input.pop();
+                }
+                Key::Esc => view.input.clear(),
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        if now.duration_since(last_frame_time) >= frame_interval {
+            if !debugger.is_paused() {
+                match debugger.run(instructions_per_frame) {
+                    Some(StopReason::Step) => view.message.clear(),
+                    Some(reason) => view.message = format!("stopped: {}", reason),
+                    None => debugger.cpu_mut().decrement_timers(),
+                }
+                for line in debugger.take_logs() {
+                    view.push_log(line);
+                }
+            }
+
+            let mut buffer = String::from("\x1b[?25l\x1b[H");
+            for line in render(&debugger, &view) {
+                buffer.push_str(&line);
+                buffer.push_str("\x1b[K\n");
+            }
+            buffer.push_str("\x1b[J\x1b[?25h");
+            print!("{}", buffer);
+            std::io::stdout().flush().unwrap();
+            last_frame_time = now;
+        }
+
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+// --name <値> または --name=<値> の形式で指定されたオプションを読み取る
+#[cfg(not(target_arch = "wasm32"))]
+fn option_value(name: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let flag = format!("--{}", name);
+    let prefix = format!("--{}=", name);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if let Some(value) = arg.strip_prefix(&prefix) {
+            return Some(value.to_string());
+        }
+        if *arg == flag {
+            match iter.next() {
+                Some(value) => return Some(value.clone()),
+                None => {
+                    eprintln!("{} requires a value", flag);
+                    std::process::exit(1)
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_option<V: std::str::FromStr>(name: &str) -> Option<V>
+where
+    V::Err: std::fmt::Display,
+{
+    option_value(name).map(|value| match value.parse() {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("--{}: {}", name, e);
+            std::process::exit(1)
+        }
+    })
+}
+
+// desktop disasm <ROM> [--platform 名前]: ROMをOcto形式で逆アセンブルして表示する
+#[cfg(not(target_arch = "wasm32"))]
+fn disasm_command(path: Option<&String>, platform: Option<Platform>) {
+    let Some(path) = path else {
+        eprintln!("usage: desktop disasm <rom> [--platform <name>]");
+        std::process::exit(1)
+    };
+    let rom = std::fs::read(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1)
+    });
+    print!("{}", disassemble(&rom, platform));
+}
+
+// desktop cfg <ROM> [--platform 名前]: 制御フローグラフをGraphviz DOT形式で表示する
+#[cfg(not(target_arch = "wasm32"))]
+fn cfg_command(path: Option<&String>, platform: Option<Platform>) {
+    let Some(path) = path else {
+        eprintln!("usage: desktop cfg <rom> [--platform <name>]");
+        std::process::exit(1)
+    };
+    let rom = std::fs::read(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1)
+    });
+    let graph = analyze(&rom, platform);
+    for boundary in graph.boundaries() {
+        eprintln!("warning: {:?}", boundary);
+    }
+    print!("{}", graph.to_dot());
+}
+
+// desktop decompile <ROM> [--platform 名前]: 構造化したOcto風のソースを表示する
+#[cfg(not(target_arch = "wasm32"))]
+fn decompile_command(path: Option<&String>, platform: Option<Platform>) {
+    let Some(path) = path else {
+        eprintln!("usage: desktop decompile <rom> [--platform <name>]");
+        std::process::exit(1)
+    };
+    let rom = std::fs::read(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1)
+    });
+    print!("{}", decompile(&rom, platform));
+}
+
+// desktop verify <ROM> [--platform 名前]: 抽象解釈で実行時の問題を検出する
+// 必ず起きる問題（error）があれば終了コード1で終わる
+#[cfg(not(target_arch = "wasm32"))]
+fn verify_command(path: Option<&String>, platform: Option<Platform>) {
+    let Some(path) = path else {
+        eprintln!("usage: desktop verify <rom> [--platform <name>]");
+        std::process::exit(1)
+    };
+    let rom = std::fs::read(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1)
+    });
+    let report = verify(&rom, platform);
+    for finding in &report.findings {
+        println!("{}:{}", path, finding);
+    }
+    if !report.complete {
+        eprintln!("warning: analysis stopped early; some paths were not checked");
+    }
+    if report.findings.is_empty() && report.complete {
+        println!("{}: no problems found", path);
+    }
+    if report.has_errors() {
+        std::process::exit(1)
+    }
+}
+
+// desktop lint <ROM> [--platform 名前] [--steps 命令数]: ROMが依存するQuirksを調べて設定を薦める
+#[cfg(not(target_arch = "wasm32"))]
+fn lint_command(path: Option<&String>, platform: Option<Platform>) {
+    let Some(path) = path else {
+        eprintln!("usage: desktop lint <rom> [--platform <name>] [--steps <count>]");
+        std::process::exit(1)
+    };
+    let rom = std::fs::read(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1)
+    });
+    let steps = parse_option::<usize>("steps").unwrap_or(DEFAULT_STEPS);
+    let report = lint(&rom, platform, steps);
+    for finding in &report.findings {
+        println!("{}:{}", path, finding);
+    }
+    for divergence in &report.divergences {
+        println!("{}: {}", path, divergence);
+    }
+    let names = |quirks: std::collections::BTreeSet<Quirk>| {
+        quirks
+            .iter()
+            .map(Quirk::name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let sensitive = report.sensitive();
+    let possibly = report.possibly_sensitive();
+    if sensitive.is_empty() && possibly.is_empty() {
+        println!(
+            "{}: no quirk dependencies found in {} steps",
+            path, report.steps
+        );
+    }
+    if !sensitive.is_empty() {
+        println!("sensitive to: {}", names(sensitive));
+    }
+    if !possibly.is_empty() {
+        println!("possibly sensitive to: {}", names(possibly));
+    }
+    let recommendation = &report.recommendation;
+    println!(
+        "recommended: --platform {} ({})",
+        recommendation.platform.name(),
+        recommendation.platform.description()
+    );
+    for quirk in Quirk::ALL {
+        let note = if recommendation.adjusted.contains(&quirk) {
+            " (changed: the platform setting fails)"
+        } else if recommendation.undecided.contains(&quirk) {
+            " (the ROM depends on it: check by playing)"
+        } else {
+            ""
+        };
+        println!("  {}{}", quirk.setting(&recommendation.quirks), note);
+    }
+}
+
+// desktop sprites <ROM> [--platform 名前] [--range 開始-終了[:16],...] [--png 出力] [--scale 倍率]
+// Annn + Dxyn で描かれるスプライトと指定した範囲を、ASCIIアートかPNGのシートで出力する
+#[cfg(not(target_arch = "wasm32"))]
+fn sprites_command(path: Option<&String>, platform: Option<Platform>) {
+    let Some(path) = path else {
+        eprintln!(
+            "usage: desktop sprites <rom> [--platform <name>] [--range <start>-<end>[:16],...] [--png <output.png>] [--scale <n>]"
+        );
+        std::process::exit(1)
+    };
+    let rom = std::fs::read(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1)
+    });
+    let mut sprites = find_sprites(&rom, platform);
+    for range in option_value("range")
+        .iter()
+        .flat_map(|ranges| ranges.split(','))
+    {
+        match range.parse::<ByteRange>() {
+            Ok(range) => sprites.push(range.sprite()),
+            Err(e) => {
+                eprintln!("--range: {}", e);
+                std::process::exit(1)
+            }
+        }
+    }
+    let memory = memory_image(&rom, platform);
+    match option_value("png") {
+        Some(output) => {
+            let scale = parse_option::<usize>("scale").unwrap_or(4);
+            if let Err(e) = std::fs::write(&output, to_png(&sprites, &memory, scale)) {
+                eprintln!("{}: {}", output, e);
+                std::process::exit(1)
+            }
+            for sprite in &sprites {
+                println!("{}", sprite);
+            }
+            println!("{}: {} sprites", output, sprites.len());
+        }
+        None => print!("{}", to_ascii(&sprites, &memory)),
+    }
+}
+
+// desktop asm <ソース> <出力先>: Octo形式のソースをアセンブルしてROMを書き出す
+#[cfg(not(target_arch = "wasm32"))]
+fn asm_command(source: Option<&String>, output: Option<&String>) {
+    let (Some(source), Some(output)) = (source, output) else {
+        eprintln!("usage: desktop asm <source.8o> <output.ch8>");
+        std::process::exit(1)
+    };
+    let text = std::fs::read_to_string(source).unwrap_or_else(|e| {
+        eprintln!("{}: {}", source, e);
+        std::process::exit(1)
+    });
+    match assemble(&text) {
+        Ok(rom) => {
+            if let Err(e) = std::fs::write(output, &rom) {
+                eprintln!("{}: {}", output, e);
+                std::process::exit(1)
+            }
+            println!("{}: {} bytes", output, rom.len());
+        }
+        Err(e) => {
+            eprintln!("{}:{}", source, e);
+            std::process::exit(1)
+        }
+    }
+}
+
+// desktop compile <ソース> <出力>: C風の言語をROMにコンパイルする
+#[cfg(not(target_arch = "wasm32"))]
+fn compile_command(source: Option<&String>, output: Option<&String>) {
+    let (Some(source), Some(output)) = (source, output) else {
+        eprintln!("usage: desktop compile <source> <output.ch8>");
+        std::process::exit(1)
+    };
+    let text = std::fs::read_to_string(source).unwrap_or_else(|e| {
+        eprintln!("{}: {}", source, e);
+        std::process::exit(1)
+    });
+    match compile(&text) {
+        Ok(rom) => {
+            if let Err(e) = std::fs::write(output, &rom) {
+                eprintln!("{}: {}", output, e);
+                std::process::exit(1)
+            }
+            println!("{}: {} bytes", output, rom.len());
+        }
+        Err(e) => {
+            eprintln!("{}:{}", source, e);
+            std::process::exit(1)
+        }
+    }
+}
+
+// Debug Adapter Protocolのサーバとして動く。--port を指定するとTCPで1つの接続を待つ
+#[cfg(not(target_arch = "wasm32"))]
+fn dap_command() {
+    let result = match parse_option::<u16>("port") {
+        Some(port) => {
+            let listener = std::net::TcpListener::bind(("127.0.0.1", port)).unwrap_or_else(|e| {
+                eprintln!("127.0.0.1:{}: {}", port, e);
+                std::process::exit(1)
+            });
+            eprintln!("listening on 127.0.0.1:{}", port);
+            listener.accept().and_then(|(stream, _)| {
+                let input = std::io::BufReader::new(stream.try_clone()?);
+                dap::run(input, stream)
+            })
+        }
+        None => dap::run(std::io::BufReader::new(std::io::stdin()), std::io::stdout()),
+    };
+    if let Err(e) = result {
+        eprintln!("dap: {}", e);
+        std::process::exit(1)
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
+    let platform: Option<Platform> = parse_option("platform");
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("disasm") => return disasm_command(args.get(2), platform),
+        Some("asm") => return asm_command(args.get(2), args.get(3)),
+        Some("compile") => return compile_command(args.get(2), args.get(3)),
+        Some("cfg") => return cfg_command(args.get(2), platform),
+        Some("verify") => return verify_command(args.get(2), platform),
+        Some("lint") => return lint_command(args.get(2), platform),
+        Some("sprites") => return sprites_command(args.get(2), platform),
+        Some("decompile") => return decompile_command(args.get(2), platform),
+        Some("dap") => return dap_command(),
+        _ => {}
+    }
+
+    let seed: Option<u32> = parse_option("seed");
+    let rng_algorithm = match option_value("rng").as_deref() {
+        None | Some("xorshift") => RngAlgorithm::Xorshift,
+        Some("timer") => RngAlgorithm::Timer,
+        Some("vip") => RngAlgorithm::CosmacVip,
+        Some(other) => {
+            eprintln!(
+                "--rng: unknown algorithm '{}' (expected xorshift, timer or vip)",
+                other
+            );
+            std::process::exit(1)
+        }
+    };
+
     CombinedLogger::init(vec![WriteLogger::new(
         LevelFilter::Debug,
         Config::default(),
@@ -77,9 +635,31 @@ fn main() {
         }
     };
 
-    let keyboard = GetchKeyboard::new();
-    let cpu = Cpu::new(rom, keyboard);
+    let rom_data = std::fs::read(rom).expect("Failed to read the file");
+    // デバッガは端末の入力を自分で読み、実行中のキーだけをCHIP-8に渡す
+    let (keyboard, keys) = if std::env::args().any(|arg| arg == "--debug") {
+        let (sender, receiver) = mpsc::channel();
+        (GetchKeyboard::with_receiver(receiver), Some(sender))
+    } else {
+        (GetchKeyboard::new(), None)
+    };
+    // シードを指定すると毎回同じ乱数列になる
+    let seed = seed.unwrap_or_else(rand::random);
+    log::info!("RNG seed: {}", seed);
+    let rng = Rng::new(rng_algorithm, seed);
+    let (mut cpu, cpu_frequency) = match platform {
+        Some(platform) => (
+            Cpu::from_platform_with_rng(&rom_data, keyboard, platform, rng),
+            platform.clock_speed() as u64,
+        ),
+        None => (Cpu::with_rng(&rom_data, keyboard, rng), 600), // 600命令/秒
+    };
 
-    let drawer = CUIDraw;
-    start(cpu, drawer);
+    cpu.set_audio_sink(Box::new(TerminalBell));
+
+    let rom_name = Path::new(rom).file_name().unwrap().to_string_lossy();
+    match keys {
+        Some(keys) => start_debugger(cpu, keys, cpu_frequency),
+        None => start(cpu, CUIDraw, cpu_frequency, &rom_name),
+    }
 }
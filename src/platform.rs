@@ -0,0 +1,232 @@
+use std::{fmt, str::FromStr};
+
+use crate::chip8::{DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH};
+use crate::font::{SMALL_FONT, VIP_FONT};
+use crate::instruction::InstructionSet;
+use crate::quirks::{IndexIncrement, Quirks, SysCall};
+
+// 歴史的なCHIP-8インタプリタごとの設定一式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    XoChip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: usize,
+    pub height: usize,
+}
+
+const LORES: Resolution = Resolution {
+    width: DISPLAY_WIDTH,
+    height: DISPLAY_HEIGHT,
+};
+const HIRES: Resolution = Resolution {
+    width: HIRES_DISPLAY_WIDTH,
+    height: HIRES_DISPLAY_HEIGHT,
+};
+
+impl Platform {
+    pub const ALL: [Platform; 4] = [
+        Platform::CosmacVip,
+        Platform::Chip48,
+        Platform::SuperChip,
+        Platform::XoChip,
+    ];
+
+    // セーブステートに記録する番号（0はプラットフォーム指定なし）
+    pub fn id(&self) -> u8 {
+        match self {
+            Platform::CosmacVip => 1,
+            Platform::Chip48 => 2,
+            Platform::SuperChip => 3,
+            Platform::XoChip => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Platform> {
+        Platform::ALL.into_iter().find(|p| p.id() == id)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::CosmacVip => "vip",
+            Platform::Chip48 => "chip48",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Platform::CosmacVip => "COSMAC VIP",
+            Platform::Chip48 => "CHIP-48",
+            Platform::SuperChip => "SUPER-CHIP 1.1",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                index_increment: IndexIncrement::XPlusOne,
+                vf_reset: true,
+                jump_uses_vx: false,
+                clip_sprites: true,
+                display_wait: true,
+                sys_call: SysCall::Ignore,
+            },
+            Platform::Chip48 => Quirks {
+                shift_uses_vy: false,
+                index_increment: IndexIncrement::X,
+                vf_reset: false,
+                jump_uses_vx: true,
+                clip_sprites: true,
+                display_wait: false,
+                sys_call: SysCall::Ignore,
+            },
+            Platform::SuperChip => Quirks {
+                shift_uses_vy: false,
+                index_increment: IndexIncrement::Unchanged,
+                vf_reset: false,
+                jump_uses_vx: true,
+                clip_sprites: true,
+                display_wait: false,
+                sys_call: SysCall::Ignore,
+            },
+            Platform::XoChip => Quirks {
+                shift_uses_vy: true,
+                index_increment: IndexIncrement::XPlusOne,
+                vf_reset: false,
+                jump_uses_vx: false,
+                clip_sprites: false,
+                display_wait: false,
+                sys_call: SysCall::Unsupported,
+            },
+        }
+    }
+
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::XoChip => 0x10000,
+            _ => 0x1000,
+        }
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        match self {
+            Platform::CosmacVip => 12,
+            _ => 16,
+        }
+    }
+
+    // 対応している画面解像度（先頭が起動時の解像度）
+    pub fn resolutions(&self) -> &'static [Resolution] {
+        match self {
+            Platform::CosmacVip | Platform::Chip48 => &[LORES],
+            Platform::SuperChip | Platform::XoChip => &[LORES, HIRES],
+        }
+    }
+
+    // プラットフォームの指定が無い場合は低解像度だけ
+    pub fn resolutions_of(platform: Option<Platform>) -> &'static [Resolution] {
+        platform.map_or(&[LORES], |platform| platform.resolutions())
+    }
+
+    // 00Cn, 00FB-00FF, Dxy0, Fx30, Fx75, Fx85 が使えるか
+    pub fn superchip_instructions(&self) -> bool {
+        matches!(self, Platform::SuperChip | Platform::XoChip)
+    }
+
+    // 命令セットが使えるか（Noneは拡張命令なしのCHIP-8として扱う）
+    pub fn supports(platform: Option<Platform>, set: InstructionSet) -> bool {
+        match set {
+            InstructionSet::Chip8 => true,
+            InstructionSet::SuperChip => platform.is_some_and(|p| p.superchip_instructions()),
+            InstructionSet::XoChip => platform == Some(Platform::XoChip),
+        }
+    }
+
+    pub fn font(&self) -> &'static [u8; 80] {
+        match self {
+            Platform::CosmacVip => &VIP_FONT,
+            _ => &SMALL_FONT,
+        }
+    }
+
+    // 1秒あたりの命令数
+    pub fn clock_speed(&self) -> u32 {
+        match self {
+            Platform::CosmacVip => 540,
+            Platform::Chip48 => 600,
+            Platform::SuperChip => 1800,
+            Platform::XoChip => 60000,
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPlatform(pub String);
+
+impl fmt::Display for UnknownPlatform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = Platform::ALL.iter().map(|p| p.name()).collect();
+        write!(
+            f,
+            "unknown platform '{}' (expected one of: {})",
+            self.0,
+            names.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownPlatform {}
+
+impl FromStr for Platform {
+    type Err = UnknownPlatform;
+
+    // "COSMAC VIP" や "super-chip 1.1" のような表記も受け付ける
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized: String = s
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        match normalized.as_str() {
+            "vip" | "cosmacvip" | "chip8" => Ok(Platform::CosmacVip),
+            "chip48" => Ok(Platform::Chip48),
+            "schip" | "superchip" | "schip11" | "superchip11" => Ok(Platform::SuperChip),
+            "xochip" | "xo" => Ok(Platform::XoChip),
+            _ => Err(UnknownPlatform(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_platform_names() {
+        assert_eq!("vip".parse(), Ok(Platform::CosmacVip));
+        assert_eq!("COSMAC VIP".parse(), Ok(Platform::CosmacVip));
+        assert_eq!("SUPER-CHIP 1.1".parse(), Ok(Platform::SuperChip));
+        assert_eq!("xo-chip".parse(), Ok(Platform::XoChip));
+        assert!("gameboy".parse::<Platform>().is_err());
+
+        // name()で出力した名前は必ず読み戻せる
+        for platform in Platform::ALL {
+            assert_eq!(platform.name().parse(), Ok(platform));
+        }
+    }
+}
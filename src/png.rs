@@ -0,0 +1,123 @@
+// パレット形式（1ピクセル1バイト）のPNGを書き出す
+// 画像は小さいので、圧縮はせずDeflateの無圧縮ブロックに入れる
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+// 無圧縮ブロック1つに入れられる最大のバイト数
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// pixels は左上から行ごとに並んだパレットの番号
+pub fn encode(width: usize, height: usize, palette: &[[u8; 3]], pixels: &[u8]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height, "pixel count mismatch");
+    assert!(!palette.is_empty() && palette.len() <= 256);
+
+    let mut png = SIGNATURE.to_vec();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // ビット深度8、カラータイプ3（パレット）、圧縮・フィルタ・インターレースは既定
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+
+    write_chunk(&mut png, b"PLTE", &palette.concat());
+
+    // 各行の先頭にフィルタの種類（0: なし）を置く
+    let mut scanlines = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width.max(1)).take(height) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    // CRCは種類とデータから計算する
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // CM=8（Deflate）、ウィンドウ32KB、FCHECKで31の倍数にする
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(last as u8); // BFINAL と BTYPE=00
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % MOD;
+        b = (b + a) % MOD;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_encode() {
+        let png = encode(2, 2, &[[0, 0, 0], [255, 255, 255]], &[0, 1, 1, 0]);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 0, 2, 0, 0, 0, 2]);
+        assert_eq!(png[24..29], [8, 3, 0, 0, 0]);
+        assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+
+        // IDATの中身はフィルタ番号付きの行をそのまま格納している
+        let idat = png.windows(4).position(|w| w == b"IDAT").unwrap();
+        let length = u32::from_be_bytes(png[idat - 4..idat].try_into().unwrap()) as usize;
+        let zlib = &png[idat + 4..idat + 4 + length];
+        assert_eq!(zlib[..7], [0x78, 0x01, 1, 6, 0, 0xF9, 0xFF]);
+        assert_eq!(zlib[7..13], [0, 0, 1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_large_image_uses_multiple_blocks() {
+        let zlib = zlib_stored(&vec![0; MAX_STORED_BLOCK + 1]);
+        assert_eq!(zlib[2], 0);
+        assert_eq!(zlib[2 + 5 + MAX_STORED_BLOCK], 1);
+        assert_eq!(zlib.len(), 2 + 5 * 2 + MAX_STORED_BLOCK + 1 + 4);
+    }
+}
@@ -0,0 +1,96 @@
+// 実装ごとに挙動が異なる命令の解釈を切り替えるための設定
+
+// Fx55 / Fx65 実行後のIレジスタの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    Unchanged, // Iは変化しない (SUPER-CHIP)
+    X,         // I += x (CHIP-48)
+    XPlusOne,  // I += x + 1 (COSMAC VIP)
+}
+
+// 0nnn (SYS addr) の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysCall {
+    Jump,        // nnnにジャンプする
+    Ignore,      // 何もしない
+    Unsupported, // 未知の命令としてエラーにする
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8xy6 / 8xyE でVYをシフトした結果をVXに入れる
+    pub shift_uses_vy: bool,
+    pub index_increment: IndexIncrement,
+    // 8xy1 / 8xy2 / 8xy3 の後にVFを0にする
+    pub vf_reset: bool,
+    // Bnnn を Bxnn として VX + nnn にジャンプする
+    pub jump_uses_vx: bool,
+    // 画面外にはみ出したスプライトを折り返さずに切り取る
+    pub clip_sprites: bool,
+    // Dxyn は垂直帰線期間（タイマー更新）まで待ってから描画する
+    pub display_wait: bool,
+    pub sys_call: SysCall,
+}
+
+impl Default for Quirks {
+    // これまでのエミュレータの挙動
+    fn default() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            vf_reset: false,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            display_wait: false,
+            sys_call: SysCall::Jump,
+        }
+    }
+}
+
+impl Quirks {
+    // セーブステート用に3バイトへ詰める
+    pub fn to_bytes(&self) -> [u8; 3] {
+        let flags = self.shift_uses_vy as u8
+            | (self.vf_reset as u8) << 1
+            | (self.jump_uses_vx as u8) << 2
+            | (self.clip_sprites as u8) << 3
+            | (self.display_wait as u8) << 4;
+        let index_increment = match self.index_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::X => 1,
+            IndexIncrement::XPlusOne => 2,
+        };
+        let sys_call = match self.sys_call {
+            SysCall::Jump => 0,
+            SysCall::Ignore => 1,
+            SysCall::Unsupported => 2,
+        };
+        [flags, index_increment, sys_call]
+    }
+
+    pub fn from_bytes(bytes: [u8; 3]) -> Option<Quirks> {
+        let [flags, index_increment, sys_call] = bytes;
+        if flags & !0x1F != 0 {
+            return None;
+        }
+        Some(Quirks {
+            shift_uses_vy: flags & 1 != 0,
+            vf_reset: flags & 2 != 0,
+            jump_uses_vx: flags & 4 != 0,
+            clip_sprites: flags & 8 != 0,
+            display_wait: flags & 16 != 0,
+            index_increment: match index_increment {
+                0 => IndexIncrement::Unchanged,
+                1 => IndexIncrement::X,
+                2 => IndexIncrement::XPlusOne,
+                _ => return None,
+            },
+            sys_call: match sys_call {
+                0 => SysCall::Jump,
+                1 => SysCall::Ignore,
+                2 => SysCall::Unsupported,
+                _ => return None,
+            },
+        })
+    }
+}
@@ -0,0 +1,217 @@
+use crate::chip8::Cpu;
+use crate::keyboard::KeyboardInput;
+use std::collections::VecDeque;
+
+// 巻き戻し用のリングバッファ
+// 最新のスナップショットだけを完全な形で持ち、それより古いものは
+// 「1つ新しいスナップショットとの差分」として保存する
+pub struct RewindBuffer {
+    capacity: usize,
+    interval: u32,
+    frames: u32,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+}
+
+// 既定では4フレームごとに記録し、最大600個（約40秒分）を保持する
+pub const DEFAULT_INTERVAL: u32 = 4;
+pub const DEFAULT_CAPACITY: usize = 600;
+
+impl RewindBuffer {
+    pub fn new(capacity: usize, interval: u32) -> Self {
+        RewindBuffer {
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            frames: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    // 保持しているスナップショットの数
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    // スナップショットの保存に使っているバイト数
+    pub fn memory_usage(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |state| state.len());
+        latest
+            + self
+                .deltas
+                .iter()
+                .map(|delta| delta.data.len())
+                .sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    // 60Hzのフレームごとに呼び出す。interval フレームに1回状態を記録する
+    pub fn record_frame<T: KeyboardInput>(&mut self, cpu: &Cpu<T>) {
+        if self.frames == 0 {
+            self.push(cpu.save_state());
+        }
+        self.frames = (self.frames + 1) % self.interval;
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(Delta::encode(&state, &previous));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // 最新のスナップショットを取り出し、1つ前のものを最新にする
+    // 最後の1つは取り出さずに残すので、押し続けても最古の状態で止まる
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        match self.deltas.pop_back() {
+            Some(delta) => {
+                self.latest = Some(delta.apply(&latest));
+                Some(latest)
+            }
+            None => {
+                self.latest = Some(latest.clone());
+                Some(latest)
+            }
+        }
+    }
+
+    // 1スナップショット分巻き戻す。巻き戻せた場合はtrueを返す
+    pub fn step_back<T: KeyboardInput>(&mut self, cpu: &mut Cpu<T>) -> bool {
+        let Some(state) = self.pop() else {
+            return false;
+        };
+        // 自分で記録した状態なので通常は読み込みに失敗しない
+        if cpu.load_state(&state).is_err() {
+            self.clear();
+            return false;
+        }
+        // 復元した状態はバッファに残っているので、次の記録は interval 後
+        self.frames = 1 % self.interval;
+        true
+    }
+}
+
+impl Default for RewindBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_INTERVAL)
+    }
+}
+
+// base との XOR を取り、0 の連続をランレングス圧縮した差分
+// 0x00 の後ろに連続数(1〜255)、それ以外はそのままの値
+struct Delta {
+    len: usize,
+    data: Vec<u8>,
+}
+
+impl Delta {
+    // base から target を復元するための差分を作る
+    fn encode(base: &[u8], target: &[u8]) -> Delta {
+        let mut data = Vec::new();
+        let mut zeros = 0u8;
+        for (i, &byte) in target.iter().enumerate() {
+            let xor = byte ^ base.get(i).copied().unwrap_or(0);
+            if xor == 0 {
+                if zeros == u8::MAX {
+                    data.extend_from_slice(&[0, zeros]);
+                    zeros = 0;
+                }
+                zeros += 1;
+            } else {
+                if zeros > 0 {
+                    data.extend_from_slice(&[0, zeros]);
+                    zeros = 0;
+                }
+                data.push(xor);
+            }
+        }
+        // 末尾の0は復元時に補われるので書かない
+        Delta {
+            len: target.len(),
+            data,
+        }
+    }
+
+    fn apply(&self, base: &[u8]) -> Vec<u8> {
+        let mut target = Vec::with_capacity(self.len);
+        let mut bytes = self.data.iter();
+        let base_at = |i: usize| base.get(i).copied().unwrap_or(0);
+        while let Some(&byte) = bytes.next() {
+            if byte == 0 {
+                let run = bytes.next().copied().unwrap_or(0);
+                for _ in 0..run {
+                    target.push(base_at(target.len()));
+                }
+            } else {
+                target.push(byte ^ base_at(target.len()));
+            }
+        }
+        while target.len() < self.len {
+            target.push(base_at(target.len()));
+        }
+        target
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base: Vec<u8> = (0..600).map(|i| (i % 251) as u8).collect();
+        let mut target = base.clone();
+        target[3] = 0xFF;
+        target[400] ^= 0x10;
+        target.truncate(550);
+        target.extend_from_slice(&[1, 2, 3]);
+
+        let delta = Delta::encode(&base, &target);
+        assert!(delta.data.len() < 16);
+        assert_eq!(delta.apply(&base), target);
+
+        // 長さが伸びる方向も復元できる
+        let delta = Delta::encode(&target, &base);
+        assert_eq!(delta.apply(&target), base);
+    }
+
+    #[test]
+    fn test_pop_returns_snapshots_newest_first() {
+        let mut buffer = RewindBuffer::new(3, 1);
+        for n in 1..=5u8 {
+            buffer.push(vec![n; 32]);
+        }
+        assert_eq!(buffer.len(), 3); // 古いものから捨てられる
+
+        assert_eq!(buffer.pop(), Some(vec![5; 32]));
+        assert_eq!(buffer.pop(), Some(vec![4; 32]));
+        assert_eq!(buffer.pop(), Some(vec![3; 32]));
+        // 最古の状態で止まる
+        assert_eq!(buffer.pop(), Some(vec![3; 32]));
+        assert_eq!(buffer.len(), 1);
+    }
+}
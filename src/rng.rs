@@ -0,0 +1,215 @@
+// Cxkk (RND) で使う乱数生成器
+// 状態をすべて保持しているので、同じシードからは常に同じ乱数列が得られる
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngAlgorithm {
+    // xorshift32（既定）
+    Xorshift,
+    // 割り込み（60Hz）ごとに進むカウンタと前回の値を組み合わせる。
+    // 結果が命令を実行したタイミングに依存する（COSMAC VIPのインタプリタの処理そのものではない）
+    Timer,
+    // COSMAC VIPのインタプリタのRNDルーチン（0x1D9）をそのまま再現する
+    CosmacVip,
+}
+
+impl RngAlgorithm {
+    pub fn id(&self) -> u8 {
+        match self {
+            RngAlgorithm::Xorshift => 0,
+            RngAlgorithm::Timer => 1,
+            RngAlgorithm::CosmacVip => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<RngAlgorithm> {
+        match id {
+            0 => Some(RngAlgorithm::Xorshift),
+            1 => Some(RngAlgorithm::Timer),
+            2 => Some(RngAlgorithm::CosmacVip),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    algorithm: RngAlgorithm,
+    state: u32,
+}
+
+// xorshiftは状態0から抜け出せないため、その場合はこの値を使う
+const XORSHIFT_ZERO_SEED: u32 = 0x9E37_79B9;
+
+// COSMAC VIPのインタプリタの0x100〜0x1FF。RNDルーチンはR9の下位バイトで
+// ここを指し、インタプリタ自身のコードを乱数表の代わりに読む
+const VIP_INTERPRETER_PAGE: [u8; 256] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x45, 0xA3, 0x98, 0x56, 0xD4, 0xF8, 0x81, 0xBC, 0xF8, 0x95, 0xAC,
+    0x22, 0xDC, 0x12, 0x56, 0xD4, 0x06, 0xB8, 0xD4, 0x06, 0xA8, 0xD4, 0x64, 0x0A, 0x01, 0xE6, 0x8A,
+    0xF4, 0xAA, 0x3B, 0x28, 0x9A, 0xFC, 0x01, 0xBA, 0xD4, 0xF8, 0x81, 0xBA, 0x06, 0xFA, 0x0F, 0xAA,
+    0x0A, 0xAA, 0xD4, 0xE6, 0x06, 0xBF, 0x93, 0xBE, 0xF8, 0x1B, 0xAE, 0x2A, 0x1A, 0xF8, 0x00, 0x5A,
+    0x0E, 0xF5, 0x3B, 0x4B, 0x56, 0x0A, 0xFC, 0x01, 0x5A, 0x30, 0x40, 0x4E, 0xF6, 0x3B, 0x3C, 0x9F,
+    0x56, 0x2A, 0x2A, 0xD4, 0x00, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x07, 0x5A, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x5B, 0x12, 0xD4, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x0A, 0x57, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x6B, 0x12, 0xD4, 0x15, 0x85, 0x22, 0x73, 0x95, 0x52, 0x25, 0x45, 0xA5, 0x86, 0xFA,
+    0x0F, 0xB5, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x82, 0x15, 0x15, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x88,
+    0xD4, 0x45, 0x07, 0x30, 0x8C, 0x45, 0x07, 0x30, 0x84, 0xE6, 0x62, 0x26, 0x45, 0xA3, 0x36, 0x88,
+    0xD4, 0x3E, 0x88, 0xD4, 0xF8, 0xF0, 0xA7, 0xE7, 0x45, 0xF4, 0xA5, 0x86, 0xFA, 0x0F, 0x3B, 0xB2,
+    0xFC, 0x01, 0xB5, 0xD4, 0x45, 0x56, 0xD4, 0x45, 0xE6, 0xF4, 0x56, 0xD4, 0x45, 0xFA, 0x0F, 0x3A,
+    0xC4, 0x07, 0x56, 0xD4, 0xAF, 0x22, 0xF8, 0xD3, 0x73, 0x8F, 0xF9, 0xF0, 0x52, 0xE6, 0x07, 0xD2,
+    0x56, 0xF8, 0xFF, 0xA6, 0xF8, 0x00, 0x7E, 0x56, 0xD4, 0x19, 0x89, 0xAE, 0x93, 0xBE, 0x99, 0xEE,
+    0xF4, 0x56, 0x76, 0xE6, 0xF4, 0xB9, 0x56, 0x45, 0xF2, 0x56, 0xD4, 0x45, 0xAA, 0x86, 0xFA, 0x0F,
+    0xBA, 0xD4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x4B,
+];
+
+impl Rng {
+    pub fn new(algorithm: RngAlgorithm, seed: u32) -> Self {
+        let state = match algorithm {
+            RngAlgorithm::Xorshift if seed == 0 => XORSHIFT_ZERO_SEED,
+            RngAlgorithm::Xorshift => seed,
+            RngAlgorithm::Timer | RngAlgorithm::CosmacVip => seed & 0xFFFF,
+        };
+        Rng { algorithm, state }
+    }
+
+    pub fn seeded(seed: u32) -> Self {
+        Self::new(RngAlgorithm::Xorshift, seed)
+    }
+
+    // シードを指定しない場合（ゲームを遊ぶとき）は毎回異なるシードを使う
+    pub fn from_entropy() -> Self {
+        Self::seeded(rand::random())
+    }
+
+    pub fn algorithm(&self) -> RngAlgorithm {
+        self.algorithm
+    }
+
+    // スナップショット用の内部状態
+    pub fn state(&self) -> u32 {
+        self.state
+    }
+
+    pub fn from_state(algorithm: RngAlgorithm, state: u32) -> Self {
+        Rng { algorithm, state }
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        match self.algorithm {
+            RngAlgorithm::Xorshift => {
+                let mut x = self.state;
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                self.state = x;
+                (x >> 24) as u8
+            }
+            RngAlgorithm::Timer => {
+                let high = (self.state >> 8) as u8;
+                let low = self.state as u8;
+                let value = high.wrapping_add(low).rotate_right(1);
+                self.state = (value as u32) << 8 | low.wrapping_add(1) as u32;
+                value
+            }
+            RngAlgorithm::CosmacVip => {
+                // 状態はR9（上位バイトが前回の値、下位バイトが表の位置）
+                // INC R9 / R9.1+M(0x100+R9.0) / SHRC / シフト前の和を足す / PHI R9
+                let r9 = (self.state as u16).wrapping_add(1);
+                let [low, high] = r9.to_le_bytes();
+                let (sum, carry) = high.overflowing_add(VIP_INTERPRETER_PAGE[low as usize]);
+                let value = ((sum >> 1) | (carry as u8) << 7).wrapping_add(sum);
+                self.state = u16::from_le_bytes([low, value]) as u32;
+                value
+            }
+        }
+    }
+
+    // 60Hzの割り込みごとに呼ばれる
+    pub fn tick(&mut self) {
+        match self.algorithm {
+            RngAlgorithm::Xorshift => {}
+            RngAlgorithm::Timer => {
+                let low = (self.state as u8).wrapping_add(1);
+                self.state = (self.state & 0xFF00) | low as u32;
+            }
+            // VIPの割り込みルーチンもINC R9を実行する
+            RngAlgorithm::CosmacVip => {
+                self.state = (self.state as u16).wrapping_add(1) as u32;
+            }
+        }
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::from_entropy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(rng: &mut Rng, len: usize) -> Vec<u8> {
+        (0..len).map(|_| rng.next_byte()).collect()
+    }
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        for algorithm in [
+            RngAlgorithm::Xorshift,
+            RngAlgorithm::Timer,
+            RngAlgorithm::CosmacVip,
+        ] {
+            let a = sequence(&mut Rng::new(algorithm, 1234), 32);
+            let b = sequence(&mut Rng::new(algorithm, 1234), 32);
+            let c = sequence(&mut Rng::new(algorithm, 4321), 32);
+            assert_eq!(a, b);
+            assert_ne!(a, c);
+        }
+    }
+
+    #[test]
+    fn test_zero_seed_is_usable() {
+        let values = sequence(&mut Rng::seeded(0), 16);
+        assert!(values.iter().any(|&v| v != 0));
+    }
+
+    #[test]
+    fn test_restore_from_state() {
+        let mut rng = Rng::seeded(42);
+        sequence(&mut rng, 10);
+        let mut restored = Rng::from_state(rng.algorithm(), rng.state());
+        assert_eq!(sequence(&mut rng, 10), sequence(&mut restored, 10));
+    }
+
+    #[test]
+    fn test_timer_depends_on_interrupts() {
+        let mut a = Rng::new(RngAlgorithm::Timer, 99);
+        let mut b = Rng::new(RngAlgorithm::Timer, 99);
+        b.tick();
+        assert_ne!(a.next_byte(), b.next_byte());
+
+        // VIPでは割り込みでR9全体が進む
+        let mut vip = Rng::new(RngAlgorithm::CosmacVip, 0x12FF);
+        vip.tick();
+        assert_eq!(vip.state(), 0x1300);
+
+        // xorshiftは割り込みの影響を受けない
+        let mut a = Rng::seeded(99);
+        let mut b = Rng::seeded(99);
+        b.tick();
+        assert_eq!(a.next_byte(), b.next_byte());
+    }
+
+    #[test]
+    fn test_cosmac_vip_routine() {
+        // R9=0x0000: 0x00+M(0x101)=0x00+0x00=0x00 → 0x00+0x00=0x00
+        // R9=0x0104: 0x01+M(0x105)=0x01+0x45=0x46 → 0x23+0x46=0x69
+        // R9=0xF006: 0xF0+M(0x107)=0xF0+0x98=0x88 (DF=1) → 0xC4+0x88=0x4C
+        for (seed, value) in [(0x0000, 0x00), (0x0104, 0x69), (0xF006, 0x4C)] {
+            let mut rng = Rng::new(RngAlgorithm::CosmacVip, seed);
+            assert_eq!(rng.next_byte(), value);
+            assert_eq!(rng.state(), (value as u32) << 8 | (seed + 1) & 0xFF);
+        }
+    }
+}
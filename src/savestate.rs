@@ -0,0 +1,213 @@
+use std::fmt;
+
+// セーブステートのバイナリ形式
+//
+// ヘッダ: "C8ST" | バージョン(u16) | ROMハッシュ(u64) | プラットフォーム(u8)
+// 以降はCpuの状態をリトルエンディアンで順に並べる（並びはバージョンごとに固定）
+pub const MAGIC: &[u8; 4] = b"C8ST";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    RomMismatch { expected: u64, found: u64 },
+    PlatformMismatch { expected: u8, found: u8 },
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "not a CHIP-8 save state"),
+            SaveStateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            SaveStateError::RomMismatch { expected, found } => write!(
+                f,
+                "save state belongs to a different ROM (expected {:016x}, found {:016x})",
+                expected, found
+            ),
+            SaveStateError::PlatformMismatch { expected, found } => write!(
+                f,
+                "save state was made for a different platform (expected {}, found {})",
+                expected, found
+            ),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Invalid(what) => write!(f, "invalid save state: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+// ROMの同一性を確認するためのハッシュ（FNV-1a 64bit）
+pub fn rom_hash(rom_data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in rom_data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    buffer: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // ビット列を8ビットずつ詰めて書き込む
+    pub fn bits(&mut self, bits: impl Iterator<Item = bool>) {
+        let mut byte = 0u8;
+        let mut count = 0;
+        for bit in bits {
+            byte = byte << 1 | bit as u8;
+            count += 1;
+            if count == 8 {
+                self.u8(byte);
+                byte = 0;
+                count = 0;
+            }
+        }
+        if count > 0 {
+            self.u8(byte << (8 - count));
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, position: 0 }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .ok_or(SaveStateError::Truncated)?;
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or(SaveStateError::Truncated)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Invalid("boolean")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn bits(&mut self, count: usize) -> Result<Vec<bool>, SaveStateError> {
+        let bytes = self.bytes(count.div_ceil(8))?;
+        Ok((0..count)
+            .map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0)
+            .collect())
+    }
+
+    // 余分なデータが残っていないか確認する
+    pub fn finish(self) -> Result<(), SaveStateError> {
+        if self.position == self.data.len() {
+            Ok(())
+        } else {
+            Err(SaveStateError::Invalid("trailing data"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_primitives() {
+        let mut writer = StateWriter::new();
+        writer.u8(0x12);
+        writer.bool(true);
+        writer.u16(0x3456);
+        writer.u32(0x789A_BCDE);
+        writer.u64(u64::MAX - 1);
+        writer.bits([true, false, true, true, false, false, false, false, true].into_iter());
+        let data = writer.finish();
+        assert_eq!(data.len(), 1 + 1 + 2 + 4 + 8 + 2);
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(reader.u8(), Ok(0x12));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0x3456));
+        assert_eq!(reader.u32(), Ok(0x789A_BCDE));
+        assert_eq!(reader.u64(), Ok(u64::MAX - 1));
+        assert_eq!(
+            reader.bits(9),
+            Ok(vec![
+                true, false, true, true, false, false, false, false, true
+            ])
+        );
+        assert_eq!(reader.u8(), Err(SaveStateError::Truncated));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn test_rom_hash() {
+        assert_eq!(rom_hash(&[]), 0xcbf2_9ce4_8422_2325);
+        assert_ne!(rom_hash(&[1, 2, 3]), rom_hash(&[1, 2, 4]));
+    }
+}
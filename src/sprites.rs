@@ -0,0 +1,319 @@
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::cfg::analyze;
+use crate::font::SMALL_FONT;
+use crate::framebuffer::PLANE_COUNT;
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+use crate::png;
+
+const PROGRAM_START: usize = 0x200;
+
+// シートに横に並べるスプライトの数
+const COLUMNS: usize = 8;
+
+// ブラウザ版と同じ色（プレーンの組み合わせごと）と、区切り線の色
+const PALETTE: [[u8; 3]; 5] = [
+    [0x00, 0x00, 0x00],
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x20, 0x40, 0x80],
+];
+const GRID: u8 = 4;
+
+const ASCII: [char; 4] = ['.', '#', '+', '@'];
+
+// メモリ上のスプライト。drw_xy と同じく、各行は上位ビットが左で、
+// 複数のプレーンに描く場合はプレーンごとのデータが続けて並ぶ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sprite {
+    pub address: usize,
+    pub rows: usize,
+    pub bytes_per_row: usize, // 16x16のスプライトは2
+    pub planes: usize,
+    pub draws: Vec<usize>, // このスプライトを描くDxynのアドレス（範囲で指定した場合は空）
+}
+
+impl Sprite {
+    pub fn width(&self) -> usize {
+        self.bytes_per_row * 8
+    }
+
+    // プレーンすべてを合わせたバイト数
+    pub fn size(&self) -> usize {
+        self.rows * self.bytes_per_row * self.planes
+    }
+
+    // (x, y) の色。ビット0が1つ目のプレーン
+    pub fn pixel(&self, memory: &[u8], x: usize, y: usize) -> u8 {
+        let mut color = 0;
+        for plane in 0..self.planes {
+            let address = self.address
+                + plane * self.rows * self.bytes_per_row
+                + y * self.bytes_per_row
+                + x / 8;
+            let byte = memory.get(address).copied().unwrap_or(0);
+            color |= ((byte >> (7 - x % 8)) & 1) << plane;
+        }
+        color
+    }
+
+    pub fn to_ascii(&self, memory: &[u8]) -> String {
+        let mut text = String::new();
+        for y in 0..self.rows {
+            for x in 0..self.width() {
+                text.push(ASCII[self.pixel(memory, x, y) as usize]);
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl fmt::Display for Sprite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:03X}: {}x{}", self.address, self.width(), self.rows)?;
+        if self.planes > 1 {
+            write!(f, ", {} planes", self.planes)?;
+        }
+        if !self.draws.is_empty() {
+            let draws: Vec<String> = self.draws.iter().map(|a| format!("{:03X}", a)).collect();
+            write!(f, " (drawn at {})", draws.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// --range で指定するバイト列。"START-END" または "START-END:16"（16進数、ENDは含まない）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: usize,
+    pub end: usize,
+    pub width: usize, // 8 か 16
+}
+
+impl ByteRange {
+    pub fn sprite(&self) -> Sprite {
+        let bytes_per_row = self.width / 8;
+        Sprite {
+            address: self.start,
+            rows: (self.end - self.start).div_ceil(bytes_per_row),
+            bytes_per_row,
+            planes: 1,
+            draws: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRange(pub String);
+
+impl fmt::Display for InvalidRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid range '{}' (expected START-END or START-END:16 in hex)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidRange {}
+
+impl FromStr for ByteRange {
+    type Err = InvalidRange;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || InvalidRange(s.to_string());
+        let hex = |text: &str| {
+            let text = text.trim();
+            let digits = text
+                .strip_prefix("0x")
+                .or_else(|| text.strip_prefix("0X"))
+                .unwrap_or(text);
+            usize::from_str_radix(digits, 16).map_err(|_| error())
+        };
+        let (range, width) = match s.split_once(':') {
+            Some((range, width)) => (range, width.trim().parse().map_err(|_| error())?),
+            None => (s, 8),
+        };
+        let (start, end) = range.split_once('-').ok_or_else(error)?;
+        let (start, end) = (hex(start)?, hex(end)?);
+        if start >= end || !matches!(width, 8 | 16) {
+            return Err(error());
+        }
+        Ok(ByteRange { start, end, width })
+    }
+}
+
+// フォントとROMを読み込んだ直後のメモリ
+pub fn memory_image(rom: &[u8], platform: Option<Platform>) -> Vec<u8> {
+    let size = platform.map_or(0x1000, |platform| platform.memory_size());
+    let font = platform.map_or(&SMALL_FONT, |platform| platform.font());
+    let mut memory = vec![0; size.max(PROGRAM_START + rom.len())];
+    memory[..font.len()].copy_from_slice(font);
+    memory[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
+    memory
+}
+
+// 到達するコードから Annn（またはF000 NNNN）の後にIを変えずに実行する Dxyn を探し、
+// 描かれるスプライトを返す。Iの値は基本ブロックの中だけで追う
+pub fn find_sprites(rom: &[u8], platform: Option<Platform>) -> Vec<Sprite> {
+    use Instruction::*;
+
+    let graph = analyze(rom, platform.or(Some(Platform::XoChip)));
+    let memory_size = memory_image(rom, platform).len();
+    // プラットフォームの指定が無い場合、Dxy0は16x16として扱う（0行の描画に意味は無いため）
+    let wide = platform.is_none_or(|platform| platform.superchip_instructions());
+
+    let mut sprites: Vec<Sprite> = Vec::new();
+    for block in graph.blocks().values() {
+        let mut index = None;
+        let mut planes = 1;
+        for &(address, instruction) in &block.instructions {
+            match instruction {
+                LdI(nnn) | LdILong(nnn) => index = Some(nnn as usize),
+                Plane(n) => planes = (n as usize & ((1 << PLANE_COUNT) - 1)).count_ones() as usize,
+                AddIVx(_) | LdFVx(_) | LdHfVx(_) | LdIVx(_) | LdVxI(_) | Call(_) => index = None,
+                Drw(_, _, n) => {
+                    let Some(start) = index else { continue };
+                    let (rows, bytes_per_row) = match n {
+                        0 if wide => (16, 2),
+                        0 => continue,
+                        n => (n as usize, 1),
+                    };
+                    let sprite = Sprite {
+                        address: start,
+                        rows,
+                        bytes_per_row,
+                        planes,
+                        draws: vec![address],
+                    };
+                    if sprite.planes == 0 || sprite.address + sprite.size() > memory_size {
+                        continue;
+                    }
+                    let same = sprites.iter_mut().find(|other| {
+                        (other.address, other.rows, other.bytes_per_row, other.planes)
+                            == (start, rows, bytes_per_row, planes)
+                    });
+                    match same {
+                        Some(other) => other.draws.push(address),
+                        None => sprites.push(sprite),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    sprites.sort_by_key(|sprite| (sprite.address, sprite.rows, sprite.bytes_per_row));
+    sprites
+}
+
+// ASCIIアートで並べる
+pub fn to_ascii(sprites: &[Sprite], memory: &[u8]) -> String {
+    let mut text = String::new();
+    for sprite in sprites {
+        writeln!(text, "{}", sprite).unwrap();
+        text.push_str(&sprite.to_ascii(memory));
+        text.push('\n');
+    }
+    text
+}
+
+// 1行に COLUMNS 個ずつ並べたPNGのシートを作る。scale は1ピクセルの大きさ
+pub fn to_png(sprites: &[Sprite], memory: &[u8], scale: usize) -> Vec<u8> {
+    let scale = scale.max(1);
+    let cell_width = sprites.iter().map(Sprite::width).max().unwrap_or(8);
+    let row_heights: Vec<usize> = sprites
+        .chunks(COLUMNS)
+        .map(|row| row.iter().map(|sprite| sprite.rows).max().unwrap_or(0))
+        .collect();
+    let columns = sprites.len().clamp(1, COLUMNS);
+
+    // スプライトの間と周りに1ピクセルの区切り線を引く
+    let width = (columns * (cell_width + 1) + 1) * scale;
+    let height = (row_heights.iter().map(|h| h + 1).sum::<usize>() + 1) * scale;
+    let mut pixels = vec![GRID; width * height];
+
+    let mut top = 1;
+    for (row, &row_height) in sprites.chunks(COLUMNS).zip(&row_heights) {
+        for (column, sprite) in row.iter().enumerate() {
+            let left = 1 + column * (cell_width + 1);
+            for y in 0..row_height {
+                for x in 0..cell_width {
+                    let color = if x < sprite.width() && y < sprite.rows {
+                        sprite.pixel(memory, x, y)
+                    } else {
+                        0
+                    };
+                    for dy in 0..scale {
+                        let offset = ((top + y) * scale + dy) * width + (left + x) * scale;
+                        pixels[offset..offset + scale].fill(color);
+                    }
+                }
+            }
+        }
+        top += row_height + 1;
+    }
+    png::encode(width, height, &PALETTE, &pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn test_find_sprites() {
+        let rom = assemble(
+            "i := ball
+            sprite v0 v1 2
+            i := ball
+            v0 += 1
+            sprite v0 v1 2
+            i := hex v2
+            sprite v0 v1 5
+            i := big
+            sprite v0 v1 0
+            loop again
+            : ball 0x60 0xF0
+            : big 0x80 0x01",
+        )
+        .unwrap();
+        let sprites = find_sprites(&rom, Some(Platform::SuperChip));
+        let found: Vec<String> = sprites.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            found,
+            ["214: 8x2 (drawn at 202, 208)", "216: 16x16 (drawn at 210)"]
+        );
+
+        // CHIP-8ではDxy0は何も描かない
+        assert_eq!(find_sprites(&rom, Some(Platform::CosmacVip)).len(), 1);
+
+        let memory = memory_image(&rom, None);
+        assert_eq!(sprites[0].to_ascii(&memory), ".##.....\n####....\n");
+        assert_eq!(sprites[1].pixel(&memory, 0, 0), 1);
+        assert_eq!(sprites[1].pixel(&memory, 15, 0), 1);
+        assert_eq!(sprites[1].pixel(&memory, 8, 0), 0);
+    }
+
+    #[test]
+    fn test_byte_range() {
+        assert_eq!(
+            "0x300-0x305".parse(),
+            Ok(ByteRange {
+                start: 0x300,
+                end: 0x305,
+                width: 8
+            })
+        );
+        let wide: ByteRange = "2A0-2A3:16".parse().unwrap();
+        assert_eq!((wide.sprite().rows, wide.sprite().width()), (2, 16));
+        assert!("300".parse::<ByteRange>().is_err());
+        assert!("305-300".parse::<ByteRange>().is_err());
+        assert!("300-305:12".parse::<ByteRange>().is_err());
+    }
+}
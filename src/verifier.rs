@@ -0,0 +1,891 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use crate::chip8::MemoryAccess;
+use crate::disassembler::{decode_at, skipped_size};
+use crate::font::BIG_FONT_ADDRESS;
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+use crate::quirks::{IndexIncrement, Quirks, SysCall};
+
+const PROGRAM_START: usize = 0x200;
+// 同じ位置・同じ呼び出し履歴で個別に保持する状態の数。超えたら1つに併合する
+const STATES_PER_POINT: usize = 64;
+// 併合した状態がこの回数以上変化したら拡大（widening）する
+const JOINS_BEFORE_WIDENING: usize = 8;
+// 解析する状態の上限
+const MAX_STEPS: usize = 1_000_000;
+// Bnnn の飛び先をすべて調べる範囲の上限
+const MAX_JUMP_TARGETS: u32 = 64;
+
+// 値が取りうる範囲（両端を含む）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Range {
+    lo: u32,
+    hi: u32,
+}
+
+impl Range {
+    const BYTE: Range = Range { lo: 0, hi: 0xFF };
+
+    fn exact(value: u32) -> Range {
+        Range {
+            lo: value,
+            hi: value,
+        }
+    }
+
+    fn single(&self) -> Option<u32> {
+        (self.lo == self.hi).then_some(self.lo)
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.lo <= value && value <= self.hi
+    }
+
+    fn includes(&self, other: &Range) -> bool {
+        self.lo <= other.lo && other.hi <= self.hi
+    }
+
+    fn join(&self, other: &Range) -> Range {
+        Range {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    // 広がった側を広げる。上限は thresholds（昇順、最後が最大値）の次の値まで
+    fn widen(&self, next: &Range, thresholds: &[u32]) -> Range {
+        let hi = if next.hi > self.hi {
+            let max = thresholds[thresholds.len() - 1];
+            thresholds
+                .iter()
+                .copied()
+                .find(|&t| t >= next.hi)
+                .unwrap_or(max)
+        } else {
+            self.hi
+        };
+        Range {
+            lo: if next.lo < self.lo { 0 } else { self.lo },
+            hi,
+        }
+    }
+
+    // 0..=max で折り返す加算
+    fn add(&self, other: &Range, max: u32) -> Range {
+        let (lo, hi) = (self.lo + other.lo, self.hi + other.hi);
+        if hi <= max {
+            Range { lo, hi }
+        } else if lo > max {
+            Range {
+                lo: lo - max - 1,
+                hi: hi - max - 1,
+            }
+        } else {
+            Range { lo: 0, hi: max }
+        }
+    }
+
+    // 8ビットの減算
+    fn sub(&self, other: &Range) -> Range {
+        if self.lo >= other.hi {
+            Range {
+                lo: self.lo - other.hi,
+                hi: self.hi - other.lo,
+            }
+        } else if self.hi < other.lo {
+            Range {
+                lo: self.lo + 0x100 - other.hi,
+                hi: self.hi + 0x100 - other.lo,
+            }
+        } else {
+            Range::BYTE
+        }
+    }
+
+    // 0/1 のフラグ。always なら1、never なら0、それ以外は両方
+    fn flag(always: bool, never: bool) -> Range {
+        match (always, never) {
+            (true, _) => Range::exact(1),
+            (_, true) => Range::exact(0),
+            _ => Range { lo: 0, hi: 1 },
+        }
+    }
+
+    // ビット演算の結果の上限（全ビットが立った値）
+    fn bit_ceiling(&self, other: &Range) -> u32 {
+        let max = self.hi.max(other.hi);
+        if max == 0 {
+            0
+        } else {
+            u32::MAX >> max.leading_zeros()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct State {
+    registers: [Range; 16],
+    index: Range,
+    // 深さごとに戻り先アドレスの候補を持つ
+    stack: Vec<BTreeSet<usize>>,
+}
+
+impl State {
+    fn includes(&self, other: &State) -> bool {
+        self.index.includes(&other.index)
+            && self
+                .registers
+                .iter()
+                .zip(other.registers.iter())
+                .all(|(a, b)| a.includes(b))
+            && self.stack.len() == other.stack.len()
+            && self
+                .stack
+                .iter()
+                .zip(other.stack.iter())
+                .all(|(a, b)| a.is_superset(b))
+    }
+
+    fn join(&self, other: &State) -> State {
+        let mut registers = self.registers;
+        for (register, other) in registers.iter_mut().zip(other.registers.iter()) {
+            *register = register.join(other);
+        }
+        State {
+            registers,
+            index: self.index.join(&other.index),
+            stack: self
+                .stack
+                .iter()
+                .zip(other.stack.iter())
+                .map(|(a, b)| a.union(b).copied().collect())
+                .collect(),
+        }
+    }
+
+    fn widen(&self, next: &State, index_thresholds: &[u32]) -> State {
+        let mut registers = self.registers;
+        for (register, next) in registers.iter_mut().zip(next.registers.iter()) {
+            *register = register.widen(next, &[0xFF]);
+        }
+        State {
+            registers,
+            index: self.index.widen(&next.index, index_thresholds),
+            stack: next.stack.clone(),
+        }
+    }
+}
+
+// データを実行してしまう理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataKind {
+    UnknownOpcode(u16),
+    OutsideRom, // ROMの外（フォント領域や空きメモリ）
+    Misaligned, // 別の命令の途中
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    StackOverflow { depth: usize },
+    UnbalancedReturn,
+    MemoryOutOfBounds { access: MemoryAccess, end: usize },
+    ExecutingData(DataKind),
+    UnboundedComputedJump,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::StackOverflow { depth } => {
+                write!(f, "call exceeds the {}-entry stack", depth)
+            }
+            Problem::UnbalancedReturn => write!(f, "return with an empty stack"),
+            Problem::MemoryOutOfBounds { access, end } => {
+                write!(
+                    f,
+                    "{} through I may reach {:04X}, past the end of memory",
+                    access, end
+                )
+            }
+            Problem::ExecutingData(DataKind::UnknownOpcode(opcode)) => {
+                write!(f, "executes data (unknown opcode {:04X})", opcode)
+            }
+            Problem::ExecutingData(DataKind::OutsideRom) => {
+                write!(f, "executes memory outside the ROM")
+            }
+            Problem::ExecutingData(DataKind::Misaligned) => {
+                write!(f, "executes from the middle of another instruction")
+            }
+            Problem::UnboundedComputedJump => {
+                write!(f, "computed jump has too many possible targets")
+            }
+        }
+    }
+}
+
+// definite が true の場合はどの実行経路でも問題が起きる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Finding {
+    pub address: usize,
+    pub problem: Problem,
+    pub definite: bool,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = if self.definite { "error" } else { "warning" };
+        write!(f, "{:03X}: {}: {}", self.address, severity, self.problem)
+    }
+}
+
+pub struct Report {
+    pub findings: Vec<Finding>,
+    // 状態数の上限に達して解析を打ち切った場合はfalse
+    pub complete: bool,
+}
+
+impl Report {
+    pub fn has_errors(&self) -> bool {
+        self.findings.iter().any(|finding| finding.definite)
+    }
+}
+
+// 同じ位置・同じ呼び出し履歴での状態
+#[derive(Default)]
+struct Visits {
+    states: Vec<State>,
+    merged: Option<State>,
+    joins: usize,
+}
+
+struct Verifier<'a> {
+    rom: &'a [u8],
+    platform: Option<Platform>,
+    quirks: Quirks,
+    memory_size: usize,
+    stack_depth: usize,
+    index_thresholds: Vec<u32>,
+    visits: HashMap<(usize, usize), Visits>,
+    instruction_sizes: BTreeMap<usize, usize>,
+    findings: Vec<Finding>,
+    reports: usize,
+    // 問題なく実行できたことがある命令
+    clean: HashSet<usize>,
+    pending: VecDeque<(usize, State)>,
+}
+
+// ROMを抽象解釈し、実行時に起こりうる問題を報告する
+// レジスタとIは値の範囲として扱い、呼び出し履歴ごとに状態を分けて調べる
+pub fn verify(rom: &[u8], platform: Option<Platform>) -> Report {
+    let (quirks, memory_size, stack_depth) = match platform {
+        Some(platform) => (
+            platform.quirks(),
+            platform.memory_size(),
+            platform.stack_depth(),
+        ),
+        None => (Quirks::default(), 0x1000, 16),
+    };
+    let mut verifier = Verifier {
+        rom,
+        platform,
+        quirks,
+        memory_size,
+        stack_depth,
+        index_thresholds: index_thresholds(rom, platform, memory_size),
+        visits: HashMap::new(),
+        instruction_sizes: BTreeMap::new(),
+        findings: Vec::new(),
+        reports: 0,
+        clean: HashSet::new(),
+        pending: VecDeque::new(),
+    };
+    let initial = State {
+        registers: [Range::exact(0); 16],
+        index: Range::exact(0),
+        stack: Vec::new(),
+    };
+    verifier.enqueue(PROGRAM_START, initial);
+
+    let mut steps = 0;
+    while let Some((pc, state)) = verifier.pending.pop_front() {
+        steps += 1;
+        if steps > MAX_STEPS {
+            break;
+        }
+        let reports = verifier.reports;
+        verifier.step(pc, state);
+        if verifier.reports == reports {
+            verifier.clean.insert(pc);
+        }
+    }
+
+    // 問題なく実行できる経路がある命令の問題は、起こりうるものとして報告する
+    let mut findings = verifier.findings;
+    for finding in &mut findings {
+        finding.definite &= !verifier.clean.contains(&finding.address);
+    }
+    findings.sort_by_key(|finding| finding.address);
+    Report {
+        findings,
+        complete: steps <= MAX_STEPS,
+    }
+}
+
+// I の拡大に使う閾値。ROM中で I に設定する値にレジスタ1つ分を足した位置と、メモリの終端
+fn index_thresholds(rom: &[u8], platform: Option<Platform>, memory_size: usize) -> Vec<u32> {
+    let mut thresholds: Vec<u32> = (0..rom.len())
+        .filter_map(|offset| match decode_at(rom, offset, platform) {
+            Some(Instruction::LdI(nnn) | Instruction::LdILong(nnn)) => Some(nnn as u32 + 0xFF),
+            _ => None,
+        })
+        .chain([memory_size as u32 - 1, 0xFFFF])
+        .collect();
+    thresholds.sort_unstable();
+    thresholds.dedup();
+    thresholds
+}
+
+impl Verifier<'_> {
+    fn report(&mut self, address: usize, problem: Problem, definite: bool) {
+        // どの状態で実行しても起きる場合だけ definite として残す
+        self.reports += 1;
+        let existing = self
+            .findings
+            .iter_mut()
+            .find(|finding| finding.address == address && finding.problem == problem);
+        match existing {
+            Some(finding) => finding.definite &= definite,
+            None => self.findings.push(Finding {
+                address,
+                problem,
+                definite,
+            }),
+        }
+    }
+
+    // 既に調べた状態に含まれていなければ、調べる状態に加える
+    fn enqueue(&mut self, pc: usize, state: State) {
+        let visits = self.visits.entry((pc, state.stack.len())).or_default();
+
+        if let Some(merged) = &visits.merged {
+            if merged.includes(&state) {
+                return;
+            }
+            visits.joins += 1;
+            let mut joined = merged.join(&state);
+            if visits.joins > JOINS_BEFORE_WIDENING {
+                joined = merged.widen(&joined, &self.index_thresholds);
+            }
+            visits.merged = Some(joined.clone());
+            self.pending.push_back((pc, joined));
+            return;
+        }
+
+        if visits.states.iter().any(|seen| seen.includes(&state)) {
+            return;
+        }
+        visits.states.push(state.clone());
+        if visits.states.len() > STATES_PER_POINT {
+            let states = std::mem::take(&mut visits.states);
+            let merged = states[1..]
+                .iter()
+                .fold(states[0].clone(), |acc, next| acc.join(next));
+            visits.merged = Some(merged.clone());
+            self.pending.push_back((pc, merged));
+        } else {
+            self.pending.push_back((pc, state));
+        }
+    }
+
+    fn in_rom(&self, address: usize) -> bool {
+        address >= PROGRAM_START && address - PROGRAM_START < self.rom.len()
+    }
+
+    // I から length バイトにアクセスする
+    fn check_access(&mut self, pc: usize, index: Range, length: usize, access: MemoryAccess) {
+        self.check_access_between(pc, index, (length, length), access);
+    }
+
+    // アクセスするバイト数が状態によって変わる場合（XO-CHIPのプレーン数など）
+    fn check_access_between(
+        &mut self,
+        pc: usize,
+        index: Range,
+        (shortest, longest): (usize, usize),
+        access: MemoryAccess,
+    ) {
+        if longest == 0 {
+            return;
+        }
+        let last = |start: u32, length: usize| (start as usize + length).saturating_sub(1);
+        if last(index.hi, longest) >= self.memory_size {
+            let definite = shortest > 0 && last(index.lo, shortest) >= self.memory_size;
+            self.report(
+                pc,
+                Problem::MemoryOutOfBounds {
+                    access,
+                    end: last(index.hi, longest),
+                },
+                definite,
+            );
+        }
+    }
+
+    fn decode(&mut self, pc: usize) -> Option<Instruction> {
+        if !self.in_rom(pc) {
+            self.report(pc, Problem::ExecutingData(DataKind::OutsideRom), true);
+            return None;
+        }
+        let Some(instruction) = decode_at(self.rom, pc - PROGRAM_START, self.platform) else {
+            let opcode = u16::from_be_bytes([
+                self.rom[pc - PROGRAM_START],
+                self.rom.get(pc - PROGRAM_START + 1).copied().unwrap_or(0),
+            ]);
+            self.report(
+                pc,
+                Problem::ExecutingData(DataKind::UnknownOpcode(opcode)),
+                true,
+            );
+            return None;
+        };
+
+        // 既に命令として解釈した範囲の途中から実行していないか
+        let overlaps = self
+            .instruction_sizes
+            .range(..pc)
+            .next_back()
+            .is_some_and(|(&start, &size)| pc < start + size)
+            || self
+                .instruction_sizes
+                .range(pc + 1..pc + instruction.size())
+                .next()
+                .is_some();
+        if overlaps {
+            self.report(pc, Problem::ExecutingData(DataKind::Misaligned), false);
+        }
+        self.instruction_sizes.insert(pc, instruction.size());
+        Some(instruction)
+    }
+
+    fn step(&mut self, pc: usize, mut state: State) {
+        let Some(instruction) = self.decode(pc) else {
+            return;
+        };
+        let next = pc + instruction.size();
+        let skipped = if self.in_rom(next) {
+            next + skipped_size(self.rom, next - PROGRAM_START, self.platform)
+        } else {
+            next + 2
+        };
+
+        use Instruction::*;
+        let v = state.registers;
+        let exact = Range::exact;
+        match instruction {
+            Sys(nnn) => match self.quirks.sys_call {
+                SysCall::Jump => return self.enqueue(nnn as usize, state),
+                SysCall::Ignore => {}
+                SysCall::Unsupported => {
+                    self.report(
+                        pc,
+                        Problem::ExecutingData(DataKind::UnknownOpcode(instruction.encode())),
+                        true,
+                    );
+                    return;
+                }
+            },
+            Exit => return,
+            Jp(nnn) => return self.enqueue(nnn as usize, state),
+            JpV0(nnn) => {
+                let register = if self.quirks.jump_uses_vx {
+                    (nnn >> 8) as usize & 0xF
+                } else {
+                    0
+                };
+                let offset = v[register];
+                if offset.hi - offset.lo >= MAX_JUMP_TARGETS {
+                    self.report(pc, Problem::UnboundedComputedJump, false);
+                    return;
+                }
+                for value in offset.lo..=offset.hi {
+                    let mut target_state = state.clone();
+                    target_state.registers[register] = exact(value);
+                    self.enqueue(nnn as usize + value as usize, target_state);
+                }
+                return;
+            }
+            Call(nnn) => {
+                if state.stack.len() >= self.stack_depth {
+                    self.report(
+                        pc,
+                        Problem::StackOverflow {
+                            depth: self.stack_depth,
+                        },
+                        true,
+                    );
+                    return;
+                }
+                state.stack.push(BTreeSet::from([next]));
+                return self.enqueue(nnn as usize, state);
+            }
+            Ret => {
+                let Some(targets) = state.stack.pop() else {
+                    self.report(pc, Problem::UnbalancedReturn, true);
+                    return;
+                };
+                for target in targets {
+                    self.enqueue(target, state.clone());
+                }
+                return;
+            }
+            SeByte(x, kk) | SneByte(x, kk) => {
+                let range = v[x as usize];
+                let kk = kk as u32;
+                let equal = {
+                    let mut state = state.clone();
+                    state.registers[x as usize] = exact(kk);
+                    range.contains(kk).then_some(state)
+                };
+                let not_equal = (range.single() != Some(kk)).then(|| {
+                    let mut state = state.clone();
+                    let register = &mut state.registers[x as usize];
+                    if register.lo == kk {
+                        register.lo += 1;
+                    } else if register.hi == kk {
+                        register.hi -= 1;
+                    }
+                    state
+                });
+                let (skip, run) = match instruction {
+                    SeByte(..) => (equal, not_equal),
+                    _ => (not_equal, equal),
+                };
+                if let Some(state) = skip {
+                    self.enqueue(skipped, state);
+                }
+                if let Some(state) = run {
+                    self.enqueue(next, state);
+                }
+                return;
+            }
+            SeXy(x, y) | SneXy(x, y) => {
+                let (a, b) = (v[x as usize], v[y as usize]);
+                let always_equal = a.single().is_some() && a.single() == b.single();
+                let never_equal = a.hi < b.lo || b.hi < a.lo;
+                let (skip, run) = match instruction {
+                    SeXy(..) => (!never_equal, !always_equal),
+                    _ => (!always_equal, !never_equal),
+                };
+                if skip {
+                    self.enqueue(skipped, state.clone());
+                }
+                if run {
+                    self.enqueue(next, state);
+                }
+                return;
+            }
+            Skp(_) | Sknp(_) => {
+                self.enqueue(skipped, state.clone());
+                return self.enqueue(next, state);
+            }
+            LdByte(x, kk) => state.registers[x as usize] = exact(kk as u32),
+            AddByte(x, kk) => {
+                state.registers[x as usize] = v[x as usize].add(&exact(kk as u32), 0xFF)
+            }
+            LdXy(x, y) => state.registers[x as usize] = v[y as usize],
+            OrXy(x, y) | AndXy(x, y) | XorXy(x, y) => {
+                let (a, b) = (v[x as usize], v[y as usize]);
+                let result = match (a.single(), b.single(), instruction) {
+                    (Some(a), Some(b), OrXy(..)) => exact(a | b),
+                    (Some(a), Some(b), AndXy(..)) => exact(a & b),
+                    (Some(a), Some(b), _) => exact(a ^ b),
+                    (_, _, OrXy(..)) => Range {
+                        lo: a.lo.max(b.lo),
+                        hi: a.bit_ceiling(&b),
+                    },
+                    (_, _, AndXy(..)) => Range {
+                        lo: 0,
+                        hi: a.hi.min(b.hi),
+                    },
+                    _ => Range {
+                        lo: 0,
+                        hi: a.bit_ceiling(&b),
+                    },
+                };
+                state.registers[x as usize] = result;
+                if self.quirks.vf_reset {
+                    state.registers[0xF] = exact(0);
+                }
+            }
+            AddXy(x, y) => {
+                let (a, b) = (v[x as usize], v[y as usize]);
+                state.registers[x as usize] = a.add(&b, 0xFF);
+                state.registers[0xF] = Range::flag(a.lo + b.lo > 0xFF, a.hi + b.hi <= 0xFF);
+            }
+            SubXy(x, y) | SubnXy(x, y) => {
+                let (a, b) = match instruction {
+                    SubXy(..) => (v[x as usize], v[y as usize]),
+                    _ => (v[y as usize], v[x as usize]),
+                };
+                state.registers[x as usize] = a.sub(&b);
+                // 借りが無ければ1
+                state.registers[0xF] = Range::flag(a.lo >= b.hi, a.hi < b.lo);
+            }
+            ShrXy(x, y) | ShlXy(x, y) => {
+                let source = if self.quirks.shift_uses_vy {
+                    v[y as usize]
+                } else {
+                    v[x as usize]
+                };
+                let (result, flag) = match instruction {
+                    ShrXy(..) => (
+                        Range {
+                            lo: source.lo >> 1,
+                            hi: source.hi >> 1,
+                        },
+                        source
+                            .single()
+                            .map_or(Range { lo: 0, hi: 1 }, |value| exact(value & 1)),
+                    ),
+                    _ if source.hi < 0x80 => (
+                        Range {
+                            lo: source.lo << 1,
+                            hi: source.hi << 1,
+                        },
+                        exact(0),
+                    ),
+                    _ => (Range::BYTE, Range::flag(source.lo >= 0x80, false)),
+                };
+                state.registers[x as usize] = result;
+                state.registers[0xF] = flag;
+            }
+            LdI(nnn) | LdILong(nnn) => state.index = exact(nnn as u32),
+            Rnd(x, kk) => {
+                state.registers[x as usize] = Range {
+                    lo: 0,
+                    hi: kk as u32,
+                }
+            }
+            Drw(_, _, n) => {
+                // 16x16のスプライトは32バイト。選択中のプレーンは追跡しないので最大数で調べる
+                let superchip = self
+                    .platform
+                    .is_some_and(|platform| platform.superchip_instructions());
+                let bytes = if n == 0 && superchip { 32 } else { n as usize };
+                let planes = if self.platform == Some(Platform::XoChip) {
+                    2
+                } else {
+                    1
+                };
+                self.check_access_between(
+                    pc,
+                    state.index,
+                    (bytes, bytes * planes),
+                    MemoryAccess::Read,
+                );
+                state.registers[0xF] = Range { lo: 0, hi: 1 };
+            }
+            Audio => self.check_access(pc, state.index, 16, MemoryAccess::Read),
+            LdVxDt(x) => state.registers[x as usize] = Range::BYTE,
+            LdVxK(x) => state.registers[x as usize] = Range { lo: 0, hi: 0xF },
+            AddIVx(x) => state.index = state.index.add(&v[x as usize], 0xFFFF),
+            LdFVx(x) => {
+                let value = v[x as usize];
+                state.index = Range {
+                    lo: value.lo * 5,
+                    hi: value.hi * 5,
+                };
+            }
+            LdHfVx(x) => {
+                let value = v[x as usize];
+                let digits = match value.single() {
+                    Some(digit) => exact(digit & 0xF),
+                    None if value.hi < 0x10 => value,
+                    None => Range { lo: 0, hi: 0xF },
+                };
+                let base = BIG_FONT_ADDRESS as u32;
+                state.index = Range {
+                    lo: base + digits.lo * 10,
+                    hi: base + digits.hi * 10,
+                };
+            }
+            LdBVx(_) => self.check_access(pc, state.index, 3, MemoryAccess::Write),
+            LdIVx(x) | LdVxI(x) => {
+                let access = match instruction {
+                    LdIVx(_) => MemoryAccess::Write,
+                    _ => MemoryAccess::Read,
+                };
+                self.check_access(pc, state.index, x as usize + 1, access);
+                if access == MemoryAccess::Read {
+                    for register in &mut state.registers[..=x as usize] {
+                        *register = Range::BYTE;
+                    }
+                }
+                let amount = match self.quirks.index_increment {
+                    IndexIncrement::Unchanged => 0,
+                    IndexIncrement::X => x as u32,
+                    IndexIncrement::XPlusOne => x as u32 + 1,
+                };
+                state.index = state.index.add(&exact(amount), 0xFFFF);
+            }
+            SaveXy(x, y) => self.check_access(
+                pc,
+                state.index,
+                x.abs_diff(y) as usize + 1,
+                MemoryAccess::Write,
+            ),
+            LoadXy(x, y) => {
+                self.check_access(
+                    pc,
+                    state.index,
+                    x.abs_diff(y) as usize + 1,
+                    MemoryAccess::Read,
+                );
+                for register in x.min(y)..=x.max(y) {
+                    state.registers[register as usize] = Range::BYTE;
+                }
+            }
+            LdVxR(x) => {
+                for register in &mut state.registers[..=x as usize] {
+                    *register = Range::BYTE;
+                }
+            }
+            Cls | Scd(_) | Scu(_) | Scr | Scl | Low | High | Plane(_) | LdDtVx(_) | LdStVx(_)
+            | Pitch(_) | LdRVx(_) => {}
+        }
+        self.enqueue(next, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn problems(source: &str) -> Vec<(usize, Problem, bool)> {
+        let rom = assemble(source).unwrap();
+        let report = verify(&rom, None);
+        assert!(report.complete);
+        report
+            .findings
+            .iter()
+            .map(|finding| (finding.address, finding.problem, finding.definite))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_loop_has_no_findings() {
+        let source = "
+            : main
+                v0 := 0
+                i := box
+                loop
+                    sprite v0 v0 4
+                    v0 += 1
+                    if v0 != 10 then
+                again
+                loop again
+            : box
+                0xF0 0x90 0x90 0xF0
+        ";
+        assert_eq!(problems(source), vec![]);
+    }
+
+    #[test]
+    fn test_stack_and_return() {
+        let recursive = "
+            : main
+                recurse
+            : recurse
+                recurse
+        ";
+        // 浅い呼び出しは成功するので、起こりうる問題として報告する
+        assert_eq!(
+            problems(recursive),
+            vec![(0x202, Problem::StackOverflow { depth: 16 }, false)]
+        );
+        assert_eq!(
+            problems(": main v0 := 1 return"),
+            vec![(0x202, Problem::UnbalancedReturn, true)]
+        );
+    }
+
+    #[test]
+    fn test_memory_bounds_follow_value_ranges() {
+        // v1 は 0..=0xFF の範囲なので、I + v1 + 15 がメモリの外に出る可能性がある
+        let source = "
+            : main
+                i := 0xF00
+                v1 := random 0xFF
+                i += v1
+                load vf
+                jump main
+        ";
+        assert_eq!(
+            problems(source),
+            vec![(
+                0x206,
+                Problem::MemoryOutOfBounds {
+                    access: MemoryAccess::Read,
+                    end: 0x100E
+                },
+                false
+            )]
+        );
+
+        let report = verify(
+            &assemble(": main i := 0xFFE bcd v0 loop again").unwrap(),
+            None,
+        );
+        assert_eq!(report.findings.len(), 1);
+        assert!(report.has_errors());
+    }
+
+    #[test]
+    fn test_executing_data() {
+        let source = "
+            : main
+                jump data
+            : data
+                0xE0 0x00
+        ";
+        assert_eq!(
+            problems(source),
+            vec![(
+                0x202,
+                Problem::ExecutingData(DataKind::UnknownOpcode(0xE000)),
+                true
+            )]
+        );
+        assert_eq!(
+            problems(": main v0 := 1"),
+            vec![(0x202, Problem::ExecutingData(DataKind::OutsideRom), true)]
+        );
+    }
+
+    #[test]
+    fn test_bundled_roms_verify() {
+        for rom in [
+            &include_bytes!("../rom/BRIX")[..],
+            &include_bytes!("../rom/GUESS")[..],
+        ] {
+            let report = verify(rom, None);
+            assert!(report.complete);
+            assert_eq!(report.findings, vec![]);
+        }
+
+        // INVADERS はキー入力でサブルーチンから抜け出すため、スタックが溢れうる
+        let report = verify(include_bytes!("../rom/INVADERS"), None);
+        assert!(report.complete);
+        assert_eq!(
+            report.findings,
+            vec![Finding {
+                address: 0x34D,
+                problem: Problem::StackOverflow { depth: 16 },
+                definite: false,
+            }]
+        );
+    }
+}
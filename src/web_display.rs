@@ -1,11 +1,15 @@
-use crate::chip8::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::chip8::{ExecutionError, DISPLAY_HEIGHT, DISPLAY_WIDTH};
 use crate::display::Draw;
+use crate::framebuffer::FrameBuffer;
 use wasm_bindgen::prelude::*;
 use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
 
+const PALETTE: [&str; 4] = ["#000000", "#ffffff", "#aaaaaa", "#555555"];
+
 pub struct WebDraw {
     context: CanvasRenderingContext2d,
-    pixel_size: f64,
+    width: f64,
+    height: f64,
 }
 
 impl WebDraw {
@@ -17,10 +21,12 @@ impl WebDraw {
             .ok_or("Failed to get canvas element")?
             .dyn_into::<HtmlCanvasElement>()?;
         
-        // Canvasのサイズを設定（64x32の10倍）
+        // Canvasのサイズを設定（64x32の10倍、128x64の場合は5倍で描画）
         let pixel_size = 10.0;
-        canvas.set_width((DISPLAY_WIDTH as f64 * pixel_size) as u32);
-        canvas.set_height((DISPLAY_HEIGHT as f64 * pixel_size) as u32);
+        let width = DISPLAY_WIDTH as f64 * pixel_size;
+        let height = DISPLAY_HEIGHT as f64 * pixel_size;
+        canvas.set_width(width as u32);
+        canvas.set_height(height as u32);
         
         let context = canvas
             .get_context("2d")?
@@ -28,37 +34,49 @@ impl WebDraw {
             .dyn_into::<CanvasRenderingContext2d>()?;
         
         // 背景を黒に設定
-        context.set_fill_style(&"#000000".into());
-        context.fill_rect(0.0, 0.0, 
-            DISPLAY_WIDTH as f64 * pixel_size,
-            DISPLAY_HEIGHT as f64 * pixel_size);
+        context.set_fill_style_str("#000000");
+        context.fill_rect(0.0, 0.0, width, height);
         
-        Ok(WebDraw { context, pixel_size })
+        Ok(WebDraw { context, width, height })
     }
 }
 
 impl Draw for WebDraw {
-    fn draw(&self, display: &[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT]) {
+    fn draw(&self, display: &FrameBuffer) {
         // 画面全体をクリア（黒で塗りつぶし）
-        self.context.set_fill_style(&"#000000".into());
-        self.context.fill_rect(0.0, 0.0,
-            DISPLAY_WIDTH as f64 * self.pixel_size,
-            DISPLAY_HEIGHT as f64 * self.pixel_size);
-        
-        // ピクセルを描画（白で塗りつぶし）
-        self.context.set_fill_style(&"#ffffff".into());
+        self.context.set_fill_style_str("#000000");
+        self.context.fill_rect(0.0, 0.0, self.width, self.height);
+
+        // 現在の解像度に合わせてピクセルサイズを決める
+        let pixel_width = self.width / display.width() as f64;
+        let pixel_height = self.height / display.height() as f64;
         
-        for (y, row) in display.iter().enumerate() {
+        // ピクセルを描画（プレーン1は白、XO-CHIPのプレーン2以降は灰色系）
+        for (y, row) in display.rows().enumerate() {
             for (x, &pixel) in row.iter().enumerate() {
-                if pixel {
+                if pixel != 0 {
+                    self.context.set_fill_style_str(PALETTE[pixel as usize]);
                     self.context.fill_rect(
-                        x as f64 * self.pixel_size,
-                        y as f64 * self.pixel_size,
-                        self.pixel_size,
-                        self.pixel_size,
+                        x as f64 * pixel_width,
+                        y as f64 * pixel_height,
+                        pixel_width,
+                        pixel_height,
                     );
                 }
             }
         }
     }
-}
\ No newline at end of file
+
+    fn draw_fault(&self, error: &ExecutionError) {
+        // 最後の画面の上に半透明の赤を重ねてエラー内容を表示
+        let height = self.height;
+        self.context.set_fill_style_str("rgba(128, 0, 0, 0.7)");
+        self.context.fill_rect(0.0, 0.0, self.width, height);
+
+        self.context.set_fill_style_str("#ffffff");
+        self.context.set_font("20px monospace");
+        let _ = self.context.fill_text("CHIP-8 FAULT", 20.0, height / 2.0 - 15.0);
+        self.context.set_font("14px monospace");
+        let _ = self.context.fill_text(&error.to_string(), 20.0, height / 2.0 + 15.0);
+    }
+}
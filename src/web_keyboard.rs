@@ -1,43 +1,42 @@
-use crate::keyboard::KeyboardInput;
+use crate::keyboard::{map_key, InputEvent, KeyEvent, KeyState, KeyboardInput};
 use std::sync::mpsc;
 use wasm_bindgen::prelude::*;
 use web_sys::KeyboardEvent;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 pub struct WebKeyboard {
-    current_key: Rc<RefCell<Option<u8>>>,
+    state: Rc<RefCell<KeyState>>,
+    rewind: Rc<Cell<bool>>,
+}
+
+// 押している間は巻き戻すキー
+const REWIND_KEY: &str = "Backspace";
+
+// 1文字のキー名をCHIP-8のキーに変換する
+fn key_code(event: &KeyboardEvent) -> Option<u8> {
+    let key = event.key();
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => map_key(c),
+        _ => None,
+    }
 }
 
 impl WebKeyboard {
     pub fn new() -> Self {
-        let current_key = Rc::new(RefCell::new(None));
+        let state = Rc::new(RefCell::new(KeyState::default()));
+        let rewind = Rc::new(Cell::new(false));
         
         // キーボードイベントリスナーを設定
-        let current_key_clone = current_key.clone();
+        let state_clone = state.clone();
+        let rewind_clone = rewind.clone();
         let closure = Closure::wrap(Box::new(move |event: KeyboardEvent| {
-            let key_code = match event.key().as_str() {
-                "1" => Some(0x1),
-                "2" => Some(0x2),
-                "3" => Some(0x3),
-                "4" => Some(0xC),
-                "q" | "Q" => Some(0x4),
-                "w" | "W" => Some(0x5),
-                "e" | "E" => Some(0x6),
-                "r" | "R" => Some(0xD),
-                "a" | "A" => Some(0x7),
-                "s" | "S" => Some(0x8),
-                "d" | "D" => Some(0x9),
-                "f" | "F" => Some(0xE),
-                "z" | "Z" => Some(0xA),
-                "x" | "X" => Some(0x0),
-                "c" | "C" => Some(0xB),
-                "v" | "V" => Some(0xF),
-                _ => None,
-            };
-            
-            if let Some(key) = key_code {
-                *current_key_clone.borrow_mut() = Some(key);
+            if event.key() == REWIND_KEY {
+                event.prevent_default();
+                rewind_clone.set(true);
+            } else if let Some(key) = key_code(&event) {
+                state_clone.borrow_mut().press(key);
             }
         }) as Box<dyn FnMut(KeyboardEvent)>);
 
@@ -50,10 +49,15 @@ impl WebKeyboard {
         
         closure.forget(); // メモリリークを防ぐため、クロージャを忘れる
         
-        // キーリリース時にキーをクリア
-        let current_key_clone2 = current_key.clone();
-        let keyup_closure = Closure::wrap(Box::new(move |_event: KeyboardEvent| {
-            *current_key_clone2.borrow_mut() = None;
+        // キーリリース時にそのキーだけを離す
+        let state_clone2 = state.clone();
+        let rewind_clone2 = rewind.clone();
+        let keyup_closure = Closure::wrap(Box::new(move |event: KeyboardEvent| {
+            if event.key() == REWIND_KEY {
+                rewind_clone2.set(false);
+            } else if let Some(key) = key_code(&event) {
+                state_clone2.borrow_mut().release(key);
+            }
         }) as Box<dyn FnMut(KeyboardEvent)>);
 
         document
@@ -62,16 +66,24 @@ impl WebKeyboard {
         
         keyup_closure.forget();
 
-        WebKeyboard { current_key }
+        WebKeyboard { state, rewind }
     }
 }
 
 impl KeyboardInput for WebKeyboard {
-    fn start_keyboard_thread(_sender: mpsc::Sender<u8>) {
+    fn start_keyboard_thread(_sender: mpsc::Sender<InputEvent>) {
         // Webでは不要
     }
 
-    fn get_key(&self) -> Option<u8> {
-        *self.current_key.borrow()
+    fn pressed_keys(&mut self) -> u16 {
+        self.state.borrow().pressed()
     }
-}
\ No newline at end of file
+
+    fn take_events(&mut self) -> Vec<KeyEvent> {
+        self.state.borrow_mut().take_events()
+    }
+
+    fn rewind_held(&mut self) -> bool {
+        self.rewind.get()
+    }
+}